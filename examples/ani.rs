@@ -2,44 +2,23 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use std::sync::Arc;
-use winit::{
-  event::{Event, KeyEvent, WindowEvent},
-  event_loop::{ControlFlow, EventLoop},
-  keyboard::{KeyCode, PhysicalKey},
-  window::WindowBuilder,
-};
-
 use rust_animation::animation::Animation;
 use rust_animation::animation::EasingFunction;
-use rust_animation::layer::LayoutMode;
+use rust_animation::app::App;
 use rust_animation::layer::Layer;
 use rust_animation::play::Play;
 
 fn main() {
-  let event_loop = EventLoop::new().unwrap();
-  let window = Arc::new(
-    WindowBuilder::new()
-      .with_title("Animation test")
-      .with_inner_size(winit::dpi::LogicalSize::new(1280, 720))
-      .build(&event_loop)
-      .unwrap(),
-  );
-
-  // Get the actual window size (may differ from requested due to DPI scaling)
-  let window_size = window.inner_size();
-  let (width, height) = (window_size.width, window_size.height);
-
-  let mut play = Play::new(
-    "Animation test".to_string(),
-    width as i32,
-    height as i32,
-    LayoutMode::UserDefine,
-  );
-
-  // Initialize wgpu context with surface using actual window size
-  play.init_wgpu_with_surface(window.clone(), width, height);
+  App::new("Animation test")
+    .exit_on_escape(true)
+    .run(|play, window| {
+      let size = window.inner_size();
+      build_stage(play, size.width, size.height);
+    })
+    .unwrap();
+}
 
+fn build_stage(play: &mut Play, width: u32, height: u32) {
   let mut stage = Layer::new("stage".to_string(), width, height, None);
   stage.set_visible(true);
 
@@ -80,37 +59,4 @@ fn main() {
   stage.add_sub_layer(layer_2);
 
   play.add_stage(stage);
-
-  event_loop
-    .run(move |event, elwt| {
-      elwt.set_control_flow(ControlFlow::Poll);
-
-      match event {
-        Event::WindowEvent { event, .. } => match event {
-          WindowEvent::CloseRequested => elwt.exit(),
-          WindowEvent::KeyboardInput {
-            event:
-              KeyEvent {
-                physical_key: PhysicalKey::Code(KeyCode::Escape),
-                ..
-              },
-            ..
-          } => elwt.exit(),
-          WindowEvent::Resized(new_size) => {
-            // Update wgpu surface and projection when window is resized
-            play.resize(new_size.width, new_size.height);
-          }
-          WindowEvent::RedrawRequested => {
-            play.render();
-            window.request_redraw();
-          }
-          _ => {}
-        },
-        Event::AboutToWait => {
-          window.request_redraw();
-        }
-        _ => {}
-      }
-    })
-    .unwrap();
 }
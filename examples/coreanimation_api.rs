@@ -4,44 +4,31 @@
 
 // This example demonstrates the CoreAnimation-style API
 
-use std::sync::Arc;
-use winit::{
-  event::{Event, KeyEvent, WindowEvent},
-  event_loop::{ControlFlow, EventLoop},
-  keyboard::{KeyCode, PhysicalKey},
-  window::WindowBuilder,
-};
+use std::f32::consts::{FRAC_PI_2, PI};
 
 use rust_animation::animation::Animation;
 use rust_animation::animation::EasingFunction;
-use rust_animation::layer::LayoutMode;
+use rust_animation::app::App;
+use rust_animation::gradient::Gradient;
 use rust_animation::layer::Layer;
 use rust_animation::play::Play;
+use rust_animation::pointer::PointerEventKind;
+use rust_animation::shape::{LineCap, LineJoin, Path, Shape};
+use rust_animation::text::{Text, TextAlignment, TextRenderMode, VerticalAlignment};
+use rust_animation::text_field::TextField;
 
 fn main() {
-  let event_loop = EventLoop::new().unwrap();
-  let window = Arc::new(
-    WindowBuilder::new()
-      .with_title("CoreAnimation API Demo")
-      .with_inner_size(winit::dpi::LogicalSize::new(1280, 720))
-      .build(&event_loop)
-      .unwrap(),
-  );
-
-  // Get the actual window size (may differ from requested due to DPI scaling)
-  let window_size = window.inner_size();
-  let (width, height) = (window_size.width, window_size.height);
-
-  let mut play = Play::new(
-    "CoreAnimation API test".to_string(),
-    width as i32,
-    height as i32,
-    LayoutMode::UserDefine,
-  );
-
-  // Initialize wgpu context with surface using actual window size
-  play.init_wgpu_with_surface(window.clone(), width, height);
+  App::new("CoreAnimation API Demo")
+    .exit_on_escape(true)
+    .run(|play, window| {
+      // The actual size may differ from the requested one due to DPI scaling
+      let size = window.inner_size();
+      build_stage(play, size.width, size.height);
+    })
+    .unwrap();
+}
 
+fn build_stage(play: &mut Play, width: u32, height: u32) {
   let mut stage = Layer::new("stage".to_string(), width, height, None);
   stage.set_visible(true);
 
@@ -133,13 +120,237 @@ fn main() {
   // Add child using CoreAnimation-style API
   parent_layer.add_sublayer(child_layer);
 
-  // Add all layers to stage using CoreAnimation-style API
+  // Example 7: 3D card flip with perspective
+  let mut card_container = Layer::new("cardContainer".to_string(), 200, 200, None);
+  card_container.set_position(800, 350);
+  card_container.set_background_color(0.2, 0.2, 0.2); // Dark gray
+  card_container.set_sublayer_transform_m34(-1.0 / 500.0);
+
+  let mut card = Layer::new("card".to_string(), 120, 160, None);
+  card.set_position(40, 20);
+  card.set_background_color(0.0, 1.0, 1.0); // Cyan
+
+  let mut flip_animation = Animation::with_key_path("transform.rotation.y");
+  flip_animation.duration = 3.0;
+  flip_animation.timing_function = Some(EasingFunction::EaseInOut);
+  flip_animation.set_from_value_rotation_y(0);
+  flip_animation.set_to_value_rotation_y(360);
+  card.add_animation(flip_animation, Some("flip"));
+
+  card_container.add_sublayer(card);
+
+  // Example 8: Rounded corners and borders
+  let mut rounded_layer = Layer::new("roundedLayer".to_string(), 160, 100, None);
+  rounded_layer.set_position(1050, 100);
+  rounded_layer.set_background_color(1.0, 0.5, 0.0); // Orange
+  rounded_layer.set_border_width(4.0);
+  rounded_layer.set_border_color(1.0, 1.0, 1.0);
+  rounded_layer.set_shadow_opacity(0.5);
+  rounded_layer.set_shadow_radius(10.0);
+  rounded_layer.set_shadow_offset(0.0, 6.0);
+
+  let mut corner_animation = Animation::with_key_path("cornerRadius");
+  corner_animation.duration = 2.0;
+  corner_animation.timing_function = Some(EasingFunction::EaseInOut);
+  corner_animation.set_from_value_corner_radius(0.0);
+  corner_animation.set_to_value_corner_radius(50.0);
+  corner_animation.set_from_value_border_color([1.0, 1.0, 1.0]);
+  corner_animation.set_to_value_border_color([0.0, 0.0, 1.0]);
+  rounded_layer.add_animation(corner_animation, Some("roundCorners"));
+
+  // Example 9: Clipping sublayers to bounds
+  let mut clip_container = Layer::new("clipContainer".to_string(), 200, 120, None);
+  clip_container.set_position(1050, 300);
+  clip_container.set_background_color(0.3, 0.3, 0.3);
+  clip_container.set_corner_radius(16.0);
+  clip_container.set_masks_to_bounds(true);
+
+  let mut sliding_layer = Layer::new("slidingLayer".to_string(), 80, 80, None);
+  sliding_layer.set_position(-80, 20);
+  sliding_layer.set_background_color(0.0, 1.0, 0.5);
+
+  let mut slide_animation = Animation::with_key_path("position.x");
+  slide_animation.duration = 3.0;
+  slide_animation.timing_function = Some(EasingFunction::Linear);
+  slide_animation.set_from_value_position_x(-80);
+  slide_animation.set_to_value_position_x(200);
+  sliding_layer.add_animation(slide_animation, Some("slide"));
+
+  clip_container.add_sublayer(sliding_layer);
+
+  // Example 10: Gradient layer with animated colors
+  let mut gradient_layer = Layer::new("gradientLayer".to_string(), 200, 120, None);
+  gradient_layer.set_position(1050, 480);
+  gradient_layer.set_corner_radius(12.0);
+  gradient_layer.set_gradient(Some(
+    Gradient::linear(vec![[1.0, 0.4, 0.0, 1.0], [0.6, 0.0, 0.8, 1.0]])
+      .with_points((0.0, 0.0), (1.0, 1.0)),
+  ));
+
+  let mut colors_animation = Animation::with_key_path("colors");
+  colors_animation.duration = 2.0;
+  colors_animation.timing_function = Some(EasingFunction::EaseInOut);
+  colors_animation.set_from_value_gradient_colors(vec![[1.0, 0.4, 0.0, 1.0], [0.6, 0.0, 0.8, 1.0]]);
+  colors_animation.set_to_value_gradient_colors(vec![[0.0, 0.8, 1.0, 1.0], [0.0, 0.2, 0.4, 1.0]]);
+  gradient_layer.add_animation(colors_animation, Some("colors"));
+
+  // Example 11: Progress ring and checkmark drawn with shape layers
+  let mut ring_layer = Layer::new("progressRing".to_string(), 120, 120, None);
+  ring_layer.set_position(500, 450);
+  ring_layer.set_shape(Some(
+    Shape::new(Path::new().arc(60.0, 60.0, 50.0, -FRAC_PI_2, 1.5 * PI, true))
+      .with_fill_color(None)
+      .with_stroke([0.2, 0.8, 1.0, 1.0], 10.0)
+      .with_line_cap(LineCap::Round),
+  ));
+
+  let mut progress_animation = Animation::with_key_path("strokeEnd");
+  progress_animation.duration = 3.0;
+  progress_animation.timing_function = Some(EasingFunction::EaseInOut);
+  progress_animation.set_from_value_stroke_end(0.0);
+  progress_animation.set_to_value_stroke_end(1.0);
+  ring_layer.add_animation(progress_animation, Some("progress"));
+
+  let mut check_layer = Layer::new("checkmark".to_string(), 60, 60, None);
+  check_layer.set_position(30, 30);
+  check_layer.set_shape(Some(
+    Shape::new(
+      Path::new()
+        .move_to(10.0, 32.0)
+        .line_to(25.0, 46.0)
+        .line_to(50.0, 16.0),
+    )
+    .with_fill_color(None)
+    .with_stroke([1.0, 1.0, 1.0, 1.0], 6.0)
+    .with_line_cap(LineCap::Round)
+    .with_line_join(LineJoin::Round),
+  ));
+
+  let mut check_animation = Animation::with_key_path("strokeEnd");
+  check_animation.duration = 1.0;
+  check_animation.timing_function = Some(EasingFunction::EaseOut);
+  check_animation.set_from_value_stroke_end(0.0);
+  check_animation.set_to_value_stroke_end(1.0);
+  check_layer.add_animation(check_animation, Some("draw"));
+
+  ring_layer.add_sublayer(check_layer);
+
+  // Example 12: SVG icon imported as shape layers, next to the same icon rasterized
+  let svg_layer =
+    match rust_animation::svg::load_layer("svgIcon".to_string(), "examples/play_icon.svg") {
+      Ok(mut svg_layer) => {
+        svg_layer.set_position(660, 460);
+        Some(svg_layer)
+      }
+      Err(err) => {
+        println!("Failed to load the SVG icon: {}", err);
+        None
+      }
+    };
+
+  let mut svg_image_layer = Layer::new("svgImage".to_string(), 96, 96, None);
+  svg_image_layer.set_position(660, 580);
+  svg_image_layer.set_svg_image("examples/play_icon.svg".to_string(), 2.0);
+
+  // Example 13: Text layer with wrapping, alignment, ellipsis truncation and
+  // inline styles from markup
+  let mut text_panel = Layer::new("textPanel".to_string(), 280, 90, None);
+  text_panel.set_position(820, 620);
+  text_panel.set_background_color(0.15, 0.15, 0.2);
+  text_panel.set_corner_radius(8.0);
+
+  // The text is drawn with a transparent background over the panel
+  let mut text_layer = Layer::new("textLayer".to_string(), 260, 80, None);
+  text_layer.set_position(10, 5);
+  text_layer.set_text_content(Some(
+    Text::from_markup(
+      "Text layers <b>wrap</b> long lines, <color=#ffcc00>align</color> them and <u>truncate</u> the rest with an ellipsis",
+    )
+      .with_font_size(20.0)
+      .with_color(1.0, 1.0, 1.0, 1.0)
+      .with_line_height(1.1)
+      .with_alignment(TextAlignment::Center)
+      .with_vertical_alignment(VerticalAlignment::Center)
+      .with_max_lines(3),
+  ));
+  text_panel.add_sublayer(text_layer);
+
+  // Example 14: Signed distance field text with an outline and a glow, staying
+  // crisp while it is scaled up
+  let mut sdf_text_layer = Layer::new("sdfText".to_string(), 220, 60, None);
+  sdf_text_layer.set_position(260, 300);
+  sdf_text_layer.set_text_content(Some(
+    Text::new("Focus")
+      .with_font_size(40.0)
+      .with_color(1.0, 1.0, 1.0, 1.0)
+      .with_alignment(TextAlignment::Center)
+      .with_vertical_alignment(VerticalAlignment::Center)
+      .with_render_mode(TextRenderMode::Sdf)
+      .with_outline([0.1, 0.2, 0.6, 1.0], 2.0)
+      .with_glow([0.3, 0.7, 1.0, 0.8], 6.0),
+  ));
+
+  let mut focus_animation = Animation::with_key_path("transform.scale");
+  focus_animation.duration = 2.0;
+  focus_animation.timing_function = Some(EasingFunction::EaseInOut);
+  focus_animation.set_from_value_scale(1.0);
+  focus_animation.set_to_value_scale(1.6);
+  sdf_text_layer.add_animation(focus_animation, Some("focus"));
+
+  // Example 15: A focused search field edited with the keyboard
+  let mut search_panel = Layer::new("searchPanel".to_string(), 300, 44, None);
+  search_panel.set_position(900, 40);
+  search_panel.set_background_color(1.0, 1.0, 1.0);
+  search_panel.set_corner_radius(6.0);
+
+  let mut search_field = Layer::new("searchField".to_string(), 280, 44, None);
+  search_field.set_position(10, 0);
+  search_field.set_text_field(Some(
+    TextField::new()
+      .with_placeholder("Search")
+      .with_style(Text::new("").with_font_size(20.0)),
+  ));
+  search_field.set_focus(true);
+  search_panel.add_sublayer(search_field);
+  search_panel.set_focus(true);
+
+  // Example 16: A button that responds to the mouse and to touches
+  let mut button = Layer::new("button".to_string(), 160, 50, None);
+  button.set_position(1040, 110);
+  button.set_background_color(0.2, 0.45, 0.8);
+  button.set_corner_radius(10.0);
+  // Highlight it under the pointer and darken it while it is pressed
+  button.on_pointer_event(|event, layer| match event.kind {
+    PointerEventKind::Enter | PointerEventKind::Up => layer.set_background_color(0.3, 0.6, 1.0),
+    PointerEventKind::Down => layer.set_background_color(0.1, 0.3, 0.6),
+    PointerEventKind::Leave | PointerEventKind::Cancel => {
+      layer.set_background_color(0.2, 0.45, 0.8)
+    }
+    _ => {}
+  });
+  button.on_pointer_up(|_, layer| println!("{} clicked", layer.name));
+
+  // Add all layers to stage using CoreAnimation-style API. The focused search
+  // panel is the first sublayer, which the stage draws last.
+  stage.add_sublayer(search_panel);
   stage.add_sublayer(layer1);
   stage.add_sublayer(layer2);
   stage.add_sublayer(layer3);
   stage.add_sublayer(layer4);
   stage.add_sublayer(layer5);
   stage.add_sublayer(parent_layer);
+  stage.add_sublayer(card_container);
+  stage.add_sublayer(rounded_layer);
+  stage.add_sublayer(clip_container);
+  stage.add_sublayer(gradient_layer);
+  stage.add_sublayer(ring_layer);
+  stage.add_sublayer(svg_image_layer);
+  stage.add_sublayer(text_panel);
+  stage.add_sublayer(sdf_text_layer);
+  stage.add_sublayer(button);
+  if let Some(svg_layer) = svg_layer {
+    stage.add_sublayer(svg_layer);
+  }
 
   play.add_stage(stage);
 
@@ -151,38 +362,15 @@ fn main() {
   println!("Yellow box: Rotation animation (0 -> 360)");
   println!("Magenta box: Position Y + Scale animations");
   println!("Gray box: Parent layer with white child sublayer");
+  println!("Cyan card: 3D flip around the Y axis with perspective");
+  println!("Orange box: Corner radius and border color animation with a drop shadow");
+  println!("Dark box: Sublayer sliding through a container that masks to bounds");
+  println!("Gradient box: Linear gradient with animated colors");
+  println!("Blue ring: Shape layer with animated strokeEnd and a checkmark");
+  println!("Play icons: SVG imported as shape layers (top) and rasterized (bottom)");
+  println!("Dark panel: Text layer with word wrap, centered alignment and ellipsis");
+  println!("Focus: SDF text with outline and glow, crisp while scaling");
+  println!("White box: Search field, type to edit it");
+  println!("Blue button: Highlights under the mouse or a touch");
   println!("\nPress ESC to exit");
-
-  event_loop
-    .run(move |event, elwt| {
-      elwt.set_control_flow(ControlFlow::Poll);
-
-      match event {
-        Event::WindowEvent { event, .. } => match event {
-          WindowEvent::CloseRequested => elwt.exit(),
-          WindowEvent::KeyboardInput {
-            event:
-              KeyEvent {
-                physical_key: PhysicalKey::Code(KeyCode::Escape),
-                ..
-              },
-            ..
-          } => elwt.exit(),
-          WindowEvent::Resized(new_size) => {
-            // Update wgpu surface and projection when window is resized
-            play.resize(new_size.width, new_size.height);
-          }
-          WindowEvent::RedrawRequested => {
-            play.render();
-            window.request_redraw();
-          }
-          _ => {}
-        },
-        Event::AboutToWait => {
-          window.request_redraw();
-        }
-        _ => {}
-      }
-    })
-    .unwrap();
 }
@@ -12,8 +12,8 @@ use winit::{
 
 use rust_animation::animation::Animation;
 use rust_animation::animation::EasingFunction;
-use rust_animation::layer::LayoutMode;
 use rust_animation::layer::Layer;
+use rust_animation::layer::LayoutMode;
 use rust_animation::play::Play;
 
 fn main() {
@@ -66,7 +66,7 @@ fn main() {
   let time = 5.0;
   let width_layer = 63;
   let height_layer = width_layer;
-  for i in 0..17 {
+  for (i, easing_function) in easing_functions.into_iter().enumerate() {
     let layer_name = format!("layer_{}", i + 1);
     let mut layer = Layer::new(layer_name.to_string(), width_layer, height_layer, None);
     layer.x = 0;
@@ -77,7 +77,7 @@ fn main() {
     let mut animation = Animation::new();
     // Animate from left edge (0) to right edge of window (width - width_layer)
     // This adapts to the actual window size, which may differ from 1920 due to DPI scaling
-    animation.apply_translation_x(0, (width - width_layer) as i32, time, easing_functions[i]);
+    animation.apply_translation_x(0, (width - width_layer) as i32, time, easing_function);
     animation.apply_rotation(0, 360, time, EasingFunction::Linear);
     layer.set_animation(Some(animation));
     stage.add_sub_layer(layer);
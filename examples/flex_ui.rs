@@ -11,15 +11,23 @@ use winit::{
   window::WindowBuilder,
 };
 
+use rust_animation::font::FontRegistry;
+use rust_animation::layer::Layer;
 use rust_animation::layer::Layout;
 use rust_animation::layer::LayoutMode;
-use rust_animation::layer::Layer;
 use rust_animation::play::Play;
+use rust_animation::text::Text;
 
 pub struct FlexLayout {
   name: String,
 }
 
+impl Default for FlexLayout {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl FlexLayout {
   pub fn new() -> Self {
     let flex_layout = FlexLayout {
@@ -43,6 +51,10 @@ impl Layout for FlexLayout {
     if let Some(stretch_obj) = stretch {
       if let Some(style_obj) = layer.style {
         layer.node = Some(stretch_obj.new_node(style_obj, vec![]).unwrap());
+      } else if let Some(text) = layer.text_content() {
+        // Text layers are sized by their text
+        let measure = text.measure_func(FontRegistry::shared());
+        layer.node = Some(stretch_obj.new_leaf(Style::default(), measure).unwrap());
       } else {
         //println!("default style: {}: {},{}", self.name, self.width, self.height);
         layer.node = Some(
@@ -58,7 +70,6 @@ impl Layout for FlexLayout {
                   end: Dimension::Points(2.0),
                   top: Dimension::Points(2.0),
                   bottom: Dimension::Points(2.0),
-                  ..Default::default()
                 },
                 ..Default::default()
               },
@@ -71,15 +82,12 @@ impl Layout for FlexLayout {
       println!("layer name {}", layer.name);
 
       if let Some(parent_layer) = parent_layer {
-        if !parent_layer.node.is_none() && !layer.node.is_none() {
-          match stretch_obj.add_child(parent_layer.node.unwrap(), layer.node.unwrap()) {
-            Ok(()) => {
-              println!(
-                " stretch node  is added {} {}",
-                parent_layer.name, layer.name
-              )
-            }
-            Err(..) => {}
+        if let (Some(parent_node), Some(node)) = (parent_layer.node, layer.node) {
+          if let Ok(()) = stretch_obj.add_child(parent_node, node) {
+            println!(
+              " stretch node  is added {} {}",
+              parent_layer.name, layer.name
+            )
           }
         }
       }
@@ -90,10 +98,14 @@ impl Layout for FlexLayout {
 
   fn update_layout(&mut self, layer: &mut Layer, stretch: &mut Option<Stretch>) {
     if let Some(stretch_obj) = stretch {
-      if !layer.node.is_none() {
-        let layout = stretch_obj.layout(layer.node.unwrap()).unwrap();
+      if let Some(node) = layer.node {
+        let layout = stretch_obj.layout(node).unwrap();
         layer.x = layout.location.x as i32;
         layer.y = layout.location.y as i32;
+        if layer.text_content().is_some() {
+          layer.width = layout.size.width as u32;
+          layer.height = layout.size.height as u32;
+        }
         println!(
           "run update_layout for FlexLayout {} = {},{}",
           layer.name, layer.x, layer.y
@@ -121,7 +133,12 @@ fn main() {
   let window_size = window.inner_size();
   let (width, height) = (window_size.width, window_size.height);
 
-  let mut play = Play::new("Flex UI test".to_string(), width as i32, height as i32, LayoutMode::Flex);
+  let mut play = Play::new(
+    "Flex UI test".to_string(),
+    width as i32,
+    height as i32,
+    LayoutMode::Flex,
+  );
 
   // Initialize wgpu context with surface using actual window size
   play.init_wgpu_with_surface(window.clone(), width, height);
@@ -140,13 +157,24 @@ fn main() {
       end: Dimension::Points(1.0),
       top: Dimension::Points(1.0),
       bottom: Dimension::Points(1.0),
-      ..Default::default()
     },
     ..Default::default()
   });
   stage.set_visible(true);
 
-  let justify_content = vec![
+  // A title that sizes itself to its text
+  let mut title = Layer::new("title".to_string(), 0, 0, None);
+  title.set_text_content(Some(
+    Text::new(
+      "justify_content: FlexStart, FlexEnd, Center, SpaceBetween, SpaceAround, SpaceEvenly",
+    )
+    .with_font_size(20.0)
+    .with_color(1.0, 1.0, 1.0, 1.0),
+  ));
+  title.set_layout(Some(Box::new(FlexLayout::new())));
+  stage.add_sub_layer(title);
+
+  let justify_content = [
     JustifyContent::FlexStart,
     JustifyContent::FlexEnd,
     JustifyContent::Center,
@@ -156,7 +184,7 @@ fn main() {
   ];
   let width = 1200;
   let height = 108;
-  for i in 0..6 {
+  for (i, justify_content) in justify_content.into_iter().enumerate() {
     let layer_name = format!("layer_{}", i + 1);
     let mut layer = Layer::new(layer_name.to_string(), width, height, None);
     layer.set_color(i as f32 / 6.0, i as f32 / 6.0, i as f32 / 6.0);
@@ -165,14 +193,13 @@ fn main() {
         width: Dimension::Points(width as f32),
         height: Dimension::Points(height as f32),
       },
-      justify_content: justify_content[i],
+      justify_content,
       align_items: AlignItems::Center,
       margin: Rect {
         start: Dimension::Points(1.0),
         end: Dimension::Points(1.0),
         top: Dimension::Points(1.0),
         bottom: Dimension::Points(1.0),
-        ..Default::default()
       },
       padding: Rect {
         start: Dimension::Points(2.0),
@@ -10,9 +10,10 @@ use winit::{
   window::WindowBuilder,
 };
 
-use rust_animation::layer::LayoutMode;
 use rust_animation::layer::Layer;
+use rust_animation::layer::LayoutMode;
 use rust_animation::play::Play;
+use rust_animation::text::{Text, TextRenderMode};
 
 fn main() {
   let event_loop = EventLoop::new().unwrap();
@@ -48,12 +49,46 @@ fn main() {
 
   // Get wgpu context to set text
   if let Some(wgpu_ctx) = &play.wgpu_context {
-    layer_1.set_text("hello", &wgpu_ctx.device, &wgpu_ctx.queue);
+    if let Err(err) = layer_1.set_text(
+      "hello",
+      play.font_registry(),
+      &wgpu_ctx.device,
+      &wgpu_ctx.queue,
+    ) {
+      eprintln!("{}", err);
+    }
   }
 
   stage.add_sub_layer(layer_1);
 
-  play.add_stage(stage);
+  // Text layers are rendered in memory with fonts shared through the font
+  // registry of Play, so each font file is read once
+  let mut layer_2 = Layer::new("layer_2".to_string(), 400, 120, None);
+  layer_2.x = 100;
+  layer_2.y = 250;
+  layer_2.set_text_content(Some(
+    Text::new("Fonts are loaded once\nand shared by all text layers")
+      .with_font("DejaVu Sans Mono")
+      .with_font_size(24.0)
+      .with_color(1.0, 1.0, 1.0, 1.0),
+  ));
+  stage.add_sub_layer(layer_2);
+
+  // A label that changes every frame draws its glyphs from the shared glyph
+  // atlas instead of rendering a new texture each time
+  let mut counter = Layer::new("counter".to_string(), 400, 60, None);
+  counter.x = 100;
+  counter.y = 400;
+  counter.set_text_content(Some(
+    Text::new("Frame 0")
+      .with_font_size(32.0)
+      .with_color(1.0, 0.9, 0.2, 1.0)
+      .with_render_mode(TextRenderMode::GlyphAtlas),
+  ));
+  stage.add_sub_layer(counter);
+
+  let stage_name = play.add_stage(stage);
+  let mut frame: u64 = 0;
 
   event_loop
     .run(move |event, elwt| {
@@ -75,6 +110,14 @@ fn main() {
             play.resize(new_size.width, new_size.height);
           }
           WindowEvent::RedrawRequested => {
+            frame += 1;
+            let counter = play
+              .stage_mut(&stage_name)
+              .and_then(|stage| stage.sub_layer_list.last_mut())
+              .and_then(|layer| layer.text_content_mut());
+            if let Some(text) = counter {
+              text.string = format!("Frame {}", frame);
+            }
             play.render();
             window.request_redraw();
           }
@@ -22,18 +22,21 @@ use std::thread;
 
 use rust_animation::animation::Animation;
 use rust_animation::animation::EasingFunction;
+use rust_animation::app::InputAdapter;
+use rust_animation::event::{EventPhase, KeyEvent as AnimKeyEvent};
+use rust_animation::gesture::{GestureRecognizer, GestureState, SwipeDirection};
 use rust_animation::layer::EventHandler;
 use rust_animation::layer::Key as AnimKey;
+use rust_animation::layer::Layer;
 use rust_animation::layer::Layout;
 use rust_animation::layer::LayoutMode;
-use rust_animation::layer::Layer;
 use rust_animation::play::Play;
 
 type ResultUrl<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 fn fetch_url(url: String, file_name: String) -> ResultUrl<()> {
   let response = reqwest::blocking::get(&url)?;
-  println!("Downloading {}", url.to_string());
+  println!("Downloading {}", url);
   if response.status().as_str() == "200" {
     let mut file = std::fs::File::create(file_name)?;
     let mut content = Cursor::new(response.bytes()?);
@@ -45,7 +48,7 @@ fn fetch_url(url: String, file_name: String) -> ResultUrl<()> {
 fn download_images() -> Result<(), Error> {
   fs::create_dir_all("examples/images").unwrap_or_else(|e| panic!("Error creating dir: {}", e));
 
-  let request_url = format!("https://api.disneyapi.dev/character");
+  let request_url = "https://api.disneyapi.dev/character".to_string();
   let response = reqwest::blocking::get(&request_url)?;
   let text_json: String = response.text()?;
   let json_value: Value = serde_json::from_str(&text_json).unwrap();
@@ -57,7 +60,7 @@ fn download_images() -> Result<(), Error> {
     println!("{}", file_name);
 
     if Path::new(&file_name.to_string()).exists() {
-      println!("Skip the downloaded file: {}", file_name.to_string());
+      println!("Skip the downloaded file: {}", file_name);
     } else {
       match image_url.as_str() {
         Some(url) => {
@@ -88,6 +91,12 @@ pub struct LayerEvent {
   name: String,
 }
 
+impl Default for LayerEvent {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl LayerEvent {
   pub fn new() -> Self {
     LayerEvent {
@@ -102,15 +111,19 @@ impl EventHandler for LayerEvent {
     let mut animation = Animation::new();
     animation.apply_scale(1.0, 1.1, 0.3, EasingFunction::EaseInOut);
     layer.set_animation(Some(animation));
+    layer.set_shadow_opacity(0.6);
+    layer.set_shadow_radius(12.0);
+    layer.set_shadow_offset(0.0, 8.0);
   }
 
   fn key_focus_out(&mut self, layer: &mut Layer) {
     println!("key_focus_out: {} {}", self.name, layer.name);
     layer.scale_x = 1.0;
     layer.scale_y = 1.0;
+    layer.set_shadow_opacity(0.0);
   }
 
-  fn key_down(&mut self, key: rust_animation::layer::Key, layer: &mut Layer) {
+  fn key_down(&mut self, key: rust_animation::layer::Key, layer: &mut Layer) -> bool {
     println!("key_down: {}  {:?}  {}", self.name, key, layer.name);
 
     if key == rust_animation::layer::Key::Right {
@@ -119,6 +132,48 @@ impl EventHandler for LayerEvent {
     } else if key == rust_animation::layer::Key::Left {
       // left cursor
       layer.select_prev_sub_layer();
+    } else {
+      return false;
+    }
+    true
+  }
+
+  fn key_event(&mut self, event: &mut AnimKeyEvent, layer: &mut Layer) {
+    // The stage pages through the pictures with the arrows before the focused
+    // picture sees them. Other keys go to the picture and bubble up.
+    if event.phase == EventPhase::Capture && matches!(event.key, AnimKey::Left | AnimKey::Right) {
+      self.key_down(event.key, layer);
+      event.set_handled();
+      event.stop_propagation();
+    } else if event.phase != EventPhase::Capture
+      && !event.is_handled()
+      && self.key_down(event.key, layer)
+    {
+      event.set_handled();
+    }
+  }
+
+  fn gesture(&mut self, gesture: &GestureRecognizer, layer: &mut Layer) {
+    match (gesture.name.as_str(), gesture.state()) {
+      // Swipe to page through the pictures
+      ("swipe", GestureState::Ended) => match gesture.direction() {
+        Some(SwipeDirection::Left) => layer.select_next_sub_layer(),
+        Some(SwipeDirection::Right) => layer.select_prev_sub_layer(),
+        _ => {}
+      },
+      // Pinch to zoom, and spring back when the fingers are lifted
+      ("pinch", GestureState::Began | GestureState::Changed) => {
+        layer.scale_x = gesture.scale().clamp(0.5, 3.0);
+        layer.scale_y = layer.scale_x;
+      }
+      ("pinch", GestureState::Ended | GestureState::Cancelled) => {
+        // A faster pinch springs back faster
+        let duration = (0.4 / (1.0 + gesture.scale_velocity().abs())).max(0.1);
+        let mut animation = Animation::new();
+        animation.apply_scale(layer.scale_x, 1.0, duration, EasingFunction::EaseOut);
+        layer.set_animation(Some(animation));
+      }
+      _ => {}
     }
   }
 }
@@ -128,6 +183,12 @@ pub struct ActorLayout {
   cur_x: i32,
 }
 
+impl Default for ActorLayout {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl ActorLayout {
   pub fn new() -> Self {
     ActorLayout {
@@ -145,13 +206,12 @@ impl Layout for ActorLayout {
     _stretch: &mut Option<Stretch>,
   ) {
     println!("layout_sub_layer {}", self.name);
-    let mut index: i32 = 0;
-    for sub_layer in layer.sub_layer_list.iter_mut() {
+    for (index, sub_layer) in layer.sub_layer_list.iter_mut().enumerate() {
+      let index = index as i32;
       self.cur_x += sub_layer.width as i32;
       sub_layer.x = index % 5 * IMAGE_WIDTH as i32;
       let col = index / 5;
       sub_layer.y = col * IMAGE_HEIGHT as i32;
-      index += 1;
     }
   }
 
@@ -207,6 +267,8 @@ impl PictureBrowser {
     );
     stage.set_visible(false);
     stage.set_layout(Some(Box::new(ActorLayout::new())));
+    stage.add_gesture_recognizer(GestureRecognizer::swipe("swipe"));
+    stage.add_gesture_recognizer(GestureRecognizer::pinch("pinch"));
     //  stage.set_needs_layout();
     self.main_stage_name = self.play.add_stage(stage);
   }
@@ -216,13 +278,13 @@ impl PictureBrowser {
 
     for path in paths {
       let file_path = path.unwrap().path().display().to_string();
-      println!("Loading {}", file_path.to_string());
+      println!("Loading {}", file_path);
       self.file_list.push(file_path);
     }
   }
 
   pub fn load_images(&mut self) {
-    if self.file_list.len() == 0 {
+    if self.file_list.is_empty() {
       return;
     }
 
@@ -253,12 +315,8 @@ impl PictureBrowser {
     self.play.render();
   }
 
-  pub fn handle_input(&mut self, key: AnimKey) {
-    self.play.handle_input(key);
-  }
-
   pub fn render_splash_screen(&mut self) {
-    if self.image_loaded == true {
+    if self.image_loaded {
       self.play.set_visible_stage(&self.splash_stage_name, false);
       self.play.set_visible_stage(&self.main_stage_name, true);
       return;
@@ -282,16 +340,16 @@ fn main() {
   let (width, height) = (window_size.width, window_size.height);
 
   let (tx, rx) = mpsc::channel();
-  thread::spawn(move || match download_images() {
-    Ok(()) => {
+  thread::spawn(move || {
+    if let Ok(()) = download_images() {
       tx.send(true).unwrap();
     }
-    Err(..) => {}
   });
 
   let mut picture_browser = PictureBrowser::new(width, height);
   picture_browser.initialize(window.clone(), width, height);
 
+  let mut input = InputAdapter::default();
   event_loop
     .run(move |event, elwt| {
       elwt.set_control_flow(ControlFlow::Poll);
@@ -302,21 +360,12 @@ fn main() {
           WindowEvent::KeyboardInput {
             event:
               KeyEvent {
-                physical_key: PhysicalKey::Code(key_code),
+                physical_key: PhysicalKey::Code(KeyCode::Escape),
                 state: ElementState::Pressed,
                 ..
               },
             ..
-          } => match key_code {
-            KeyCode::Escape => elwt.exit(),
-            KeyCode::ArrowUp => picture_browser.handle_input(AnimKey::Up),
-            KeyCode::ArrowDown => picture_browser.handle_input(AnimKey::Down),
-            KeyCode::ArrowLeft => picture_browser.handle_input(AnimKey::Left),
-            KeyCode::ArrowRight => picture_browser.handle_input(AnimKey::Right),
-            KeyCode::Enter => picture_browser.handle_input(AnimKey::Enter),
-            KeyCode::Space => picture_browser.handle_input(AnimKey::Space),
-            _ => {}
-          },
+          } => elwt.exit(),
           WindowEvent::Resized(new_size) => {
             // Update wgpu surface and projection when window is resized
             picture_browser.play.resize(new_size.width, new_size.height);
@@ -335,7 +384,7 @@ fn main() {
             picture_browser.render();
             window.request_redraw();
           }
-          _ => {}
+          event => input.handle_window_event(&mut picture_browser.play, &event),
         },
         Event::AboutToWait => {
           window.request_redraw();
@@ -1,6 +1,7 @@
 use crate::layer::Layer;
+use cgmath::{InnerSpace, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
 use keyframe::{ease, functions::*};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 #[derive(Copy, Clone, Debug)]
 pub enum EasingFunction {
@@ -27,7 +28,7 @@ pub enum EasingFunction {
 pub type CAMediaTimingFunction = EasingFunction;
 
 pub struct Animation {
-  animation_time_instance: Instant,
+  animation_time_instance: Option<Instant>, // set by the first run
   translation_x_running: bool,
   translation_x_starting_time: u128,
   translation_x_time_duration: f32,
@@ -63,6 +64,83 @@ pub struct Animation {
   opacity_to_value: f32,
   opacity_ease: EasingFunction,
 
+  rotation_x_running: bool,
+  rotation_x_starting_time: u128,
+  rotation_x_time_duration: f32,
+  rotation_x_from_value: i32,
+  rotation_x_to_value: i32,
+  rotation_x_ease: EasingFunction,
+
+  rotation_y_running: bool,
+  rotation_y_starting_time: u128,
+  rotation_y_time_duration: f32,
+  rotation_y_from_value: i32,
+  rotation_y_to_value: i32,
+  rotation_y_ease: EasingFunction,
+
+  scale_z_running: bool,
+  scale_z_starting_time: u128,
+  scale_z_time_duration: f32,
+  scale_z_from_value: f32,
+  scale_z_to_value: f32,
+  scale_z_ease: EasingFunction,
+
+  transform_running: bool,
+  transform_starting_time: u128,
+  transform_time_duration: f32,
+  transform_from_value: Matrix4<f32>,
+  transform_to_value: Matrix4<f32>,
+  transform_ease: EasingFunction,
+
+  sublayer_transform_running: bool,
+  sublayer_transform_starting_time: u128,
+  sublayer_transform_time_duration: f32,
+  sublayer_transform_from_value: Matrix4<f32>,
+  sublayer_transform_to_value: Matrix4<f32>,
+  sublayer_transform_ease: EasingFunction,
+
+  corner_radius_running: bool,
+  corner_radius_starting_time: u128,
+  corner_radius_time_duration: f32,
+  corner_radius_from_value: f32,
+  corner_radius_to_value: f32,
+  corner_radius_ease: EasingFunction,
+
+  border_color_running: bool,
+  border_color_starting_time: u128,
+  border_color_time_duration: f32,
+  border_color_from_value: [f32; 3],
+  border_color_to_value: [f32; 3],
+  border_color_ease: EasingFunction,
+
+  gradient_colors_running: bool,
+  gradient_colors_starting_time: u128,
+  gradient_colors_time_duration: f32,
+  gradient_colors_from_value: Vec<[f32; 4]>,
+  gradient_colors_to_value: Vec<[f32; 4]>,
+  gradient_colors_ease: EasingFunction,
+
+  gradient_locations_running: bool,
+  gradient_locations_starting_time: u128,
+  gradient_locations_time_duration: f32,
+  gradient_locations_from_value: Vec<f32>,
+  gradient_locations_to_value: Vec<f32>,
+  gradient_locations_ease: EasingFunction,
+
+  stroke_start_running: bool,
+  stroke_start_starting_time: u128,
+  stroke_start_time_duration: f32,
+  stroke_start_from_value: f32,
+  stroke_start_to_value: f32,
+  stroke_start_ease: EasingFunction,
+
+  stroke_end_running: bool,
+  stroke_end_starting_time: u128,
+  stroke_end_time_duration: f32,
+  stroke_end_from_value: f32,
+  stroke_end_to_value: f32,
+  stroke_end_ease: EasingFunction,
+
   // CoreAnimation-style properties
   pub duration: f32,
   pub timing_function: Option<EasingFunction>,
@@ -70,10 +148,16 @@ pub struct Animation {
   pub autoreverses: bool,
 }
 
+impl Default for Animation {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Animation {
   pub fn new() -> Animation {
     Animation {
-      animation_time_instance: Instant::now(),
+      animation_time_instance: None,
       translation_x_running: false,
       translation_x_starting_time: 0,
       translation_x_time_duration: 0.0,
@@ -109,6 +193,83 @@ impl Animation {
       opacity_to_value: 0.0,
       opacity_ease: EasingFunction::Linear,
 
+      rotation_x_running: false,
+      rotation_x_starting_time: 0,
+      rotation_x_time_duration: 0.0,
+      rotation_x_from_value: 0,
+      rotation_x_to_value: 0,
+      rotation_x_ease: EasingFunction::Linear,
+
+      rotation_y_running: false,
+      rotation_y_starting_time: 0,
+      rotation_y_time_duration: 0.0,
+      rotation_y_from_value: 0,
+      rotation_y_to_value: 0,
+      rotation_y_ease: EasingFunction::Linear,
+
+      scale_z_running: false,
+      scale_z_starting_time: 0,
+      scale_z_time_duration: 0.0,
+      scale_z_from_value: 0.0,
+      scale_z_to_value: 0.0,
+      scale_z_ease: EasingFunction::Linear,
+
+      transform_running: false,
+      transform_starting_time: 0,
+      transform_time_duration: 0.0,
+      transform_from_value: Matrix4::identity(),
+      transform_to_value: Matrix4::identity(),
+      transform_ease: EasingFunction::Linear,
+
+      sublayer_transform_running: false,
+      sublayer_transform_starting_time: 0,
+      sublayer_transform_time_duration: 0.0,
+      sublayer_transform_from_value: Matrix4::identity(),
+      sublayer_transform_to_value: Matrix4::identity(),
+      sublayer_transform_ease: EasingFunction::Linear,
+
+      corner_radius_running: false,
+      corner_radius_starting_time: 0,
+      corner_radius_time_duration: 0.0,
+      corner_radius_from_value: 0.0,
+      corner_radius_to_value: 0.0,
+      corner_radius_ease: EasingFunction::Linear,
+
+      border_color_running: false,
+      border_color_starting_time: 0,
+      border_color_time_duration: 0.0,
+      border_color_from_value: [0.0; 3],
+      border_color_to_value: [0.0; 3],
+      border_color_ease: EasingFunction::Linear,
+
+      gradient_colors_running: false,
+      gradient_colors_starting_time: 0,
+      gradient_colors_time_duration: 0.0,
+      gradient_colors_from_value: Vec::new(),
+      gradient_colors_to_value: Vec::new(),
+      gradient_colors_ease: EasingFunction::Linear,
+
+      gradient_locations_running: false,
+      gradient_locations_starting_time: 0,
+      gradient_locations_time_duration: 0.0,
+      gradient_locations_from_value: Vec::new(),
+      gradient_locations_to_value: Vec::new(),
+      gradient_locations_ease: EasingFunction::Linear,
+
+      stroke_start_running: false,
+      stroke_start_starting_time: 0,
+      stroke_start_time_duration: 0.0,
+      stroke_start_from_value: 0.0,
+      stroke_start_to_value: 0.0,
+      stroke_start_ease: EasingFunction::Linear,
+
+      stroke_end_running: false,
+      stroke_end_starting_time: 0,
+      stroke_end_time_duration: 0.0,
+      stroke_end_from_value: 0.0,
+      stroke_end_to_value: 0.0,
+      stroke_end_ease: EasingFunction::Linear,
+
       duration: 0.0,
       timing_function: None,
       repeat_count: 0.0,
@@ -138,6 +299,84 @@ impl Animation {
     }
   }
 
+  // Matrices are decomposed into translation, rotation and scale, which are
+  // interpolated separately (rotations along the shortest arc), so that a
+  // rotation turns instead of shrinking through a flat matrix. The perspective
+  // row is interpolated linearly. Matrices with shear or a zero scale can't be
+  // decomposed and are interpolated component-wise.
+  fn easing_matrix(
+    easing: EasingFunction,
+    from: Matrix4<f32>,
+    to: Matrix4<f32>,
+    progress: f32,
+  ) -> Matrix4<f32> {
+    let progress = Animation::easing_function(easing, 0.0, 1.0, progress);
+    let (Some(a), Some(b)) = (decompose(&from), decompose(&to)) else {
+      return from * (1.0 - progress) + to * progress;
+    };
+    let translation = a.0.lerp(b.0, progress);
+    let rotation = a.1.slerp(b.1, progress);
+    let scale = a.2.lerp(b.2, progress);
+    let mut matrix = Matrix4::from_translation(translation)
+      * Matrix4::from(rotation)
+      * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+    // The perspective row
+    let row = |m: &Matrix4<f32>| [m.x.w, m.y.w, m.z.w, m.w.w];
+    let (from_row, to_row) = (row(&from), row(&to));
+    let lerp = |i: usize| from_row[i] + (to_row[i] - from_row[i]) * progress;
+    (matrix.x.w, matrix.y.w, matrix.z.w, matrix.w.w) = (lerp(0), lerp(1), lerp(2), lerp(3));
+    matrix
+  }
+
+  fn easing_color(easing: EasingFunction, from: [f32; 3], to: [f32; 3], progress: f32) -> [f32; 3] {
+    [
+      Animation::easing_function(easing, from[0], to[0], progress),
+      Animation::easing_function(easing, from[1], to[1], progress),
+      Animation::easing_function(easing, from[2], to[2], progress),
+    ]
+  }
+
+  // Stops are interpolated pairwise; if the number of stops differs, the
+  // animation jumps to the final value.
+  fn easing_gradient_colors(
+    easing: EasingFunction,
+    from: &[[f32; 4]],
+    to: &[[f32; 4]],
+    progress: f32,
+  ) -> Vec<[f32; 4]> {
+    if from.len() != to.len() {
+      return to.to_vec();
+    }
+    from
+      .iter()
+      .zip(to)
+      .map(|(from, to)| {
+        [
+          Animation::easing_function(easing, from[0], to[0], progress),
+          Animation::easing_function(easing, from[1], to[1], progress),
+          Animation::easing_function(easing, from[2], to[2], progress),
+          Animation::easing_function(easing, from[3], to[3], progress),
+        ]
+      })
+      .collect()
+  }
+
+  fn easing_gradient_locations(
+    easing: EasingFunction,
+    from: &[f32],
+    to: &[f32],
+    progress: f32,
+  ) -> Vec<f32> {
+    if from.len() != to.len() {
+      return to.to_vec();
+    }
+    from
+      .iter()
+      .zip(to)
+      .map(|(from, to)| Animation::easing_function(easing, *from, *to, progress))
+      .collect()
+  }
+
   pub fn apply_translation_x(
     &mut self,
     from_value: i32,
@@ -202,6 +441,162 @@ impl Animation {
     self.opacity_time_duration = time * 1000.0; // msec.
   }
 
+  pub fn apply_rotation_x(
+    &mut self,
+    from_value: i32,
+    to_value: i32,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.rotation_x_running = true;
+    self.rotation_x_ease = easing;
+    self.rotation_x_from_value = from_value;
+    self.rotation_x_to_value = to_value;
+    self.rotation_x_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_rotation_y(
+    &mut self,
+    from_value: i32,
+    to_value: i32,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.rotation_y_running = true;
+    self.rotation_y_ease = easing;
+    self.rotation_y_from_value = from_value;
+    self.rotation_y_to_value = to_value;
+    self.rotation_y_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_scale_z(
+    &mut self,
+    from_value: f32,
+    to_value: f32,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.scale_z_running = true;
+    self.scale_z_ease = easing;
+    self.scale_z_from_value = from_value;
+    self.scale_z_to_value = to_value;
+    self.scale_z_time_duration = time * 1000.0; // msec.
+  }
+
+  /// Animate `Layer::transform`. The matrices are interpolated by their
+  /// translation, rotation and scale, see `easing_matrix`.
+  pub fn apply_transform(
+    &mut self,
+    from_value: Matrix4<f32>,
+    to_value: Matrix4<f32>,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.transform_running = true;
+    self.transform_ease = easing;
+    self.transform_from_value = from_value;
+    self.transform_to_value = to_value;
+    self.transform_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_sublayer_transform(
+    &mut self,
+    from_value: Matrix4<f32>,
+    to_value: Matrix4<f32>,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.sublayer_transform_running = true;
+    self.sublayer_transform_ease = easing;
+    self.sublayer_transform_from_value = from_value;
+    self.sublayer_transform_to_value = to_value;
+    self.sublayer_transform_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_corner_radius(
+    &mut self,
+    from_value: f32,
+    to_value: f32,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.corner_radius_running = true;
+    self.corner_radius_ease = easing;
+    self.corner_radius_from_value = from_value;
+    self.corner_radius_to_value = to_value;
+    self.corner_radius_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_border_color(
+    &mut self,
+    from_value: [f32; 3],
+    to_value: [f32; 3],
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.border_color_running = true;
+    self.border_color_ease = easing;
+    self.border_color_from_value = from_value;
+    self.border_color_to_value = to_value;
+    self.border_color_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_gradient_colors(
+    &mut self,
+    from_value: Vec<[f32; 4]>,
+    to_value: Vec<[f32; 4]>,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.gradient_colors_running = true;
+    self.gradient_colors_ease = easing;
+    self.gradient_colors_from_value = from_value;
+    self.gradient_colors_to_value = to_value;
+    self.gradient_colors_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_gradient_locations(
+    &mut self,
+    from_value: Vec<f32>,
+    to_value: Vec<f32>,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.gradient_locations_running = true;
+    self.gradient_locations_ease = easing;
+    self.gradient_locations_from_value = from_value;
+    self.gradient_locations_to_value = to_value;
+    self.gradient_locations_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_stroke_start(
+    &mut self,
+    from_value: f32,
+    to_value: f32,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.stroke_start_running = true;
+    self.stroke_start_ease = easing;
+    self.stroke_start_from_value = from_value;
+    self.stroke_start_to_value = to_value;
+    self.stroke_start_time_duration = time * 1000.0; // msec.
+  }
+
+  pub fn apply_stroke_end(
+    &mut self,
+    from_value: f32,
+    to_value: f32,
+    time: f32,
+    easing: EasingFunction,
+  ) {
+    self.stroke_end_running = true;
+    self.stroke_end_ease = easing;
+    self.stroke_end_from_value = from_value;
+    self.stroke_end_to_value = to_value;
+    self.stroke_end_time_duration = time * 1000.0; // msec.
+  }
+
   // CoreAnimation-style API: Create basic animation with keyPath
   // Note: Currently key_path is for API compatibility only. In the future, this could
   // automatically configure the animation type based on the key path (e.g., "position.x",
@@ -290,14 +685,189 @@ impl Animation {
     self.rotation_time_duration = self.duration * 1000.0;
   }
 
+  // CoreAnimation-style API: Set from value for transform.rotation.x
+  pub fn set_from_value_rotation_x(&mut self, value: i32) {
+    self.rotation_x_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for transform.rotation.x
+  pub fn set_to_value_rotation_x(&mut self, value: i32) {
+    self.rotation_x_to_value = value;
+    self.rotation_x_running = true;
+    if let Some(timing) = self.timing_function {
+      self.rotation_x_ease = timing;
+    }
+    self.rotation_x_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for transform.rotation.y
+  pub fn set_from_value_rotation_y(&mut self, value: i32) {
+    self.rotation_y_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for transform.rotation.y
+  pub fn set_to_value_rotation_y(&mut self, value: i32) {
+    self.rotation_y_to_value = value;
+    self.rotation_y_running = true;
+    if let Some(timing) = self.timing_function {
+      self.rotation_y_ease = timing;
+    }
+    self.rotation_y_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for transform.scale.z
+  pub fn set_from_value_scale_z(&mut self, value: f32) {
+    self.scale_z_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for transform.scale.z
+  pub fn set_to_value_scale_z(&mut self, value: f32) {
+    self.scale_z_to_value = value;
+    self.scale_z_running = true;
+    if let Some(timing) = self.timing_function {
+      self.scale_z_ease = timing;
+    }
+    self.scale_z_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for transform
+  pub fn set_from_value_transform(&mut self, value: Matrix4<f32>) {
+    self.transform_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for transform
+  pub fn set_to_value_transform(&mut self, value: Matrix4<f32>) {
+    self.transform_to_value = value;
+    self.transform_running = true;
+    if let Some(timing) = self.timing_function {
+      self.transform_ease = timing;
+    }
+    self.transform_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for sublayerTransform
+  pub fn set_from_value_sublayer_transform(&mut self, value: Matrix4<f32>) {
+    self.sublayer_transform_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for sublayerTransform
+  pub fn set_to_value_sublayer_transform(&mut self, value: Matrix4<f32>) {
+    self.sublayer_transform_to_value = value;
+    self.sublayer_transform_running = true;
+    if let Some(timing) = self.timing_function {
+      self.sublayer_transform_ease = timing;
+    }
+    self.sublayer_transform_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for cornerRadius
+  pub fn set_from_value_corner_radius(&mut self, value: f32) {
+    self.corner_radius_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for cornerRadius
+  pub fn set_to_value_corner_radius(&mut self, value: f32) {
+    self.corner_radius_to_value = value;
+    self.corner_radius_running = true;
+    if let Some(timing) = self.timing_function {
+      self.corner_radius_ease = timing;
+    }
+    self.corner_radius_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for borderColor
+  pub fn set_from_value_border_color(&mut self, value: [f32; 3]) {
+    self.border_color_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for borderColor
+  pub fn set_to_value_border_color(&mut self, value: [f32; 3]) {
+    self.border_color_to_value = value;
+    self.border_color_running = true;
+    if let Some(timing) = self.timing_function {
+      self.border_color_ease = timing;
+    }
+    self.border_color_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for colors (CAGradientLayer)
+  pub fn set_from_value_gradient_colors(&mut self, value: Vec<[f32; 4]>) {
+    self.gradient_colors_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for colors (CAGradientLayer)
+  pub fn set_to_value_gradient_colors(&mut self, value: Vec<[f32; 4]>) {
+    self.gradient_colors_to_value = value;
+    self.gradient_colors_running = true;
+    if let Some(timing) = self.timing_function {
+      self.gradient_colors_ease = timing;
+    }
+    self.gradient_colors_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for locations (CAGradientLayer)
+  pub fn set_from_value_gradient_locations(&mut self, value: Vec<f32>) {
+    self.gradient_locations_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for locations (CAGradientLayer)
+  pub fn set_to_value_gradient_locations(&mut self, value: Vec<f32>) {
+    self.gradient_locations_to_value = value;
+    self.gradient_locations_running = true;
+    if let Some(timing) = self.timing_function {
+      self.gradient_locations_ease = timing;
+    }
+    self.gradient_locations_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for strokeStart (CAShapeLayer)
+  pub fn set_from_value_stroke_start(&mut self, value: f32) {
+    self.stroke_start_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for strokeStart (CAShapeLayer)
+  pub fn set_to_value_stroke_start(&mut self, value: f32) {
+    self.stroke_start_to_value = value;
+    self.stroke_start_running = true;
+    if let Some(timing) = self.timing_function {
+      self.stroke_start_ease = timing;
+    }
+    self.stroke_start_time_duration = self.duration * 1000.0;
+  }
+
+  // CoreAnimation-style API: Set from value for strokeEnd (CAShapeLayer)
+  pub fn set_from_value_stroke_end(&mut self, value: f32) {
+    self.stroke_end_from_value = value;
+  }
+
+  // CoreAnimation-style API: Set to value for strokeEnd (CAShapeLayer)
+  pub fn set_to_value_stroke_end(&mut self, value: f32) {
+    self.stroke_end_to_value = value;
+    self.stroke_end_running = true;
+    if let Some(timing) = self.timing_function {
+      self.stroke_end_ease = timing;
+    }
+    self.stroke_end_time_duration = self.duration * 1000.0;
+  }
+
   pub fn run(&mut self, layer: &mut Layer) {
+    self.run_at(layer, Instant::now());
+  }
+
+  /// Update the layer to the state of the animation at `time`, e.g. the time of
+  /// a deterministic `Play` clock
+  pub fn run_at(&mut self, layer: &mut Layer, time: Instant) {
+    // Starting times of 0 mean "not started", so the clock starts at 1 ms
+    let origin = *self
+      .animation_time_instance
+      .get_or_insert_with(|| time.checked_sub(Duration::from_millis(1)).unwrap_or(time));
+    let elapsed = time.saturating_duration_since(origin).as_millis().max(1);
     if self.translation_x_running {
       if self.translation_x_starting_time == 0 {
-        self.translation_x_starting_time = self.animation_time_instance.elapsed().as_millis();
+        self.translation_x_starting_time = elapsed;
       }
-      let cur_time = (self.animation_time_instance.elapsed().as_millis()
-        - self.translation_x_starting_time) as f32
-        / self.translation_x_time_duration;
+      let cur_time =
+        (elapsed - self.translation_x_starting_time) as f32 / self.translation_x_time_duration;
       if cur_time <= 1.0 {
         layer.x = Animation::easing_function(
           self.translation_x_ease,
@@ -314,11 +884,10 @@ impl Animation {
 
     if self.translation_y_running {
       if self.translation_y_starting_time == 0 {
-        self.translation_y_starting_time = self.animation_time_instance.elapsed().as_millis();
+        self.translation_y_starting_time = elapsed;
       }
-      let cur_time = (self.animation_time_instance.elapsed().as_millis()
-        - self.translation_y_starting_time) as f32
-        / self.translation_y_time_duration;
+      let cur_time =
+        (elapsed - self.translation_y_starting_time) as f32 / self.translation_y_time_duration;
       if cur_time <= 1.0 {
         layer.y = Animation::easing_function(
           self.translation_y_ease,
@@ -335,12 +904,10 @@ impl Animation {
 
     if self.rotation_running {
       if self.rotation_starting_time == 0 {
-        self.rotation_starting_time = self.animation_time_instance.elapsed().as_millis();
+        self.rotation_starting_time = elapsed;
       }
 
-      let cur_time = (self.animation_time_instance.elapsed().as_millis()
-        - self.rotation_starting_time) as f32
-        / self.rotation_time_duration as f32;
+      let cur_time = (elapsed - self.rotation_starting_time) as f32 / self.rotation_time_duration;
       if cur_time <= 1.0 {
         layer.rotation = Animation::easing_function(
           self.rotation_ease,
@@ -357,25 +924,23 @@ impl Animation {
 
     if self.scale_running {
       if self.scale_starting_time == 0 {
-        self.scale_starting_time = self.animation_time_instance.elapsed().as_millis();
+        self.scale_starting_time = elapsed;
       }
 
-      let cur_time = (self.animation_time_instance.elapsed().as_millis() - self.scale_starting_time)
-        as f32
-        / self.scale_time_duration as f32;
+      let cur_time = (elapsed - self.scale_starting_time) as f32 / self.scale_time_duration;
       if cur_time <= 1.0 {
         layer.scale_x = Animation::easing_function(
           self.scale_ease,
           self.scale_from_value,
           self.scale_to_value,
           cur_time,
-        ) as f32;
+        );
         layer.scale_y = Animation::easing_function(
           self.scale_ease,
           self.scale_from_value,
           self.scale_to_value,
           cur_time,
-        ) as f32;
+        );
       } else {
         self.scale_running = false;
         self.scale_starting_time = 0;
@@ -386,12 +951,10 @@ impl Animation {
 
     if self.opacity_running {
       if self.opacity_starting_time == 0 {
-        self.opacity_starting_time = self.animation_time_instance.elapsed().as_millis();
+        self.opacity_starting_time = elapsed;
       }
 
-      let cur_time = (self.animation_time_instance.elapsed().as_millis()
-        - self.opacity_starting_time) as f32
-        / self.opacity_time_duration as f32;
+      let cur_time = (elapsed - self.opacity_starting_time) as f32 / self.opacity_time_duration;
       if cur_time <= 1.0 {
         layer.opacity = Animation::easing_function(
           self.opacity_ease,
@@ -406,22 +969,307 @@ impl Animation {
       }
     }
 
-    if self.translation_x_running
+    if self.rotation_x_running {
+      if self.rotation_x_starting_time == 0 {
+        self.rotation_x_starting_time = elapsed;
+      }
+
+      let cur_time =
+        (elapsed - self.rotation_x_starting_time) as f32 / self.rotation_x_time_duration;
+      if cur_time <= 1.0 {
+        layer.rotation_x = Animation::easing_function(
+          self.rotation_x_ease,
+          self.rotation_x_from_value as f32,
+          self.rotation_x_to_value as f32,
+          cur_time,
+        ) as i32;
+      } else {
+        self.rotation_x_running = false;
+        self.rotation_x_starting_time = 0;
+        layer.rotation_x = self.rotation_x_to_value;
+      }
+    }
+
+    if self.rotation_y_running {
+      if self.rotation_y_starting_time == 0 {
+        self.rotation_y_starting_time = elapsed;
+      }
+
+      let cur_time =
+        (elapsed - self.rotation_y_starting_time) as f32 / self.rotation_y_time_duration;
+      if cur_time <= 1.0 {
+        layer.rotation_y = Animation::easing_function(
+          self.rotation_y_ease,
+          self.rotation_y_from_value as f32,
+          self.rotation_y_to_value as f32,
+          cur_time,
+        ) as i32;
+      } else {
+        self.rotation_y_running = false;
+        self.rotation_y_starting_time = 0;
+        layer.rotation_y = self.rotation_y_to_value;
+      }
+    }
+
+    if self.scale_z_running {
+      if self.scale_z_starting_time == 0 {
+        self.scale_z_starting_time = elapsed;
+      }
+
+      let cur_time = (elapsed - self.scale_z_starting_time) as f32 / self.scale_z_time_duration;
+      if cur_time <= 1.0 {
+        layer.scale_z = Animation::easing_function(
+          self.scale_z_ease,
+          self.scale_z_from_value,
+          self.scale_z_to_value,
+          cur_time,
+        );
+      } else {
+        self.scale_z_running = false;
+        self.scale_z_starting_time = 0;
+        layer.scale_z = self.scale_z_to_value;
+      }
+    }
+
+    if self.transform_running {
+      if self.transform_starting_time == 0 {
+        self.transform_starting_time = elapsed;
+      }
+
+      let cur_time = (elapsed - self.transform_starting_time) as f32 / self.transform_time_duration;
+      if cur_time <= 1.0 {
+        layer.transform = Animation::easing_matrix(
+          self.transform_ease,
+          self.transform_from_value,
+          self.transform_to_value,
+          cur_time,
+        );
+      } else {
+        self.transform_running = false;
+        self.transform_starting_time = 0;
+        layer.transform = self.transform_to_value;
+      }
+    }
+
+    if self.sublayer_transform_running {
+      if self.sublayer_transform_starting_time == 0 {
+        self.sublayer_transform_starting_time = elapsed;
+      }
+
+      let cur_time = (elapsed - self.sublayer_transform_starting_time) as f32
+        / self.sublayer_transform_time_duration;
+      if cur_time <= 1.0 {
+        layer.sublayer_transform = Animation::easing_matrix(
+          self.sublayer_transform_ease,
+          self.sublayer_transform_from_value,
+          self.sublayer_transform_to_value,
+          cur_time,
+        );
+      } else {
+        self.sublayer_transform_running = false;
+        self.sublayer_transform_starting_time = 0;
+        layer.sublayer_transform = self.sublayer_transform_to_value;
+      }
+    }
+
+    if self.corner_radius_running {
+      if self.corner_radius_starting_time == 0 {
+        self.corner_radius_starting_time = elapsed;
+      }
+
+      let cur_time =
+        (elapsed - self.corner_radius_starting_time) as f32 / self.corner_radius_time_duration;
+      if cur_time <= 1.0 {
+        layer.set_corner_radius(Animation::easing_function(
+          self.corner_radius_ease,
+          self.corner_radius_from_value,
+          self.corner_radius_to_value,
+          cur_time,
+        ));
+      } else {
+        self.corner_radius_running = false;
+        self.corner_radius_starting_time = 0;
+        layer.set_corner_radius(self.corner_radius_to_value);
+      }
+    }
+
+    if self.border_color_running {
+      if self.border_color_starting_time == 0 {
+        self.border_color_starting_time = elapsed;
+      }
+
+      let cur_time =
+        (elapsed - self.border_color_starting_time) as f32 / self.border_color_time_duration;
+      let [r, g, b] = if cur_time <= 1.0 {
+        Animation::easing_color(
+          self.border_color_ease,
+          self.border_color_from_value,
+          self.border_color_to_value,
+          cur_time,
+        )
+      } else {
+        self.border_color_running = false;
+        self.border_color_starting_time = 0;
+        self.border_color_to_value
+      };
+      layer.set_border_color(r, g, b);
+    }
+
+    if self.gradient_colors_running {
+      if self.gradient_colors_starting_time == 0 {
+        self.gradient_colors_starting_time = elapsed;
+      }
+
+      let cur_time =
+        (elapsed - self.gradient_colors_starting_time) as f32 / self.gradient_colors_time_duration;
+      let colors = if cur_time <= 1.0 {
+        Animation::easing_gradient_colors(
+          self.gradient_colors_ease,
+          &self.gradient_colors_from_value,
+          &self.gradient_colors_to_value,
+          cur_time,
+        )
+      } else {
+        self.gradient_colors_running = false;
+        self.gradient_colors_starting_time = 0;
+        self.gradient_colors_to_value.clone()
+      };
+      if let Some(gradient) = layer.gradient.as_mut() {
+        gradient.colors = colors;
+      }
+    }
+
+    if self.gradient_locations_running {
+      if self.gradient_locations_starting_time == 0 {
+        self.gradient_locations_starting_time = elapsed;
+      }
+
+      let cur_time = (elapsed - self.gradient_locations_starting_time) as f32
+        / self.gradient_locations_time_duration;
+      let locations = if cur_time <= 1.0 {
+        Animation::easing_gradient_locations(
+          self.gradient_locations_ease,
+          &self.gradient_locations_from_value,
+          &self.gradient_locations_to_value,
+          cur_time,
+        )
+      } else {
+        self.gradient_locations_running = false;
+        self.gradient_locations_starting_time = 0;
+        self.gradient_locations_to_value.clone()
+      };
+      if let Some(gradient) = layer.gradient.as_mut() {
+        gradient.locations = locations;
+      }
+    }
+
+    if self.stroke_start_running {
+      if self.stroke_start_starting_time == 0 {
+        self.stroke_start_starting_time = elapsed;
+      }
+
+      let cur_time =
+        (elapsed - self.stroke_start_starting_time) as f32 / self.stroke_start_time_duration;
+      let value = if cur_time <= 1.0 {
+        Animation::easing_function(
+          self.stroke_start_ease,
+          self.stroke_start_from_value,
+          self.stroke_start_to_value,
+          cur_time,
+        )
+      } else {
+        self.stroke_start_running = false;
+        self.stroke_start_starting_time = 0;
+        self.stroke_start_to_value
+      };
+      if let Some(shape) = layer.shape.as_mut() {
+        shape.stroke_start = value;
+      }
+    }
+
+    if self.stroke_end_running {
+      if self.stroke_end_starting_time == 0 {
+        self.stroke_end_starting_time = elapsed;
+      }
+
+      let cur_time =
+        (elapsed - self.stroke_end_starting_time) as f32 / self.stroke_end_time_duration;
+      let value = if cur_time <= 1.0 {
+        Animation::easing_function(
+          self.stroke_end_ease,
+          self.stroke_end_from_value,
+          self.stroke_end_to_value,
+          cur_time,
+        )
+      } else {
+        self.stroke_end_running = false;
+        self.stroke_end_starting_time = 0;
+        self.stroke_end_to_value
+      };
+      if let Some(shape) = layer.shape.as_mut() {
+        shape.stroke_end = value;
+      }
+    }
+
+    layer.animated = self.translation_x_running
       || self.translation_y_running
       || self.rotation_running
       || self.scale_running
       || self.opacity_running
-    {
-      layer.animated = true;
-    } else {
-      layer.animated = false;
-    }
+      || self.rotation_x_running
+      || self.rotation_y_running
+      || self.scale_z_running
+      || self.transform_running
+      || self.sublayer_transform_running
+      || self.corner_radius_running
+      || self.border_color_running
+      || self.gradient_colors_running
+      || self.gradient_locations_running
+      || self.stroke_start_running
+      || self.stroke_end_running;
   }
 }
 
+// Split the affine part of a matrix into translation, rotation and scale, or
+// None if it has shear or a zero scale
+fn decompose(matrix: &Matrix4<f32>) -> Option<(Vector3<f32>, Quaternion<f32>, Vector3<f32>)> {
+  let translation = matrix.w.truncate();
+  let mut columns = [
+    matrix.x.truncate(),
+    matrix.y.truncate(),
+    matrix.z.truncate(),
+  ];
+  let mut scale = Vector3::new(
+    columns[0].magnitude(),
+    columns[1].magnitude(),
+    columns[2].magnitude(),
+  );
+  if scale.x < 1e-6 || scale.y < 1e-6 || scale.z < 1e-6 {
+    return None;
+  }
+  for (column, scale) in columns.iter_mut().zip([scale.x, scale.y, scale.z]) {
+    *column /= scale;
+  }
+  let orthogonal = |a: Vector3<f32>, b: Vector3<f32>| a.dot(b).abs() < 1e-4;
+  if !orthogonal(columns[0], columns[1])
+    || !orthogonal(columns[0], columns[2])
+    || !orthogonal(columns[1], columns[2])
+  {
+    return None;
+  }
+  let mut rotation = Matrix3::from_cols(columns[0], columns[1], columns[2]);
+  // A mirror is a negative scale
+  if rotation.determinant() < 0.0 {
+    rotation.x = -rotation.x;
+    scale.x = -scale.x;
+  }
+  Some((translation, Quaternion::from(rotation), scale))
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use cgmath::Deg;
 
   #[test]
   fn test_animation_with_key_path() {
@@ -509,4 +1357,148 @@ mod tests {
     assert!(animation.scale_running);
     assert!(animation.rotation_running);
   }
+
+  #[test]
+  fn test_3d_rotation_animation_setters() {
+    let mut animation = Animation::with_key_path("transform.rotation.y");
+    animation.duration = 1.0;
+
+    animation.set_from_value_rotation_y(0);
+    animation.set_to_value_rotation_y(180);
+    animation.apply_rotation_x(0, 45, 1.0, EasingFunction::EaseIn);
+    animation.apply_scale_z(1.0, 2.0, 1.0, EasingFunction::Linear);
+
+    assert_eq!(animation.rotation_y_to_value, 180);
+    assert!(animation.rotation_y_running);
+    assert!(animation.rotation_x_running);
+    assert!(animation.scale_z_running);
+  }
+
+  #[test]
+  fn test_corner_radius_and_border_color_setters() {
+    let mut animation = Animation::with_key_path("cornerRadius");
+    animation.duration = 0.5;
+
+    animation.set_from_value_corner_radius(0.0);
+    animation.set_to_value_corner_radius(16.0);
+    animation.set_from_value_border_color([0.0, 0.0, 0.0]);
+    animation.set_to_value_border_color([1.0, 0.5, 0.0]);
+
+    assert_eq!(animation.corner_radius_to_value, 16.0);
+    assert!(animation.corner_radius_running);
+    assert!(animation.border_color_running);
+
+    let halfway = Animation::easing_color(
+      EasingFunction::Linear,
+      animation.border_color_from_value,
+      animation.border_color_to_value,
+      0.5,
+    );
+    assert_eq!(halfway, [0.5, 0.25, 0.0]);
+  }
+
+  #[test]
+  fn test_gradient_animation() {
+    let mut animation = Animation::with_key_path("colors");
+    animation.duration = 1.0;
+    animation.set_from_value_gradient_colors(vec![[0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]]);
+    animation.set_to_value_gradient_colors(vec![[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 0.0]]);
+    assert!(animation.gradient_colors_running);
+
+    let halfway = Animation::easing_gradient_colors(
+      EasingFunction::Linear,
+      &animation.gradient_colors_from_value,
+      &animation.gradient_colors_to_value,
+      0.5,
+    );
+    assert_eq!(halfway, vec![[0.5, 0.0, 0.0, 1.0], [0.5, 0.5, 1.0, 0.5]]);
+
+    // Mismatched stop counts jump to the final value
+    let locations = Animation::easing_gradient_locations(
+      EasingFunction::Linear,
+      &[0.0, 1.0],
+      &[0.2, 0.4, 1.0],
+      0.5,
+    );
+    assert_eq!(locations, vec![0.2, 0.4, 1.0]);
+    assert_eq!(
+      Animation::easing_gradient_locations(EasingFunction::Linear, &[0.0, 1.0], &[0.5, 1.0], 0.5),
+      vec![0.25, 1.0]
+    );
+  }
+
+  #[test]
+  fn test_stroke_end_animation() {
+    use crate::shape::{Path, Shape};
+
+    let mut layer = Layer::new("ring".to_string(), 100, 100, None);
+    layer.set_shape(Some(Shape::new(Path::circle(50.0, 50.0, 40.0))));
+
+    let mut animation = Animation::with_key_path("strokeEnd");
+    animation.duration = 0.0;
+    animation.set_from_value_stroke_end(0.0);
+    animation.set_to_value_stroke_end(0.75);
+    assert!(animation.stroke_end_running);
+
+    // A zero duration animation completes on the first frame
+    animation.run(&mut layer);
+    assert!(!animation.stroke_end_running);
+    assert_eq!(layer.shape().unwrap().stroke_end, 0.75);
+    assert!(!layer.animated);
+  }
+
+  #[test]
+  fn test_transform_rotation_interpolation() {
+    // Halfway from 0 to 180 degrees is a quarter turn either way, not a flat
+    // matrix
+    let from = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0));
+    let to = Matrix4::from_translation(Vector3::new(30.0, 0.0, 0.0))
+      * Matrix4::from_angle_z(Deg(180.0))
+      * Matrix4::from_scale(2.0);
+    let halfway = Animation::easing_matrix(EasingFunction::Linear, from, to, 0.5);
+    let quarter_turn = |angle| {
+      Matrix4::from_translation(Vector3::new(20.0, 0.0, 0.0))
+        * Matrix4::from_angle_z(Deg(angle))
+        * Matrix4::from_scale(1.5)
+    };
+    let near = |expected: Matrix4<f32>| {
+      [
+        (halfway.x, expected.x),
+        (halfway.y, expected.y),
+        (halfway.w, expected.w),
+      ]
+      .iter()
+      .all(|(column, expected)| (column - expected).magnitude() < 1e-4)
+    };
+    assert!(
+      near(quarter_turn(90.0)) || near(quarter_turn(-90.0)),
+      "{:?}",
+      halfway
+    );
+
+    // The ends are kept, including the perspective row
+    let mut to = to;
+    to.z.w = -1.0 / 500.0;
+    assert_eq!(
+      Animation::easing_matrix(EasingFunction::Linear, from, to, 1.0)
+        .z
+        .w,
+      to.z.w
+    );
+  }
+
+  #[test]
+  fn test_transform_animation_interpolation() {
+    let from = Matrix4::identity();
+    let to = Matrix4::from_scale(3.0);
+
+    let halfway = Animation::easing_matrix(EasingFunction::Linear, from, to, 0.5);
+    assert_eq!(halfway, Matrix4::from_nonuniform_scale(2.0, 2.0, 2.0));
+
+    let mut animation = Animation::with_key_path("transform");
+    animation.set_from_value_transform(from);
+    animation.set_to_value_transform(to);
+    assert!(animation.transform_running);
+    assert_eq!(animation.transform_to_value, to);
+  }
 }
@@ -0,0 +1,269 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! winit integration (`winit` feature).
+//!
+//! `App` owns the window and the event loop. It sets up the wgpu surface,
+//! sends keyboard, text, modifier, mouse and touch input to the `Play`,
+//! resizes it with the window, including when the scale factor changes, and
+//! renders a frame whenever the window can be redrawn:
+//!
+//! ```no_run
+//! use rust_animation::app::App;
+//! use rust_animation::layer::Layer;
+//!
+//! App::new("Hello")
+//!   .exit_on_escape(true)
+//!   .run(|play, window| {
+//!     let size = window.inner_size();
+//!     let mut stage = Layer::new("stage".to_string(), size.width, size.height, None);
+//!     stage.set_background_color(0.2, 0.2, 0.2);
+//!     play.add_stage(stage);
+//!   })
+//!   .unwrap();
+//! ```
+//!
+//! Apps with their own event loop can send window events to a `Play` with an
+//! `InputAdapter`. Sizes and positions are in physical pixels.
+
+use std::sync::Arc;
+
+mod keys;
+
+pub use keys::{key_from_winit, key_input};
+pub use winit;
+use winit::dpi::LogicalSize;
+use winit::error::EventLoopError;
+use winit::event::{
+  ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent,
+};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
+use winit::window::{Window, WindowBuilder};
+
+use crate::layer::{LayoutMode, Modifiers};
+use crate::play::Play;
+use crate::pointer::{PointerButton, PointerId, PointerInput};
+
+pub type FrameCallback = Box<dyn FnMut(&mut Play, &Window)>;
+
+/// Runs a `Play` in a window
+pub struct App {
+  title: String,
+  size: (u32, u32), // logical
+  layout_mode: LayoutMode,
+  exit_on_escape: bool,
+  frame: Option<FrameCallback>,
+}
+
+impl App {
+  /// A 1280 x 720 window with the title
+  pub fn new(title: &str) -> Self {
+    App {
+      title: title.to_string(),
+      size: (1280, 720),
+      layout_mode: LayoutMode::UserDefine,
+      exit_on_escape: false,
+      frame: None,
+    }
+  }
+
+  /// The size of the window in logical pixels
+  pub fn size(mut self, width: u32, height: u32) -> Self {
+    self.size = (width, height);
+    self
+  }
+
+  pub fn layout_mode(mut self, layout_mode: LayoutMode) -> Self {
+    self.layout_mode = layout_mode;
+    self
+  }
+
+  /// Close the window when Escape is pressed. Off by default.
+  pub fn exit_on_escape(mut self, exit: bool) -> Self {
+    self.exit_on_escape = exit;
+    self
+  }
+
+  /// Call `frame` before every frame is rendered, e.g. to update the stages
+  pub fn on_frame(mut self, frame: impl FnMut(&mut Play, &Window) + 'static) -> Self {
+    self.frame = Some(Box::new(frame));
+    self
+  }
+
+  /// Open the window, call `build` to add the stages to the play, and run the
+  /// event loop until the window is closed
+  pub fn run(self, build: impl FnOnce(&mut Play, &Window)) -> Result<(), EventLoopError> {
+    let event_loop = EventLoop::new()?;
+    let window = Arc::new(
+      WindowBuilder::new()
+        .with_title(self.title.as_str())
+        .with_inner_size(LogicalSize::new(self.size.0, self.size.1))
+        .build(&event_loop)
+        .map_err(EventLoopError::Os)?,
+    );
+
+    // The actual size may differ from the requested one due to DPI scaling
+    let size = window.inner_size();
+    let mut play = Play::new(
+      self.title.clone(),
+      size.width as i32,
+      size.height as i32,
+      self.layout_mode,
+    );
+    play.init_wgpu_with_surface(window.clone(), size.width, size.height);
+    build(&mut play, &window);
+
+    let App {
+      exit_on_escape,
+      mut frame,
+      ..
+    } = self;
+    let mut input = InputAdapter::default();
+    event_loop.run(move |event, elwt| {
+      elwt.set_control_flow(ControlFlow::Poll);
+
+      match event {
+        Event::WindowEvent { event, .. } => match event {
+          WindowEvent::CloseRequested => elwt.exit(),
+          WindowEvent::KeyboardInput {
+            event:
+              KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::Escape),
+                state: ElementState::Pressed,
+                ..
+              },
+            ..
+          } if exit_on_escape => elwt.exit(),
+          WindowEvent::Resized(size) => resize(&mut play, size.width, size.height),
+          WindowEvent::ScaleFactorChanged { .. } => {
+            // winit keeps the logical size, so the physical size changes
+            let size = window.inner_size();
+            resize(&mut play, size.width, size.height);
+          }
+          WindowEvent::RedrawRequested => {
+            if let Some(frame) = frame.as_mut() {
+              frame(&mut play, &window);
+            }
+            play.render();
+            window.request_redraw();
+          }
+          event => input.handle_window_event(&mut play, &event),
+        },
+        Event::AboutToWait => {
+          window.request_redraw();
+        }
+        _ => {}
+      }
+    })
+  }
+}
+
+// A minimized window has no size to render into
+fn resize(play: &mut Play, width: u32, height: u32) {
+  if width > 0 && height > 0 {
+    play.resize(width, height);
+  }
+}
+
+/// Sends the input of winit window events to a `Play`
+#[derive(Default)]
+pub struct InputAdapter {
+  cursor: (f32, f32), // mouse buttons and the wheel act at the cursor
+}
+
+impl InputAdapter {
+  /// Send keys, typed text, modifiers, mouse and touch input to `play`, and
+  /// ignore other events. The text of a key is typed unless a layer handled
+  /// the key, e.g. a text field moving its caret.
+  pub fn handle_window_event(&mut self, play: &mut Play, event: &WindowEvent) {
+    match event {
+      WindowEvent::ModifiersChanged(modifiers) => {
+        play.set_modifiers(modifiers_from_winit(modifiers.state()));
+      }
+      WindowEvent::KeyboardInput { event, .. } => {
+        let handled = key_input(event).is_some_and(|input| play.handle_key_input(input));
+        if let (false, ElementState::Pressed, Some(text)) = (handled, event.state, &event.text) {
+          play.handle_text_input(text);
+        }
+      }
+      WindowEvent::CursorMoved { position, .. } => {
+        self.cursor = (position.x as f32, position.y as f32);
+        play.handle_pointer_input(PointerInput::Move {
+          pointer: PointerId::Mouse,
+          x: self.cursor.0,
+          y: self.cursor.1,
+        });
+      }
+      WindowEvent::CursorLeft { .. } => {
+        play.handle_pointer_input(PointerInput::Leave {
+          pointer: PointerId::Mouse,
+        });
+      }
+      WindowEvent::MouseInput { state, button, .. } => {
+        let button = match button {
+          MouseButton::Left => PointerButton::Primary,
+          MouseButton::Right => PointerButton::Secondary,
+          MouseButton::Middle => PointerButton::Middle,
+          MouseButton::Back | MouseButton::Forward => return,
+          MouseButton::Other(button) => PointerButton::Other(*button),
+        };
+        let (pointer, (x, y)) = (PointerId::Mouse, self.cursor);
+        play.handle_pointer_input(match state {
+          ElementState::Pressed => PointerInput::Down {
+            pointer,
+            button,
+            x,
+            y,
+          },
+          ElementState::Released => PointerInput::Up {
+            pointer,
+            button,
+            x,
+            y,
+          },
+        });
+      }
+      WindowEvent::MouseWheel { delta, .. } => {
+        // Lines are 20 pixels
+        let (delta_x, delta_y) = match delta {
+          MouseScrollDelta::LineDelta(x, y) => (x * 20.0, y * 20.0),
+          MouseScrollDelta::PixelDelta(position) => (position.x as f32, position.y as f32),
+        };
+        play.handle_pointer_input(PointerInput::Scroll { delta_x, delta_y });
+      }
+      WindowEvent::Touch(touch) => {
+        let pointer = PointerId::Touch(touch.id);
+        let (x, y) = (touch.location.x as f32, touch.location.y as f32);
+        let button = PointerButton::Primary;
+        play.handle_pointer_input(match touch.phase {
+          TouchPhase::Started => PointerInput::Down {
+            pointer,
+            button,
+            x,
+            y,
+          },
+          TouchPhase::Moved => PointerInput::Move { pointer, x, y },
+          TouchPhase::Ended => PointerInput::Up {
+            pointer,
+            button,
+            x,
+            y,
+          },
+          TouchPhase::Cancelled => PointerInput::Cancel { pointer },
+        });
+      }
+      _ => {}
+    }
+  }
+}
+
+pub fn modifiers_from_winit(state: ModifiersState) -> Modifiers {
+  Modifiers {
+    shift: state.shift_key(),
+    control: state.control_key(),
+    alt: state.alt_key(),
+    logo: state.super_key(),
+  }
+}
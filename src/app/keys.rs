@@ -0,0 +1,237 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+// Conversion of winit keys
+
+use winit::event::{ElementState, KeyEvent};
+use winit::keyboard::{Key as LogicalKey, KeyCode, NamedKey, PhysicalKey};
+
+use crate::event::KeyInput;
+use crate::layer::Key;
+
+/// Convert a winit key event, or None for keys that rust-animation doesn't know
+pub fn key_input(event: &KeyEvent) -> Option<KeyInput> {
+  let key = key_from_winit(&event.physical_key, &event.logical_key)?;
+  Some(match event.state {
+    ElementState::Pressed => KeyInput::Down {
+      key,
+      repeat: event.repeat,
+    },
+    ElementState::Released => KeyInput::Up { key },
+  })
+}
+
+/// The key of a winit key event. Keyboard keys are mapped by their position,
+/// remote control keys, which have no scancode, by their meaning. Numpad keys
+/// are mapped by their meaning too when it is a navigation key, as with NumLock
+/// off.
+pub fn key_from_winit(physical_key: &PhysicalKey, logical_key: &LogicalKey) -> Option<Key> {
+  let named = match logical_key {
+    LogicalKey::Named(named) => key_from_named(*named),
+    _ => None,
+  };
+  if let PhysicalKey::Code(code) = physical_key {
+    if is_numpad(*code) && named.is_some() {
+      return named;
+    }
+    if let Some(key) = key_from_code(*code) {
+      return Some(key);
+    }
+  }
+  named
+}
+
+fn is_numpad(code: KeyCode) -> bool {
+  matches!(
+    code,
+    KeyCode::Numpad0
+      | KeyCode::Numpad1
+      | KeyCode::Numpad2
+      | KeyCode::Numpad3
+      | KeyCode::Numpad4
+      | KeyCode::Numpad5
+      | KeyCode::Numpad6
+      | KeyCode::Numpad7
+      | KeyCode::Numpad8
+      | KeyCode::Numpad9
+      | KeyCode::NumpadDecimal
+  )
+}
+
+fn key_from_named(named: NamedKey) -> Option<Key> {
+  Some(match named {
+    NamedKey::ArrowLeft => Key::Left,
+    NamedKey::ArrowRight => Key::Right,
+    NamedKey::ArrowUp => Key::Up,
+    NamedKey::ArrowDown => Key::Down,
+    NamedKey::Home => Key::Home,
+    NamedKey::End => Key::End,
+    NamedKey::PageUp => Key::PageUp,
+    NamedKey::PageDown => Key::PageDown,
+    NamedKey::Insert => Key::Insert,
+    NamedKey::Delete => Key::Delete,
+    NamedKey::GoBack | NamedKey::BrowserBack => Key::Back,
+    NamedKey::ContextMenu => Key::Menu,
+    NamedKey::MediaPlayPause => Key::PlayPause,
+    NamedKey::MediaPlay => Key::Play,
+    NamedKey::MediaPause => Key::Pause,
+    NamedKey::MediaStop => Key::Stop,
+    NamedKey::MediaFastForward => Key::FastForward,
+    NamedKey::MediaRewind => Key::Rewind,
+    NamedKey::MediaTrackNext => Key::NextTrack,
+    NamedKey::MediaTrackPrevious => Key::PreviousTrack,
+    NamedKey::AudioVolumeUp => Key::VolumeUp,
+    NamedKey::AudioVolumeDown => Key::VolumeDown,
+    NamedKey::AudioVolumeMute => Key::Mute,
+    NamedKey::ChannelUp => Key::ChannelUp,
+    NamedKey::ChannelDown => Key::ChannelDown,
+    NamedKey::Info => Key::Info,
+    NamedKey::Guide => Key::Guide,
+    NamedKey::ColorF0Red => Key::Red,
+    NamedKey::ColorF1Green => Key::Green,
+    NamedKey::ColorF2Yellow => Key::Yellow,
+    NamedKey::ColorF3Blue => Key::Blue,
+    _ => return None,
+  })
+}
+
+fn key_from_code(code: KeyCode) -> Option<Key> {
+  Some(match code {
+    KeyCode::KeyA => Key::A,
+    KeyCode::KeyB => Key::B,
+    KeyCode::KeyC => Key::C,
+    KeyCode::KeyD => Key::D,
+    KeyCode::KeyE => Key::E,
+    KeyCode::KeyF => Key::F,
+    KeyCode::KeyG => Key::G,
+    KeyCode::KeyH => Key::H,
+    KeyCode::KeyI => Key::I,
+    KeyCode::KeyJ => Key::J,
+    KeyCode::KeyK => Key::K,
+    KeyCode::KeyL => Key::L,
+    KeyCode::KeyM => Key::M,
+    KeyCode::KeyN => Key::N,
+    KeyCode::KeyO => Key::O,
+    KeyCode::KeyP => Key::P,
+    KeyCode::KeyQ => Key::Q,
+    KeyCode::KeyR => Key::R,
+    KeyCode::KeyS => Key::S,
+    KeyCode::KeyT => Key::T,
+    KeyCode::KeyU => Key::U,
+    KeyCode::KeyV => Key::V,
+    KeyCode::KeyW => Key::W,
+    KeyCode::KeyX => Key::X,
+    KeyCode::KeyY => Key::Y,
+    KeyCode::KeyZ => Key::Z,
+    KeyCode::Digit0 | KeyCode::Numpad0 => Key::Digit0,
+    KeyCode::Digit1 | KeyCode::Numpad1 => Key::Digit1,
+    KeyCode::Digit2 | KeyCode::Numpad2 => Key::Digit2,
+    KeyCode::Digit3 | KeyCode::Numpad3 => Key::Digit3,
+    KeyCode::Digit4 | KeyCode::Numpad4 => Key::Digit4,
+    KeyCode::Digit5 | KeyCode::Numpad5 => Key::Digit5,
+    KeyCode::Digit6 | KeyCode::Numpad6 => Key::Digit6,
+    KeyCode::Digit7 | KeyCode::Numpad7 => Key::Digit7,
+    KeyCode::Digit8 | KeyCode::Numpad8 => Key::Digit8,
+    KeyCode::Digit9 | KeyCode::Numpad9 => Key::Digit9,
+    KeyCode::F1 => Key::F1,
+    KeyCode::F2 => Key::F2,
+    KeyCode::F3 => Key::F3,
+    KeyCode::F4 => Key::F4,
+    KeyCode::F5 => Key::F5,
+    KeyCode::F6 => Key::F6,
+    KeyCode::F7 => Key::F7,
+    KeyCode::F8 => Key::F8,
+    KeyCode::F9 => Key::F9,
+    KeyCode::F10 => Key::F10,
+    KeyCode::F11 => Key::F11,
+    KeyCode::F12 => Key::F12,
+    KeyCode::Space => Key::Space,
+    KeyCode::Enter | KeyCode::NumpadEnter => Key::Enter,
+    KeyCode::Tab => Key::Tab,
+    KeyCode::Backspace | KeyCode::NumpadBackspace => Key::Backspace,
+    KeyCode::Escape => Key::Escape,
+    KeyCode::Insert => Key::Insert,
+    KeyCode::Delete => Key::Delete,
+    KeyCode::Home => Key::Home,
+    KeyCode::End => Key::End,
+    KeyCode::PageUp => Key::PageUp,
+    KeyCode::PageDown => Key::PageDown,
+    KeyCode::ArrowRight => Key::Right,
+    KeyCode::ArrowLeft => Key::Left,
+    KeyCode::ArrowDown => Key::Down,
+    KeyCode::ArrowUp => Key::Up,
+    KeyCode::Minus | KeyCode::NumpadSubtract => Key::Minus,
+    KeyCode::Equal | KeyCode::NumpadEqual => Key::Equal,
+    KeyCode::BracketLeft => Key::BracketLeft,
+    KeyCode::BracketRight => Key::BracketRight,
+    KeyCode::Backslash => Key::Backslash,
+    KeyCode::Semicolon => Key::Semicolon,
+    KeyCode::Quote => Key::Quote,
+    KeyCode::Backquote => Key::Backquote,
+    KeyCode::Comma | KeyCode::NumpadComma => Key::Comma,
+    KeyCode::Period | KeyCode::NumpadDecimal => Key::Period,
+    KeyCode::Slash | KeyCode::NumpadDivide => Key::Slash,
+    KeyCode::ShiftLeft | KeyCode::ShiftRight => Key::Shift,
+    KeyCode::ControlLeft | KeyCode::ControlRight => Key::Control,
+    KeyCode::AltLeft | KeyCode::AltRight => Key::Alt,
+    KeyCode::SuperLeft | KeyCode::SuperRight => Key::Logo,
+    KeyCode::CapsLock => Key::CapsLock,
+    KeyCode::BrowserBack => Key::Back,
+    KeyCode::ContextMenu => Key::Menu,
+    KeyCode::MediaPlayPause => Key::PlayPause,
+    KeyCode::MediaStop => Key::Stop,
+    KeyCode::MediaTrackNext => Key::NextTrack,
+    KeyCode::MediaTrackPrevious => Key::PreviousTrack,
+    KeyCode::AudioVolumeUp => Key::VolumeUp,
+    KeyCode::AudioVolumeDown => Key::VolumeDown,
+    KeyCode::AudioVolumeMute => Key::Mute,
+    _ => return None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use winit::keyboard::NativeKeyCode;
+
+  #[test]
+  fn test_key_from_winit() {
+    let key = |physical, logical| key_from_winit(&physical, &logical);
+    let unidentified = PhysicalKey::Unidentified(NativeKeyCode::Unidentified);
+    let character = LogicalKey::Character("a".into());
+    // By position, so that shortcuts work on any keyboard layout
+    assert_eq!(
+      key(PhysicalKey::Code(KeyCode::KeyQ), character.clone()),
+      Some(Key::Q)
+    );
+    assert_eq!(
+      key(
+        PhysicalKey::Code(KeyCode::Numpad4),
+        LogicalKey::Named(NamedKey::ArrowLeft)
+      ),
+      Some(Key::Left)
+    );
+    // Numpad keys are digits with NumLock on
+    assert_eq!(
+      key(
+        PhysicalKey::Code(KeyCode::Numpad4),
+        LogicalKey::Character("4".into())
+      ),
+      Some(Key::Digit4)
+    );
+    // Remote control keys by meaning
+    assert_eq!(
+      key(unidentified, LogicalKey::Named(NamedKey::ColorF0Red)),
+      Some(Key::Red)
+    );
+    assert_eq!(
+      key(
+        PhysicalKey::Code(KeyCode::Fn),
+        LogicalKey::Named(NamedKey::MediaFastForward)
+      ),
+      Some(Key::FastForward)
+    );
+    assert_eq!(key(unidentified, character), None);
+  }
+}
@@ -0,0 +1,290 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Key event propagation through the layer tree.
+//!
+//! A key goes to the focused layer of a stage: the end of the chain of focused
+//! sublayers (see `Layer::focus_path`). Like DOM events it travels in three
+//! phases. In the capture phase the ancestors of the target get it from the
+//! stage down, then the target gets it, and in the bubble phase the ancestors
+//! get it again from the parent of the target up to the stage. Any handler can
+//! stop the propagation, and mark the event handled to skip the default actions
+//! of later layers, such as editing a text field or `EventHandler::key_down`.
+//!
+//! For example a carousel can take Left and Right in the capture phase, before
+//! its focused button sees them, and leave Enter to the button.
+//!
+//! The windowing system sends `KeyInput` to `Play::handle_key_input`, with the
+//! repeats of a held key as further `Down` input.
+
+use crate::layer::{Key, Modifiers};
+
+/// Key input from the windowing system
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyInput {
+  Down {
+    key: Key,
+    repeat: bool, // sent again while the key is held
+  },
+  Up {
+    key: Key,
+  },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyEventKind {
+  Down,
+  Up,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventPhase {
+  /// From the stage down to the parent of the target
+  Capture,
+  /// At the target
+  Target,
+  /// From the parent of the target up to the stage
+  Bubble,
+}
+
+/// A key event sent to `EventHandler::key_event`
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyEvent {
+  pub kind: KeyEventKind,
+  pub key: Key,
+  pub repeat: bool, // for Down
+  pub modifiers: Modifiers,
+  pub phase: EventPhase,
+  pub target: Vec<usize>,         // path of the focused layer from the stage
+  pub current_target: Vec<usize>, // path of the layer that gets the event now
+  handled: bool,
+  propagation_stopped: bool,
+}
+
+impl KeyEvent {
+  /// A key down event for the focused layer at `target`
+  pub fn new(key: Key, modifiers: Modifiers, target: Vec<usize>) -> Self {
+    KeyEvent {
+      kind: KeyEventKind::Down,
+      key,
+      repeat: false,
+      modifiers,
+      phase: EventPhase::Capture,
+      target,
+      current_target: Vec::new(),
+      handled: false,
+      propagation_stopped: false,
+    }
+  }
+
+  /// Don't send the event to any other layer
+  pub fn stop_propagation(&mut self) {
+    self.propagation_stopped = true;
+  }
+
+  pub fn is_propagation_stopped(&self) -> bool {
+    self.propagation_stopped
+  }
+
+  /// Mark the event as consumed. Later layers still get it, but their default
+  /// actions are skipped.
+  pub fn set_handled(&mut self) {
+    self.handled = true;
+  }
+
+  pub fn is_handled(&self) -> bool {
+    self.handled
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::layer::{EventHandler, Layer, LayoutMode};
+  use crate::play::Play;
+  use crate::text_field::TextField;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  type Log = Rc<RefCell<Vec<(String, EventPhase, Key)>>>;
+
+  // Records the key events of a layer. A carousel takes Left and Right in the
+  // capture phase.
+  struct Recorder {
+    log: Log,
+    carousel: bool,
+  }
+
+  impl EventHandler for Recorder {
+    fn key_focus_in(&mut self, _layer: &mut Layer) {}
+    fn key_focus_out(&mut self, _layer: &mut Layer) {}
+    fn key_down(&mut self, key: Key, layer: &mut Layer) -> bool {
+      let entry = (layer.name.clone(), EventPhase::Target, key);
+      self.log.borrow_mut().push(entry);
+      false
+    }
+    fn key_event(&mut self, event: &mut KeyEvent, layer: &mut Layer) {
+      if event.phase == EventPhase::Target {
+        // Logged by key_down
+        if !event.is_handled() {
+          self.key_down(event.key, layer);
+        }
+        return;
+      }
+      let entry = (layer.name.clone(), event.phase, event.key);
+      self.log.borrow_mut().push(entry);
+      if self.carousel && matches!(event.key, Key::Left | Key::Right) {
+        match event.key {
+          Key::Right => layer.select_next_sub_layer(),
+          _ => layer.select_prev_sub_layer(),
+        }
+        event.set_handled();
+        event.stop_propagation();
+      }
+    }
+  }
+
+  fn layer(name: &str, log: &Log, carousel: bool) -> Layer {
+    let log = log.clone();
+    let handler = Recorder { log, carousel };
+    Layer::new(name.to_string(), 100, 100, Some(Box::new(handler)))
+  }
+
+  #[test]
+  fn test_key_propagation() {
+    let log: Log = Rc::default();
+    let mut play = Play::new("test".to_string(), 400, 400, LayoutMode::UserDefine);
+    let mut stage = layer("stage", &log, false);
+    let mut carousel = layer("carousel", &log, true);
+    for name in ["button1", "button2"] {
+      carousel.add_sublayer(layer(name, &log, false));
+    }
+    carousel.sub_layer_list[0].set_focus(true);
+    carousel.set_focus(true);
+    stage.add_sublayer(carousel);
+    play.add_stage(stage);
+    let entry = |name: &str, phase, key| (name.to_string(), phase, key);
+
+    // The button handles Enter, which bubbles up to the stage
+    assert!(!play.handle_input(Key::Enter));
+    assert_eq!(
+      log.borrow_mut().drain(..).collect::<Vec<_>>(),
+      vec![
+        entry("stage", EventPhase::Capture, Key::Enter),
+        entry("carousel", EventPhase::Capture, Key::Enter),
+        entry("button1", EventPhase::Target, Key::Enter),
+        entry("carousel", EventPhase::Bubble, Key::Enter),
+        entry("stage", EventPhase::Bubble, Key::Enter),
+      ]
+    );
+
+    // The carousel consumes Right before the button sees it
+    assert!(play.handle_input(Key::Right));
+    assert_eq!(
+      log.borrow_mut().drain(..).collect::<Vec<_>>(),
+      vec![
+        entry("stage", EventPhase::Capture, Key::Right),
+        entry("carousel", EventPhase::Capture, Key::Right),
+      ]
+    );
+    let stage = play.stage_mut("stage").unwrap();
+    assert_eq!(stage.focus_path(), vec![0, 1]);
+    play.handle_input(Key::Enter);
+    assert_eq!(
+      log.borrow()[2],
+      entry("button2", EventPhase::Target, Key::Enter)
+    );
+  }
+
+  #[test]
+  fn test_text_field_handles_keys() {
+    let log: Log = Rc::default();
+    let mut stage = layer("stage", &log, false);
+    let mut field = layer("field", &log, false);
+    field.set_text_field(Some(TextField::new().with_value("abc")));
+    field.set_focus(true);
+    stage.add_sublayer(field);
+
+    // The field moves its caret, so the default key_down of the target is
+    // skipped and its ancestors see a handled event
+    assert!(stage.handle_key(Key::Left, Modifiers::default()));
+    assert_eq!(stage.sub_layer_list[0].text_field().unwrap().caret(), 2);
+    assert_eq!(
+      log.borrow_mut().drain(..).collect::<Vec<_>>(),
+      vec![
+        ("stage".to_string(), EventPhase::Capture, Key::Left),
+        ("stage".to_string(), EventPhase::Bubble, Key::Left),
+      ]
+    );
+
+    // Escape isn't an editing key
+    assert!(!stage.handle_key(Key::Escape, Modifiers::default()));
+    assert_eq!(
+      log.borrow()[1],
+      ("field".to_string(), EventPhase::Target, Key::Escape)
+    );
+  }
+
+  // Counts the key downs and ups of a layer that ignores repeats
+  #[derive(Default)]
+  struct Counter(Rc<RefCell<(Vec<Key>, Vec<Key>)>>);
+
+  impl EventHandler for Counter {
+    fn key_focus_in(&mut self, _layer: &mut Layer) {}
+    fn key_focus_out(&mut self, _layer: &mut Layer) {}
+    fn key_down(&mut self, key: Key, _layer: &mut Layer) -> bool {
+      self.0.borrow_mut().0.push(key);
+      false
+    }
+    fn key_up(&mut self, key: Key, _layer: &mut Layer) {
+      self.0.borrow_mut().1.push(key);
+    }
+    fn key_repeat(&self) -> bool {
+      false
+    }
+  }
+
+  #[test]
+  fn test_key_up_and_repeat() {
+    let counter = Counter::default();
+    let keys = counter.0.clone();
+    let mut play = Play::new("test".to_string(), 400, 400, LayoutMode::UserDefine);
+    let mut stage = Layer::new("stage".to_string(), 400, 400, None);
+    let mut field = Layer::new("field".to_string(), 100, 30, Some(Box::new(counter)));
+    field.set_text_field(Some(TextField::new().with_value("abc")));
+    field.set_focus(true);
+    stage.add_sublayer(field);
+    play.add_stage(stage);
+
+    play.handle_key_input(KeyInput::Down {
+      key: Key::Shift,
+      repeat: false,
+    });
+    assert!(play.modifiers().shift);
+    for repeat in [false, true, true] {
+      play.handle_key_input(KeyInput::Down {
+        key: Key::Left,
+        repeat,
+      });
+    }
+    assert!(!play.handle_key_input(KeyInput::Up { key: Key::Left }));
+    play.handle_key_input(KeyInput::Down {
+      key: Key::PlayPause,
+      repeat: true,
+    });
+    play.handle_key_input(KeyInput::Up { key: Key::Shift });
+    assert!(!play.modifiers().shift);
+
+    // The repeats moved the caret with the selection, but only the key ups
+    // and the first Shift down reached the handler
+    let field = play
+      .stage_mut("stage")
+      .unwrap()
+      .sublayer_at_path(&[0])
+      .unwrap();
+    assert_eq!(field.text_field().unwrap().selection(), Some(0..3));
+    assert_eq!(keys.borrow().0, vec![Key::Shift]);
+    assert_eq!(keys.borrow().1, vec![Key::Left, Key::Shift]);
+  }
+}
@@ -0,0 +1,776 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Spatial focus navigation.
+//!
+//! `Play::move_focus` moves the focus from the focused layer to the nearest
+//! focusable layer (see `Layer::set_focusable`) in a direction, comparing their
+//! bounds on the screen after every transform, across nested layers and
+//! stages. Arrow keys that no layer handled move the focus the same way, so a
+//! remote control can navigate a whole UI.
+//!
+//! A focus group (see `Layer::set_focus_group`), such as a row of a TV home
+//! screen, keeps the focus while it has a focusable layer in the direction.
+//! From outside, a group is a single target: entering it focuses the layer that
+//! was focused last in it, or the nearest one. `Layer::set_next_focus` sets the
+//! next layer in a direction explicitly, for a layer or for leaving a group.
+//!
+//! Play manages the focus: `Play::current_focus` is the focused layer,
+//! `Play::request_focus` focuses a layer by name, and Tab and Shift+Tab go
+//! through the focusable layers in tree order. At the start, or when the stage
+//! of the focused layer is hidden, the first focusable layer or the layer that
+//! was focused last in the topmost shown stage gets the focus, and a stage that
+//! is shown again can take its focus back with `Play::restore_focus`.
+
+use cgmath::{Matrix4, Vector4};
+
+use crate::layer::{Key, Layer};
+use crate::pointer::path_transform;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FocusDirection {
+  Left,
+  Right,
+  Up,
+  Down,
+}
+
+impl FocusDirection {
+  /// The direction of an arrow key
+  pub fn from_key(key: Key) -> Option<Self> {
+    match key {
+      Key::Left => Some(FocusDirection::Left),
+      Key::Right => Some(FocusDirection::Right),
+      Key::Up => Some(FocusDirection::Up),
+      Key::Down => Some(FocusDirection::Down),
+      _ => None,
+    }
+  }
+}
+
+/// A layer by the index of its stage and its path in the stage
+pub(crate) type FocusTarget = (usize, Vec<usize>);
+
+/// A change of the focused layer, for `Play::set_focus_change_handler`. The
+/// layers are given by the index of their stage and their path in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FocusChange {
+  pub old: Option<(usize, Vec<usize>)>,
+  pub new: Option<(usize, Vec<usize>)>,
+  pub old_name: Option<String>,
+  pub new_name: Option<String>,
+}
+
+pub type FocusChangeHandler = Box<dyn FnMut(&FocusChange)>;
+
+/// Bounds in window coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Rect {
+  min_x: f32,
+  min_y: f32,
+  max_x: f32,
+  max_y: f32,
+}
+
+impl Rect {
+  fn center(&self) -> (f32, f32) {
+    (
+      (self.min_x + self.max_x) / 2.0,
+      (self.min_y + self.max_y) / 2.0,
+    )
+  }
+}
+
+// The bounds of a layer of size `width` x `height` drawn with `transform`
+fn bounds(transform: &Matrix4<f32>, width: u32, height: u32) -> Option<Rect> {
+  let (width, height) = (width as f32, height as f32);
+  let mut rect = Rect {
+    min_x: f32::MAX,
+    min_y: f32::MAX,
+    max_x: f32::MIN,
+    max_y: f32::MIN,
+  };
+  for (x, y) in [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)] {
+    let point = transform * Vector4::new(x, y, 0.0, 1.0);
+    if point.w <= 0.0 {
+      return None;
+    }
+    let (x, y) = (point.x / point.w, point.y / point.w);
+    rect.min_x = rect.min_x.min(x);
+    rect.min_y = rect.min_y.min(y);
+    rect.max_x = rect.max_x.max(x);
+    rect.max_y = rect.max_y.max(y);
+  }
+  Some(rect)
+}
+
+pub(crate) fn screen_bounds(stage: &Layer, path: &[usize]) -> Option<Rect> {
+  let layer = stage.sublayer_at_path(path)?;
+  bounds(&path_transform(stage, path)?, layer.width, layer.height)
+}
+
+fn shown(layer: &Layer) -> bool {
+  layer.visible && layer.opacity >= 0.01
+}
+
+// Returns true if the layer at `path` and its ancestors are shown
+fn path_shown(stage: &Layer, path: &[usize]) -> bool {
+  let mut layer = stage;
+  if !shown(layer) {
+    return false;
+  }
+  for &index in path {
+    match layer.sub_layer_list.get(index) {
+      Some(sub_layer) if shown(sub_layer) => layer = sub_layer,
+      _ => return false,
+    }
+  }
+  true
+}
+
+/// The focused layer: the deepest focusable layer on the focus path of the
+/// topmost shown stage that has one
+pub(crate) fn current(stages: &[Layer]) -> Option<FocusTarget> {
+  stages
+    .iter()
+    .enumerate()
+    .rev()
+    .filter(|(_, stage)| shown(stage))
+    .find_map(|(index, stage)| {
+      let path = stage.focus_path();
+      (1..=path.len())
+        .rev()
+        .find(|&depth| {
+          stage
+            .sublayer_at_path(&path[..depth])
+            .is_some_and(|layer| layer.focusable)
+        })
+        .map(|depth| (index, path[..depth].to_vec()))
+    })
+}
+
+// Collect the shown focusable layers and focus groups below the layer at
+// `path`, skipping `current`. A group that doesn't hold `current` is a single
+// candidate.
+fn collect(
+  stage_index: usize,
+  layer: &Layer,
+  path: &mut Vec<usize>,
+  transform: Matrix4<f32>,
+  current: Option<&[usize]>,
+  candidates: &mut Vec<(FocusTarget, Rect)>,
+) {
+  let sublayer_transform = transform * layer.sublayer_matrix();
+  for (index, sub_layer) in layer.sub_layer_list.iter().enumerate() {
+    if !shown(sub_layer) {
+      continue;
+    }
+    path.push(index);
+    let transform = sublayer_transform * sub_layer.model_matrix();
+    let holds_current = current.is_some_and(|current| current.starts_with(path));
+    let is_current = current == Some(&path[..]);
+    if (sub_layer.focus_group && !holds_current) || (sub_layer.focusable && !is_current) {
+      if let Some(rect) = bounds(&transform, sub_layer.width, sub_layer.height) {
+        candidates.push(((stage_index, path.clone()), rect));
+      }
+    }
+    if !sub_layer.focus_group || holds_current {
+      collect(stage_index, sub_layer, path, transform, current, candidates);
+    }
+    path.pop();
+  }
+}
+
+// The candidates below the layer at `path`
+fn candidates_in(
+  stages: &[Layer],
+  (stage_index, path): &FocusTarget,
+  current: Option<&[usize]>,
+) -> Vec<(FocusTarget, Rect)> {
+  let mut candidates = Vec::new();
+  let stage = &stages[*stage_index];
+  if let (Some(layer), Some(transform)) =
+    (stage.sublayer_at_path(path), path_transform(stage, path))
+  {
+    let mut path = path.clone();
+    collect(
+      *stage_index,
+      layer,
+      &mut path,
+      transform,
+      current,
+      &mut candidates,
+    );
+  }
+  candidates
+}
+
+// Returns true if `to` lies in the direction from `from` (as Android's
+// FocusFinder decides it)
+fn is_candidate(from: &Rect, to: &Rect, direction: FocusDirection) -> bool {
+  match direction {
+    FocusDirection::Left => {
+      (from.max_x > to.max_x || from.min_x >= to.max_x) && from.min_x > to.min_x
+    }
+    FocusDirection::Right => {
+      (from.min_x < to.min_x || from.max_x <= to.min_x) && from.max_x < to.max_x
+    }
+    FocusDirection::Up => {
+      (from.max_y > to.max_y || from.min_y >= to.max_y) && from.min_y > to.min_y
+    }
+    FocusDirection::Down => {
+      (from.min_y < to.min_y || from.max_y <= to.min_y) && from.max_y < to.max_y
+    }
+  }
+}
+
+// Ranks a candidate in the direction: candidates that overlap `from` across the
+// direction come first, then the nearest, weighting the distance along the
+// direction more than the offset across it
+fn score(from: &Rect, to: &Rect, direction: FocusDirection) -> (bool, f32) {
+  let (from_center, to_center) = (from.center(), to.center());
+  let (major, minor, overlap) = match direction {
+    FocusDirection::Left | FocusDirection::Right => {
+      let major = match direction {
+        FocusDirection::Left => from.min_x - to.max_x,
+        _ => to.min_x - from.max_x,
+      };
+      let overlap = from.min_y < to.max_y && to.min_y < from.max_y;
+      (major, from_center.1 - to_center.1, overlap)
+    }
+    FocusDirection::Up | FocusDirection::Down => {
+      let major = match direction {
+        FocusDirection::Up => from.min_y - to.max_y,
+        _ => to.min_y - from.max_y,
+      };
+      let overlap = from.min_x < to.max_x && to.min_x < from.max_x;
+      (major, from_center.0 - to_center.0, overlap)
+    }
+  };
+  let major = major.max(0.0);
+  (!overlap, 13.0 * major * major + minor * minor)
+}
+
+fn best(
+  candidates: Vec<(FocusTarget, Rect)>,
+  from: &Rect,
+  direction: FocusDirection,
+) -> Option<FocusTarget> {
+  candidates
+    .into_iter()
+    .filter(|(_, rect)| is_candidate(from, rect, direction))
+    .map(|(target, rect)| (score(from, &rect, direction), target))
+    .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+    .map(|(_, target)| target)
+}
+
+fn nearest(candidates: Vec<(FocusTarget, Rect)>, from: &Rect) -> Option<FocusTarget> {
+  let (x, y) = from.center();
+  let distance = |rect: &Rect| {
+    let (cx, cy) = rect.center();
+    (cx - x) * (cx - x) + (cy - y) * (cy - y)
+  };
+  candidates
+    .into_iter()
+    .map(|(target, rect)| (distance(&rect), target))
+    .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+    .map(|(_, target)| target)
+}
+
+/// Turn a target into a layer to focus: a focus group gives the layer focused
+/// last in it if it is still focusable, or else its layer that is best in the
+/// direction from `from`, or the nearest to `from`.
+pub(crate) fn resolve(
+  stages: &[Layer],
+  target: FocusTarget,
+  from: &Rect,
+  direction: Option<FocusDirection>,
+) -> Option<FocusTarget> {
+  let stage = stages.get(target.0)?;
+  let layer = stage.sublayer_at_path(&target.1)?;
+  if !layer.focus_group {
+    return Some(target);
+  }
+  if let Some(remembered) = &layer.remembered_focus {
+    let path = [&target.1[..], remembered].concat();
+    if stage
+      .sublayer_at_path(&path)
+      .is_some_and(|layer| layer.focusable)
+      && path_shown(stage, &path)
+    {
+      return Some((target.0, path));
+    }
+  }
+  let candidates = candidates_in(stages, &target, None);
+  let inner = match direction {
+    Some(direction) => {
+      best(candidates.clone(), from, direction).or_else(|| nearest(candidates, from))
+    }
+    None => nearest(candidates, from),
+  };
+  match inner {
+    Some(inner) => resolve(stages, inner, from, direction),
+    None => layer.focusable.then_some(target),
+  }
+}
+
+// The first focusable layer of a stage, entering focus groups at their top
+// left
+fn first(stages: &[Layer], stage_index: usize) -> Option<FocusTarget> {
+  let (target, rect) = candidates_in(stages, &(stage_index, Vec::new()), None)
+    .into_iter()
+    .next()?;
+  let corner = Rect {
+    max_x: rect.min_x,
+    max_y: rect.min_y,
+    ..rect
+  };
+  resolve(stages, target, &corner, None)
+}
+
+/// The layer to focus when no shown stage has a focused layer: the layer
+/// focused last in the topmost shown stage that has a focusable layer, or its
+/// first focusable layer
+pub(crate) fn restore(stages: &[Layer]) -> Option<FocusTarget> {
+  stages
+    .iter()
+    .enumerate()
+    .rev()
+    .filter(|(_, stage)| shown(stage))
+    .find_map(|(index, _)| restore_stage(stages, index).or_else(|| first(stages, index)))
+}
+
+/// The layer focused last in a stage if it can still be focused
+pub(crate) fn restore_stage(stages: &[Layer], stage_index: usize) -> Option<FocusTarget> {
+  let stage = stages.get(stage_index)?;
+  let path = stage.remembered_focus.as_ref()?;
+  can_focus(stages, &(stage_index, path.clone())).then(|| (stage_index, path.clone()))
+}
+
+/// Returns true if the layer is focusable and shown in a shown stage
+pub(crate) fn can_focus(stages: &[Layer], (stage_index, path): &FocusTarget) -> bool {
+  stages.get(*stage_index).is_some_and(|stage| {
+    !path.is_empty()
+      && path_shown(stage, path)
+      && stage
+        .sublayer_at_path(path)
+        .is_some_and(|layer| layer.focusable)
+  })
+}
+
+/// The shown focusable layers of the shown stages in tree order, for Tab
+pub(crate) fn in_order(stages: &[Layer]) -> Vec<FocusTarget> {
+  fn walk(stage_index: usize, layer: &Layer, path: &mut Vec<usize>, order: &mut Vec<FocusTarget>) {
+    for (index, sub_layer) in layer.sub_layer_list.iter().enumerate() {
+      if !shown(sub_layer) {
+        continue;
+      }
+      path.push(index);
+      if sub_layer.focusable {
+        order.push((stage_index, path.clone()));
+      }
+      walk(stage_index, sub_layer, path, order);
+      path.pop();
+    }
+  }
+  let mut order = Vec::new();
+  for (index, stage) in stages.iter().enumerate() {
+    if shown(stage) {
+      walk(index, stage, &mut Vec::new(), &mut order);
+    }
+  }
+  order
+}
+
+/// Unfocus the focus path of a stage down to its focused layer, keeping it as
+/// the layer to restore
+pub(crate) fn clear(stage: &mut Layer) {
+  let path = stage.focus_path();
+  let Some(depth) = (1..=path.len()).rev().find(|&depth| {
+    stage
+      .sublayer_at_path(&path[..depth])
+      .is_some_and(|layer| layer.focusable)
+  }) else {
+    return;
+  };
+  stage.remembered_focus = Some(path[..depth].to_vec());
+  for depth in (1..=depth).rev() {
+    if let Some(layer) = stage.sublayer_at_path_mut(&path[..depth]) {
+      layer.set_focus(false);
+    }
+  }
+}
+
+/// The first layer called `name` in the shown stages
+pub(crate) fn find_by_name(stages: &[Layer], name: &str) -> Option<FocusTarget> {
+  fn find(layer: &Layer, name: &str, path: &mut Vec<usize>) -> bool {
+    for (index, sub_layer) in layer.sub_layer_list.iter().enumerate() {
+      path.push(index);
+      if sub_layer.name == name || find(sub_layer, name, path) {
+        return true;
+      }
+      path.pop();
+    }
+    false
+  }
+  stages
+    .iter()
+    .enumerate()
+    .filter(|(_, stage)| shown(stage))
+    .find_map(|(index, stage)| {
+      let mut path = Vec::new();
+      find(stage, name, &mut path).then_some((index, path))
+    })
+}
+
+fn explicit(
+  stages: &[Layer],
+  layer: &Layer,
+  from: &Rect,
+  direction: FocusDirection,
+) -> Option<FocusTarget> {
+  let name = layer.next_focus.get(&direction)?;
+  resolve(stages, find_by_name(stages, name)?, from, Some(direction))
+}
+
+/// The layer to focus when moving from `current` in the direction. Without a
+/// focused layer, it is the first focusable layer of the topmost shown stage.
+pub(crate) fn find_next(
+  stages: &[Layer],
+  current: Option<&FocusTarget>,
+  direction: FocusDirection,
+) -> Option<FocusTarget> {
+  let Some((stage_index, path)) = current else {
+    return stages
+      .iter()
+      .enumerate()
+      .rev()
+      .filter(|(_, stage)| shown(stage))
+      .find_map(|(index, _)| first(stages, index));
+  };
+  let stage = stages.get(*stage_index)?;
+  let from = screen_bounds(stage, path)?;
+  if let Some(next) = explicit(stages, stage.sublayer_at_path(path)?, &from, direction) {
+    return Some(next);
+  }
+  // Stay in the groups that hold the focused layer, from the innermost
+  for depth in (0..path.len()).rev() {
+    let group = stage.sublayer_at_path(&path[..depth])?;
+    if !group.focus_group {
+      continue;
+    }
+    let candidates = candidates_in(stages, &(*stage_index, path[..depth].to_vec()), Some(path));
+    if let Some(next) = best(candidates, &from, direction) {
+      return resolve(stages, next, &from, Some(direction));
+    }
+    if let Some(next) = explicit(stages, group, &from, direction) {
+      return Some(next);
+    }
+  }
+  let candidates = (0..stages.len())
+    .filter(|&index| shown(&stages[index]))
+    .flat_map(|index| {
+      let current = (index == *stage_index).then_some(&path[..]);
+      candidates_in(stages, &(index, Vec::new()), current)
+    })
+    .collect();
+  let next = best(candidates, &from, direction)?;
+  resolve(stages, next, &from, Some(direction))
+}
+
+/// Move the focus from the layer at `old` to the layer at `new`: unfocus the
+/// layers on the old focus path that aren't on the new one, focus the new path
+/// and remember the new layer in its stage and the focus groups that hold it
+pub(crate) fn set_focus_target(stages: &mut [Layer], old: Option<&FocusTarget>, new: &FocusTarget) {
+  if let Some((old_stage, old_path)) = old {
+    // The length of the path that stays focused
+    let shared = if old_stage == &new.0 {
+      old_path
+        .iter()
+        .zip(&new.1)
+        .take_while(|(a, b)| a == b)
+        .count()
+    } else {
+      0
+    };
+    if let Some(stage) = stages.get_mut(*old_stage) {
+      for depth in (shared + 1..=old_path.len()).rev() {
+        if let Some(layer) = stage.sublayer_at_path_mut(&old_path[..depth]) {
+          layer.set_focus(false);
+        }
+      }
+    }
+  }
+  let (stage_index, path) = new;
+  let Some(stage) = stages.get_mut(*stage_index) else {
+    return;
+  };
+  for depth in 0..path.len() {
+    let Some(parent) = stage.sublayer_at_path_mut(&path[..depth]) else {
+      return;
+    };
+    // Stages remember their focused layer too, to restore it
+    if parent.focus_group || depth == 0 {
+      parent.remembered_focus = Some(path[depth..].to_vec());
+    }
+    parent.focused_sub_layer = path[depth];
+    let Some(layer) = parent.sub_layer_list.get_mut(path[depth]) else {
+      return;
+    };
+    if !layer.focused {
+      layer.set_focus(true);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::layer::{EventHandler, LayoutMode, Modifiers};
+  use crate::play::Play;
+  use crate::text_field::TextField;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  fn button(name: &str, x: i32, y: i32) -> Layer {
+    let mut button = Layer::new(name.to_string(), 100, 50, None);
+    button.set_position(x, y);
+    button.set_focusable(true);
+    button
+  }
+
+  // A row of three buttons at y
+  fn row(name: &str, y: i32) -> Layer {
+    let mut row = Layer::new(name.to_string(), 400, 50, None);
+    row.set_position(0, y);
+    row.set_focus_group(true);
+    for index in 0..3 {
+      row.add_sublayer(button(&format!("{}{}", name, index), index * 150, 0));
+    }
+    row
+  }
+
+  fn focused(play: &Play) -> String {
+    let (stage, path) = current(&play.stage_list).unwrap();
+    play.stage_list[stage]
+      .sublayer_at_path(&path)
+      .unwrap()
+      .name
+      .clone()
+  }
+
+  #[test]
+  fn test_spatial_navigation() {
+    let mut play = Play::new("test".to_string(), 800, 600, LayoutMode::UserDefine);
+    let mut stage = Layer::new("stage".to_string(), 800, 600, None);
+    stage.set_visible(true);
+    stage.add_sublayer(row("a", 0));
+    stage.add_sublayer(row("b", 100));
+    let mut menu = button("menu", 600, 300);
+    menu.rotation = 90; // still found by its bounds on the screen
+    stage.add_sublayer(menu);
+    play.add_stage(stage);
+
+    // The first focusable layer, then along the row
+    assert!(play.move_focus(FocusDirection::Right));
+    assert_eq!(focused(&play), "a0");
+    play.move_focus(FocusDirection::Right);
+    play.move_focus(FocusDirection::Right);
+    assert_eq!(focused(&play), "a2");
+    // The row keeps the focus at its end, and the menu is the only layer
+    // further right
+    play.move_focus(FocusDirection::Right);
+    assert_eq!(focused(&play), "menu");
+
+    // Entering a row goes to the nearest button, and back to the one focused
+    // last
+    play.move_focus(FocusDirection::Left);
+    assert_eq!(focused(&play), "b2");
+    play.move_focus(FocusDirection::Left);
+    play.move_focus(FocusDirection::Up);
+    assert_eq!(focused(&play), "a2");
+    play.move_focus(FocusDirection::Down);
+    assert_eq!(focused(&play), "b1");
+
+    // The focus path follows, and only one button of a row is focused
+    let stage = &play.stage_list[0];
+    assert_eq!(stage.focus_path(), vec![1, 1]);
+    assert!(!stage.sub_layer_list[0].focused);
+    assert!(!stage.sub_layer_list[1].sub_layer_list[2].focused);
+  }
+
+  #[test]
+  fn test_next_focus_and_stages() {
+    let mut play = Play::new("test".to_string(), 800, 600, LayoutMode::UserDefine);
+    let mut stage = Layer::new("stage".to_string(), 800, 600, None);
+    stage.set_visible(true);
+    let mut row = row("a", 0);
+    row.set_next_focus(FocusDirection::Down, Some("dialog1".to_string()));
+    stage.add_sublayer(row);
+    stage.add_sublayer(button("hidden", 0, 200));
+    stage.sub_layer_list[1].set_visible(false);
+    play.add_stage(stage);
+    let mut dialog = Layer::new("dialog".to_string(), 800, 600, None);
+    dialog.set_visible(true);
+    dialog.add_sublayer(button("dialog0", 0, 300));
+    dialog.add_sublayer(button("dialog1", 300, 300));
+    play.add_stage(dialog);
+
+    // The topmost stage has the first focusable layer
+    play.move_focus(FocusDirection::Down);
+    assert_eq!(focused(&play), "dialog0");
+    // Across stages
+    play.move_focus(FocusDirection::Up);
+    assert_eq!(focused(&play), "a0");
+    assert!(play.stage_list[1].focus_path().is_empty());
+    // Leaving the row down goes to the layer it names
+    play.move_focus(FocusDirection::Down);
+    assert_eq!(focused(&play), "dialog1");
+    // The arrow keys move the focus when no layer handles them
+    assert!(play.handle_input(Key::Left));
+    assert_eq!(focused(&play), "dialog0");
+    assert!(!play.handle_input(Key::Left));
+  }
+
+  #[test]
+  fn test_focus_leaves_text_field() {
+    let mut play = Play::new("test".to_string(), 800, 600, LayoutMode::UserDefine);
+    let mut stage = Layer::new("stage".to_string(), 800, 600, None);
+    stage.set_visible(true);
+    let mut field = button("field", 0, 0);
+    field.set_text_field(Some(TextField::new().with_value("ab")));
+    stage.add_sublayer(field);
+    stage.add_sublayer(button("next", 150, 0));
+    stage.add_sublayer(button("below", 0, 100));
+    play.add_stage(stage);
+    assert!(play.request_focus("field"));
+
+    // The arrows edit the field while the caret can move
+    assert!(play.handle_input(Key::Left));
+    assert_eq!(focused(&play), "field");
+    assert!(play.handle_input(Key::Right));
+    assert_eq!(focused(&play), "field");
+    // and move the focus when it can't
+    assert!(play.handle_input(Key::Right));
+    assert_eq!(focused(&play), "next");
+    assert!(play.request_focus("field"));
+    assert!(play.handle_input(Key::Down));
+    assert_eq!(focused(&play), "below");
+  }
+
+  // A slider that steps its value with Right
+  struct Slider(Rc<RefCell<u32>>);
+
+  impl EventHandler for Slider {
+    fn key_focus_in(&mut self, _layer: &mut Layer) {}
+    fn key_focus_out(&mut self, _layer: &mut Layer) {}
+    fn key_down(&mut self, key: Key, _layer: &mut Layer) -> bool {
+      if key != Key::Right {
+        return false;
+      }
+      *self.0.borrow_mut() += 1;
+      true
+    }
+  }
+
+  #[test]
+  fn test_used_keys_keep_focus() {
+    let value = Rc::new(RefCell::new(0));
+    let mut play = Play::new("test".to_string(), 800, 600, LayoutMode::UserDefine);
+    let mut stage = Layer::new("stage".to_string(), 800, 600, None);
+    stage.set_visible(true);
+    stage.add_sublayer(button("prev", 0, 0));
+    let handler = Slider(value.clone());
+    let mut slider = Layer::new("slider".to_string(), 100, 50, Some(Box::new(handler)));
+    slider.set_position(150, 0);
+    slider.set_focusable(true);
+    stage.add_sublayer(slider);
+    stage.add_sublayer(button("next", 300, 0));
+    play.add_stage(stage);
+    assert!(play.request_focus("slider"));
+
+    // The slider uses Right, and other arrows move the focus
+    assert!(play.handle_input(Key::Right));
+    assert_eq!(*value.borrow(), 1);
+    assert_eq!(focused(&play), "slider");
+    assert!(play.handle_input(Key::Left));
+    assert_eq!(focused(&play), "prev");
+  }
+
+  #[test]
+  fn test_focus_manager() {
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let mut play = Play::new("test".to_string(), 800, 600, LayoutMode::UserDefine);
+    let log = changes.clone();
+    play.set_focus_change_handler(Some(Box::new(move |change: &FocusChange| {
+      let change = (change.old_name.clone(), change.new_name.clone());
+      log.borrow_mut().push(change);
+    })));
+    let mut stage = Layer::new("main".to_string(), 800, 600, None);
+    stage.set_visible(true);
+    stage.add_sublayer(Layer::new("label".to_string(), 100, 20, None));
+    let mut panel = Layer::new("panel".to_string(), 400, 100, None);
+    panel.add_sublayer(button("b0", 0, 0));
+    panel.add_sublayer(button("b1", 150, 0));
+    stage.add_sublayer(panel);
+    stage.add_sublayer(button("b2", 0, 300));
+    play.add_stage(stage);
+    let mut dialog = Layer::new("dialog".to_string(), 800, 600, None);
+    dialog.add_sublayer(button("ok", 300, 300));
+    dialog.set_visible(false);
+    play.add_stage(dialog);
+    let name = |name: &str| Some(name.to_string());
+    let changed = |old, new| {
+      let change = std::mem::take(&mut *changes.borrow_mut()).pop();
+      assert_eq!(change, Some((old, new)));
+    };
+
+    // The first focusable layer is focused
+    play.update_focus();
+    changed(None, name("b0"));
+    assert_eq!(play.current_focus(), Some((0, vec![1, 0])));
+    assert!(play.stage_list[0].sub_layer_list[1].is_focused());
+
+    // Tab and Shift+Tab in tree order
+    assert!(play.handle_input(Key::Tab));
+    assert!(play.handle_input(Key::Tab));
+    assert_eq!(play.focused_layer().unwrap().name, "b2");
+    play.handle_input(Key::Tab);
+    changed(name("b2"), name("b0"));
+    play.set_modifiers(Modifiers {
+      shift: true,
+      ..Default::default()
+    });
+    play.handle_input(Key::Tab);
+    changed(name("b0"), name("b2"));
+    play.set_modifiers(Modifiers::default());
+
+    assert!(!play.request_focus("label"));
+    assert!(play.request_focus("b1"));
+    changed(name("b2"), name("b1"));
+
+    // A dialog takes the focus, and gives it back when it is hidden
+    play.stage_mut("dialog").unwrap().set_visible(true);
+    assert!(play.request_focus("ok"));
+    changed(name("b1"), name("ok"));
+    assert!(play.stage_list[0].focus_path().is_empty());
+    play.stage_mut("dialog").unwrap().set_visible(false);
+    play.update_focus();
+    changed(name("ok"), name("b1"));
+
+    // and gets it back when it is shown again
+    play.stage_mut("dialog").unwrap().set_visible(true);
+    assert!(play.restore_focus("dialog"));
+    changed(name("b1"), name("ok"));
+    assert!(play.stage_list[0].focus_path().is_empty());
+
+    // The old name is the layer that had the focus, even once it is removed
+    play.stage_mut("dialog").unwrap().set_visible(false);
+    assert!(play.request_focus("b2"));
+    changed(name("ok"), name("b2"));
+    play.stage_list[0].sub_layer_list.remove(2);
+    play.update_focus();
+    changed(name("b2"), name("b0"));
+  }
+}
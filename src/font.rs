@@ -1,53 +1,335 @@
 // Adapted from the file below:
 // https://github.com/alexheretic/ab-glyph/blob/main/dev/examples/image.rs
 
-use ab_glyph::{point, Font, FontVec, Glyph, Point, PxScale, ScaleFont};
+use ab_glyph::{point, Font, FontVec, Glyph, GlyphId, Point, PxScale, ScaleFont};
 use image::{DynamicImage, ImageBuffer, Rgba};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 
-pub struct FontRenderer {
-  font_path: String,
-  font: Option<FontVec>,
+use crate::shaping::{shape_line_with_faces, shaping_faces};
+
+// Directories searched for font families, after the fonts directory of the project
+const SYSTEM_FONT_DIRS: [&str; 5] = [
+  "/usr/share/fonts",
+  "/usr/local/share/fonts",
+  "/Library/Fonts",
+  "/System/Library/Fonts",
+  "C:\\Windows\\Fonts",
+];
+
+/// Find a font by file path, or by family name (e.g. "DejaVu Sans") in the fonts
+/// directory and the system font directories.
+pub fn find_font(font: &str) -> Option<PathBuf> {
+  let path = Path::new(font);
+  if path.is_file() {
+    return Some(path.to_path_buf());
+  }
+
+  let family = normalize_font_name(font);
+  let mut dirs = vec![PathBuf::from("fonts")];
+  if let Some(home) = std::env::var_os("HOME") {
+    dirs.push(Path::new(&home).join(".fonts"));
+    dirs.push(Path::new(&home).join(".local/share/fonts"));
+  }
+  dirs.extend(SYSTEM_FONT_DIRS.iter().map(PathBuf::from));
+
+  dirs
+    .iter()
+    .find_map(|dir| find_font_in_dir(dir, &family, 0))
 }
 
-impl FontRenderer {
-  pub fn new(font_path: String) -> Self {
-    let mut font_renderer = FontRenderer {
-      font_path: "".to_string(),
-      font: None,
-    };
-    font_renderer.load_font(font_path);
+#[derive(Clone, Debug, PartialEq)]
+pub enum FontError {
+  // No font file or family with this name
+  NotFound(String),
+  Io(PathBuf, std::io::ErrorKind),
+  // The data is not a TrueType or OpenType font
+  InvalidFont,
+}
 
-    font_renderer
+impl std::fmt::Display for FontError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      FontError::NotFound(name) => write!(f, "font not found: {}", name),
+      FontError::Io(path, kind) => write!(f, "can't read the font file {:?}: {}", path, kind),
+      FontError::InvalidFont => write!(f, "invalid font data"),
+    }
   }
+}
 
-  pub fn load_font(&mut self, font_path: String) {
-    println!("load_font: {}", font_path);
-    let font_path = std::env::current_dir().unwrap().join(font_path);
-    let data = std::fs::read(&font_path).unwrap();
+impl std::error::Error for FontError {}
 
-    match &mut self.font {
-      Some(_font) => {
-        println!("font already loaded");
-      }
-      None => {
-        println!("loading font...");
-        self.font = Some(FontVec::try_from_vec(data).unwrap_or_else(|_| {
-          panic!("error constructing a Font from data at {:?}", font_path);
-        }));
-        if let Some(name) = font_path.file_name().and_then(|n| n.to_str()) {
-          eprintln!("Using font: {name}");
+impl From<ab_glyph::InvalidFont> for FontError {
+  fn from(_: ab_glyph::InvalidFont) -> Self {
+    FontError::InvalidFont
+  }
+}
+
+/// Load a font by file path or family name. See `find_font`.
+pub fn load_font_by_name(font: &str) -> Result<FontVec, FontError> {
+  let path = find_font(font).ok_or_else(|| FontError::NotFound(font.to_string()))?;
+  let data = std::fs::read(&path).map_err(|err| FontError::Io(path, err.kind()))?;
+  load_font_data(data)
+}
+
+/// Load a font from memory, e.g. `load_font_data(include_bytes!("Brand.ttf"))`
+pub fn load_font_data(data: impl Into<Vec<u8>>) -> Result<FontVec, FontError> {
+  Ok(FontVec::try_from_vec(data.into())?)
+}
+
+// Lowercase without spaces, dashes and underscores, so that "DejaVu Sans"
+// matches DejaVuSans.ttf
+fn normalize_font_name(name: &str) -> String {
+  name
+    .chars()
+    .filter(|c| !matches!(c, ' ' | '-' | '_'))
+    .flat_map(char::to_lowercase)
+    .collect()
+}
+
+fn find_font_in_dir(dir: &Path, family: &str, depth: u32) -> Option<PathBuf> {
+  let entries = std::fs::read_dir(dir).ok()?;
+  let mut sub_dirs = Vec::new();
+  for entry in entries.flatten() {
+    let path = entry.path();
+    if path.is_dir() {
+      sub_dirs.push(path);
+      continue;
+    }
+    let is_font = path
+      .extension()
+      .and_then(|e| e.to_str())
+      .is_some_and(|e| e.eq_ignore_ascii_case("ttf") || e.eq_ignore_ascii_case("otf"));
+    let stem = path
+      .file_stem()
+      .and_then(|s| s.to_str())
+      .map(normalize_font_name);
+    if is_font && stem.is_some_and(|stem| stem == family || stem == format!("{}regular", family)) {
+      return Some(path);
+    }
+  }
+
+  if depth < 4 {
+    sub_dirs.sort();
+    return sub_dirs
+      .iter()
+      .find_map(|dir| find_font_in_dir(dir, family, depth + 1));
+  }
+  None
+}
+
+/// A font followed by the fonts used for the characters it doesn't have, in
+/// order, e.g. a Latin font, then a CJK font, then an emoji font.
+#[derive(Clone, Default)]
+pub struct FontCollection {
+  names: Vec<String>,
+  fonts: Vec<Arc<FontVec>>,
+}
+
+impl FontCollection {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn push(&mut self, name: &str, font: Arc<FontVec>) {
+    self.names.push(name.to_string());
+    self.fonts.push(font);
+  }
+
+  pub fn len(&self) -> usize {
+    self.fonts.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.fonts.is_empty()
+  }
+
+  /// The name the font at `index` was registered or loaded with
+  pub fn name(&self, index: usize) -> &str {
+    &self.names[index]
+  }
+
+  pub fn font(&self, index: usize) -> &FontVec {
+    &self.fonts[index]
+  }
+
+  /// The fonts in fallback order, for `Text::layout` and `shaping::shape_line`
+  pub fn fonts(&self) -> Vec<&FontVec> {
+    self.fonts.iter().map(|font| font.as_ref()).collect()
+  }
+}
+
+/// Index of the font used for `c`, given the font `current` of the previous
+/// character: the first font that has the character. Spaces, combining marks,
+/// joiners and variation selectors stay in the current font if it has them, and
+/// characters no font has stay in the current font.
+pub fn fallback_font_index<F: Font>(fonts: &[F], current: usize, c: char) -> usize {
+  let has_glyph = |font: &F| font.glyph_id(c) != GlyphId(0);
+  if joins_previous(c) && fonts.get(current).is_some_and(has_glyph) {
+    return current;
+  }
+  fonts.iter().position(has_glyph).unwrap_or(current)
+}
+
+// Characters drawn together with the previous character
+fn joins_previous(c: char) -> bool {
+  c.is_whitespace()
+    || matches!(c,
+      '\u{0300}'..='\u{036f}' // combining diacritical marks
+      | '\u{1ab0}'..='\u{1aff}'
+      | '\u{1dc0}'..='\u{1dff}'
+      | '\u{200c}'..='\u{200d}' // zero width non-joiner and joiner
+      | '\u{20d0}'..='\u{20ff}'
+      | '\u{fe00}'..='\u{fe0f}' // variation selectors
+      | '\u{fe20}'..='\u{fe2f}'
+      | '\u{1f3fb}'..='\u{1f3ff}' // emoji skin tone modifiers
+      | '\u{e0020}'..='\u{e007f}' // tags
+      | '\u{e0100}'..='\u{e01ef}')
+}
+
+type FontCache = HashMap<String, Result<Arc<FontVec>, FontError>>;
+
+/// Fonts loaded once and shared by every layer that renders text.
+///
+/// `Play` uses `FontRegistry::shared()` unless another registry is set with
+/// `Play::set_font_registry`. Cloning a registry shares its fonts.
+///
+/// Text layers may name several fonts separated by commas ("Brand, Noto Sans CJK
+/// SC"); the fallback fonts of the registry are tried after them for characters
+/// missing from all of them.
+#[derive(Clone, Default)]
+pub struct FontRegistry {
+  fonts: Arc<Mutex<FontCache>>,
+  fallbacks: Arc<Mutex<Vec<String>>>,
+}
+
+impl FontRegistry {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The process-wide registry
+  pub fn shared() -> &'static FontRegistry {
+    static SHARED: OnceLock<FontRegistry> = OnceLock::new();
+    SHARED.get_or_init(FontRegistry::new)
+  }
+
+  /// Register a font under a name, replacing any font with the same name
+  pub fn register(&self, name: &str, font: FontVec) {
+    self.lock().insert(name.to_string(), Ok(Arc::new(font)));
+  }
+
+  /// Register a font from memory, e.g. data embedded with `include_bytes!`
+  pub fn register_data(&self, name: &str, data: impl Into<Vec<u8>>) -> Result<(), FontError> {
+    self.register(name, load_font_data(data)?);
+    Ok(())
+  }
+
+  /// Get a font by name, loading it by file path or family name the first time
+  /// (see `find_font`). Fonts that fail to load are not looked up again, and
+  /// later calls return the same error.
+  pub fn load(&self, name: &str) -> Result<Arc<FontVec>, FontError> {
+    let mut fonts = self.lock();
+    if let Some(font) = fonts.get(name) {
+      return font.clone();
+    }
+    let font = load_font_by_name(name).map(Arc::new);
+    fonts.insert(name.to_string(), font.clone());
+    font
+  }
+
+  /// Like `load`, but prints the error the first time a font fails to load
+  pub fn font(&self, name: &str) -> Option<Arc<FontVec>> {
+    let reported = self.lock().contains_key(name);
+    match self.load(name) {
+      Ok(font) => Some(font),
+      Err(err) => {
+        if !reported {
+          println!("Fail to load a font {}: {}", name, err);
         }
+        None
+      }
+    }
+  }
+
+  /// Set the fonts tried, in order, for characters missing from the font of a
+  /// text, e.g. `["Noto Sans CJK SC", "Noto Color Emoji"]`
+  pub fn set_fallbacks(&self, names: &[&str]) {
+    let mut fallbacks = self.fallbacks.lock().unwrap_or_else(|e| e.into_inner());
+    *fallbacks = names.iter().map(|name| name.to_string()).collect();
+  }
+
+  pub fn fallbacks(&self) -> Vec<String> {
+    self
+      .fallbacks
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .clone()
+  }
 
-        self.font_path = font_path.to_str().unwrap().to_string();
+  /// The fonts of a comma separated list of names followed by the fallback
+  /// fonts. Fonts that fail to load are left out; returns None if none loads.
+  pub fn collection(&self, names: &str) -> Option<FontCollection> {
+    let mut collection = FontCollection::new();
+    let names = names
+      .split(',')
+      .map(|name| name.trim().to_string())
+      .filter(|name| !name.is_empty());
+    for name in names.chain(self.fallbacks()) {
+      if collection.names.contains(&name) {
+        continue;
+      }
+      if let Some(font) = self.font(&name) {
+        collection.push(&name, font);
       }
     }
+    (!collection.is_empty()).then_some(collection)
+  }
+
+  /// Returns true if the font was loaded or registered
+  pub fn contains(&self, name: &str) -> bool {
+    self.lock().get(name).is_some_and(|font| font.is_ok())
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, FontCache> {
+    // A panic while holding the lock can't leave the map half updated
+    self.fonts.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+pub struct FontRenderer {
+  font: Arc<FontVec>,
+}
+
+impl FontRenderer {
+  /// Create a renderer with a font file path or family name
+  pub fn new(font_path: String) -> Result<Self, FontError> {
+    // Fonts are read from disk once and shared through the registry
+    Ok(Self::with_font(FontRegistry::shared().load(&font_path)?))
+  }
+
+  /// Create a renderer with font data in memory
+  pub fn from_data(data: impl Into<Vec<u8>>) -> Result<Self, FontError> {
+    Ok(Self::with_font(Arc::new(load_font_data(data)?)))
+  }
+
+  /// Create a renderer with an already loaded font
+  pub fn with_font(font: Arc<FontVec>) -> Self {
+    FontRenderer { font }
+  }
+
+  /// Replace the font. The current font is kept if the new one fails to load.
+  pub fn load_font(&mut self, font_path: String) -> Result<(), FontError> {
+    self.font = FontRegistry::shared().load(&font_path)?;
+    Ok(())
   }
 
   pub fn render(&mut self, text: &str) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
     // The font size to use
     let scale = PxScale::from(45.0);
 
-    let font = self.font.as_ref().unwrap();
+    let font = self.font.as_ref();
     let scaled_font = font.as_scaled(scale);
 
     let mut glyphs = Vec::new();
@@ -58,11 +340,13 @@ impl FontRenderer {
 
     // work out the layout size
     let glyphs_height = scaled_font.height().ceil() as u32;
-    let glyphs_width = {
-      let min_x = glyphs.first().unwrap().position.x;
-      let last_glyph = glyphs.last().unwrap();
-      let max_x = last_glyph.position.x + scaled_font.h_advance(last_glyph.id);
-      (max_x - min_x).ceil() as u32
+    let glyphs_width = match (glyphs.first(), glyphs.last()) {
+      (Some(first_glyph), Some(last_glyph)) => {
+        let min_x = first_glyph.position.x;
+        let max_x = last_glyph.position.x + scaled_font.h_advance(last_glyph.id);
+        (max_x - min_x).ceil() as u32
+      }
+      _ => 0,
     };
 
     println!(
@@ -109,34 +393,124 @@ impl FontRenderer {
   {
     let v_advance = font.height() + font.line_gap();
     let mut caret = position + point(0.0, font.ascent());
-    let mut last_glyph: Option<Glyph> = None;
-    for c in text.chars() {
-      if c.is_control() {
-        if c == '\n' {
-          caret = point(position.x, caret.y + v_advance);
-          last_glyph = None;
-        }
-        continue;
+    let fonts = std::slice::from_ref(font.font());
+    let faces = shaping_faces(fonts);
+    for (i, line) in text.split('\n').enumerate() {
+      if i > 0 {
+        caret = point(position.x, caret.y + v_advance);
       }
-      let mut glyph = font.scaled_glyph(c);
-      if let Some(previous) = last_glyph.take() {
-        caret.x += font.kern(previous.id, glyph.id);
-      }
-      glyph.position = caret;
-
-      last_glyph = Some(glyph.clone());
-      caret.x += font.h_advance(glyph.id);
-
-      if !c.is_whitespace() && caret.x > position.x + max_width {
-        caret = Point {
-          x: position.x,
-          y: caret.y + v_advance,
-        };
-        glyph.position = caret;
-        last_glyph = None;
+      // Glyphs come from the shaper (see shaping::shape_line), in visual order
+      let line: String = line.chars().filter(|c| !c.is_control()).collect();
+      let shaped = shape_line_with_faces(fonts, &faces, font.scale(), &line, 0.0);
+      let mut line_x = caret.x;
+      for shaped_glyph in shaped.glyphs {
+        let mut glyph = shaped_glyph.id.with_scale_and_position(
+          font.scale(),
+          point(line_x + shaped_glyph.x, caret.y + shaped_glyph.y_offset),
+        );
+        let is_space = line[shaped_glyph.cluster..]
+          .chars()
+          .next()
+          .is_some_and(char::is_whitespace);
+        if !is_space && glyph.position.x + font.h_advance(glyph.id) > position.x + max_width {
+          // Wrap before this glyph
+          caret = Point {
+            x: position.x,
+            y: caret.y + v_advance,
+          };
+          line_x = caret.x - shaped_glyph.x;
+          glyph.position = point(caret.x, caret.y + shaped_glyph.y_offset);
+        }
+        target.push(glyph);
       }
-
-      target.push(glyph);
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::text::{Text, TextCache};
+
+  #[test]
+  fn test_find_font_by_family() {
+    assert!(find_font("DejaVu Sans").is_some());
+    assert!(find_font("dejavu-sans-mono").is_some());
+    assert!(find_font("fonts/DejaVuSans.ttf").is_some());
+    assert!(find_font("No Such Font Family").is_none());
+  }
+
+  #[test]
+  fn test_font_registry() {
+    let fonts = FontRegistry::new();
+    let font = fonts.font("fonts/DejaVuSans.ttf").unwrap();
+    // Loaded once and shared
+    assert!(Arc::ptr_eq(
+      &font,
+      &fonts.font("fonts/DejaVuSans.ttf").unwrap()
+    ));
+    assert!(fonts.clone().contains("fonts/DejaVuSans.ttf"));
+    assert!(fonts.font("No Such Font Family").is_none());
+
+    fonts.register("Custom", load_font_by_name("DejaVu Sans").unwrap());
+    assert!(fonts.contains("Custom"));
+    let text = Text::new("Hi").with_font("Custom");
+    assert!(TextCache::default().render(&text, 40, 30, &fonts).is_some());
+  }
+
+  #[test]
+  fn test_font_errors() {
+    assert_eq!(
+      load_font_by_name("No Such Font Family").err(),
+      Some(FontError::NotFound("No Such Font Family".to_string()))
+    );
+    assert_eq!(
+      load_font_data(vec![0u8; 16]).err(),
+      Some(FontError::InvalidFont)
+    );
+    assert!(FontRenderer::new("No Such Font Family".to_string()).is_err());
+
+    // Fonts embedded in the binary
+    let fonts = FontRegistry::new();
+    fonts
+      .register_data("Embedded", include_bytes!("../fonts/DejaVuSans.ttf"))
+      .unwrap();
+    assert!(fonts.load("Embedded").is_ok());
+    assert!(fonts.register_data("Broken", b"not a font").is_err());
+    assert!(!fonts.contains("Broken"));
+    // Failures are remembered
+    assert!(fonts.load("No Such Font Family").is_err());
+    assert!(!fonts.contains("No Such Font Family"));
+    assert!(fonts.load("No Such Font Family").is_err());
+  }
+
+  #[test]
+  fn test_font_fallback() {
+    // DejaVu Sans Mono has no glyph for U+01C4, DejaVu Sans has one
+    let fonts = FontRegistry::new();
+    let string = "a\u{01c4}b";
+    let mono = fonts.collection("DejaVu Sans Mono").unwrap();
+    assert_eq!(mono.len(), 1);
+    let glyphs = Text::new(string).layout(&mono.fonts(), 200.0, 50.0);
+    assert_eq!(glyphs[1].1.id, GlyphId(0));
+
+    fonts.set_fallbacks(&["DejaVu Sans"]);
+    let collection = fonts.collection("DejaVu Sans Mono").unwrap();
+    assert_eq!(collection.len(), 2);
+    assert_eq!(collection.name(1), "DejaVu Sans");
+    let glyphs = Text::new(string).layout(&collection.fonts(), 200.0, 50.0);
+    let font_indices: Vec<usize> = glyphs.iter().map(|(font, _)| *font).collect();
+    assert_eq!(font_indices, vec![0, 1, 0]);
+    assert_ne!(glyphs[1].1.id, GlyphId(0));
+
+    // Fallbacks can also be listed in the font of the text; missing fonts are skipped
+    let collection = FontRegistry::new()
+      .collection("No Such Font Family, DejaVu Sans Mono, DejaVu Sans")
+      .unwrap();
+    assert_eq!(collection.len(), 2);
+    assert_eq!(collection.name(0), "DejaVu Sans Mono");
+    assert!(FontRegistry::new()
+      .collection("No Such Font Family")
+      .is_none());
+  }
+}
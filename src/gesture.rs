@@ -0,0 +1,962 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Gesture recognizers (UIGestureRecognizer-style API).
+//!
+//! Recognizers are attached to a layer with `Layer::add_gesture_recognizer`.
+//! They see the pointers that go down on the layer or its sublayers, move
+//! through their states and report every state change to
+//! `EventHandler::gesture`. Taps, swipes and presses that end are discrete: they
+//! go from `Possible` to `Ended` once. Pans, pinches and long presses are
+//! continuous: `Began`, any number of `Changed`, then `Ended` or `Cancelled`.
+//!
+//! When a recognizer begins (or a discrete one ends), every other recognizer
+//! that tracks one of its pointers fails or is cancelled, unless one of the two
+//! recognizes simultaneously (`with_simultaneous`). A recognizer can wait for
+//! another one to fail first (`with_required_failure`), e.g. a single tap that
+//! waits for a double tap. By default, the layer under the pointer gets a
+//! `PointerEventKind::Cancel` once a gesture claims its pointer.
+
+use cgmath::{Matrix4, SquareMatrix};
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::layer::Layer;
+use crate::pointer::{unproject, PointerEvent, PointerEventKind, PointerId};
+
+// How far a pointer may move before it is no longer a tap or a press, and
+// before a pan or pinch begins, in pixels
+const SLOP: f32 = 10.0;
+// Longest time a tap may be held, in seconds
+const TAP_MAX_DURATION: f32 = 0.5;
+// Longest time between the taps of a multiple tap, in seconds
+const MULTI_TAP_INTERVAL: f32 = 0.3;
+// Shortest distance in pixels and lowest speed in pixels per second of a swipe
+const SWIPE_MIN_DISTANCE: f32 = 50.0;
+const SWIPE_MIN_VELOCITY: f32 = 300.0;
+// Velocities are measured over the latest samples within this time, in seconds
+const VELOCITY_WINDOW: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GestureState {
+  /// Waiting for pointers or for the gesture to be recognized
+  Possible,
+  Began,
+  Changed,
+  /// The gesture finished, or a discrete gesture was recognized
+  Ended,
+  /// The pointers didn't make the gesture, or another gesture won them
+  Failed,
+  /// A continuous gesture stopped because its pointer was cancelled or another
+  /// gesture won it
+  Cancelled,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GestureKind {
+  /// One or more quick taps of one pointer (discrete)
+  Tap { taps: u32 },
+  /// A pointer held still for `duration` seconds (continuous: it changes as the
+  /// pointer moves and ends when it goes up)
+  LongPress { duration: f32 },
+  /// Pointers dragged further than a few pixels (continuous)
+  Pan,
+  /// Two pointers moved closer together or further apart (continuous)
+  Pinch,
+  /// A fast, straight stroke of one pointer (discrete)
+  Swipe,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SwipeDirection {
+  Left,
+  Right,
+  Up,
+  Down,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct TrackedPointer {
+  id: PointerId,
+  position: (f32, f32), // in window coordinates
+  down: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Sample {
+  time: Instant,
+  centroid: (f32, f32),
+  distance: f32,
+}
+
+pub struct GestureRecognizer {
+  pub name: String,
+  pub kind: GestureKind,
+  pub enabled: bool,
+  pub cancels_pointer_events: bool, // send Cancel to the layer under a claimed pointer
+  simultaneous: bool,
+  required_failure: Option<String>,
+  state: GestureState,
+  pending: Option<GestureState>, // recognized while waiting for `required_failure`
+  changed: bool,                 // not reported to the event handler yet
+  pointers: Vec<TrackedPointer>, // every pointer seen since the last reset
+  start_time: Option<Instant>,
+  last_up: Option<Instant>,
+  taps: u32,
+  start_centroid: (f32, f32),
+  start_distance: f32,
+  samples: VecDeque<Sample>,
+  location: (f32, f32),
+  translation: (f32, f32),
+  velocity: (f32, f32),
+  scale: f32,
+  scale_velocity: f32,
+  direction: Option<SwipeDirection>,
+}
+
+impl GestureRecognizer {
+  pub fn new(name: &str, kind: GestureKind) -> Self {
+    GestureRecognizer {
+      name: name.to_string(),
+      kind,
+      enabled: true,
+      cancels_pointer_events: true,
+      simultaneous: false,
+      required_failure: None,
+      state: GestureState::Possible,
+      pending: None,
+      changed: false,
+      pointers: Vec::new(),
+      start_time: None,
+      last_up: None,
+      taps: 0,
+      start_centroid: (0.0, 0.0),
+      start_distance: 0.0,
+      samples: VecDeque::new(),
+      location: (0.0, 0.0),
+      translation: (0.0, 0.0),
+      velocity: (0.0, 0.0),
+      scale: 1.0,
+      scale_velocity: 0.0,
+      direction: None,
+    }
+  }
+
+  pub fn tap(name: &str) -> Self {
+    Self::new(name, GestureKind::Tap { taps: 1 })
+  }
+
+  pub fn double_tap(name: &str) -> Self {
+    Self::new(name, GestureKind::Tap { taps: 2 })
+  }
+
+  pub fn long_press(name: &str) -> Self {
+    Self::new(name, GestureKind::LongPress { duration: 0.5 })
+  }
+
+  pub fn pan(name: &str) -> Self {
+    Self::new(name, GestureKind::Pan)
+  }
+
+  pub fn pinch(name: &str) -> Self {
+    Self::new(name, GestureKind::Pinch)
+  }
+
+  pub fn swipe(name: &str) -> Self {
+    Self::new(name, GestureKind::Swipe)
+  }
+
+  /// Recognize together with any other gesture instead of excluding it, e.g. a
+  /// pinch and a pan on the same photo
+  pub fn with_simultaneous(mut self, simultaneous: bool) -> Self {
+    self.simultaneous = simultaneous;
+    self
+  }
+
+  /// Wait until the recognizer named `name` fails before recognizing. If it
+  /// recognizes instead, this recognizer fails.
+  pub fn with_required_failure(mut self, name: &str) -> Self {
+    self.required_failure = Some(name.to_string());
+    self
+  }
+
+  pub fn with_cancels_pointer_events(mut self, cancels: bool) -> Self {
+    self.cancels_pointer_events = cancels;
+    self
+  }
+
+  pub fn state(&self) -> GestureState {
+    self.state
+  }
+
+  /// Where the latest pointer event was, in the coordinates of the layer
+  pub fn location(&self) -> (f32, f32) {
+    self.location
+  }
+
+  /// How far the pointers moved since the gesture started, in window pixels
+  pub fn translation(&self) -> (f32, f32) {
+    self.translation
+  }
+
+  /// Velocity of the pointers in window pixels per second, e.g. to hand to a
+  /// deceleration animation when a pan or swipe ends
+  pub fn velocity(&self) -> (f32, f32) {
+    self.velocity
+  }
+
+  /// Distance between the pointers of a pinch relative to where it started
+  pub fn scale(&self) -> f32 {
+    self.scale
+  }
+
+  /// How fast the scale of a pinch changes, per second
+  pub fn scale_velocity(&self) -> f32 {
+    self.scale_velocity
+  }
+
+  /// The direction of a recognized swipe
+  pub fn direction(&self) -> Option<SwipeDirection> {
+    self.direction
+  }
+
+  /// The number of pointers that are down
+  pub fn pointer_count(&self) -> usize {
+    self.pointers.iter().filter(|pointer| pointer.down).count()
+  }
+
+  /// Track a pointer event. `event` is in the coordinates of the layer the
+  /// recognizer is attached to. Only Down adds pointers, so the other events
+  /// of untracked pointers are ignored.
+  pub fn handle_pointer_event(&mut self, event: &PointerEvent, time: Instant) {
+    let index = self
+      .pointers
+      .iter()
+      .position(|p| p.id == event.pointer && p.down);
+    if !self.enabled || self.is_finished() {
+      // Only follow the pointers until they are lifted
+      if let (Some(index), PointerEventKind::Up | PointerEventKind::Cancel) = (index, event.kind) {
+        self.pointers[index].down = false;
+      }
+      return;
+    }
+    let position = (event.x, event.y);
+    match event.kind {
+      PointerEventKind::Down if index.is_none() => {
+        self.location = (event.local_x, event.local_y);
+        self.pointers.push(TrackedPointer {
+          id: event.pointer,
+          position,
+          down: true,
+        });
+        self.pointers_changed(time);
+        self.pointer_down(time);
+      }
+      PointerEventKind::Move => {
+        let Some(index) = index else {
+          return;
+        };
+        self.location = (event.local_x, event.local_y);
+        self.pointers[index].position = position;
+        self.add_sample(time);
+        self.pointer_moved(time);
+      }
+      PointerEventKind::Up => {
+        let Some(index) = index else {
+          return;
+        };
+        self.location = (event.local_x, event.local_y);
+        self.pointers[index].position = position;
+        self.add_sample(time);
+        self.pointers[index].down = false;
+        self.pointer_up(time);
+        self.pointers_changed(time);
+      }
+      PointerEventKind::Cancel => {
+        if let Some(index) = index {
+          self.pointers[index].down = false;
+          self.fail_or_cancel();
+        }
+      }
+      _ => {}
+    }
+  }
+
+  /// Advance the recognizer to `time` without pointer events, for timeouts
+  pub fn update(&mut self, time: Instant) {
+    if !self.enabled || self.state != GestureState::Possible || self.pending.is_some() {
+      return;
+    }
+    let elapsed = |since: Option<Instant>| {
+      since.map_or(0.0, |since| {
+        time.saturating_duration_since(since).as_secs_f32()
+      })
+    };
+    match self.kind {
+      GestureKind::Tap { .. } => {
+        let down = self.pointer_count() > 0;
+        if (down && elapsed(self.start_time) > TAP_MAX_DURATION)
+          || (!down && self.taps > 0 && elapsed(self.last_up) > MULTI_TAP_INTERVAL)
+        {
+          self.set_state(GestureState::Failed);
+        }
+      }
+      GestureKind::LongPress { duration }
+        if self.pointer_count() > 0 && elapsed(self.start_time) >= duration =>
+      {
+        self.set_state(GestureState::Began);
+      }
+      _ => {}
+    }
+  }
+
+  fn pointer_down(&mut self, time: Instant) {
+    let count = self.pointer_count();
+    match self.kind {
+      GestureKind::Tap { .. } | GestureKind::Swipe | GestureKind::LongPress { .. } => {
+        if count > 1 {
+          self.fail_or_cancel();
+          return;
+        }
+        if let (GestureKind::Tap { .. }, Some(last_up)) = (self.kind, self.last_up) {
+          if time.saturating_duration_since(last_up).as_secs_f32() > MULTI_TAP_INTERVAL {
+            self.set_state(GestureState::Failed);
+            return;
+          }
+        }
+        self.start_time = Some(time);
+      }
+      GestureKind::Pan => {
+        if count == 1 {
+          self.start_time = Some(time);
+        }
+      }
+      GestureKind::Pinch => {
+        if count == 2 {
+          self.start_distance = self.distance();
+          self.scale = 1.0;
+        }
+      }
+    }
+  }
+
+  fn pointer_moved(&mut self, time: Instant) {
+    let moved = self.distance_from_start();
+    match self.kind {
+      GestureKind::Tap { .. } if moved > SLOP => self.set_state(GestureState::Failed),
+      GestureKind::LongPress { .. } if self.state == GestureState::Possible => {
+        if moved > SLOP {
+          self.set_state(GestureState::Failed);
+        } else {
+          self.update(time);
+        }
+      }
+      GestureKind::LongPress { .. } => self.set_state(GestureState::Changed),
+      GestureKind::Pan => match self.state {
+        GestureState::Possible if moved > SLOP => self.set_state(GestureState::Began),
+        GestureState::Began | GestureState::Changed => self.set_state(GestureState::Changed),
+        _ => {}
+      },
+      GestureKind::Pinch if self.pointer_count() >= 2 => {
+        self.scale = self.distance() / self.start_distance.max(f32::EPSILON);
+        match self.state {
+          GestureState::Possible if (self.distance() - self.start_distance).abs() > SLOP => {
+            self.set_state(GestureState::Began)
+          }
+          GestureState::Began | GestureState::Changed => self.set_state(GestureState::Changed),
+          _ => {}
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn pointer_up(&mut self, time: Instant) {
+    let count = self.pointer_count();
+    let active = self.is_active();
+    match self.kind {
+      GestureKind::Tap { taps } => {
+        let held = self.start_time.map_or(0.0, |start| {
+          time.saturating_duration_since(start).as_secs_f32()
+        });
+        if held > TAP_MAX_DURATION {
+          self.set_state(GestureState::Failed);
+        } else {
+          self.taps += 1;
+          self.last_up = Some(time);
+          if self.taps >= taps {
+            self.set_state(GestureState::Ended);
+          }
+        }
+      }
+      GestureKind::LongPress { .. } | GestureKind::Pan if count == 0 => {
+        self.set_state(if active {
+          GestureState::Ended
+        } else {
+          GestureState::Failed
+        });
+      }
+      GestureKind::Pinch if count < 2 && active => self.set_state(GestureState::Ended),
+      GestureKind::Pinch if count == 0 => self.set_state(GestureState::Failed),
+      GestureKind::Swipe => {
+        let (dx, dy) = self.translation;
+        let (vx, vy) = self.velocity;
+        let horizontal = dx.abs() >= dy.abs();
+        let (distance, speed) = if horizontal {
+          (dx.abs(), vx.abs())
+        } else {
+          (dy.abs(), vy.abs())
+        };
+        if distance >= SWIPE_MIN_DISTANCE && speed >= SWIPE_MIN_VELOCITY {
+          self.direction = Some(match (horizontal, dx, dy) {
+            (true, dx, _) if dx < 0.0 => SwipeDirection::Left,
+            (true, ..) => SwipeDirection::Right,
+            (false, _, dy) if dy < 0.0 => SwipeDirection::Up,
+            _ => SwipeDirection::Down,
+          });
+          self.set_state(GestureState::Ended);
+        } else {
+          self.set_state(GestureState::Failed);
+        }
+      }
+      _ => {}
+    }
+  }
+
+  // Stop the recognizer because its pointers were taken away
+  fn fail_or_cancel(&mut self) {
+    self.pending = None;
+    self.set_state(if self.is_active() {
+      GestureState::Cancelled
+    } else {
+      GestureState::Failed
+    });
+  }
+
+  fn set_state(&mut self, state: GestureState) {
+    self.state = state;
+    self.changed = true;
+  }
+
+  // Keep the translation continuous when pointers are added or lifted
+  fn pointers_changed(&mut self, time: Instant) {
+    let previous = self.samples.back().map(|sample| sample.centroid);
+    self.samples.clear();
+    if self.pointer_count() == 0 {
+      return;
+    }
+    let centroid = self.centroid();
+    match previous {
+      Some(previous) => {
+        self.start_centroid.0 += centroid.0 - previous.0;
+        self.start_centroid.1 += centroid.1 - previous.1;
+      }
+      None => self.start_centroid = centroid,
+    }
+    self.add_sample(time);
+  }
+
+  fn add_sample(&mut self, time: Instant) {
+    let sample = Sample {
+      time,
+      centroid: self.centroid(),
+      distance: self.distance(),
+    };
+    self.translation = (
+      sample.centroid.0 - self.start_centroid.0,
+      sample.centroid.1 - self.start_centroid.1,
+    );
+    while self.samples.front().is_some_and(|first| {
+      time.saturating_duration_since(first.time).as_secs_f32() > VELOCITY_WINDOW
+    }) {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(sample);
+    let first = self.samples.front().unwrap();
+    let dt = time.saturating_duration_since(first.time).as_secs_f32();
+    if dt > 0.0 {
+      self.velocity = (
+        (sample.centroid.0 - first.centroid.0) / dt,
+        (sample.centroid.1 - first.centroid.1) / dt,
+      );
+      if self.start_distance > 0.0 {
+        self.scale_velocity = (sample.distance - first.distance) / self.start_distance / dt;
+      }
+    }
+  }
+
+  fn down_positions(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+    self
+      .pointers
+      .iter()
+      .filter(|pointer| pointer.down)
+      .map(|pointer| pointer.position)
+  }
+
+  fn centroid(&self) -> (f32, f32) {
+    let count = self.pointer_count().max(1) as f32;
+    let (x, y) = self
+      .down_positions()
+      .fold((0.0, 0.0), |(x, y), (px, py)| (x + px, y + py));
+    (x / count, y / count)
+  }
+
+  // Distance between the first two pointers that are down
+  fn distance(&self) -> f32 {
+    let mut positions = self.down_positions();
+    match (positions.next(), positions.next()) {
+      (Some(a), Some(b)) => ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt(),
+      _ => 0.0,
+    }
+  }
+
+  fn distance_from_start(&self) -> f32 {
+    (self.translation.0.powi(2) + self.translation.1.powi(2)).sqrt()
+  }
+
+  fn is_active(&self) -> bool {
+    matches!(self.state, GestureState::Began | GestureState::Changed)
+  }
+
+  fn is_finished(&self) -> bool {
+    matches!(
+      self.state,
+      GestureState::Ended | GestureState::Failed | GestureState::Cancelled
+    )
+  }
+
+  fn shares_pointer(&self, other: &GestureRecognizer) -> bool {
+    self
+      .pointers
+      .iter()
+      .any(|pointer| other.pointers.iter().any(|p| p.id == pointer.id))
+  }
+
+  // Back to Possible once the gesture finished and its pointers are up
+  fn reset_if_finished(&mut self) {
+    let finished = self.is_finished() || (self.state == GestureState::Possible && self.taps == 0);
+    if finished && self.pointer_count() == 0 && self.pending.is_none() {
+      *self = GestureRecognizer {
+        name: std::mem::take(&mut self.name),
+        enabled: self.enabled,
+        cancels_pointer_events: self.cancels_pointer_events,
+        simultaneous: self.simultaneous,
+        required_failure: self.required_failure.take(),
+        // Keep the results of the last gesture for reading until the next one
+        location: self.location,
+        translation: self.translation,
+        velocity: self.velocity,
+        scale: self.scale,
+        scale_velocity: self.scale_velocity,
+        direction: self.direction,
+        ..GestureRecognizer::new("", self.kind)
+      };
+    }
+  }
+}
+
+// A recognizer of a stage, with the transform and path of its layer
+struct Entry<'a> {
+  transform: Matrix4<f32>,
+  path: Vec<usize>,
+  recognizer: &'a mut GestureRecognizer,
+}
+
+fn collect<'a>(
+  layer: &'a mut Layer,
+  parent_transform: Matrix4<f32>,
+  path: &mut Vec<usize>,
+  entries: &mut Vec<Entry<'a>>,
+) {
+  let transform = parent_transform * layer.model_matrix();
+  let sub_layer_transform = transform * layer.sublayer_matrix();
+  for recognizer in layer.gesture_recognizers.iter_mut() {
+    entries.push(Entry {
+      transform,
+      path: path.clone(),
+      recognizer,
+    });
+  }
+  for (index, sub_layer) in layer.sub_layer_list.iter_mut().enumerate() {
+    path.push(index);
+    collect(sub_layer, sub_layer_transform, path, entries);
+    path.pop();
+  }
+}
+
+/// Feed a pointer event of the layer at `target` (a path in `stage`) to the
+/// recognizers of the stage, or only advance them to `time` without an event.
+/// Resolves conflicts, reports state changes to the event handlers and returns
+/// the pointers claimed by recognizers that began or recognized.
+pub(crate) fn process(
+  stage: &mut Layer,
+  input: Option<(&[usize], &PointerEvent)>,
+  time: Instant,
+) -> Vec<PointerId> {
+  let mut claimed = Vec::new();
+  {
+    let mut entries = Vec::new();
+    collect(stage, Matrix4::identity(), &mut Vec::new(), &mut entries);
+    if entries.is_empty() {
+      return claimed;
+    }
+    let before: Vec<GestureState> = entries.iter().map(|e| e.recognizer.state).collect();
+
+    for entry in entries.iter_mut() {
+      match input {
+        // Pointers go down on the recognizers of the target and its ancestors
+        Some((target, event)) => {
+          if event.kind == PointerEventKind::Down && !target.starts_with(&entry.path) {
+            continue;
+          }
+          let mut event = *event;
+          if let Some(((x, y), _)) = unproject(&entry.transform, event.x, event.y) {
+            (event.local_x, event.local_y) = (x, y);
+          }
+          entry.recognizer.handle_pointer_event(&event, time);
+        }
+        None => entry.recognizer.update(time),
+      }
+    }
+
+    let recognized =
+      |state: GestureState| matches!(state, GestureState::Began | GestureState::Ended);
+
+    // Hold recognizers back until the recognizers they require fail
+    for i in 0..entries.len() {
+      let Some(required) = entries[i].recognizer.required_failure.clone() else {
+        continue;
+      };
+      let waiting = entries[i].recognizer.pending.is_some();
+      let newly = before[i] == GestureState::Possible && recognized(entries[i].recognizer.state);
+      if !waiting && !newly {
+        continue;
+      }
+      let other_state = entries
+        .iter()
+        .enumerate()
+        .find(|(j, e)| *j != i && e.recognizer.name == required)
+        .map(|(_, e)| e.recognizer.state);
+      let recognizer = &mut *entries[i].recognizer;
+      match other_state {
+        Some(GestureState::Possible) => {
+          if newly {
+            recognizer.pending = Some(recognizer.state);
+            recognizer.state = GestureState::Possible;
+            recognizer.changed = false;
+          }
+        }
+        Some(GestureState::Began | GestureState::Changed | GestureState::Ended) => {
+          recognizer.pending = None;
+          recognizer.set_state(GestureState::Failed);
+        }
+        _ => {
+          if let Some(state) = recognizer.pending.take() {
+            recognizer.set_state(state);
+          }
+        }
+      }
+    }
+
+    // The deepest recognizer that recognized wins the pointers it shares with
+    // recognizers that don't recognize simultaneously
+    let mut winners: Vec<usize> = (0..entries.len())
+      .filter(|&i| before[i] == GestureState::Possible && recognized(entries[i].recognizer.state))
+      .collect();
+    winners.sort_by_key(|&i| std::cmp::Reverse(entries[i].path.len()));
+    for &winner in &winners {
+      if !recognized(entries[winner].recognizer.state) {
+        continue;
+      }
+      for other in 0..entries.len() {
+        let (w, o) = (&*entries[winner].recognizer, &*entries[other].recognizer);
+        let excludes = other != winner
+          && !w.simultaneous
+          && !o.simultaneous
+          && !o.is_finished()
+          && w.shares_pointer(o);
+        if excludes {
+          entries[other].recognizer.fail_or_cancel();
+        }
+      }
+      let winner = &*entries[winner].recognizer;
+      if winner.cancels_pointer_events && recognized(winner.state) {
+        claimed.extend(winner.pointers.iter().map(|pointer| pointer.id));
+      }
+    }
+  }
+
+  report(stage);
+  claimed.dedup();
+  claimed
+}
+
+// Send the state changes of the recognizers to the event handlers of their
+// layers, then reset the recognizers that finished
+fn report(layer: &mut Layer) {
+  if layer.gesture_recognizers.iter().any(|r| r.changed) {
+    let mut recognizers = std::mem::take(&mut layer.gesture_recognizers);
+    for recognizer in recognizers.iter_mut().filter(|r| r.changed) {
+      recognizer.changed = false;
+      layer.handle_gesture(recognizer);
+    }
+    // Keep recognizers added by the event handler
+    let added = std::mem::replace(&mut layer.gesture_recognizers, recognizers);
+    layer.gesture_recognizers.extend(added);
+  }
+  for recognizer in layer.gesture_recognizers.iter_mut() {
+    recognizer.reset_if_finished();
+  }
+  for sub_layer in layer.sub_layer_list.iter_mut() {
+    report(sub_layer);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::layer::{EventHandler, Key, LayoutMode};
+  use crate::play::Play;
+  use crate::pointer::{PointerButton, PointerInput};
+  use std::cell::RefCell;
+  use std::rc::Rc;
+  use std::time::Duration;
+
+  type Log = Rc<RefCell<Vec<String>>>;
+
+  // Records gesture state changes and pointer events as "name State"
+  struct Recorder(Log);
+
+  impl EventHandler for Recorder {
+    fn key_focus_in(&mut self, _layer: &mut Layer) {}
+    fn key_focus_out(&mut self, _layer: &mut Layer) {}
+    fn key_down(&mut self, _key: Key, _layer: &mut Layer) -> bool {
+      false
+    }
+    fn pointer_event(&mut self, event: &PointerEvent, layer: &mut Layer) {
+      if event.kind == PointerEventKind::Cancel {
+        self.0.borrow_mut().push(format!("{} Cancel", layer.name));
+      }
+    }
+    fn gesture(&mut self, gesture: &GestureRecognizer, _layer: &mut Layer) {
+      self
+        .0
+        .borrow_mut()
+        .push(format!("{} {:?}", gesture.name, gesture.state()));
+    }
+  }
+
+  struct Input {
+    play: Play,
+    start: Instant,
+    log: Log,
+  }
+
+  impl Input {
+    // A stage with a 200x200 "photo" layer at (100, 100) with a "button" child
+    fn new(photo: Vec<GestureRecognizer>, button: Vec<GestureRecognizer>) -> Self {
+      let log: Log = Rc::default();
+      let recorder = || Some(Box::new(Recorder(log.clone())) as Box<dyn EventHandler>);
+      let mut play = Play::new("test".to_string(), 400, 400, LayoutMode::UserDefine);
+      let mut stage = Layer::new("stage".to_string(), 400, 400, None);
+      let mut photo_layer = Layer::new("photo".to_string(), 200, 200, recorder());
+      photo_layer.set_position(100, 100);
+      let mut button_layer = Layer::new("button".to_string(), 50, 50, recorder());
+      button_layer.set_position(10, 10);
+      for recognizer in photo {
+        photo_layer.add_gesture_recognizer(recognizer);
+      }
+      for recognizer in button {
+        button_layer.add_gesture_recognizer(recognizer);
+      }
+      photo_layer.add_sublayer(button_layer);
+      stage.add_sublayer(photo_layer);
+      play.add_stage(stage);
+      Input {
+        play,
+        start: Instant::now(),
+        log,
+      }
+    }
+
+    fn at(&self, seconds: f32) -> Instant {
+      self.start + Duration::from_secs_f32(seconds)
+    }
+
+    fn down(&mut self, touch: u64, x: f32, y: f32, seconds: f32) {
+      let input = PointerInput::Down {
+        pointer: PointerId::Touch(touch),
+        button: PointerButton::Primary,
+        x,
+        y,
+      };
+      self.play.handle_pointer_input_at(input, self.at(seconds));
+    }
+
+    fn moved(&mut self, touch: u64, x: f32, y: f32, seconds: f32) {
+      let input = PointerInput::Move {
+        pointer: PointerId::Touch(touch),
+        x,
+        y,
+      };
+      self.play.handle_pointer_input_at(input, self.at(seconds));
+    }
+
+    fn up(&mut self, touch: u64, x: f32, y: f32, seconds: f32) {
+      let input = PointerInput::Up {
+        pointer: PointerId::Touch(touch),
+        button: PointerButton::Primary,
+        x,
+        y,
+      };
+      self.play.handle_pointer_input_at(input, self.at(seconds));
+    }
+
+    fn update(&mut self, seconds: f32) {
+      let time = self.at(seconds);
+      self.play.update_gestures_at(time);
+    }
+
+    fn log(&self) -> Vec<String> {
+      self.log.borrow_mut().drain(..).collect()
+    }
+
+    fn photo(&mut self) -> &mut Layer {
+      self
+        .play
+        .stage_mut("stage")
+        .unwrap()
+        .sublayer_at_path_mut(&[0])
+        .unwrap()
+    }
+  }
+
+  #[test]
+  fn test_tap_waits_for_double_tap() {
+    let mut input = Input::new(
+      vec![
+        GestureRecognizer::tap("tap").with_required_failure("doubleTap"),
+        GestureRecognizer::double_tap("doubleTap"),
+      ],
+      vec![],
+    );
+    // A single tap is recognized once the double tap times out
+    input.down(1, 200.0, 200.0, 0.0);
+    input.up(1, 202.0, 200.0, 0.1);
+    assert!(input.log().is_empty());
+    input.update(0.2);
+    assert!(input.log().is_empty());
+    input.update(0.5);
+    assert_eq!(input.log(), vec!["tap Ended", "doubleTap Failed"]);
+
+    // Two taps make a double tap, and the single tap fails
+    input.down(1, 200.0, 200.0, 1.0);
+    input.up(1, 200.0, 200.0, 1.1);
+    input.down(2, 205.0, 200.0, 1.2);
+    input.up(2, 205.0, 200.0, 1.3);
+    assert_eq!(
+      input.log(),
+      vec!["tap Failed", "doubleTap Ended", "photo Cancel"]
+    );
+
+    // Moving too far isn't a tap
+    input.down(1, 200.0, 200.0, 2.0);
+    input.moved(1, 230.0, 200.0, 2.1);
+    input.up(1, 230.0, 200.0, 2.2);
+    input.update(3.0);
+    assert_eq!(input.log(), vec!["tap Failed", "doubleTap Failed"]);
+  }
+
+  #[test]
+  fn test_pan_velocity_and_conflicts() {
+    let mut input = Input::new(
+      vec![GestureRecognizer::pan("pan")],
+      vec![GestureRecognizer::tap("buttonTap")],
+    );
+    // A drag that starts on the button is a pan of the photo: the tap fails and
+    // the button gets Cancel
+    input.down(1, 120.0, 120.0, 0.0);
+    input.moved(1, 125.0, 120.0, 0.02);
+    assert!(input.log().is_empty());
+    input.moved(1, 140.0, 120.0, 0.04);
+    assert_eq!(
+      input.log(),
+      vec!["pan Began", "buttonTap Failed", "button Cancel"]
+    );
+    input.moved(1, 160.0, 120.0, 0.06);
+    input.up(1, 180.0, 120.0, 0.08);
+    assert_eq!(input.log(), vec!["pan Changed", "pan Ended"]);
+    let pan = &input.photo().gesture_recognizers()[0];
+    assert_eq!(pan.translation(), (60.0, 0.0));
+    assert!((pan.velocity().0 - 750.0).abs() < 1.0);
+    assert_eq!(pan.state(), GestureState::Possible);
+
+    // A tap on the button wins over the pan, which never began
+    input.down(1, 120.0, 120.0, 1.0);
+    input.up(1, 121.0, 120.0, 1.1);
+    assert_eq!(
+      input.log(),
+      vec!["pan Failed", "buttonTap Ended", "button Cancel"]
+    );
+  }
+
+  #[test]
+  fn test_pinch_and_pan_simultaneously() {
+    let mut input = Input::new(
+      vec![
+        GestureRecognizer::pinch("pinch"),
+        GestureRecognizer::pan("pan").with_simultaneous(true),
+      ],
+      vec![],
+    );
+    input.down(1, 200.0, 200.0, 0.0);
+    input.down(2, 250.0, 200.0, 0.01);
+    input.moved(2, 300.0, 200.0, 0.05);
+    assert_eq!(
+      input.log(),
+      vec!["pinch Began", "pan Began", "photo Cancel", "photo Cancel"]
+    );
+    let pinch = &input.photo().gesture_recognizers()[0];
+    assert_eq!(pinch.scale(), 2.0);
+    assert_eq!(pinch.pointer_count(), 2);
+    input.up(2, 300.0, 200.0, 0.06);
+    assert_eq!(input.log(), vec!["pinch Ended"]);
+    input.up(1, 200.0, 200.0, 0.07);
+    assert_eq!(input.log(), vec!["pan Ended"]);
+  }
+
+  #[test]
+  fn test_swipe_and_long_press() {
+    let mut input = Input::new(
+      vec![
+        GestureRecognizer::swipe("swipe"),
+        GestureRecognizer::long_press("press"),
+      ],
+      vec![],
+    );
+    input.down(1, 250.0, 200.0, 0.0);
+    input.moved(1, 200.0, 200.0, 0.05);
+    input.up(1, 150.0, 200.0, 0.1);
+    assert_eq!(
+      input.log(),
+      vec!["press Failed", "swipe Ended", "photo Cancel"]
+    );
+    let swipe = &input.photo().gesture_recognizers()[0];
+    assert_eq!(swipe.direction(), Some(SwipeDirection::Left));
+    assert!(swipe.velocity().0 < -SWIPE_MIN_VELOCITY);
+
+    // Holding still is a long press, which ends when the pointer goes up
+    input.down(1, 250.0, 200.0, 1.0);
+    input.update(1.3);
+    assert!(input.log().is_empty());
+    input.update(1.6);
+    assert_eq!(
+      input.log(),
+      vec!["swipe Failed", "press Began", "photo Cancel"]
+    );
+    input.moved(1, 260.0, 200.0, 1.7);
+    input.up(1, 260.0, 200.0, 1.8);
+    assert_eq!(input.log(), vec!["press Changed", "press Ended"]);
+  }
+}
@@ -0,0 +1,726 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::collections::HashMap;
+
+use ab_glyph::{point, Font, FontVec, Glyph};
+
+use crate::font::FontRegistry;
+use crate::layer::Vertex;
+use crate::text::{Text, TextRenderMode};
+
+/// Width and height of the glyph atlas texture
+pub const ATLAS_SIZE: u32 = 1024;
+
+// Glyphs are rasterized at quarter pixel horizontal offsets
+const SUBPIXEL_STEPS: f32 = 4.0;
+// Empty pixels around each glyph so that linear filtering doesn't bleed
+const GLYPH_PADDING: u32 = 1;
+
+/// Size in pixels at which signed distance fields of glyphs are rendered
+pub const SDF_GLYPH_SIZE: f32 = 32.0;
+/// Distance in `SDF_GLYPH_SIZE` pixels covered by a signed distance field on
+/// each side of the outline. Outlines and glows can reach up to
+/// `font_size * SDF_SPREAD / SDF_GLYPH_SIZE` pixels from the glyphs.
+pub const SDF_SPREAD: f32 = 8.0;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct GlyphKey {
+  font: String,
+  glyph: u16,
+  size: u32,     // font size in 1/64 px
+  subpixel: u32, // horizontal offset in 1/SUBPIXEL_STEPS px
+  sdf: bool,
+}
+
+/// Location of a rasterized glyph in the atlas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasGlyph {
+  pub uv_min: [f32; 2],
+  pub uv_max: [f32; 2],
+  pub offset: [f32; 2], // top left corner relative to the glyph origin
+  pub size: [f32; 2],
+}
+
+/// Glyphs rasterized once with ab_glyph into a single-channel texture shared
+/// by all text layers that use `TextRenderMode::GlyphAtlas`.
+///
+/// Glyphs are packed into shelves. When the atlas is full it is cleared and
+/// `generation()` changes, so text meshes built before must be built again.
+pub struct GlyphAtlas {
+  size: u32,
+  pixels: Vec<u8>,
+  glyphs: HashMap<GlyphKey, Option<AtlasGlyph>>,
+  shelf_x: u32,
+  shelf_y: u32,
+  shelf_height: u32,
+  generation: u64,
+  dirty_rows: Option<(u32, u32)>,
+  texture: Option<wgpu::Texture>,
+  bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Default for GlyphAtlas {
+  fn default() -> Self {
+    Self::new(ATLAS_SIZE)
+  }
+}
+
+impl GlyphAtlas {
+  pub fn new(size: u32) -> Self {
+    GlyphAtlas {
+      size,
+      pixels: vec![0; (size * size) as usize],
+      glyphs: HashMap::new(),
+      shelf_x: 0,
+      shelf_y: 0,
+      shelf_height: 0,
+      generation: 0,
+      dirty_rows: None,
+      texture: None,
+      bind_group: None,
+    }
+  }
+
+  pub fn size(&self) -> u32 {
+    self.size
+  }
+
+  /// Changes every time the atlas is cleared
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  /// Number of glyphs in the atlas, including empty ones like spaces
+  pub fn glyph_count(&self) -> usize {
+    self.glyphs.len()
+  }
+
+  /// Coverage of the pixel at (x, y)
+  pub fn pixel(&self, x: u32, y: u32) -> u8 {
+    self.pixels[(y * self.size + x) as usize]
+  }
+
+  /// Remove all glyphs
+  pub fn clear(&mut self) {
+    self.pixels.fill(0);
+    self.glyphs.clear();
+    self.shelf_x = 0;
+    self.shelf_y = 0;
+    self.shelf_height = 0;
+    self.generation += 1;
+    self.dirty_rows = Some((0, self.size));
+  }
+
+  /// Get a glyph positioned at `glyph.position` (the baseline origin), rasterizing
+  /// it on first use. Returns the atlas entry and the integer pixel origin the entry
+  /// is relative to, or None for glyphs without an outline or too large for the atlas.
+  /// The atlas is cleared when it runs out of space.
+  pub fn glyph(
+    &mut self,
+    font_name: &str,
+    font: &FontVec,
+    glyph: &Glyph,
+  ) -> Option<(AtlasGlyph, [f32; 2])> {
+    let origin_x = glyph.position.x.floor();
+    let origin_y = glyph.position.y.round();
+    let subpixel = ((glyph.position.x - origin_x) * SUBPIXEL_STEPS).floor() as u32;
+    let key = GlyphKey {
+      font: font_name.to_string(),
+      glyph: glyph.id.0,
+      size: (glyph.scale.y * 64.0).round() as u32,
+      subpixel,
+      sdf: false,
+    };
+    let origin = [origin_x, origin_y];
+
+    if let Some(entry) = self.glyphs.get(&key) {
+      return entry.map(|entry| (entry, origin));
+    }
+
+    let mut positioned = glyph.clone();
+    positioned.position = point(subpixel as f32 / SUBPIXEL_STEPS, 0.0);
+    let Some(outlined) = font.outline_glyph(positioned) else {
+      self.glyphs.insert(key, None);
+      return None;
+    };
+    let bounds = outlined.px_bounds();
+    let width = bounds.width() as u32;
+    let height = bounds.height() as u32;
+    let mut pixels = vec![0; (width * height) as usize];
+    outlined.draw(|x, y, coverage| {
+      if x < width && y < height {
+        pixels[(y * width + x) as usize] = (coverage.min(1.0) * 255.0).round() as u8;
+      }
+    });
+
+    let entry = self.insert(key, width, height, &pixels, [bounds.min.x, bounds.min.y])?;
+    Some((entry, origin))
+  }
+
+  /// Get the signed distance field of a glyph, rendered once at `SDF_GLYPH_SIZE`
+  /// for every font size. Returns the atlas entry in `SDF_GLYPH_SIZE` pixels and
+  /// the scale to the font size of `glyph`. Texels are 0.5 on the outline and
+  /// change by 0.5 over `SDF_SPREAD` pixels inside and outside.
+  pub fn sdf_glyph(
+    &mut self,
+    font_name: &str,
+    font: &FontVec,
+    glyph: &Glyph,
+  ) -> Option<(AtlasGlyph, f32)> {
+    let scale = glyph.scale.y / SDF_GLYPH_SIZE;
+    let key = GlyphKey {
+      font: font_name.to_string(),
+      glyph: glyph.id.0,
+      size: (SDF_GLYPH_SIZE * 64.0) as u32,
+      subpixel: 0,
+      sdf: true,
+    };
+    if let Some(entry) = self.glyphs.get(&key) {
+      return entry.map(|entry| (entry, scale));
+    }
+
+    let base = glyph
+      .id
+      .with_scale_and_position(SDF_GLYPH_SIZE, point(0.0, 0.0));
+    let Some(outlined) = font.outline_glyph(base) else {
+      self.glyphs.insert(key, None);
+      return None;
+    };
+    let bounds = outlined.px_bounds();
+    let spread = SDF_SPREAD as u32;
+    let width = bounds.width() as u32 + spread * 2;
+    let height = bounds.height() as u32 + spread * 2;
+    let mut coverage = vec![0.0; (width * height) as usize];
+    outlined.draw(|x, y, c| {
+      coverage[((y + spread) * width + x + spread) as usize] = c.min(1.0);
+    });
+    let pixels = distance_field(&coverage, width as usize, height as usize, SDF_SPREAD);
+
+    let offset = [bounds.min.x - SDF_SPREAD, bounds.min.y - SDF_SPREAD];
+    let entry = self.insert(key, width, height, &pixels, offset)?;
+    Some((entry, scale))
+  }
+
+  // Copy a glyph bitmap into free space, clearing the atlas if there is none
+  fn insert(
+    &mut self,
+    key: GlyphKey,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+    offset: [f32; 2],
+  ) -> Option<AtlasGlyph> {
+    let (x, y) = match self.allocate(width, height) {
+      Some(position) => position,
+      None => {
+        if width + GLYPH_PADDING * 2 > self.size || height + GLYPH_PADDING * 2 > self.size {
+          return None;
+        }
+        self.clear();
+        self.allocate(width, height)?
+      }
+    };
+
+    for row in 0..height {
+      let start = ((y + row) * self.size + x) as usize;
+      let source = (row * width) as usize;
+      self.pixels[start..start + width as usize]
+        .copy_from_slice(&pixels[source..source + width as usize]);
+    }
+    self.mark_dirty(y, y + height);
+
+    let size = self.size as f32;
+    let entry = AtlasGlyph {
+      uv_min: [x as f32 / size, y as f32 / size],
+      uv_max: [(x + width) as f32 / size, (y + height) as f32 / size],
+      offset,
+      size: [width as f32, height as f32],
+    };
+    self.glyphs.insert(key, Some(entry));
+    Some(entry)
+  }
+
+  // Find space for a glyph in the current shelf or start a new one
+  fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+    let padded_width = width + GLYPH_PADDING * 2;
+    let padded_height = height + GLYPH_PADDING * 2;
+    if self.shelf_x + padded_width > self.size {
+      self.shelf_y += self.shelf_height;
+      self.shelf_x = 0;
+      self.shelf_height = 0;
+    }
+    if self.shelf_y + padded_height > self.size || padded_width > self.size {
+      return None;
+    }
+
+    let position = (self.shelf_x + GLYPH_PADDING, self.shelf_y + GLYPH_PADDING);
+    self.shelf_x += padded_width;
+    self.shelf_height = self.shelf_height.max(padded_height);
+    Some(position)
+  }
+
+  fn mark_dirty(&mut self, start: u32, end: u32) {
+    self.dirty_rows = Some(match self.dirty_rows {
+      Some((dirty_start, dirty_end)) => (dirty_start.min(start), dirty_end.max(end)),
+      None => (start, end),
+    });
+  }
+
+  /// Copy the rows changed since the last upload to the atlas texture
+  pub(crate) fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+    if self.texture.is_none() {
+      self.texture = Some(device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Glyph Atlas Texture"),
+        size: wgpu::Extent3d {
+          width: self.size,
+          height: self.size,
+          depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+      }));
+      self.dirty_rows = Some((0, self.size));
+    }
+
+    let (Some(texture), Some((start, end))) = (&self.texture, self.dirty_rows.take()) else {
+      return;
+    };
+    queue.write_texture(
+      wgpu::ImageCopyTexture {
+        texture,
+        mip_level: 0,
+        origin: wgpu::Origin3d {
+          x: 0,
+          y: start,
+          z: 0,
+        },
+        aspect: wgpu::TextureAspect::All,
+      },
+      &self.pixels[(start * self.size) as usize..(end * self.size) as usize],
+      wgpu::ImageDataLayout {
+        offset: 0,
+        bytes_per_row: Some(self.size),
+        rows_per_image: Some(end - start),
+      },
+      wgpu::Extent3d {
+        width: self.size,
+        height: end - start,
+        depth_or_array_layers: 1,
+      },
+    );
+  }
+
+  /// Texture bind group of the atlas, created after the first upload
+  pub(crate) fn bind_group(
+    &mut self,
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+  ) -> Option<&wgpu::BindGroup> {
+    if self.bind_group.is_none() {
+      let view = self
+        .texture
+        .as_ref()?
+        .create_view(&wgpu::TextureViewDescriptor::default());
+      self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Glyph Atlas Bind Group"),
+        layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(sampler),
+          },
+        ],
+      }));
+    }
+    self.bind_group.as_ref()
+  }
+}
+
+/// Signed distance field of a coverage bitmap, encoded as 0.5 on the edge and
+/// changing by 0.5 over `spread` pixels. Uses the exact Euclidean distance
+/// transform of Felzenszwalb and Huttenlocher, with partial coverage used as
+/// a sub-pixel offset of the edge.
+fn distance_field(coverage: &[f32], width: usize, height: usize, spread: f32) -> Vec<u8> {
+  const INF: f64 = 1e20;
+  // Squared distances to the nearest pixel outside and inside the glyph
+  let mut outer = vec![0.0; coverage.len()];
+  let mut inner = vec![0.0; coverage.len()];
+  for (i, &c) in coverage.iter().enumerate() {
+    if c >= 1.0 {
+      outer[i] = 0.0;
+      inner[i] = INF;
+    } else if c <= 0.0 {
+      outer[i] = INF;
+      inner[i] = 0.0;
+    } else {
+      let d = 0.5 - c as f64;
+      outer[i] = if d > 0.0 { d * d } else { 0.0 };
+      inner[i] = if d < 0.0 { d * d } else { 0.0 };
+    }
+  }
+  edt(&mut outer, width, height);
+  edt(&mut inner, width, height);
+
+  outer
+    .iter()
+    .zip(inner.iter())
+    .map(|(o, i)| {
+      let distance = (o.sqrt() - i.sqrt()) as f32;
+      ((0.5 - distance / (2.0 * spread)).clamp(0.0, 1.0) * 255.0).round() as u8
+    })
+    .collect()
+}
+
+// 2D squared Euclidean distance transform, columns then rows
+fn edt(grid: &mut [f64], width: usize, height: usize) {
+  let mut line = vec![0.0; width.max(height)];
+  for x in 0..width {
+    for y in 0..height {
+      line[y] = grid[y * width + x];
+    }
+    let result = edt_1d(&line[..height]);
+    for y in 0..height {
+      grid[y * width + x] = result[y];
+    }
+  }
+  for y in 0..height {
+    let row = &mut grid[y * width..(y + 1) * width];
+    let result = edt_1d(row);
+    row.copy_from_slice(&result);
+  }
+}
+
+// Lower envelope of the parabolas rooted at each sample
+fn edt_1d(f: &[f64]) -> Vec<f64> {
+  let n = f.len();
+  let mut d = vec![0.0; n];
+  if n == 0 {
+    return d;
+  }
+  let mut v = vec![0usize; n];
+  let mut z = vec![0.0f64; n + 1];
+  let mut k = 0;
+  z[0] = f64::NEG_INFINITY;
+  z[1] = f64::INFINITY;
+  for q in 1..n {
+    let intersection = |r: usize| {
+      ((f[q] + (q * q) as f64) - (f[r] + (r * r) as f64)) / (2.0 * (q as f64 - r as f64))
+    };
+    // z[0] is -inf, so k never goes below 0
+    let mut s = intersection(v[k]);
+    while s <= z[k] {
+      k -= 1;
+      s = intersection(v[k]);
+    }
+    k += 1;
+    v[k] = q;
+    z[k] = s;
+    z[k + 1] = f64::INFINITY;
+  }
+  k = 0;
+  for (q, distance) in d.iter_mut().enumerate() {
+    while z[k + 1] < q as f64 {
+      k += 1;
+    }
+    let r = v[k];
+    *distance = (q as f64 - r as f64).powi(2) + f[r];
+  }
+  d
+}
+
+/// Quads of the glyphs of a text, two triangles per glyph
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextMesh {
+  pub vertices: Vec<Vertex>,
+  pub indices: Vec<u32>,
+}
+
+impl TextMesh {
+  /// Layout `text` in a `width` x `height` box and add its glyphs to the atlas.
+  /// Returns None if the font can't be loaded.
+  pub fn build(
+    text: &Text,
+    width: f32,
+    height: f32,
+    fonts: &FontRegistry,
+    atlas: &mut GlyphAtlas,
+  ) -> Option<TextMesh> {
+    let fonts = text.fonts(fonts)?;
+    let glyphs = text.layout_with_fonts(&fonts, width, height);
+    let mut mesh = TextMesh::default();
+    for attempt in 0..2 {
+      mesh = TextMesh::default();
+      let mut generation = atlas.generation();
+      let mut restart = false;
+      for (index, glyph) in &glyphs {
+        // Entries are keyed by the name of the font that has the glyph
+        let (name, font) = (fonts.name(*index), fonts.font(*index));
+        let quad = match text.render_mode {
+          TextRenderMode::Sdf => atlas
+            .sdf_glyph(name, font, glyph)
+            .map(|(entry, scale)| (entry, [glyph.position.x, glyph.position.y], scale)),
+          _ => atlas
+            .glyph(name, font, glyph)
+            .map(|(entry, origin)| (entry, origin, 1.0)),
+        };
+        let Some((entry, origin, scale)) = quad else {
+          continue;
+        };
+        // Glyphs added before the atlas was cleared are gone. Start over once;
+        // if the text doesn't fit even in an empty atlas, keep the glyphs added
+        // since the last clear.
+        if atlas.generation() != generation {
+          if attempt == 0 {
+            restart = true;
+            break;
+          }
+          mesh = TextMesh::default();
+          generation = atlas.generation();
+        }
+        mesh.push_quad(&entry, origin, scale);
+      }
+      if !restart {
+        break;
+      }
+    }
+    Some(mesh)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.indices.is_empty()
+  }
+
+  // `scale` maps the atlas entry to the font size, 1.0 for coverage glyphs
+  fn push_quad(&mut self, glyph: &AtlasGlyph, origin: [f32; 2], scale: f32) {
+    let x0 = origin[0] + glyph.offset[0] * scale;
+    let y0 = origin[1] + glyph.offset[1] * scale;
+    let x1 = x0 + glyph.size[0] * scale;
+    let y1 = y0 + glyph.size[1] * scale;
+    let base = self.vertices.len() as u32;
+    let corners = [
+      ([x0, y0], [glyph.uv_min[0], glyph.uv_min[1]]),
+      ([x1, y0], [glyph.uv_max[0], glyph.uv_min[1]]),
+      ([x1, y1], [glyph.uv_max[0], glyph.uv_max[1]]),
+      ([x0, y1], [glyph.uv_min[0], glyph.uv_max[1]]),
+    ];
+    for (position, tex_coords) in corners {
+      self.vertices.push(Vertex {
+        position: [position[0], position[1], 0.0],
+        tex_coords,
+      });
+    }
+    self
+      .indices
+      .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+  }
+}
+
+/// GPU buffers of a text mesh. The buffers are reused while the text fits in them,
+/// so labels that change every frame don't allocate.
+pub(crate) struct TextMeshBuffers {
+  text: Text,
+  width: u32,
+  height: u32,
+  generation: u64,
+  pub(crate) vertex_buffer: wgpu::Buffer,
+  pub(crate) index_buffer: wgpu::Buffer,
+  capacity: usize, // in glyphs
+  pub(crate) index_count: u32,
+}
+
+impl TextMeshBuffers {
+  /// Returns true if the buffers hold `text` laid out with the current atlas
+  pub(crate) fn is_current(&self, text: &Text, width: u32, height: u32, generation: u64) -> bool {
+    self.text == *text
+      && self.width == width
+      && self.height == height
+      && self.generation == generation
+  }
+
+  /// Write the mesh into `buffers`, reallocating them only if it doesn't fit
+  pub(crate) fn update(
+    buffers: Option<TextMeshBuffers>,
+    mesh: &TextMesh,
+    text: &Text,
+    size: (u32, u32),
+    generation: u64,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+  ) -> TextMeshBuffers {
+    let glyph_count = mesh.vertices.len() / 4;
+    let mut buffers = match buffers {
+      Some(buffers) if buffers.capacity >= glyph_count => buffers,
+      _ => Self::allocate(glyph_count.max(1).next_power_of_two(), device, text),
+    };
+    queue.write_buffer(
+      &buffers.vertex_buffer,
+      0,
+      bytemuck::cast_slice(&mesh.vertices),
+    );
+    queue.write_buffer(
+      &buffers.index_buffer,
+      0,
+      bytemuck::cast_slice(&mesh.indices),
+    );
+    buffers.text = text.clone();
+    buffers.width = size.0;
+    buffers.height = size.1;
+    buffers.generation = generation;
+    buffers.index_count = mesh.indices.len() as u32;
+    buffers
+  }
+
+  fn allocate(capacity: usize, device: &wgpu::Device, text: &Text) -> TextMeshBuffers {
+    let vertex_size = std::mem::size_of::<Vertex>() as u64;
+    TextMeshBuffers {
+      text: text.clone(),
+      width: 0,
+      height: 0,
+      generation: 0,
+      vertex_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Text Vertex Buffer"),
+        size: capacity as u64 * 4 * vertex_size,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+      }),
+      index_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Text Index Buffer"),
+        size: capacity as u64 * 6 * 4,
+        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+      }),
+      capacity,
+      index_count: 0,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_glyphs_are_shared() {
+    let fonts = FontRegistry::new();
+    let mut atlas = GlyphAtlas::new(256);
+    let text = Text::new("abab").with_font_size(20.0);
+    let mesh = TextMesh::build(&text, 200.0, 50.0, &fonts, &mut atlas).unwrap();
+    assert_eq!(mesh.vertices.len(), 16);
+    assert_eq!(mesh.indices.len(), 24);
+    // At most one entry per glyph and subpixel offset
+    assert!(atlas.glyph_count() <= 2 * SUBPIXEL_STEPS as usize);
+
+    // Building the same text again adds nothing
+    let count = atlas.glyph_count();
+    let again = TextMesh::build(&text, 200.0, 50.0, &fonts, &mut atlas).unwrap();
+    assert_eq!(again, mesh);
+    assert_eq!(atlas.glyph_count(), count);
+
+    // Spaces have no quad
+    let spaced = Text::new("a b").with_font_size(20.0);
+    let mesh = TextMesh::build(&spaced, 200.0, 50.0, &fonts, &mut atlas).unwrap();
+    assert_eq!(mesh.vertices.len(), 8);
+  }
+
+  #[test]
+  fn test_quads_sample_glyph_coverage() {
+    let fonts = FontRegistry::new();
+    let mut atlas = GlyphAtlas::new(256);
+    let text = Text::new("H").with_font_size(40.0);
+    let mesh = TextMesh::build(&text, 200.0, 60.0, &fonts, &mut atlas).unwrap();
+    let [top_left, _, bottom_right, _] = [0, 1, 2, 3].map(|i| mesh.vertices[i]);
+    assert!(bottom_right.position[0] > top_left.position[0]);
+    assert!(bottom_right.position[1] > top_left.position[1]);
+
+    let size = atlas.size() as f32;
+    let x0 = (top_left.tex_coords[0] * size) as u32;
+    let y0 = (top_left.tex_coords[1] * size) as u32;
+    let x1 = (bottom_right.tex_coords[0] * size) as u32;
+    let y1 = (bottom_right.tex_coords[1] * size) as u32;
+    // The left stem of H is fully covered
+    let stem = (y0..y1).filter(|&y| atlas.pixel(x0 + 2, y) == 255).count();
+    assert!(stem as u32 > (y1 - y0) / 2);
+    // Nothing is drawn into the padding
+    assert!((y0..y1).all(|y| atlas.pixel(x1, y) == 0));
+  }
+
+  #[test]
+  fn test_distance_field() {
+    // A 10x10 square in the middle of a 30x30 bitmap
+    let mut coverage = vec![0.0; 30 * 30];
+    for y in 10..20 {
+      for x in 10..20 {
+        coverage[y * 30 + x] = 1.0;
+      }
+    }
+    let field = distance_field(&coverage, 30, 30, 8.0);
+    let value = |x: usize, y: usize| field[y * 30 + x] as f32 / 255.0;
+    // 5 pixels inside the edge at the center, 4 pixels outside on the left
+    assert!((value(15, 15) - (0.5 + 5.0 / 16.0)).abs() < 0.01);
+    assert!((value(6, 15) - (0.5 - 4.0 / 16.0)).abs() < 0.01);
+    // Corners are clamped beyond the spread
+    assert_eq!(field[0], 0);
+    // The field decreases monotonically away from the square
+    assert!((1..10).all(|x| value(x, 15) >= value(x - 1, 15)));
+  }
+
+  #[test]
+  fn test_sdf_glyphs_scale_with_font_size() {
+    let fonts = FontRegistry::new();
+    let mut atlas = GlyphAtlas::new(256);
+    let small = Text::new("H")
+      .with_font_size(20.0)
+      .with_render_mode(TextRenderMode::Sdf);
+    let large = small.clone().with_font_size(80.0);
+    let small_mesh = TextMesh::build(&small, 400.0, 200.0, &fonts, &mut atlas).unwrap();
+    let large_mesh = TextMesh::build(&large, 400.0, 200.0, &fonts, &mut atlas).unwrap();
+    // One distance field serves every size
+    assert_eq!(atlas.glyph_count(), 1);
+    assert_eq!(
+      small_mesh.vertices[0].tex_coords,
+      large_mesh.vertices[0].tex_coords
+    );
+
+    let width = |mesh: &TextMesh| mesh.vertices[1].position[0] - mesh.vertices[0].position[0];
+    assert!((width(&large_mesh) - 4.0 * width(&small_mesh)).abs() < 0.01);
+
+    // The stem of H is inside the glyph, the padding is outside
+    let size = atlas.size() as f32;
+    let top_left = small_mesh.vertices[0].tex_coords;
+    let x = (top_left[0] * size) as u32;
+    let y = (top_left[1] * size) as u32;
+    let stem_x = x + SDF_SPREAD as u32 + 2;
+    let middle_y = y + (small_mesh.vertices[2].tex_coords[1] * size - y as f32) as u32 / 2;
+    assert!(atlas.pixel(stem_x, middle_y) > 128);
+    assert!(atlas.pixel(x, y) < 64);
+  }
+
+  #[test]
+  fn test_atlas_clears_when_full() {
+    let fonts = FontRegistry::new();
+    let mut atlas = GlyphAtlas::new(64);
+    let text = Text::new("abcdefghijklmnopqrstuvwxyz").with_font_size(24.0);
+    let mesh = TextMesh::build(&text, 2000.0, 50.0, &fonts, &mut atlas).unwrap();
+    // The text doesn't fit in a tiny atlas at once; the glyphs added since the
+    // last clear are kept
+    assert!(atlas.generation() > 0);
+    assert!(!mesh.is_empty());
+    assert!(mesh.vertices.len() < 26 * 4);
+
+    let mut atlas = GlyphAtlas::new(64);
+    let short = Text::new("ab").with_font_size(24.0);
+    TextMesh::build(&short, 200.0, 50.0, &fonts, &mut atlas).unwrap();
+    assert_eq!(atlas.generation(), 0);
+  }
+}
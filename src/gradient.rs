@@ -0,0 +1,153 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+/// Maximum number of color stops the shader can evaluate
+pub const MAX_GRADIENT_STOPS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GradientType {
+  Linear,
+  Radial,
+  Conic,
+}
+
+/// Gradient content of a layer (CoreAnimation-style CAGradientLayer).
+///
+/// Points are in the unit coordinate space of the layer, where (0, 0) is the top left
+/// and (1, 1) the bottom right corner.
+/// - Linear: the gradient runs from `start_point` to `end_point`.
+/// - Radial: `start_point` is the center and `end_point` lies on the outer circle.
+/// - Conic: the gradient sweeps clockwise around `start_point`, beginning in the
+///   direction of `end_point`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+  pub gradient_type: GradientType,
+  pub colors: Vec<[f32; 4]>,
+  // Stop locations between 0.0 and 1.0, one per color. Evenly spaced if empty.
+  pub locations: Vec<f32>,
+  pub start_point: (f32, f32),
+  pub end_point: (f32, f32),
+}
+
+impl Gradient {
+  pub fn new(gradient_type: GradientType, colors: Vec<[f32; 4]>) -> Self {
+    let (start_point, end_point) = match gradient_type {
+      GradientType::Linear => ((0.5, 0.0), (0.5, 1.0)),
+      GradientType::Radial | GradientType::Conic => ((0.5, 0.5), (1.0, 0.5)),
+    };
+
+    Gradient {
+      gradient_type,
+      colors,
+      locations: Vec::new(),
+      start_point,
+      end_point,
+    }
+  }
+
+  /// Vertical linear gradient from top to bottom
+  pub fn linear(colors: Vec<[f32; 4]>) -> Self {
+    Gradient::new(GradientType::Linear, colors)
+  }
+
+  /// Radial gradient from the center to the edges
+  pub fn radial(colors: Vec<[f32; 4]>) -> Self {
+    Gradient::new(GradientType::Radial, colors)
+  }
+
+  /// Conic gradient around the center, starting at 3 o'clock
+  pub fn conic(colors: Vec<[f32; 4]>) -> Self {
+    Gradient::new(GradientType::Conic, colors)
+  }
+
+  pub fn with_locations(mut self, locations: Vec<f32>) -> Self {
+    self.locations = locations;
+    self
+  }
+
+  pub fn with_points(mut self, start_point: (f32, f32), end_point: (f32, f32)) -> Self {
+    self.start_point = start_point;
+    self.end_point = end_point;
+    self
+  }
+
+  /// Colors and locations of the stops as evaluated by the shader: at most
+  /// `MAX_GRADIENT_STOPS`, with locations clamped to 0..1 and never decreasing.
+  pub fn stops(&self) -> Vec<([f32; 4], f32)> {
+    let count = self.colors.len().min(MAX_GRADIENT_STOPS);
+    let use_locations = self.locations.len() == self.colors.len();
+
+    let mut stops: Vec<([f32; 4], f32)> = Vec::with_capacity(count);
+    for (i, color) in self.colors.iter().take(count).enumerate() {
+      let mut location = if use_locations {
+        self.locations[i].clamp(0.0, 1.0)
+      } else if count > 1 {
+        i as f32 / (count - 1) as f32
+      } else {
+        0.0
+      };
+      if let Some(&(_, previous)) = stops.last() {
+        location = location.max(previous);
+      }
+      stops.push((*color, location));
+    }
+
+    stops
+  }
+
+  pub(crate) fn shader_type(&self) -> u32 {
+    match self.gradient_type {
+      GradientType::Linear => 1,
+      GradientType::Radial => 2,
+      GradientType::Conic => 3,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+  const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+  const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+  #[test]
+  fn test_evenly_spaced_stops() {
+    let gradient = Gradient::linear(vec![RED, GREEN, BLUE]);
+    let locations: Vec<f32> = gradient.stops().iter().map(|stop| stop.1).collect();
+    assert_eq!(locations, vec![0.0, 0.5, 1.0]);
+  }
+
+  #[test]
+  fn test_explicit_locations_are_clamped_and_sorted() {
+    let gradient = Gradient::radial(vec![RED, GREEN, BLUE]).with_locations(vec![-0.5, 0.8, 0.3]);
+    let locations: Vec<f32> = gradient.stops().iter().map(|stop| stop.1).collect();
+    assert_eq!(locations, vec![0.0, 0.8, 0.8]);
+  }
+
+  #[test]
+  fn test_mismatched_locations_fall_back_to_even_spacing() {
+    let gradient = Gradient::conic(vec![RED, BLUE]).with_locations(vec![0.2]);
+    let locations: Vec<f32> = gradient.stops().iter().map(|stop| stop.1).collect();
+    assert_eq!(locations, vec![0.0, 1.0]);
+  }
+
+  #[test]
+  fn test_stops_are_limited() {
+    let gradient = Gradient::linear(vec![RED; MAX_GRADIENT_STOPS + 4]);
+    assert_eq!(gradient.stops().len(), MAX_GRADIENT_STOPS);
+  }
+
+  #[test]
+  fn test_default_points() {
+    let linear = Gradient::linear(vec![RED, BLUE]);
+    assert_eq!(linear.start_point, (0.5, 0.0));
+    assert_eq!(linear.end_point, (0.5, 1.0));
+
+    let radial = Gradient::radial(vec![RED, BLUE]).with_points((0.0, 0.0), (1.0, 1.0));
+    assert_eq!(radial.start_point, (0.0, 0.0));
+    assert_eq!(radial.end_point, (1.0, 1.0));
+  }
+}
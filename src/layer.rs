@@ -5,9 +5,10 @@
 extern crate image;
 extern crate keyframe;
 
-use cgmath::{Deg, Matrix4, SquareMatrix, Vector3};
-use image::DynamicImage;
+use cgmath::{Deg, Matrix4, SquareMatrix, Vector3, Vector4};
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::Instant;
 
 use stretch::{
   node::{Node, Stretch},
@@ -15,20 +16,168 @@ use stretch::{
 };
 
 use crate::animation::Animation;
-use crate::font::FontRenderer;
-
-#[repr(i32)]
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum Key {
-  Space = 32,
-  Enter = 36,
-  Tab = 48,
-  Backspace = 51,
-  Escape = 53,
-  Right = 262,
-  Left = 263,
-  Down = 264,
-  Up = 265,
+use crate::event::{EventPhase, KeyEvent, KeyEventKind};
+use crate::focus::FocusDirection;
+use crate::font::{FontError, FontRegistry};
+use crate::gesture::GestureRecognizer;
+use crate::glyph_atlas::{GlyphAtlas, TextMesh, TextMeshBuffers, SDF_GLYPH_SIZE, SDF_SPREAD};
+use crate::gradient::{Gradient, MAX_GRADIENT_STOPS};
+use crate::listener::{self, Listener, ListenerHandle, Listeners};
+use crate::play::MaskTarget;
+use crate::pointer::{rounded_bounds_contain, unproject, PointerEvent, PointerEventKind};
+use crate::shape::{Shape, ShapeBuffers};
+use crate::text::{Text, TextCache, TextRenderMode};
+use crate::text_field::TextField;
+
+// Declares `Key` with its variants, so that `Key::ALL` and the names of the keys
+// can't miss one
+macro_rules! keys {
+  ($($key:ident,)*) => {
+    /// A key of a keyboard or a remote control, independent of the windowing
+    /// system. Letters and digits are the keys, not the characters they type:
+    /// typed text goes to `Play::handle_text_input`.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub enum Key {
+      $($key,)*
+    }
+
+    impl Key {
+      /// Every key, in declaration order
+      pub const ALL: [Key; [$(stringify!($key)),*].len()] = [$(Key::$key),*];
+
+      /// The name of the variant, e.g. "PageUp"
+      pub fn name(self) -> &'static str {
+        match self {
+          $(Key::$key => stringify!($key),)*
+        }
+      }
+    }
+  };
+}
+
+keys! {
+  A,
+  B,
+  C,
+  D,
+  E,
+  F,
+  G,
+  H,
+  I,
+  J,
+  K,
+  L,
+  M,
+  N,
+  O,
+  P,
+  Q,
+  R,
+  S,
+  T,
+  U,
+  V,
+  W,
+  X,
+  Y,
+  Z,
+  Digit0,
+  Digit1,
+  Digit2,
+  Digit3,
+  Digit4,
+  Digit5,
+  Digit6,
+  Digit7,
+  Digit8,
+  Digit9,
+  F1,
+  F2,
+  F3,
+  F4,
+  F5,
+  F6,
+  F7,
+  F8,
+  F9,
+  F10,
+  F11,
+  F12,
+  Space,
+  Enter,
+  Tab,
+  Backspace,
+  Escape,
+  Insert,
+  Delete,
+  Home,
+  End,
+  PageUp,
+  PageDown,
+  Right,
+  Left,
+  Down,
+  Up,
+  Minus,
+  Equal,
+  BracketLeft,
+  BracketRight,
+  Backslash,
+  Semicolon,
+  Quote,
+  Backquote,
+  Comma,
+  Period,
+  Slash,
+  Shift,
+  Control,
+  Alt,
+  Logo,
+  CapsLock,
+  // Media and TV remote keys
+  Back,
+  Menu,
+  PlayPause,
+  Play,
+  Pause,
+  Stop,
+  FastForward,
+  Rewind,
+  NextTrack,
+  PreviousTrack,
+  VolumeUp,
+  VolumeDown,
+  Mute,
+  ChannelUp,
+  ChannelDown,
+  Info,
+  Guide,
+  Red,
+  Green,
+  Yellow,
+  Blue,
+}
+
+impl Key {
+  /// The key with a variant name, e.g. "PageUp"
+  pub fn from_name(name: &str) -> Option<Key> {
+    Key::ALL.into_iter().find(|key| key.name() == name)
+  }
+
+  /// Returns true for Shift, Control, Alt and Logo
+  pub fn is_modifier(self) -> bool {
+    matches!(self, Key::Shift | Key::Control | Key::Alt | Key::Logo)
+  }
+}
+
+/// Modifier keys held down while a key is pressed
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Modifiers {
+  pub shift: bool,
+  pub control: bool,
+  pub alt: bool,
+  pub logo: bool, // Command on macOS, Windows key on Windows
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -38,7 +187,7 @@ pub enum LayoutMode {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
   pub position: [f32; 3],
   pub tex_coords: [f32; 2],
@@ -65,6 +214,81 @@ impl Vertex {
   }
 }
 
+// Fragment shader modes, see `Uniforms::mode`.
+pub(crate) const SHADER_MODE_LAYER: u32 = 0;
+pub(crate) const SHADER_MODE_SHADOW: u32 = 1;
+pub(crate) const SHADER_MODE_SHAPE: u32 = 2;
+pub(crate) const SHADER_MODE_TEXT: u32 = 3;
+pub(crate) const SHADER_MODE_TEXT_SDF: u32 = 4;
+
+/// Per-draw uniforms, mirrors `Uniforms` in the WGSL shader.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct Uniforms {
+  pub transform: [[f32; 4]; 4],
+  pub projection: [[f32; 4]; 4],
+  pub color: [f32; 4],
+  pub border_color: [f32; 4],
+  pub size: [f32; 2],
+  pub corner_radius: f32,
+  pub border_width: f32,
+  // Vertex positions are mapped with `position * quad_scale + quad_offset`
+  pub quad_scale: [f32; 2],
+  pub quad_offset: [f32; 2],
+  pub shadow_offset: [f32; 2],
+  pub shadow_radius: f32,
+  pub use_texture: u32,
+  pub mode: u32,
+  // 0 when the layer has no gradient, see Gradient::shader_type()
+  pub gradient_type: u32,
+  pub gradient_stop_count: u32,
+  pub _padding: u32,
+  // start point in xy, end point in zw
+  pub gradient_points: [f32; 4],
+  pub gradient_colors: [[f32; 4]; MAX_GRADIENT_STOPS],
+  // Stop locations packed four per vector
+  pub gradient_locations: [[f32; 4]; MAX_GRADIENT_STOPS / 4],
+  // Signed distance field text: outline width, glow radius and the distance
+  // in layer pixels covered by one unit of the field
+  pub outline_color: [f32; 4],
+  pub glow_color: [f32; 4],
+  pub text_params: [f32; 4],
+}
+
+impl Uniforms {
+  /// Fill the gradient fields, or disable the gradient if there is none
+  fn set_gradient(&mut self, gradient: Option<&Gradient>) {
+    let Some(gradient) = gradient else {
+      self.gradient_type = 0;
+      return;
+    };
+
+    let stops = gradient.stops();
+    self.gradient_type = gradient.shader_type();
+    self.gradient_stop_count = stops.len() as u32;
+    self.gradient_points = [
+      gradient.start_point.0,
+      gradient.start_point.1,
+      gradient.end_point.0,
+      gradient.end_point.1,
+    ];
+    for (i, (color, location)) in stops.into_iter().enumerate() {
+      self.gradient_colors[i] = color;
+      self.gradient_locations[i / 4][i % 4] = location;
+    }
+  }
+
+  pub(crate) fn create_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+    use wgpu::util::DeviceExt;
+
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Uniform Buffer"),
+      contents: bytemuck::cast_slice(&[*self]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+  }
+}
+
 pub struct Layer {
   pub name: String,
   pub x: i32,
@@ -76,11 +300,36 @@ pub struct Layer {
   pub anchor_y: f32,
   pub scale_x: f32,
   pub scale_y: f32,
+  pub scale_z: f32,
   pub rotation: i32,
+  pub rotation_x: i32,
+  pub rotation_y: i32,
+  pub transform: Matrix4<f32>, // CoreAnimation-style 3D transform
+  pub sublayer_transform: Matrix4<f32>, // applied to sublayers around the anchor point
+  pub double_sided: bool,
   pub visible: bool,
   color: [f32; 3],
-  pub opacity: f32, // CoreAnimation-style property
+  pub opacity: f32,       // CoreAnimation-style property
+  pub corner_radius: f32, // CoreAnimation-style property
+  pub border_width: f32,  // CoreAnimation-style property
+  border_color: [f32; 3],
+  pub shadow_opacity: f32,       // CoreAnimation-style property
+  pub shadow_radius: f32,        // CoreAnimation-style property
+  pub shadow_offset: (f32, f32), // CoreAnimation-style property
+  shadow_color: [f32; 3],
+  pub masks_to_bounds: bool, // CoreAnimation-style property
+  pub(crate) mask: Option<Box<Layer>>,
+  pub(crate) mask_target: Option<MaskTarget>,
+  pub gradient: Option<Gradient>,
+  pub shape: Option<Shape>,
+  pub(crate) shape_buffers: Option<ShapeBuffers>,
+  pub text: Option<Text>,
+  text_cache: TextCache,
+  pub(crate) text_field: Option<TextField>,
+  pub(crate) text_mesh: Option<TextMeshBuffers>,
   pub image_path: String,
+  #[cfg(feature = "svg")]
+  image_scale: f32,
   pub sub_layer_list: Vec<Layer>,
   pub(crate) vertex_buffer: Option<wgpu::Buffer>,
   pub(crate) index_buffer: Option<wgpu::Buffer>,
@@ -91,9 +340,15 @@ pub struct Layer {
   pub animation: Option<Animation>,
   animations: std::collections::HashMap<String, Animation>, // CoreAnimation-style animations by key
   event_handler: Option<Box<dyn EventHandler>>,
+  pub(crate) gesture_recognizers: Vec<GestureRecognizer>,
   layout: Option<Box<dyn Layout>>,
   pub(crate) focused_sub_layer: usize,
   pub(crate) focused: bool,
+  pub(crate) focusable: bool, // a target of spatial focus navigation
+  pub(crate) focus_group: bool,
+  pub(crate) remembered_focus: Option<Vec<usize>>, // path of the layer focused last in the group
+  pub(crate) next_focus: HashMap<FocusDirection, String>,
+  pub(crate) listeners: Listeners,
   pub needs_update: bool,
   pub node: Option<Node>,   // for stretch only
   pub style: Option<Style>, // for stretch only
@@ -102,7 +357,38 @@ pub struct Layer {
 pub trait EventHandler {
   fn key_focus_in(&mut self, layer: &mut Layer);
   fn key_focus_out(&mut self, layer: &mut Layer);
-  fn key_down(&mut self, key: Key, layer: &mut Layer);
+  /// Returns true if the layer used the key, which marks the key event handled
+  /// so it doesn't move the focus
+  fn key_down(&mut self, key: Key, layer: &mut Layer) -> bool;
+  fn key_up(&mut self, _key: Key, _layer: &mut Layer) {}
+  /// Whether `key_down` gets the repeats of a held key
+  fn key_repeat(&self) -> bool {
+    true
+  }
+  /// A key event in its capture, target or bubble phase (see `crate::event`).
+  /// By default the key goes to `key_down` or `key_up` in the target and bubble
+  /// phases unless a layer handled it, and is handled if `key_down` used it.
+  fn key_event(&mut self, event: &mut KeyEvent, layer: &mut Layer) {
+    if event.phase == EventPhase::Capture || event.is_handled() {
+      return;
+    }
+    match event.kind {
+      KeyEventKind::Down if event.repeat && !self.key_repeat() => {}
+      KeyEventKind::Down => {
+        if self.key_down(event.key, layer) {
+          event.set_handled();
+        }
+      }
+      KeyEventKind::Up => self.key_up(event.key, layer),
+    }
+  }
+  /// Text typed while the layer is focused, after the IME composed it
+  fn text_input(&mut self, _text: &str, _layer: &mut Layer) {}
+  /// A pointer event for the topmost layer under the pointer or the layer that
+  /// captured the pointer
+  fn pointer_event(&mut self, _event: &PointerEvent, _layer: &mut Layer) {}
+  /// A state change of a gesture recognizer of the layer
+  fn gesture(&mut self, _gesture: &GestureRecognizer, _layer: &mut Layer) {}
 }
 
 pub trait Layout {
@@ -118,8 +404,8 @@ pub trait Layout {
 
 impl Layer {
   pub fn new(name: String, w: u32, h: u32, event_handler: Option<Box<dyn EventHandler>>) -> Self {
-    let layer = Layer {
-      name: name,
+    Layer {
+      name,
       x: 0,
       y: 0,
       z: 0.0,
@@ -129,11 +415,36 @@ impl Layer {
       anchor_y: 0.5,
       scale_x: 1.0,
       scale_y: 1.0,
+      scale_z: 1.0,
       rotation: 0,
+      rotation_x: 0,
+      rotation_y: 0,
+      transform: Matrix4::identity(),
+      sublayer_transform: Matrix4::identity(),
+      double_sided: true,
       visible: true,
       color: [1.0, 1.0, 1.0],
       opacity: 1.0,
+      corner_radius: 0.0,
+      border_width: 0.0,
+      border_color: [0.0, 0.0, 0.0],
+      shadow_opacity: 0.0,
+      shadow_radius: 3.0,
+      shadow_offset: (0.0, 3.0),
+      shadow_color: [0.0, 0.0, 0.0],
+      masks_to_bounds: false,
+      mask: None,
+      mask_target: None,
+      gradient: None,
+      shape: None,
+      shape_buffers: None,
+      text: None,
+      text_cache: TextCache::default(),
+      text_field: None,
+      text_mesh: None,
       image_path: "".to_string(),
+      #[cfg(feature = "svg")]
+      image_scale: 1.0,
       sub_layer_list: Vec::new(),
       vertex_buffer: None,
       index_buffer: None,
@@ -143,26 +454,25 @@ impl Layer {
       animated: false,
       animation: None,
       animations: std::collections::HashMap::new(),
-      event_handler: event_handler,
+      event_handler,
+      gesture_recognizers: Vec::new(),
       layout: None,
       focused_sub_layer: 0,
       focused: false,
+      focusable: false,
+      focus_group: false,
+      remembered_focus: None,
+      next_focus: HashMap::new(),
+      listeners: Listeners::default(),
       needs_update: true,
       node: None,
       style: None,
-    };
-
-    layer
+    }
   }
 
   #[allow(unused_variables)]
   pub fn init_buffers(&mut self, device: &wgpu::Device) {
     // Skip buffer initialization during tests
-    #[cfg(test)]
-    {
-      return;
-    }
-
     #[cfg(not(test))]
     {
       let vertices = [
@@ -205,26 +515,182 @@ impl Layer {
     }
   }
 
+  /// Tessellate the shape again if it changed since the buffers were created
+  pub(crate) fn update_shape_buffers(&mut self, device: &wgpu::Device) {
+    match self.shape {
+      Some(ref shape) => {
+        let outdated = match self.shape_buffers {
+          Some(ref buffers) => buffers.shape != *shape,
+          None => true,
+        };
+        if outdated {
+          self.shape_buffers = Some(ShapeBuffers::new(
+            device,
+            shape,
+            self.width as f32,
+            self.height as f32,
+          ));
+        }
+      }
+      None => self.shape_buffers = None,
+    }
+  }
+
   pub fn set_color(&mut self, r: f32, g: f32, b: f32) {
     self.color[0] = r;
     self.color[1] = g;
     self.color[2] = b;
   }
 
-  pub fn set_text(&mut self, text: &str, device: &wgpu::Device, queue: &wgpu::Queue) {
-    let mut font_renderer: FontRenderer = FontRenderer::new("fonts/DejaVuSans.ttf".to_string());
-    let image = font_renderer.render(text);
-    let dynamic_image = DynamicImage::ImageRgba8(image);
+  /// Render `text` in memory in the default text style with fonts from
+  /// `fonts`, e.g. `Play::font_registry()`, and resize the layer to fit it
+  pub fn set_text(
+    &mut self,
+    text: &str,
+    fonts: &FontRegistry,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+  ) -> Result<(), FontError> {
+    let text = Text::new(text);
+    fonts.load(&text.font)?;
+    let text_fonts = text
+      .fonts(fonts)
+      .ok_or_else(|| FontError::NotFound(text.font.clone()))?;
+    let metrics = text.measure_with(fonts, None).unwrap_or_default();
+    self.width = (metrics.width.ceil() as u32).max(1);
+    self.height = (metrics.height.ceil() as u32).max(1);
+    let image = text.rasterize_with_fonts(&text_fonts, self.width, self.height);
+    self.upload_texture(&image, "Text Texture", device, queue);
+    Ok(())
+  }
+
+  /// Set image path (for backward compatibility - actual texture loading requires wgpu context)
+  /// SVG images are rasterized at the size of the document.
+  pub fn set_image(&mut self, path: String) {
+    self.image_path = path;
+    #[cfg(feature = "svg")]
+    {
+      self.image_scale = 1.0;
+    }
+  }
+
+  /// Set an SVG image rasterized at `scale` times the size of the document,
+  /// e.g. 2.0 to keep it sharp on HiDPI displays or when the layer is scaled up
+  #[cfg(feature = "svg")]
+  pub fn set_svg_image(&mut self, path: String, scale: f32) {
+    self.image_path = path;
+    self.image_scale = scale;
+  }
+
+  fn decode_image(&self) -> Result<image::RgbaImage, String> {
+    #[cfg(feature = "svg")]
+    if crate::svg::is_svg_path(&self.image_path) {
+      return crate::svg::rasterize(&self.image_path, self.image_scale).map_err(|e| e.to_string());
+    }
+
+    // Use format auto-detection to handle images with incorrect extensions
+    image::ImageReader::open(Path::new(&self.image_path))
+      .and_then(|reader| reader.with_guessed_format())
+      .map_err(image::ImageError::IoError)
+      .and_then(|reader| reader.decode())
+      .map(|img| img.to_rgba8())
+      .map_err(|e| format!("{:?}", e))
+  }
+
+  /// Load image with wgpu context
+  pub fn load_image_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+    if !self.image_path.is_empty() {
+      match self.decode_image() {
+        Ok(rgba) => self.upload_texture(&rgba, "Image Texture", device, queue),
+        Err(err) => println!("Fail to load a image {}", err),
+      }
+    }
+  }
+
+  /// Render the text content again if it or the layer size changed
+  pub(crate) fn update_text_texture(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    fonts: &FontRegistry,
+  ) {
+    let focused = self.focused;
+    if let Some(field) = self.text_field.as_mut() {
+      if let Some(rgba) = field.render(self.width, self.height, focused, fonts) {
+        self.upload_texture(&rgba, "Text Field Texture", device, queue);
+      }
+      return;
+    }
+    let Some(text) = self.text.as_ref() else {
+      return;
+    };
+    if text.render_mode != TextRenderMode::Bitmap {
+      return;
+    }
+    if let Some(rgba) = self.text_cache.render(text, self.width, self.height, fonts) {
+      self.upload_texture(&rgba, "Text Texture", device, queue);
+    }
+  }
 
-    dynamic_image.save("temp.png").unwrap();
+  /// Returns true if the text is drawn with glyph quads from the glyph atlas
+  /// (the GlyphAtlas and Sdf render modes)
+  pub(crate) fn uses_glyph_atlas(&self) -> bool {
+    self
+      .text
+      .as_ref()
+      .is_some_and(|text| text.render_mode != TextRenderMode::Bitmap)
+  }
 
-    self.image_path = "temp".to_string();
-    self.width = dynamic_image.width();
-    self.height = dynamic_image.height();
+  /// Build the glyph quads of the text again if it, the layer size or the atlas
+  /// changed. Returns false if the atlas was cleared while adding glyphs, which
+  /// makes the meshes of other layers outdated.
+  pub(crate) fn update_text_mesh(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    fonts: &FontRegistry,
+    atlas: &mut GlyphAtlas,
+  ) -> bool {
+    let text = match self.text {
+      Some(ref text) if text.render_mode != TextRenderMode::Bitmap => text,
+      _ => {
+        self.text_mesh = None;
+        return true;
+      }
+    };
+    let generation = atlas.generation();
+    let size = (self.width, self.height);
+    if self
+      .text_mesh
+      .as_ref()
+      .is_some_and(|mesh| mesh.is_current(text, size.0, size.1, generation))
+    {
+      return true;
+    }
 
-    println!("width: {}, height: {}", self.width, self.height);
+    let Some(mesh) = TextMesh::build(text, size.0 as f32, size.1 as f32, fonts, atlas) else {
+      self.text_mesh = None;
+      return true;
+    };
+    self.text_mesh = Some(TextMeshBuffers::update(
+      self.text_mesh.take(),
+      &mesh,
+      text,
+      size,
+      atlas.generation(),
+      device,
+      queue,
+    ));
+    atlas.generation() == generation
+  }
 
-    let rgba = dynamic_image.to_rgba8();
+  fn upload_texture(
+    &mut self,
+    rgba: &image::RgbaImage,
+    label: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+  ) {
     let dimensions = rgba.dimensions();
 
     let texture_size = wgpu::Extent3d {
@@ -234,7 +700,7 @@ impl Layer {
     };
 
     let texture = device.create_texture(&wgpu::TextureDescriptor {
-      label: Some("Text Texture"),
+      label: Some(label),
       size: texture_size,
       mip_level_count: 1,
       sample_count: 1,
@@ -251,7 +717,7 @@ impl Layer {
         origin: wgpu::Origin3d::ZERO,
         aspect: wgpu::TextureAspect::All,
       },
-      &rgba,
+      rgba,
       wgpu::ImageDataLayout {
         offset: 0,
         bytes_per_row: Some(4 * dimensions.0),
@@ -263,67 +729,8 @@ impl Layer {
     let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
     self.texture = Some(texture);
     self.texture_view = Some(texture_view);
-  }
-
-  /// Set image path (for backward compatibility - actual texture loading requires wgpu context)
-  pub fn set_image(&mut self, path: String) {
-    self.image_path = path;
-  }
-
-  /// Load image with wgpu context
-  pub fn load_image_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
-    if !self.image_path.is_empty() {
-      // Use format auto-detection to handle images with incorrect extensions
-      let img_result = image::ImageReader::open(&Path::new(&self.image_path))
-        .and_then(|reader| reader.with_guessed_format())
-        .map_err(image::ImageError::IoError)
-        .and_then(|reader| reader.decode());
-      
-      match img_result {
-        Ok(img) => {
-          let rgba = img.to_rgba8();
-          let dimensions = rgba.dimensions();
-
-          let texture_size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-          };
-
-          let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Image Texture"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-          });
-
-          queue.write_texture(
-            wgpu::ImageCopyTexture {
-              texture: &texture,
-              mip_level: 0,
-              origin: wgpu::Origin3d::ZERO,
-              aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-              offset: 0,
-              bytes_per_row: Some(4 * dimensions.0),
-              rows_per_image: Some(dimensions.1),
-            },
-            texture_size,
-          );
-
-          let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-          self.texture = Some(texture);
-          self.texture_view = Some(texture_view);
-        }
-        Err(err) => println!("Fail to load a image {:?}", err),
-      }
-    }
+    // The cached bind group refers to the previous texture view
+    self.bind_group = None;
   }
 
   pub fn set_layout(&mut self, layout: Option<Box<dyn Layout>>) {
@@ -348,9 +755,20 @@ impl Layer {
   }*/
 
   pub fn animate(&mut self) {
+    self.animate_at(Instant::now());
+  }
+
+  /// Run the animations of the layer and its sublayers at `time`, and blink
+  /// the caret of text fields by it. Called by `Play::render` with the time of
+  /// its clock.
+  pub fn animate_at(&mut self, time: Instant) {
+    if let Some(field) = self.text_field.as_mut() {
+      field.set_time(time);
+    }
+
     // Run legacy animation if present
     if let Some(mut animation) = self.animation.take() {
-      animation.run(self);
+      animation.run_at(self, time);
       self.animation = Some(animation);
     }
 
@@ -358,13 +776,17 @@ impl Layer {
     // Take the animations HashMap out temporarily
     let mut animations = std::mem::take(&mut self.animations);
     for (_key, animation) in animations.iter_mut() {
-      animation.run(self);
+      animation.run_at(self, time);
     }
     // Put it back
     self.animations = animations;
 
+    if let Some(mask) = self.mask.as_deref_mut() {
+      mask.animate_at(time);
+    }
+
     for sub_layer in self.sub_layer_list.iter_mut() {
-      sub_layer.animate();
+      sub_layer.animate_at(time);
     }
   }
 
@@ -407,32 +829,169 @@ impl Layer {
       }
       self.event_handler = Some(event_handler);
     }
+    listener::notify(self, |listener, layer| match listener {
+      Listener::FocusIn(listener) if focused => listener(layer),
+      Listener::FocusOut(listener) if !focused => listener(layer),
+      _ => {}
+    });
+  }
+
+  pub fn is_focused(&self) -> bool {
+    self.focused
+  }
+
+  /// The index of the sublayer that is focused and drawn over its siblings
+  pub fn focused_sub_layer(&self) -> usize {
+    self.focused_sub_layer
+  }
+
+  /// Make the layer a target of spatial focus navigation (see `crate::focus`)
+  pub fn set_focusable(&mut self, focusable: bool) {
+    self.focusable = focusable;
+  }
+
+  pub fn is_focusable(&self) -> bool {
+    self.focusable
+  }
+
+  /// Make the layer a focus group: the focus stays among its focusable
+  /// sublayers while it can, and comes back to the one focused last
+  pub fn set_focus_group(&mut self, focus_group: bool) {
+    self.focus_group = focus_group;
+    if !focus_group {
+      self.remembered_focus = None;
+    }
+  }
+
+  pub fn is_focus_group(&self) -> bool {
+    self.focus_group
+  }
+
+  /// Set the layer that gets the focus when it moves in a direction from this
+  /// layer, or from this focus group when no sublayer is in the direction
+  pub fn set_next_focus(&mut self, direction: FocusDirection, name: Option<String>) {
+    match name {
+      Some(name) => self.next_focus.insert(direction, name),
+      None => self.next_focus.remove(&direction),
+    };
+  }
+
+  pub fn handle_input(&mut self, key: Key) -> bool {
+    self.handle_key(key, Modifiers::default())
+  }
+
+  /// Send a key to the focused layer of this subtree (see `focus_path`) and its
+  /// ancestors in the capture, target and bubble phases. Returns true if a
+  /// layer handled it.
+  pub fn handle_key(&mut self, key: Key, modifiers: Modifiers) -> bool {
+    let mut event = KeyEvent::new(key, modifiers, self.focus_path());
+    self.dispatch_key_event(&mut event);
+    event.is_handled()
+  }
+
+  /// The path of the focused layer of this subtree: the chain of focused
+  /// sublayers, preferring `focused_sub_layer` when several are focused. It is
+  /// empty if no sublayer is focused.
+  pub fn focus_path(&self) -> Vec<usize> {
+    let mut path = Vec::new();
+    let mut layer = self;
+    loop {
+      let list = &layer.sub_layer_list;
+      let next = match list.get(layer.focused_sub_layer) {
+        Some(sub_layer) if sub_layer.focused => Some(layer.focused_sub_layer),
+        _ => list.iter().position(|sub_layer| sub_layer.focused),
+      };
+      let Some(index) = next else {
+        return path;
+      };
+      path.push(index);
+      layer = &list[index];
+    }
   }
 
-  pub fn handle_input(&mut self, key: Key) {
+  /// Send a key event along its target path. A focused text field at the
+  /// target edits its value with a key down before the event handler gets it,
+  /// and marks the event handled if the key was an editing key.
+  pub fn dispatch_key_event(&mut self, event: &mut KeyEvent) {
+    let target = event.target.clone();
+    let phases = (0..target.len())
+      .map(|depth| (EventPhase::Capture, depth))
+      .chain(std::iter::once((EventPhase::Target, target.len())))
+      .chain(
+        (0..target.len())
+          .rev()
+          .map(|depth| (EventPhase::Bubble, depth)),
+      );
+    for (phase, depth) in phases {
+      // A handler may have changed the tree
+      let Some(layer) = self.sublayer_at_path_mut(&target[..depth]) else {
+        continue;
+      };
+      event.phase = phase;
+      event.current_target = target[..depth].to_vec();
+      let edits = phase == EventPhase::Target && event.kind == KeyEventKind::Down;
+      if edits && layer.focused && !event.is_handled() {
+        if let Some(field) = layer.text_field.as_mut() {
+          if field.key_down(event.key, event.modifiers) {
+            event.set_handled();
+          }
+        }
+      }
+      if let Some(mut event_handler) = layer.event_handler.take() {
+        event_handler.key_event(event, layer);
+        layer.event_handler = Some(event_handler);
+      }
+      // Listeners get keys like the default EventHandler::key_event
+      if phase != EventPhase::Capture && !event.is_handled() {
+        let (kind, key) = (event.kind, event.key);
+        let mut used = false;
+        listener::notify(layer, |listener, layer| match (listener, kind) {
+          (Listener::KeyDown(listener), KeyEventKind::Down) => used |= listener(key, layer),
+          (Listener::KeyUp(listener), KeyEventKind::Up) => used |= listener(key, layer),
+          _ => {}
+        });
+        if used {
+          event.set_handled();
+        }
+      }
+      if event.is_propagation_stopped() {
+        return;
+      }
+    }
+  }
+
+  /// Send typed text to the focused layers. A focused text field inserts it at
+  /// the caret.
+  pub fn handle_text_input(&mut self, text: &str) {
     for sub_layer in self.sub_layer_list.iter_mut() {
       if sub_layer.focused {
-        sub_layer.handle_input(key);
+        sub_layer.handle_text_input(text);
+      }
+    }
+    if self.focused {
+      if let Some(field) = self.text_field.as_mut() {
+        field.insert(text);
       }
     }
     if let Some(mut event_handler) = self.event_handler.take() {
-      event_handler.key_down(key, self);
+      event_handler.text_input(text, self);
       self.event_handler = Some(event_handler);
     }
+    listener::notify(self, |listener, layer| {
+      if let Listener::TextInput(listener) = listener {
+        listener(text, layer);
+      }
+    });
   }
 
-  pub fn layout_sub_layers(
-    &mut self,
-    parent_layer: Option<&Layer>,
-    stretch: &mut Option<Stretch>,
-  ) {
+  pub fn layout_sub_layers(&mut self, parent_layer: Option<&Layer>, stretch: &mut Option<Stretch>) {
     if let Some(mut layout) = self.layout.take() {
       layout.layout_sub_layers(self, parent_layer, stretch);
       self.layout = Some(layout); // Put back the layout
     }
 
     // Replace the sub_layer_list with an empty vector and take the original vector out
-    let mut sub_layer_list = std::mem::replace(&mut self.sub_layer_list, Vec::new());
+    let mut sub_layer_list = std::mem::take(&mut self.sub_layer_list);
 
     // Iterate over the vector outside of the self structure
     for sub_layer in &mut sub_layer_list {
@@ -464,7 +1023,7 @@ impl Layer {
   pub fn model_matrix(&self) -> Matrix4<f32> {
     let mut transform: Matrix4<f32> = Matrix4::identity();
     transform = transform
-      * Matrix4::<f32>::from_translation(Vector3::new(self.x as f32, self.y as f32, self.z as f32));
+      * Matrix4::<f32>::from_translation(Vector3::new(self.x as f32, self.y as f32, self.z));
 
     // Handle rotation and scale.
     // Move back to the original position.
@@ -475,11 +1034,22 @@ impl Layer {
         0.0,
       ));
 
+    transform = transform * self.transform;
+
     if self.rotation != 0 {
       transform = transform * Matrix4::<f32>::from_angle_z(Deg(self.rotation as f32));
     }
 
-    transform = transform * Matrix4::from_nonuniform_scale(self.scale_x, self.scale_y, 0.0);
+    if self.rotation_y != 0 {
+      transform = transform * Matrix4::<f32>::from_angle_y(Deg(self.rotation_y as f32));
+    }
+
+    if self.rotation_x != 0 {
+      transform = transform * Matrix4::<f32>::from_angle_x(Deg(self.rotation_x as f32));
+    }
+
+    transform =
+      transform * Matrix4::from_nonuniform_scale(self.scale_x, self.scale_y, self.scale_z);
 
     // Move to the origin of coordinate.
     transform = transform
@@ -492,6 +1062,36 @@ impl Layer {
     transform
   }
 
+  /// Transform applied to sublayers, relative to this layer's anchor point.
+  /// Sublayers are drawn with `parent_transform * sublayer_matrix() * model_matrix()`.
+  pub fn sublayer_matrix(&self) -> Matrix4<f32> {
+    let anchor = Vector3::new(
+      self.width as f32 * self.anchor_x,
+      self.height as f32 * self.anchor_y,
+      0.0,
+    );
+
+    Matrix4::<f32>::from_translation(anchor)
+      * self.sublayer_transform
+      * Matrix4::<f32>::from_translation(-anchor)
+  }
+
+  /// Returns true if the layer faces the viewer under the given model-view-projection matrix.
+  pub fn is_front_facing(&self, mvp: &Matrix4<f32>) -> bool {
+    let project = |x: f32, y: f32| {
+      let clip = mvp * Vector4::new(x, y, 0.0, 1.0);
+      (clip.x / clip.w, clip.y / clip.w)
+    };
+
+    // Same winding as the first triangle of the quad: top right, bottom right, top left.
+    let a = project(self.width as f32, self.height as f32);
+    let b = project(self.width as f32, 0.0);
+    let c = project(0.0, self.height as f32);
+
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0) > 0.0
+  }
+
+  #[allow(clippy::only_used_in_recursion)]
   pub fn render(&mut self, parent_model_matrix: Option<&Matrix4<f32>>, projection: &Matrix4<f32>) {
     if !self.visible {
       return;
@@ -499,14 +1099,15 @@ impl Layer {
 
     let mut transform: Matrix4<f32> = self.model_matrix();
     if let Some(parent_model_matrix) = parent_model_matrix {
-      transform = transform * parent_model_matrix;
+      transform = parent_model_matrix * transform;
     }
+    transform = transform * self.sublayer_matrix();
 
     // Rendering will be handled by the Play struct with wgpu
     // This method now just updates the transform hierarchy
 
     for sub_layer in self.sub_layer_list.iter_mut() {
-      if sub_layer.focused == false {
+      if !sub_layer.focused {
         sub_layer.render(Some(&transform), projection);
       }
     }
@@ -547,7 +1148,113 @@ impl Layer {
 
   /// Set opacity (CoreAnimation-style API)
   pub fn set_opacity(&mut self, opacity: f32) {
-    self.opacity = opacity.max(0.0).min(1.0);
+    self.opacity = opacity.clamp(0.0, 1.0);
+  }
+
+  /// Set 3D transform (CoreAnimation-style API)
+  pub fn set_transform(&mut self, transform: Matrix4<f32>) {
+    self.transform = transform;
+  }
+
+  /// Set sublayer transform (CoreAnimation-style API)
+  pub fn set_sublayer_transform(&mut self, transform: Matrix4<f32>) {
+    self.sublayer_transform = transform;
+  }
+
+  /// Set the m34 perspective term of the sublayer transform, usually -1.0 / eye_distance
+  /// (CoreAnimation-style API)
+  pub fn set_sublayer_transform_m34(&mut self, m34: f32) {
+    self.sublayer_transform.z.w = m34;
+  }
+
+  /// Clip sublayers to the bounds (and corner radius) of this layer (CoreAnimation-style API)
+  pub fn set_masks_to_bounds(&mut self, masks_to_bounds: bool) {
+    self.masks_to_bounds = masks_to_bounds;
+  }
+
+  /// Set a mask layer whose alpha is multiplied with this layer and its sublayers
+  /// (CoreAnimation-style API), so soft edges and gradients fade the content out.
+  /// The mask is positioned in this layer's coordinate space.
+  pub fn set_mask(&mut self, mask: Option<Layer>) {
+    self.mask = mask.map(Box::new);
+    self.mask_target = None;
+  }
+
+  /// Get the mask layer (CoreAnimation-style API)
+  pub fn mask(&self) -> Option<&Layer> {
+    self.mask.as_deref()
+  }
+
+  /// Get the mutable mask layer (CoreAnimation-style API)
+  pub fn mask_mut(&mut self) -> Option<&mut Layer> {
+    self.mask.as_deref_mut()
+  }
+
+  /// Set gradient content, drawn instead of the background color
+  /// (CoreAnimation-style CAGradientLayer API)
+  pub fn set_gradient(&mut self, gradient: Option<Gradient>) {
+    self.gradient = gradient;
+  }
+
+  /// Get the gradient content (CoreAnimation-style API)
+  pub fn gradient(&self) -> Option<&Gradient> {
+    self.gradient.as_ref()
+  }
+
+  /// Set vector shape content, drawn instead of the background color
+  /// (CoreAnimation-style CAShapeLayer API)
+  pub fn set_shape(&mut self, shape: Option<Shape>) {
+    self.shape = shape;
+  }
+
+  /// Get the shape content (CoreAnimation-style API)
+  pub fn shape(&self) -> Option<&Shape> {
+    self.shape.as_ref()
+  }
+
+  /// Set text content, rendered into the layer bounds with a transparent
+  /// background and rendered again whenever it changes (CoreAnimation-style
+  /// CATextLayer API)
+  pub fn set_text_content(&mut self, text: Option<Text>) {
+    self.text = text;
+    if self.text.is_none() {
+      self.text_cache = TextCache::default();
+      self.texture = None;
+      self.texture_view = None;
+      self.bind_group = None;
+    }
+  }
+
+  /// Get the text content (CoreAnimation-style API)
+  pub fn text_content(&self) -> Option<&Text> {
+    self.text.as_ref()
+  }
+
+  /// Get the text content for changing its properties
+  pub fn text_content_mut(&mut self) -> Option<&mut Text> {
+    self.text.as_mut()
+  }
+
+  /// Make the layer an editable text field, drawn into the layer bounds with a
+  /// transparent background like text content. The field is edited while the
+  /// layer is focused.
+  pub fn set_text_field(&mut self, field: Option<TextField>) {
+    self.text_field = field;
+    if self.text_field.is_none() {
+      self.texture = None;
+      self.texture_view = None;
+      self.bind_group = None;
+    }
+  }
+
+  /// Get the text field, e.g. to read its value
+  pub fn text_field(&self) -> Option<&TextField> {
+    self.text_field.as_ref()
+  }
+
+  /// Get the text field for changing its value or properties
+  pub fn text_field_mut(&mut self) -> Option<&mut TextField> {
+    self.text_field.as_mut()
   }
 
   /// Set background color (CoreAnimation-style API)
@@ -560,6 +1267,59 @@ impl Layer {
     (self.color[0], self.color[1], self.color[2])
   }
 
+  /// Set corner radius (CoreAnimation-style API)
+  pub fn set_corner_radius(&mut self, radius: f32) {
+    self.corner_radius = radius.max(0.0);
+  }
+
+  /// Set border width (CoreAnimation-style API)
+  pub fn set_border_width(&mut self, width: f32) {
+    self.border_width = width.max(0.0);
+  }
+
+  /// Set border color (CoreAnimation-style API)
+  pub fn set_border_color(&mut self, r: f32, g: f32, b: f32) {
+    self.border_color = [r, g, b];
+  }
+
+  /// Set shadow opacity (CoreAnimation-style API)
+  pub fn set_shadow_opacity(&mut self, opacity: f32) {
+    self.shadow_opacity = opacity.clamp(0.0, 1.0);
+  }
+
+  /// Set shadow blur radius (CoreAnimation-style API)
+  pub fn set_shadow_radius(&mut self, radius: f32) {
+    self.shadow_radius = radius.max(0.0);
+  }
+
+  /// Set shadow offset (CoreAnimation-style API)
+  pub fn set_shadow_offset(&mut self, x: f32, y: f32) {
+    self.shadow_offset = (x, y);
+  }
+
+  /// Set shadow color (CoreAnimation-style API)
+  pub fn set_shadow_color(&mut self, r: f32, g: f32, b: f32) {
+    self.shadow_color = [r, g, b];
+  }
+
+  /// Get shadow color (CoreAnimation-style API)
+  pub fn shadow_color(&self) -> (f32, f32, f32) {
+    (
+      self.shadow_color[0],
+      self.shadow_color[1],
+      self.shadow_color[2],
+    )
+  }
+
+  /// Get border color (CoreAnimation-style API)
+  pub fn border_color(&self) -> (f32, f32, f32) {
+    (
+      self.border_color[0],
+      self.border_color[1],
+      self.border_color[2],
+    )
+  }
+
   /// Add an animation for a specific key (CoreAnimation-style API)
   pub fn add_animation(&mut self, animation: Animation, key: Option<&str>) {
     if let Some(key_str) = key {
@@ -596,6 +1356,213 @@ impl Layer {
     &mut self.sub_layer_list
   }
 
+  /// Get a layer in the subtree by the indices of the sublayers leading to it.
+  /// An empty path is the layer itself.
+  pub fn sublayer_at_path(&self, path: &[usize]) -> Option<&Layer> {
+    path
+      .iter()
+      .try_fold(self, |layer, &index| layer.sub_layer_list.get(index))
+  }
+
+  /// Get a mutable layer in the subtree by its path (see `sublayer_at_path`)
+  pub fn sublayer_at_path_mut(&mut self, path: &[usize]) -> Option<&mut Layer> {
+    path
+      .iter()
+      .try_fold(self, |layer, &index| layer.sub_layer_list.get_mut(index))
+  }
+
+  /// The indices of the sublayers in the order they are drawn. The focused
+  /// sublayer is drawn last, over its siblings.
+  pub(crate) fn render_order(&self) -> Vec<usize> {
+    let count = self.sub_layer_list.len();
+    let mut order: Vec<usize> = (0..count)
+      .filter(|&i| !self.sub_layer_list[i].focused && i != self.focused_sub_layer)
+      .collect();
+    if self.focused_sub_layer < count {
+      order.push(self.focused_sub_layer);
+    }
+    order
+  }
+
+  /// Find the topmost layer of the subtree at a point in the coordinates of the
+  /// superlayer (window coordinates for a stage), and return its path (see
+  /// `sublayer_at_path`) (CoreAnimation-style hitTest API).
+  ///
+  /// The point is mapped through the model and sublayer transforms, perspective
+  /// included. Hidden layers, layers with an opacity below 0.01 and layers
+  /// facing away without `double_sided` are skipped, and points outside of
+  /// `masks_to_bounds` bounds or the bounds of the mask don't reach the subtree.
+  /// Layers are hit inside their rounded corners. A mask is tested by its
+  /// rounded bounds only, not by the alpha of its content.
+  pub fn hit_test(&self, x: f32, y: f32) -> Option<Vec<usize>> {
+    self.hit_test_with(&Matrix4::identity(), x, y)
+  }
+
+  fn hit_test_with(&self, parent_transform: &Matrix4<f32>, x: f32, y: f32) -> Option<Vec<usize>> {
+    if !self.visible || self.opacity < 0.01 {
+      return None;
+    }
+    let transform = parent_transform * self.model_matrix();
+    let local = unproject(&transform, x, y);
+
+    if let Some(mask) = self.mask.as_deref() {
+      let in_mask = unproject(&(transform * mask.model_matrix()), x, y)
+        .is_some_and(|(point, _)| rounded_bounds_contain(mask, point));
+      if !in_mask {
+        return None;
+      }
+    }
+    if self.masks_to_bounds && !local.is_some_and(|(point, _)| rounded_bounds_contain(self, point))
+    {
+      return None;
+    }
+
+    let sub_layer_transform = transform * self.sublayer_matrix();
+    for index in self.render_order().into_iter().rev() {
+      if let Some(mut path) = self.sub_layer_list[index].hit_test_with(&sub_layer_transform, x, y) {
+        path.insert(0, index);
+        return Some(path);
+      }
+    }
+
+    match local {
+      Some((point, front))
+        if (front || self.double_sided) && rounded_bounds_contain(self, point) =>
+      {
+        Some(Vec::new())
+      }
+      _ => None,
+    }
+  }
+
+  /// Send a pointer event to the event handler and the listeners of this layer
+  pub fn handle_pointer_event(&mut self, event: &PointerEvent) {
+    if let Some(mut event_handler) = self.event_handler.take() {
+      event_handler.pointer_event(event, self);
+      self.event_handler = Some(event_handler);
+    }
+    listener::notify(self, |listener, layer| {
+      if let Listener::Pointer(kind, listener) = listener {
+        if kind.is_none_or(|kind| kind == event.kind) {
+          listener(event, layer);
+        }
+      }
+    });
+  }
+
+  /// Attach a gesture recognizer, which sees the pointers that go down on this
+  /// layer or its sublayers (UIKit-style addGestureRecognizer API)
+  pub fn add_gesture_recognizer(&mut self, recognizer: GestureRecognizer) {
+    self.gesture_recognizers.push(recognizer);
+  }
+
+  /// Remove the gesture recognizers with the name
+  pub fn remove_gesture_recognizer(&mut self, name: &str) {
+    self
+      .gesture_recognizers
+      .retain(|recognizer| recognizer.name != name);
+  }
+
+  pub fn gesture_recognizers(&self) -> &[GestureRecognizer] {
+    &self.gesture_recognizers
+  }
+
+  /// Get a gesture recognizer by name, e.g. to disable it
+  pub fn gesture_recognizer_mut(&mut self, name: &str) -> Option<&mut GestureRecognizer> {
+    self
+      .gesture_recognizers
+      .iter_mut()
+      .find(|recognizer| recognizer.name == name)
+  }
+
+  /// Send a state change of a gesture recognizer to the event handler and the
+  /// listeners
+  pub(crate) fn handle_gesture(&mut self, recognizer: &GestureRecognizer) {
+    if let Some(mut event_handler) = self.event_handler.take() {
+      event_handler.gesture(recognizer, self);
+      self.event_handler = Some(event_handler);
+    }
+    listener::notify(self, |listener, layer| {
+      if let Listener::Gesture(listener) = listener {
+        listener(recognizer, layer);
+      }
+    });
+  }
+
+  /// Call `listener` with the key of every key down for this layer in the
+  /// target and bubble phases, as `EventHandler::key_down` gets it, repeats
+  /// included. It returns true if it used the key (see `crate::listener`).
+  pub fn on_key_down(
+    &mut self,
+    listener: impl FnMut(Key, &mut Layer) -> bool + 'static,
+  ) -> ListenerHandle {
+    self.listeners.add(Listener::KeyDown(Box::new(listener)))
+  }
+
+  pub fn on_key_up(
+    &mut self,
+    listener: impl FnMut(Key, &mut Layer) -> bool + 'static,
+  ) -> ListenerHandle {
+    self.listeners.add(Listener::KeyUp(Box::new(listener)))
+  }
+
+  pub fn on_focus_in(&mut self, listener: impl FnMut(&mut Layer) + 'static) -> ListenerHandle {
+    self.listeners.add(Listener::FocusIn(Box::new(listener)))
+  }
+
+  pub fn on_focus_out(&mut self, listener: impl FnMut(&mut Layer) + 'static) -> ListenerHandle {
+    self.listeners.add(Listener::FocusOut(Box::new(listener)))
+  }
+
+  pub fn on_text_input(
+    &mut self,
+    listener: impl FnMut(&str, &mut Layer) + 'static,
+  ) -> ListenerHandle {
+    self.listeners.add(Listener::TextInput(Box::new(listener)))
+  }
+
+  /// Call `listener` with every pointer event of this layer
+  pub fn on_pointer_event(
+    &mut self,
+    listener: impl FnMut(&PointerEvent, &mut Layer) + 'static,
+  ) -> ListenerHandle {
+    self
+      .listeners
+      .add(Listener::Pointer(None, Box::new(listener)))
+  }
+
+  pub fn on_pointer_down(
+    &mut self,
+    listener: impl FnMut(&PointerEvent, &mut Layer) + 'static,
+  ) -> ListenerHandle {
+    let kind = Some(PointerEventKind::Down);
+    self
+      .listeners
+      .add(Listener::Pointer(kind, Box::new(listener)))
+  }
+
+  pub fn on_pointer_up(
+    &mut self,
+    listener: impl FnMut(&PointerEvent, &mut Layer) + 'static,
+  ) -> ListenerHandle {
+    let kind = Some(PointerEventKind::Up);
+    self
+      .listeners
+      .add(Listener::Pointer(kind, Box::new(listener)))
+  }
+
+  pub fn on_gesture(
+    &mut self,
+    listener: impl FnMut(&GestureRecognizer, &mut Layer) + 'static,
+  ) -> ListenerHandle {
+    self.listeners.add(Listener::Gesture(Box::new(listener)))
+  }
+
+  /// Remove a listener added with one of the `on_` methods
+  pub fn remove_listener(&mut self, handle: ListenerHandle) {
+    self.listeners.remove(handle);
+  }
+
   /// Create uniform buffer with transform matrix and color
   pub fn create_uniform_buffer(
     &self,
@@ -603,33 +1570,162 @@ impl Layer {
     transform: &Matrix4<f32>,
     projection: &Matrix4<f32>,
   ) -> wgpu::Buffer {
-    use wgpu::util::DeviceExt;
+    self.uniforms(transform, projection).create_buffer(device)
+  }
+
+  /// Create uniform buffer covering the opaque bounds of the layer, ignoring its
+  /// opacity and texture. Used to clip sublayers.
+  pub fn create_bounds_uniform_buffer(
+    &self,
+    device: &wgpu::Device,
+    transform: &Matrix4<f32>,
+    projection: &Matrix4<f32>,
+  ) -> wgpu::Buffer {
+    let mut uniforms = self.uniforms(transform, projection);
+    uniforms.color[3] = 1.0;
+    uniforms.use_texture = 0;
+    uniforms.gradient_type = 0;
+    uniforms.create_buffer(device)
+  }
 
-    #[repr(C)]
-    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-    struct Uniforms {
-      transform: [[f32; 4]; 4],
-      projection: [[f32; 4]; 4],
-      color: [f32; 4],
-      use_texture: u32,
-      _padding: [u32; 3],
+  /// Create uniform buffer for drawing the fill or stroke mesh of the shape with the
+  /// given color, or with the gradient if there is one
+  pub fn create_shape_uniform_buffer(
+    &self,
+    device: &wgpu::Device,
+    transform: &Matrix4<f32>,
+    projection: &Matrix4<f32>,
+    color: [f32; 4],
+    gradient: Option<&Gradient>,
+  ) -> wgpu::Buffer {
+    self
+      .shape_uniforms(transform, projection, color, gradient)
+      .create_buffer(device)
+  }
+
+  fn shape_uniforms(
+    &self,
+    transform: &Matrix4<f32>,
+    projection: &Matrix4<f32>,
+    color: [f32; 4],
+    gradient: Option<&Gradient>,
+  ) -> Uniforms {
+    let mut uniforms = self.uniforms(transform, projection);
+    uniforms.color = [color[0], color[1], color[2], color[3] * self.opacity];
+    uniforms.border_width = 0.0;
+    uniforms.use_texture = 0;
+    uniforms.set_gradient(gradient);
+    uniforms.mode = SHADER_MODE_SHAPE;
+    uniforms
+  }
+
+  /// Create uniform buffer for drawing the glyph quads of the text with the text color
+  pub fn create_text_uniform_buffer(
+    &self,
+    device: &wgpu::Device,
+    transform: &Matrix4<f32>,
+    projection: &Matrix4<f32>,
+  ) -> wgpu::Buffer {
+    self
+      .text_uniforms(transform, projection)
+      .create_buffer(device)
+  }
+
+  fn text_uniforms(&self, transform: &Matrix4<f32>, projection: &Matrix4<f32>) -> Uniforms {
+    let mut uniforms = self.uniforms(transform, projection);
+    uniforms.border_width = 0.0;
+    uniforms.use_texture = 1;
+    uniforms.set_gradient(None);
+    uniforms.mode = SHADER_MODE_TEXT;
+
+    let Some(ref text) = self.text else {
+      return uniforms;
+    };
+    let with_opacity = |c: [f32; 4]| [c[0], c[1], c[2], c[3] * self.opacity];
+    uniforms.color = with_opacity(text.color);
+    if text.render_mode == TextRenderMode::Sdf {
+      uniforms.mode = SHADER_MODE_TEXT_SDF;
+      uniforms.outline_color = with_opacity(text.outline_color);
+      uniforms.glow_color = with_opacity(text.glow_color);
+      // A texel value of 0.5 spans SDF_SPREAD pixels at SDF_GLYPH_SIZE
+      let field_scale = 2.0 * SDF_SPREAD * text.font_size / SDF_GLYPH_SIZE;
+      uniforms.text_params = [text.outline_width, text.glow_radius, field_scale, 0.0];
     }
+    uniforms
+  }
 
-    let use_texture = if self.texture.is_some() { 1 } else { 0 };
-    
-    let uniforms = Uniforms {
+  fn uniforms(&self, transform: &Matrix4<f32>, projection: &Matrix4<f32>) -> Uniforms {
+    let mut uniforms = Uniforms {
       transform: (*transform).into(),
       projection: (*projection).into(),
       color: [self.color[0], self.color[1], self.color[2], self.opacity],
-      use_texture,
-      _padding: [0; 3],
+      border_color: [
+        self.border_color[0],
+        self.border_color[1],
+        self.border_color[2],
+        1.0,
+      ],
+      size: [self.width as f32, self.height as f32],
+      corner_radius: self.corner_radius,
+      border_width: self.border_width,
+      quad_scale: [1.0, 1.0],
+      quad_offset: [0.0, 0.0],
+      shadow_offset: [0.0, 0.0],
+      shadow_radius: 0.0,
+      use_texture: if self.texture.is_some() { 1 } else { 0 },
+      mode: SHADER_MODE_LAYER,
+      gradient_type: 0,
+      gradient_stop_count: 0,
+      _padding: 0,
+      gradient_points: [0.0; 4],
+      gradient_colors: [[0.0; 4]; MAX_GRADIENT_STOPS],
+      gradient_locations: [[0.0; 4]; MAX_GRADIENT_STOPS / 4],
+      outline_color: [0.0; 4],
+      glow_color: [0.0; 4],
+      text_params: [0.0; 4],
     };
 
-    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-      label: Some("Uniform Buffer"),
-      contents: bytemuck::cast_slice(&[uniforms]),
-      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-    })
+    uniforms.set_gradient(self.gradient.as_ref());
+    uniforms
+  }
+
+  /// Returns true if the layer casts a visible shadow
+  pub fn has_shadow(&self) -> bool {
+    self.shadow_opacity > 0.0 && self.opacity > 0.0
+  }
+
+  /// Create uniform buffer for drawing the layer's shadow. The layer quad is grown by
+  /// the shadow radius on every side so the blur is not cut off.
+  pub fn create_shadow_uniform_buffer(
+    &self,
+    device: &wgpu::Device,
+    transform: &Matrix4<f32>,
+    projection: &Matrix4<f32>,
+  ) -> wgpu::Buffer {
+    let width = (self.width as f32).max(1.0);
+    let height = (self.height as f32).max(1.0);
+    let margin = self.shadow_radius + 1.0;
+
+    let mut uniforms = self.uniforms(transform, projection);
+    uniforms.color = [
+      self.shadow_color[0],
+      self.shadow_color[1],
+      self.shadow_color[2],
+      self.shadow_opacity * self.opacity,
+    ];
+    uniforms.border_width = 0.0;
+    uniforms.quad_scale = [
+      (width + margin * 2.0) / width,
+      (height + margin * 2.0) / height,
+    ];
+    uniforms.quad_offset = [self.shadow_offset.0 - margin, self.shadow_offset.1 - margin];
+    uniforms.shadow_offset = [self.shadow_offset.0, self.shadow_offset.1];
+    uniforms.shadow_radius = self.shadow_radius;
+    uniforms.use_texture = 0;
+    uniforms.gradient_type = 0;
+    uniforms.mode = SHADER_MODE_SHADOW;
+
+    uniforms.create_buffer(device)
   }
 
   /// Get or create texture bind group
@@ -642,7 +1738,7 @@ impl Layer {
   ) -> &wgpu::BindGroup {
     if self.bind_group.is_none() {
       let texture_view = self.texture_view.as_ref().unwrap_or(default_texture_view);
-      
+
       self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: Some("Texture Bind Group"),
         layout,
@@ -658,7 +1754,7 @@ impl Layer {
         ],
       }));
     }
-    
+
     self.bind_group.as_ref().unwrap()
   }
 }
@@ -668,6 +1764,18 @@ mod tests {
   use super::*;
   use crate::animation::{Animation, EasingFunction};
 
+  #[test]
+  fn test_key_names() {
+    let keys: std::collections::HashSet<Key> = Key::ALL.into_iter().collect();
+    assert_eq!(keys.len(), Key::ALL.len());
+    for key in Key::ALL {
+      assert_eq!(key.name(), format!("{:?}", key));
+      assert_eq!(Key::from_name(key.name()), Some(key));
+    }
+    assert_eq!(Key::PageUp.name(), "PageUp");
+    assert_eq!(Key::from_name("pageup"), None);
+  }
+
   #[test]
   fn test_position_api() {
     let mut layer = Layer::new("test".to_string(), 100, 100, None);
@@ -712,6 +1820,147 @@ mod tests {
     assert_eq!(b, 0.7);
   }
 
+  #[test]
+  fn test_corner_radius_and_border_api() {
+    let mut layer = Layer::new("test".to_string(), 100, 100, None);
+    assert_eq!(layer.corner_radius, 0.0);
+    assert_eq!(layer.border_width, 0.0);
+
+    layer.set_corner_radius(12.0);
+    layer.set_border_width(2.0);
+    layer.set_border_color(0.1, 0.2, 0.3);
+    assert_eq!(layer.corner_radius, 12.0);
+    assert_eq!(layer.border_width, 2.0);
+    assert_eq!(layer.border_color(), (0.1, 0.2, 0.3));
+
+    // Negative values are clamped
+    layer.set_corner_radius(-1.0);
+    layer.set_border_width(-1.0);
+    assert_eq!(layer.corner_radius, 0.0);
+    assert_eq!(layer.border_width, 0.0);
+  }
+
+  #[test]
+  fn test_shadow_api() {
+    let mut layer = Layer::new("test".to_string(), 100, 100, None);
+    assert!(!layer.has_shadow());
+
+    layer.set_shadow_opacity(0.5);
+    layer.set_shadow_radius(8.0);
+    layer.set_shadow_offset(2.0, 6.0);
+    layer.set_shadow_color(0.1, 0.1, 0.2);
+    assert!(layer.has_shadow());
+    assert_eq!(layer.shadow_radius, 8.0);
+    assert_eq!(layer.shadow_offset, (2.0, 6.0));
+    assert_eq!(layer.shadow_color(), (0.1, 0.1, 0.2));
+
+    // Clamping
+    layer.set_shadow_opacity(2.0);
+    assert_eq!(layer.shadow_opacity, 1.0);
+    layer.set_shadow_radius(-4.0);
+    assert_eq!(layer.shadow_radius, 0.0);
+
+    // A transparent layer casts no shadow
+    layer.set_opacity(0.0);
+    assert!(!layer.has_shadow());
+  }
+
+  #[test]
+  fn test_uniforms_match_shader_layout() {
+    // WGSL rounds the uniform struct up to a multiple of 16 bytes.
+    assert_eq!(std::mem::size_of::<Uniforms>(), 448);
+  }
+
+  #[test]
+  fn test_sdf_text_uniforms() {
+    use crate::text::{Text, TextRenderMode};
+
+    let mut layer = Layer::new("text".to_string(), 200, 50, None);
+    layer.set_opacity(0.5);
+    layer.set_text_content(Some(
+      Text::new("Hi")
+        .with_font_size(SDF_GLYPH_SIZE * 2.0)
+        .with_render_mode(TextRenderMode::Sdf)
+        .with_outline([1.0, 0.0, 0.0, 1.0], 2.0)
+        .with_glow([0.0, 1.0, 0.0, 0.8], 6.0),
+    ));
+    let identity = Matrix4::identity();
+    let uniforms = layer.text_uniforms(&identity, &identity);
+    assert_eq!(uniforms.mode, SHADER_MODE_TEXT_SDF);
+    assert_eq!(uniforms.outline_color, [1.0, 0.0, 0.0, 0.5]);
+    assert_eq!(uniforms.glow_color, [0.0, 1.0, 0.0, 0.4]);
+    assert_eq!(uniforms.text_params, [2.0, 6.0, 4.0 * SDF_SPREAD, 0.0]);
+
+    layer.text_content_mut().unwrap().render_mode = TextRenderMode::GlyphAtlas;
+    assert_eq!(
+      layer.text_uniforms(&identity, &identity).mode,
+      SHADER_MODE_TEXT
+    );
+  }
+
+  #[test]
+  fn test_masks_to_bounds_and_mask_api() {
+    let mut layer = Layer::new("test".to_string(), 100, 100, None);
+    assert!(!layer.masks_to_bounds);
+    assert!(layer.mask().is_none());
+
+    layer.set_masks_to_bounds(true);
+    assert!(layer.masks_to_bounds);
+
+    let mut mask = Layer::new("mask".to_string(), 50, 50, None);
+    mask.set_position(25, 25);
+    layer.set_mask(Some(mask));
+    assert_eq!(layer.mask().unwrap().name, "mask");
+
+    layer.mask_mut().unwrap().set_bounds(80, 80);
+    assert_eq!(layer.mask().unwrap().bounds(), (80, 80));
+
+    layer.set_mask(None);
+    assert!(layer.mask().is_none());
+  }
+
+  #[test]
+  fn test_gradient_uniforms() {
+    let mut layer = Layer::new("test".to_string(), 100, 100, None);
+    let projection = Matrix4::identity();
+    assert_eq!(layer.uniforms(&projection, &projection).gradient_type, 0);
+
+    layer.set_gradient(Some(
+      Gradient::radial(vec![[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]])
+        .with_points((0.5, 0.5), (1.0, 1.0)),
+    ));
+    let uniforms = layer.uniforms(&projection, &projection);
+    assert_eq!(uniforms.gradient_type, 2);
+    assert_eq!(uniforms.gradient_stop_count, 2);
+    assert_eq!(uniforms.gradient_points, [0.5, 0.5, 1.0, 1.0]);
+    assert_eq!(uniforms.gradient_colors[1], [0.0, 0.0, 1.0, 1.0]);
+    assert_eq!(uniforms.gradient_locations[0], [0.0, 1.0, 0.0, 0.0]);
+  }
+
+  #[test]
+  fn test_shape_api() {
+    use crate::shape::Path;
+
+    let mut layer = Layer::new("test".to_string(), 100, 100, None);
+    assert!(layer.shape().is_none());
+
+    layer.set_opacity(0.5);
+    layer.set_shape(Some(
+      Shape::new(Path::circle(50.0, 50.0, 40.0)).with_stroke([1.0, 0.0, 0.0, 1.0], 4.0),
+    ));
+    assert_eq!(layer.shape().unwrap().line_width, 4.0);
+
+    let projection = Matrix4::identity();
+    let uniforms = layer.shape_uniforms(&projection, &projection, [1.0, 0.0, 0.0, 1.0], None);
+    assert_eq!(uniforms.mode, SHADER_MODE_SHAPE);
+    assert_eq!(uniforms.color, [1.0, 0.0, 0.0, 0.5]);
+    assert_eq!(uniforms.gradient_type, 0);
+
+    let gradient = Gradient::conic(vec![[1.0; 4], [0.0, 0.0, 0.0, 1.0]]);
+    let uniforms = layer.shape_uniforms(&projection, &projection, [1.0; 4], Some(&gradient));
+    assert_eq!(uniforms.gradient_type, 3);
+  }
+
   #[test]
   fn test_add_animation_with_key() {
     let mut layer = Layer::new("test".to_string(), 100, 100, None);
@@ -774,6 +2023,110 @@ mod tests {
     assert_eq!(sublayers[1].name, "child2");
   }
 
+  #[test]
+  fn test_model_matrix_keeps_z_scale() {
+    let mut layer = Layer::new("test".to_string(), 100, 100, None);
+    assert_eq!(layer.model_matrix().z.z, 1.0);
+
+    layer.scale_z = 2.0;
+    assert_eq!(layer.model_matrix().z.z, 2.0);
+  }
+
+  #[test]
+  fn test_rotation_y_around_anchor() {
+    let mut layer = Layer::new("test".to_string(), 100, 50, None);
+    layer.rotation_y = 90;
+
+    // The right edge swings along the Z axis around the vertical center line.
+    let right_edge = layer.model_matrix() * Vector4::new(100.0, 0.0, 0.0, 1.0);
+    assert!((right_edge.x - 50.0).abs() < 1e-4);
+    assert!((right_edge.z + 50.0).abs() < 1e-4);
+  }
+
+  #[test]
+  fn test_sublayer_transform_m34_perspective() {
+    let mut parent = Layer::new("parent".to_string(), 200, 200, None);
+    parent.set_sublayer_transform_m34(-1.0 / 500.0);
+
+    // Points closer to the viewer get a smaller w and are drawn larger.
+    let near = parent.sublayer_matrix() * Vector4::new(100.0, 100.0, 100.0, 1.0);
+    assert!((near.w - 0.8).abs() < 1e-6);
+
+    // The anchor point is the vanishing point.
+    let at_anchor = parent.sublayer_matrix() * Vector4::new(100.0, 100.0, 0.0, 1.0);
+    assert_eq!(at_anchor, Vector4::new(100.0, 100.0, 0.0, 1.0));
+  }
+
+  #[test]
+  fn test_is_front_facing() {
+    // Flip Y like the screen projection does.
+    let projection = Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0);
+    let mut layer = Layer::new("test".to_string(), 100, 100, None);
+    assert!(layer.is_front_facing(&(projection * layer.model_matrix())));
+
+    layer.rotation_y = 180;
+    assert!(!layer.is_front_facing(&(projection * layer.model_matrix())));
+  }
+
+  #[test]
+  fn test_hit_test() {
+    let mut stage = Layer::new("stage".to_string(), 400, 400, None);
+    let mut parent = Layer::new("parent".to_string(), 200, 200, None);
+    parent.set_position(100, 100);
+    let mut child = Layer::new("child".to_string(), 50, 50, None);
+    child.set_position(180, 180);
+    parent.add_sublayer(child);
+    let mut other = Layer::new("other".to_string(), 100, 100, None);
+    other.set_position(100, 100);
+    stage.add_sublayer(parent);
+    stage.add_sublayer(other);
+
+    // The focused sublayer is drawn last, on top of its siblings
+    assert_eq!(stage.hit_test(150.0, 150.0), Some(vec![0]));
+    stage.select_next_sub_layer();
+    assert_eq!(stage.hit_test(150.0, 150.0), Some(vec![1]));
+    stage.select_prev_sub_layer();
+    assert_eq!(stage.hit_test(50.0, 50.0), Some(vec![]));
+
+    // Children outside of the parent bounds are hit unless the parent clips them
+    assert_eq!(stage.hit_test(310.0, 310.0), Some(vec![0, 0]));
+    stage.sublayers_mut()[0].set_masks_to_bounds(true);
+    assert_eq!(stage.hit_test(310.0, 310.0), Some(vec![]));
+    assert_eq!(stage.hit_test(500.0, 500.0), None);
+
+    // Transforms, hidden and transparent layers
+    let parent = &mut stage.sublayers_mut()[0];
+    parent.set_masks_to_bounds(false);
+    parent.scale_x = 0.5;
+    parent.scale_y = 0.5;
+    // Scaled around its center, the parent draws the child at (240, 240)
+    assert_eq!(stage.hit_test(245.0, 245.0), Some(vec![0, 0]));
+    assert_eq!(stage.hit_test(310.0, 310.0), Some(vec![]));
+    stage.sublayers_mut()[0].sublayers_mut()[0].set_opacity(0.0);
+    assert_eq!(stage.hit_test(245.0, 245.0), Some(vec![0]));
+    stage.sublayers_mut()[0].set_visible(false);
+    assert_eq!(stage.hit_test(245.0, 245.0), Some(vec![]));
+    assert_eq!(
+      stage
+        .sublayer_at_path(&[1])
+        .map(|layer| layer.name.as_str()),
+      Some("other")
+    );
+
+    // Layers facing away are hit only when they are double sided
+    let mut card = Layer::new("card".to_string(), 100, 100, None);
+    card.rotation_y = 180;
+    assert_eq!(card.hit_test(25.0, 50.0), Some(vec![]));
+    card.double_sided = false;
+    assert_eq!(card.hit_test(25.0, 50.0), None);
+
+    // Rounded corners are not hit
+    let mut button = Layer::new("button".to_string(), 100, 40, None);
+    button.set_corner_radius(20.0);
+    assert_eq!(button.hit_test(2.0, 2.0), None);
+    assert_eq!(button.hit_test(20.0, 2.0), Some(vec![]));
+  }
+
   #[test]
   fn test_backward_compatibility() {
     let mut layer = Layer::new("test".to_string(), 100, 100, None);
@@ -3,7 +3,24 @@
 // found in the LICENSE file.
 
 pub mod animation;
+#[cfg(feature = "winit")]
+pub mod app;
+pub mod event;
+pub mod focus;
 pub mod font;
+pub mod gesture;
+pub mod glyph_atlas;
+pub mod gradient;
 pub mod layer;
+pub mod listener;
+pub mod markup;
 pub mod play;
+pub mod pointer;
+pub mod recording;
+pub mod shape;
+pub mod shaping;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod text;
+pub mod text_field;
 pub mod wgpu_context;
@@ -0,0 +1,163 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Closure event listeners.
+//!
+//! Besides its `EventHandler`, a layer can have any number of closures for
+//! single events, registered with `Layer::on_key_down`, `Layer::on_focus_in`,
+//! `Layer::on_pointer_up` and the like. They are called after the event
+//! handler, in the order they were added, and return a `ListenerHandle` for
+//! `Layer::remove_listener`. A listener can add and remove listeners of its
+//! layer, including itself. Key listeners return true if they used the key,
+//! which marks the key event handled like `EventHandler::key_down`.
+
+use crate::gesture::GestureRecognizer;
+use crate::layer::{Key, Layer};
+use crate::pointer::{PointerEvent, PointerEventKind};
+
+/// Identifies a listener of a layer
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ListenerHandle(u64);
+
+pub type KeyListener = Box<dyn FnMut(Key, &mut Layer) -> bool>;
+pub type FocusListener = Box<dyn FnMut(&mut Layer)>;
+pub type TextListener = Box<dyn FnMut(&str, &mut Layer)>;
+pub type PointerListener = Box<dyn FnMut(&PointerEvent, &mut Layer)>;
+pub type GestureListener = Box<dyn FnMut(&GestureRecognizer, &mut Layer)>;
+
+pub(crate) enum Listener {
+  KeyDown(KeyListener),
+  KeyUp(KeyListener),
+  FocusIn(FocusListener),
+  FocusOut(FocusListener),
+  TextInput(TextListener),
+  Pointer(Option<PointerEventKind>, PointerListener), // all kinds for None
+  Gesture(GestureListener),
+}
+
+#[derive(Default)]
+pub(crate) struct Listeners {
+  list: Vec<(ListenerHandle, Listener)>,
+  removed: Vec<ListenerHandle>, // removed while the listeners are called
+  next_id: u64,
+}
+
+impl Listeners {
+  pub(crate) fn add(&mut self, listener: Listener) -> ListenerHandle {
+    let handle = ListenerHandle(self.next_id);
+    self.next_id += 1;
+    self.list.push((handle, listener));
+    handle
+  }
+
+  pub(crate) fn remove(&mut self, handle: ListenerHandle) {
+    let count = self.list.len();
+    self.list.retain(|(other, _)| *other != handle);
+    if self.list.len() == count {
+      // It may be out for a call
+      self.removed.push(handle);
+    }
+  }
+}
+
+/// Call every listener of a layer with `call`, which picks the listeners of
+/// its event
+pub(crate) fn notify(layer: &mut Layer, mut call: impl FnMut(&mut Listener, &mut Layer)) {
+  if layer.listeners.list.is_empty() {
+    return;
+  }
+  let mut list = std::mem::take(&mut layer.listeners.list);
+  for (handle, listener) in list.iter_mut() {
+    if !layer.listeners.removed.contains(handle) {
+      call(listener, layer);
+    }
+  }
+  // Keep the listeners added meanwhile, and drop the removed ones
+  list.append(&mut layer.listeners.list);
+  let removed = std::mem::take(&mut layer.listeners.removed);
+  list.retain(|(handle, _)| !removed.contains(handle));
+  layer.listeners.list = list;
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::layer::{Key, Layer, LayoutMode};
+  use crate::play::Play;
+  use crate::pointer::{PointerButton, PointerId, PointerInput};
+  use std::cell::{Cell, RefCell};
+  use std::rc::Rc;
+
+  #[test]
+  fn test_listeners() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut play = Play::new("test".to_string(), 400, 400, LayoutMode::UserDefine);
+    let mut stage = Layer::new("stage".to_string(), 400, 400, None);
+    let mut button = Layer::new("button".to_string(), 100, 50, None);
+    button.set_focusable(true);
+    let record = |text: &'static str| {
+      let log = log.clone();
+      move |_layer: &mut Layer| log.borrow_mut().push(text.to_string())
+    };
+    button.on_focus_in(record("focus in"));
+    let first = record("key 1");
+    let first = button.on_key_down(move |_, layer| {
+      first(layer);
+      false
+    });
+    let second = record("key 2");
+    button.on_key_down(move |key, layer| {
+      second(layer);
+      if key != Key::Escape {
+        return false;
+      }
+      layer.set_background_color(1.0, 0.0, 0.0);
+      true
+    });
+    // Removes itself after the first click
+    let up = record("up");
+    let handle = Rc::new(Cell::new(None));
+    let own_handle = handle.clone();
+    handle.set(Some(button.on_pointer_up(move |event, layer| {
+      up(layer);
+      assert_eq!((event.local_x, event.local_y), (10.0, 10.0));
+      layer.remove_listener(own_handle.get().unwrap());
+    })));
+    stage.add_sublayer(button);
+    play.add_stage(stage);
+    let drain = || log.borrow_mut().drain(..).collect::<Vec<_>>();
+
+    play.update_focus();
+    // A key used by a listener is handled
+    assert!(play.handle_input(Key::Escape));
+    assert_eq!(drain(), vec!["focus in", "key 1", "key 2"]);
+    let button = play
+      .stage_mut("stage")
+      .unwrap()
+      .sublayer_at_path_mut(&[0])
+      .unwrap();
+    assert_eq!(button.background_color(), (1.0, 0.0, 0.0));
+    button.remove_listener(first);
+
+    for _ in 0..2 {
+      for input in [
+        PointerInput::Down {
+          pointer: PointerId::Mouse,
+          button: PointerButton::Primary,
+          x: 10.0,
+          y: 10.0,
+        },
+        PointerInput::Up {
+          pointer: PointerId::Mouse,
+          button: PointerButton::Primary,
+          x: 10.0,
+          y: 10.0,
+        },
+      ] {
+        play.handle_pointer_input(input);
+      }
+    }
+    assert!(!play.handle_input(Key::Enter));
+    assert_eq!(drain(), vec!["up", "key 2"]);
+  }
+}
@@ -0,0 +1,149 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A small markup language for rich text, for localized strings with emphasis:
+//! "Press <b>Start</b> to <color=#ff8800>play</color>".
+//!
+//! Tags are `<b>`, `<i>`, `<u>` (underline), `<s>` (strikethrough),
+//! `<color=#rrggbb>` or `<color=#rrggbbaa>`, `<size=24>` and `<font=Name>`, each
+//! closed with `</b>`, `</color>` and so on. Tags may nest; tags left open end
+//! with the string. `&lt;`, `&gt;` and `&amp;` stand for `<`, `>` and `&`.
+//! Unknown tags and closing tags without an opening tag are kept as text.
+
+use crate::text::{TextRun, TextStyle};
+
+/// Parse markup into a string and the runs of its tags, in the order the tags
+/// were opened so that inner tags win over outer ones.
+pub fn parse(markup: &str) -> (String, Vec<TextRun>) {
+  let mut string = String::new();
+  // Open tags: name, style, start in `string` and the order they were opened in
+  let mut open: Vec<(String, TextStyle, usize, usize)> = Vec::new();
+  let mut runs: Vec<(usize, TextRun)> = Vec::new();
+  let mut opened = 0;
+
+  let mut rest = markup;
+  'parse: while let Some(c) = rest.chars().next() {
+    if c == '<' {
+      if let Some(end) = rest.find('>') {
+        let tag = rest[1..end].trim();
+        if let Some(name) = tag.strip_prefix('/') {
+          let name = name.trim();
+          if let Some(i) = open.iter().rposition(|(open_name, ..)| open_name == name) {
+            let (_, style, start, order) = open.remove(i);
+            push_run(&mut runs, order, start..string.len(), style);
+            rest = &rest[end + 1..];
+            continue;
+          }
+        } else if let Some((name, style)) = parse_tag(tag) {
+          open.push((name, style, string.len(), opened));
+          opened += 1;
+          rest = &rest[end + 1..];
+          continue;
+        }
+      }
+    } else if c == '&' {
+      for (entity, c) in [("&lt;", '<'), ("&gt;", '>'), ("&amp;", '&')] {
+        if let Some(after) = rest.strip_prefix(entity) {
+          string.push(c);
+          rest = after;
+          continue 'parse;
+        }
+      }
+    }
+    string.push(c);
+    rest = &rest[c.len_utf8()..];
+  }
+
+  for (_, style, start, order) in open {
+    push_run(&mut runs, order, start..string.len(), style);
+  }
+  runs.sort_by_key(|(order, _)| *order);
+  (string, runs.into_iter().map(|(_, run)| run).collect())
+}
+
+fn push_run(
+  runs: &mut Vec<(usize, TextRun)>,
+  order: usize,
+  range: std::ops::Range<usize>,
+  style: TextStyle,
+) {
+  if !range.is_empty() {
+    runs.push((order, TextRun { range, style }));
+  }
+}
+
+// The name and style of an opening tag, or None if it isn't a known tag
+fn parse_tag(tag: &str) -> Option<(String, TextStyle)> {
+  let (name, value) = match tag.split_once('=') {
+    Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+    None => (tag, None),
+  };
+  let style = TextStyle::new();
+  let style = match (name, value) {
+    ("b", None) => style.with_bold(true),
+    ("i", None) => style.with_italic(true),
+    ("u", None) => style.with_underline(true),
+    ("s", None) => style.with_strikethrough(true),
+    ("color", Some(value)) => {
+      let [r, g, b, a] = parse_color(value)?;
+      style.with_color(r, g, b, a)
+    }
+    ("size", Some(value)) => style.with_font_size(value.parse().ok().filter(|size| *size > 0.0)?),
+    ("font", Some(value)) if !value.is_empty() => style.with_font(value),
+    _ => return None,
+  };
+  Some((name.to_string(), style))
+}
+
+/// Parse a "#rrggbb" or "#rrggbbaa" color
+pub fn parse_color(value: &str) -> Option<[f32; 4]> {
+  let hex = value.strip_prefix('#')?;
+  if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+    return None;
+  }
+  let mut color = [1.0; 4];
+  for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+    *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()? as f32 / 255.0;
+  }
+  Some(color)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_markup() {
+    let (string, runs) = parse("Press <b>Start</b> to <color=#ff8800>play</color>");
+    assert_eq!(string, "Press Start to play");
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0].range, 6..11);
+    assert!(runs[0].style.bold);
+    assert_eq!(runs[1].range, 15..19);
+    assert_eq!(runs[1].style.color, Some([1.0, 136.0 / 255.0, 0.0, 1.0]));
+
+    // Inner tags come after outer ones so that they win
+    let (string, runs) = parse("<color=#ff0000>a<color=#0000ff80>b</color></color>");
+    assert_eq!(string, "ab");
+    assert_eq!(runs[0].range, 0..2);
+    assert_eq!(runs[1].range, 1..2);
+    assert_eq!(runs[1].style.color, Some([0.0, 0.0, 1.0, 128.0 / 255.0]));
+
+    let (_, runs) = parse("<i><u>x</u></i><s><size=30><font=DejaVu Sans Mono>y");
+    assert!(runs[0].style.italic && runs[1].style.underline && runs[2].style.strikethrough);
+    assert_eq!(runs[3].style.font_size, Some(30.0));
+    assert_eq!(runs[4].style.font.as_deref(), Some("DejaVu Sans Mono"));
+    assert_eq!(runs[4].range, 1..2);
+  }
+
+  #[test]
+  fn test_parse_markup_text() {
+    // Escapes, unknown tags and unmatched closing tags are text
+    let (string, runs) = parse("a &lt;b&gt; &amp; <x> </b> <color=red>c 1 < 2");
+    assert_eq!(string, "a <b> & <x> </b> <color=red>c 1 < 2");
+    assert!(runs.is_empty());
+    assert_eq!(parse_color("#12345"), None);
+    assert_eq!(parse_color("#000000"), Some([0.0, 0.0, 0.0, 1.0]));
+  }
+}
@@ -2,14 +2,25 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use cgmath::{Matrix4, SquareMatrix};
+use cgmath::Matrix4;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use stretch::{geometry::Size, node::Stretch};
 
+use crate::event::{KeyEvent, KeyEventKind, KeyInput};
+use crate::focus::{self, FocusChange, FocusChangeHandler, FocusDirection};
+use crate::font::FontRegistry;
+use crate::gesture;
+use crate::glyph_atlas::GlyphAtlas;
 use crate::layer::EventHandler;
 use crate::layer::Key;
-use crate::layer::LayoutMode;
 use crate::layer::Layer;
+use crate::layer::LayoutMode;
+use crate::layer::Modifiers;
+use crate::pointer::{
+  path_transform, unproject, PointerEvent, PointerEventKind, PointerId, PointerInput,
+};
+use crate::recording::{Clock, InputEvent, InputRecording};
 use crate::wgpu_context::WgpuContext;
 
 // WGSL shader source
@@ -22,15 +33,44 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) local_position: vec2<f32>,
 }
 
 struct Uniforms {
     transform: mat4x4<f32>,
     projection: mat4x4<f32>,
     color: vec4<f32>,
+    border_color: vec4<f32>,
+    size: vec2<f32>,
+    corner_radius: f32,
+    border_width: f32,
+    quad_scale: vec2<f32>,
+    quad_offset: vec2<f32>,
+    shadow_offset: vec2<f32>,
+    shadow_radius: f32,
     use_texture: u32,
+    mode: u32,
+    gradient_type: u32,
+    gradient_stop_count: u32,
+    gradient_points: vec4<f32>,
+    gradient_colors: array<vec4<f32>, 8>,
+    gradient_locations: array<vec4<f32>, 2>,
+    outline_color: vec4<f32>,
+    glow_color: vec4<f32>,
+    // outline width, glow radius, layer pixels per unit of the distance field
+    text_params: vec4<f32>,
 }
 
+const MODE_SHADOW: u32 = 1u;
+const MODE_SHAPE: u32 = 2u;
+const MODE_TEXT: u32 = 3u;
+const MODE_TEXT_SDF: u32 = 4u;
+
+const GRADIENT_LINEAR: u32 = 1u;
+const GRADIENT_RADIAL: u32 = 2u;
+const GRADIENT_CONIC: u32 = 3u;
+const PI: f32 = 3.14159265;
+
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
 
@@ -42,38 +82,506 @@ var t_sampler: sampler;
 @vertex
 fn vs_main(vertex: VertexInput) -> VertexOutput {
     var out: VertexOutput;
-    out.clip_position = uniforms.projection * uniforms.transform * vec4<f32>(vertex.position, 1.0);
+    let local = vertex.position.xy * uniforms.quad_scale + uniforms.quad_offset;
+    out.clip_position = uniforms.projection * uniforms.transform * vec4<f32>(local, vertex.position.z, 1.0);
     out.tex_coords = vertex.tex_coords;
+    out.local_position = local;
     return out;
 }
 
+// Signed distance from p to the edge of a rounded rectangle of the given size,
+// negative inside.
+fn rounded_rect_sdf(p: vec2<f32>, size: vec2<f32>, radius: f32) -> f32 {
+    let half_size = size * 0.5;
+    let r = min(radius, min(half_size.x, half_size.y));
+    let q = abs(p - half_size) - half_size + vec2<f32>(r, r);
+    return length(max(q, vec2<f32>(0.0, 0.0))) + min(max(q.x, q.y), 0.0) - r;
+}
+
+fn gradient_location(i: u32) -> f32 {
+    return uniforms.gradient_locations[i / 4u][i % 4u];
+}
+
+// Color of the layer gradient at p, given in the unit coordinate space of the layer.
+fn gradient_color(p: vec2<f32>) -> vec4<f32> {
+    let start = uniforms.gradient_points.xy;
+    let end = uniforms.gradient_points.zw;
+    let axis = end - start;
+
+    var t = 0.0;
+    if (uniforms.gradient_type == GRADIENT_LINEAR) {
+        t = dot(p - start, axis) / max(dot(axis, axis), 0.000001);
+    } else if (uniforms.gradient_type == GRADIENT_RADIAL) {
+        t = length(p - start) / max(length(axis), 0.000001);
+    } else if (uniforms.gradient_type == GRADIENT_CONIC) {
+        let d = p - start;
+        let angle = atan2(d.y, d.x) - atan2(axis.y, axis.x);
+        t = fract(angle / (2.0 * PI));
+    }
+    t = clamp(t, 0.0, 1.0);
+
+    var color = uniforms.gradient_colors[0];
+    for (var i = 1u; i < uniforms.gradient_stop_count; i++) {
+        let from_location = gradient_location(i - 1u);
+        let to_location = gradient_location(i);
+        if (t >= to_location) {
+            color = uniforms.gradient_colors[i];
+        } else if (t > from_location) {
+            let f = (t - from_location) / (to_location - from_location);
+            color = mix(uniforms.gradient_colors[i - 1u], uniforms.gradient_colors[i], f);
+        }
+    }
+    return color;
+}
+
+// Glyph, outline and glow from a signed distance field texel (0.5 on the edge),
+// composited front to back with premultiplied alpha
+fn sdf_text_color(field: f32) -> vec4<f32> {
+    // Distance from the glyph edge in layer pixels, positive outside
+    let edge_distance = (0.5 - field) * uniforms.text_params.z;
+    let aa = max(fwidth(edge_distance), 0.0001);
+    let outline_width = uniforms.text_params.x;
+    let glow_radius = uniforms.text_params.y;
+
+    let glyph = clamp(0.5 - edge_distance / aa, 0.0, 1.0) * uniforms.color.a;
+    var rgb = uniforms.color.rgb * glyph;
+    var alpha = glyph;
+
+    if (outline_width > 0.0) {
+        let outline = clamp(0.5 - (edge_distance - outline_width) / aa, 0.0, 1.0)
+            * uniforms.outline_color.a;
+        rgb += uniforms.outline_color.rgb * outline * (1.0 - alpha);
+        alpha += outline * (1.0 - alpha);
+    }
+    if (glow_radius > 0.0) {
+        let falloff = clamp((edge_distance - outline_width) / glow_radius, 0.0, 1.0);
+        let glow = (1.0 - falloff) * (1.0 - falloff) * uniforms.glow_color.a;
+        rgb += uniforms.glow_color.rgb * glow * (1.0 - alpha);
+        alpha += glow * (1.0 - alpha);
+    }
+    return vec4<f32>(rgb / max(alpha, 0.0001), alpha);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    if (uniforms.use_texture > 0u) {
-        return textureSample(t_texture, t_sampler, in.tex_coords);
-    } else {
-        return uniforms.color;
+    let texel = textureSample(t_texture, t_sampler, in.tex_coords);
+    let distance = rounded_rect_sdf(in.local_position, uniforms.size, uniforms.corner_radius);
+    // Width of one screen pixel in layer units, for antialiasing under any transform.
+    let aa = max(fwidth(distance), 0.0001);
+
+    if (uniforms.mode == MODE_SHADOW) {
+        let shadow_distance = rounded_rect_sdf(
+            in.local_position - uniforms.shadow_offset,
+            uniforms.size,
+            uniforms.corner_radius,
+        );
+        let blur = max(uniforms.shadow_radius, aa * 0.5);
+        let shadow = 1.0 - smoothstep(-blur, blur, shadow_distance);
+        return vec4<f32>(uniforms.color.rgb, uniforms.color.a * shadow);
+    }
+
+    // Glyph quads sample coverage from the single-channel glyph atlas
+    if (uniforms.mode == MODE_TEXT) {
+        return vec4<f32>(uniforms.color.rgb, uniforms.color.a * texel.r);
+    }
+    if (uniforms.mode == MODE_TEXT_SDF) {
+        return sdf_text_color(texel.r);
     }
+
+    var fill = uniforms.color;
+    if (uniforms.gradient_type > 0u) {
+        let gradient = gradient_color(in.local_position / max(uniforms.size, vec2<f32>(1.0, 1.0)));
+        fill = vec4<f32>(gradient.rgb, gradient.a * uniforms.color.a);
+    } else if (uniforms.use_texture > 0u) {
+        fill = vec4<f32>(texel.rgb, texel.a * uniforms.color.a);
+    }
+
+    // Shape meshes cover exactly the filled or stroked area
+    if (uniforms.mode == MODE_SHAPE) {
+        return fill;
+    }
+
+    var out_color = fill;
+    if (uniforms.border_width > 0.0) {
+        let border = clamp(0.5 + (distance + uniforms.border_width) / aa, 0.0, 1.0);
+        let border_color = vec4<f32>(uniforms.border_color.rgb, uniforms.border_color.a * uniforms.color.a);
+        out_color = mix(fill, border_color, border);
+    }
+
+    let coverage = clamp(0.5 - distance / aa, 0.0, 1.0);
+    return vec4<f32>(out_color.rgb, out_color.a * coverage);
+}
+
+// Writes the rounded bounds of the layer into the stencil buffer only. Pixels
+// outside the bounds are discarded.
+@fragment
+fn fs_clip(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = rounded_rect_sdf(in.local_position, uniforms.size, uniforms.corner_radius);
+    if (distance > 0.0) {
+        discard;
+    }
+    return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+}
+"#;
+
+// Copies an offscreen texture of the size of the render target, pixel for pixel.
+const COMPOSITE_SHADER_SOURCE: &str = r#"
+@group(0) @binding(0)
+var t_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var t_sampler: sampler;
+
+// One triangle covering the whole target
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_copy(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(t_texture, vec2<i32>(position.xy), 0);
 }
 "#;
 
+// Depth range of the orthographic projection. Layers rotated around the X or Y axis
+// extend along Z, so keep it generous to avoid clipping them.
+const PROJECTION_DEPTH: f32 = 10000.0;
+
+// Maps OpenGL-style clip space depth (-1..1) to wgpu's (0..1).
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+  1.0, 0.0, 0.0, 0.0,
+  0.0, 1.0, 0.0, 0.0,
+  0.0, 0.0, 0.5, 0.0,
+  0.0, 0.0, 0.5, 1.0,
+);
+
+/// Orthographic projection for a viewport whose origin is the top left corner.
+fn orthographic_projection(width: f32, height: f32) -> Matrix4<f32> {
+  // left, right, bottom, top, near, far
+  OPENGL_TO_WGPU_MATRIX
+    * cgmath::ortho(0.0, width, height, 0.0, PROJECTION_DEPTH, -PROJECTION_DEPTH)
+}
+
+// Stencil buffer used for masks_to_bounds.
+const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+
+// Layers are drawn with straight alpha. Drawn over a transparent offscreen target,
+// they leave premultiplied colors in it.
+const LAYER_BLEND: wgpu::BlendState = wgpu::BlendState::ALPHA_BLENDING;
+
+// Draws the content of a masked layer over its mask, keeping the content where the
+// mask is opaque: content * mask alpha.
+const MASK_BLEND: wgpu::BlendState = wgpu::BlendState {
+  color: wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::DstAlpha,
+    dst_factor: wgpu::BlendFactor::Zero,
+    operation: wgpu::BlendOperation::Add,
+  },
+  alpha: wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::DstAlpha,
+    dst_factor: wgpu::BlendFactor::Zero,
+    operation: wgpu::BlendOperation::Add,
+  },
+};
+
+// Composites the premultiplied offscreen image of a masked layer.
+const COMPOSITE_BLEND: wgpu::BlendState = wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING;
+
+/// Create a pipeline for drawing layer quads. Every pipeline tests the stencil buffer
+/// against the current clip depth; `stencil_pass_op` decides what happens to it.
+/// Pipelines that modify the stencil buffer do not write colors.
+fn create_layer_pipeline(
+  device: &wgpu::Device,
+  layout: &wgpu::PipelineLayout,
+  shader: &wgpu::ShaderModule,
+  format: wgpu::TextureFormat,
+  label: &str,
+  fragment_entry_point: &str,
+  stencil_pass_op: wgpu::StencilOperation,
+) -> wgpu::RenderPipeline {
+  let write_mask = if stencil_pass_op == wgpu::StencilOperation::Keep {
+    wgpu::ColorWrites::ALL
+  } else {
+    wgpu::ColorWrites::empty()
+  };
+
+  device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    label: Some(label),
+    layout: Some(layout),
+    vertex: wgpu::VertexState {
+      module: shader,
+      entry_point: "vs_main",
+      buffers: &[crate::layer::Vertex::desc()],
+      compilation_options: wgpu::PipelineCompilationOptions::default(),
+    },
+    fragment: Some(wgpu::FragmentState {
+      module: shader,
+      entry_point: fragment_entry_point,
+      targets: &[Some(wgpu::ColorTargetState {
+        format,
+        blend: Some(LAYER_BLEND),
+        write_mask,
+      })],
+      compilation_options: wgpu::PipelineCompilationOptions::default(),
+    }),
+    primitive: wgpu::PrimitiveState {
+      topology: wgpu::PrimitiveTopology::TriangleList,
+      strip_index_format: None,
+      front_face: wgpu::FrontFace::Ccw,
+      // Back faces are culled per layer, see Layer::double_sided.
+      cull_mode: None,
+      polygon_mode: wgpu::PolygonMode::Fill,
+      unclipped_depth: false,
+      conservative: false,
+    },
+    depth_stencil: Some(stencil_state(stencil_pass_op)),
+    multisample: wgpu::MultisampleState {
+      count: 1,
+      mask: !0,
+      alpha_to_coverage_enabled: false,
+    },
+    multiview: None,
+    cache: None,
+  })
+}
+
+/// Create a pipeline that draws an offscreen texture over the whole target with the
+/// given blending, where the stencil equals the current clip depth.
+fn create_composite_pipeline(
+  device: &wgpu::Device,
+  layout: &wgpu::PipelineLayout,
+  shader: &wgpu::ShaderModule,
+  format: wgpu::TextureFormat,
+  label: &str,
+  blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+  device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    label: Some(label),
+    layout: Some(layout),
+    vertex: wgpu::VertexState {
+      module: shader,
+      entry_point: "vs_fullscreen",
+      buffers: &[],
+      compilation_options: wgpu::PipelineCompilationOptions::default(),
+    },
+    fragment: Some(wgpu::FragmentState {
+      module: shader,
+      entry_point: "fs_copy",
+      targets: &[Some(wgpu::ColorTargetState {
+        format,
+        blend: Some(blend),
+        write_mask: wgpu::ColorWrites::ALL,
+      })],
+      compilation_options: wgpu::PipelineCompilationOptions::default(),
+    }),
+    primitive: wgpu::PrimitiveState::default(),
+    depth_stencil: Some(stencil_state(wgpu::StencilOperation::Keep)),
+    multisample: wgpu::MultisampleState::default(),
+    multiview: None,
+    cache: None,
+  })
+}
+
+/// Stencil test against the current clip depth; `pass_op` is applied where it passes.
+fn stencil_state(pass_op: wgpu::StencilOperation) -> wgpu::DepthStencilState {
+  let stencil_face = wgpu::StencilFaceState {
+    compare: wgpu::CompareFunction::Equal,
+    fail_op: wgpu::StencilOperation::Keep,
+    depth_fail_op: wgpu::StencilOperation::Keep,
+    pass_op,
+  };
+  wgpu::DepthStencilState {
+    format: STENCIL_FORMAT,
+    depth_write_enabled: false,
+    depth_compare: wgpu::CompareFunction::Always,
+    stencil: wgpu::StencilState {
+      front: stencil_face,
+      back: stencil_face,
+      read_mask: 0xff,
+      write_mask: 0xff,
+    },
+    bias: wgpu::DepthBiasState::default(),
+  }
+}
+
+/// Begin a render pass that draws into an offscreen texture, cleared to transparent.
+fn begin_offscreen_pass<'a>(
+  encoder: &'a mut wgpu::CommandEncoder,
+  view: &wgpu::TextureView,
+  rc: &RenderContext,
+) -> wgpu::RenderPass<'a> {
+  encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+    label: Some("Offscreen Render Pass"),
+    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+      view,
+      resolve_target: None,
+      ops: wgpu::Operations {
+        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+        store: wgpu::StoreOp::Store,
+      },
+    })],
+    depth_stencil_attachment: rc.stencil_view.map(stencil_attachment),
+    timestamp_writes: None,
+    occlusion_query_set: None,
+  })
+}
+
+fn stencil_attachment(view: &wgpu::TextureView) -> wgpu::RenderPassDepthStencilAttachment<'_> {
+  wgpu::RenderPassDepthStencilAttachment {
+    view,
+    depth_ops: Some(wgpu::Operations {
+      load: wgpu::LoadOp::Clear(1.0),
+      store: wgpu::StoreOp::Discard,
+    }),
+    stencil_ops: Some(wgpu::Operations {
+      load: wgpu::LoadOp::Clear(0),
+      store: wgpu::StoreOp::Discard,
+    }),
+  }
+}
+
+/// Whether a clip shape is added to or removed from the stencil buffer.
+#[derive(Copy, Clone)]
+enum ClipOp {
+  Push,
+  Pop,
+}
+
+/// A texture of the size of the render target, bound for fs_copy.
+struct OffscreenTexture {
+  view: wgpu::TextureView,
+  bind_group: wgpu::BindGroup,
+}
+
+impl OffscreenTexture {
+  fn new(label: &str, rc: &RenderContext) -> Self {
+    let device = &rc.context.device;
+    let (width, height) = rc.target_size;
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some(label),
+      size: wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: rc.target_format,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+      view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      label: Some(label),
+      layout: rc.texture_bind_group_layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::TextureView(&view),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: wgpu::BindingResource::Sampler(rc.sampler),
+        },
+      ],
+    });
+    OffscreenTexture { view, bind_group }
+  }
+}
+
+/// Offscreen images of a layer with a mask: its content with its sublayers, and that
+/// content multiplied by the alpha of the mask.
+pub(crate) struct MaskTarget {
+  size: (u32, u32),
+  content: OffscreenTexture,
+  masked: OffscreenTexture,
+}
+
+impl MaskTarget {
+  fn new(rc: &RenderContext) -> Self {
+    MaskTarget {
+      size: rc.target_size,
+      content: OffscreenTexture::new("Mask Content Texture", rc),
+      masked: OffscreenTexture::new("Masked Texture", rc),
+    }
+  }
+}
+
+/// Vertex and index buffers of a draw call.
+struct DrawMesh<'a> {
+  vertex_buffer: &'a wgpu::Buffer,
+  index_buffer: &'a wgpu::Buffer,
+  index_format: wgpu::IndexFormat,
+  index_count: u32,
+}
+
+/// GPU state shared by every layer drawn in a render pass.
+struct RenderContext<'a> {
+  context: &'a WgpuContext,
+  projection: &'a Matrix4<f32>,
+  render_pipeline: &'a wgpu::RenderPipeline,
+  clip_push_pipeline: &'a wgpu::RenderPipeline,
+  clip_pop_pipeline: &'a wgpu::RenderPipeline,
+  mask_pipeline: &'a wgpu::RenderPipeline,
+  composite_pipeline: &'a wgpu::RenderPipeline,
+  bind_group_layout: &'a wgpu::BindGroupLayout,
+  texture_bind_group_layout: &'a wgpu::BindGroupLayout,
+  sampler: &'a wgpu::Sampler,
+  default_texture_view: &'a wgpu::TextureView,
+  fonts: &'a FontRegistry,
+  glyph_atlas_bind_group: Option<&'a wgpu::BindGroup>,
+  stencil_view: Option<&'a wgpu::TextureView>,
+  target_format: wgpu::TextureFormat,
+  target_size: (u32, u32),
+}
+
 pub fn render(name: String) {
   println!("Render {}", name);
 }
 
 pub struct Play {
   _name: String,
-  stage_list: Vec<Layer>,
+  pub(crate) stage_list: Vec<Layer>,
   stage_map: HashMap<String, usize>,
   projection: Matrix4<f32>,
   pub stretch: Option<Stretch>,
   pub wgpu_context: Option<WgpuContext>,
   render_pipeline: Option<wgpu::RenderPipeline>,
+  clip_push_pipeline: Option<wgpu::RenderPipeline>,
+  clip_pop_pipeline: Option<wgpu::RenderPipeline>,
+  mask_pipeline: Option<wgpu::RenderPipeline>,
+  composite_pipeline: Option<wgpu::RenderPipeline>,
   bind_group_layout: Option<wgpu::BindGroupLayout>,
   texture_bind_group_layout: Option<wgpu::BindGroupLayout>,
   default_texture: Option<wgpu::Texture>,
   default_texture_view: Option<wgpu::TextureView>,
   sampler: Option<wgpu::Sampler>,
+  stencil_texture: Option<wgpu::Texture>,
+  stencil_view: Option<wgpu::TextureView>,
+  font_registry: FontRegistry,
+  glyph_atlas: GlyphAtlas,
+  modifiers: Modifiers,
+  pointers: HashMap<PointerId, PointerState>,
+  focus: Option<(LayerTarget, String)>, // the focused layer as last reported, with its name
+  focus_change_handler: Option<FocusChangeHandler>,
+  clock: Clock,
+  recording: Option<(Instant, InputRecording)>, // with its start time
+}
+
+// A layer found by hit testing: the index of its stage and its path in the stage
+type LayerTarget = (usize, Vec<usize>);
+
+// The layer a pointer is over, or the layer that captured it while it is down
+#[derive(Default)]
+struct PointerState {
+  target: Option<LayerTarget>,
+  captured: bool,
+  claimed: bool, // by a gesture recognizer, so the layer no longer gets its events
+  x: f32,
+  y: f32,
 }
 
 impl Play {
@@ -93,33 +601,67 @@ impl Play {
       }
     }
 
-    let mut play = Play {
+    Play {
       _name: name,
       stage_list: Vec::new(),
       stage_map: HashMap::new(),
-      projection: Matrix4::identity(),
-      stretch: stretch,
+      projection: orthographic_projection(viewport_width as f32, viewport_height as f32),
+      stretch,
       wgpu_context: None,
       render_pipeline: None,
+      clip_push_pipeline: None,
+      clip_pop_pipeline: None,
+      mask_pipeline: None,
+      composite_pipeline: None,
       bind_group_layout: None,
       texture_bind_group_layout: None,
       default_texture: None,
       default_texture_view: None,
       sampler: None,
-    };
+      stencil_texture: None,
+      stencil_view: None,
+      font_registry: FontRegistry::shared().clone(),
+      glyph_atlas: GlyphAtlas::default(),
+      modifiers: Modifiers::default(),
+      pointers: HashMap::new(),
+      focus: None,
+      focus_change_handler: None,
+      clock: Clock::System,
+      recording: None,
+    }
+  }
 
-    // Apply orthographic projection matrix: left, right, bottom, top, near, far
-    let orth_matrix = cgmath::ortho(
-      0.0,
-      viewport_width as f32,
-      viewport_height as f32,
-      0.0,
-      1.0,
-      -1.0,
-    );
-    play.projection = orth_matrix;
+  /// Set the fonts used by text layers. Defaults to `FontRegistry::shared()`.
+  pub fn set_font_registry(&mut self, font_registry: FontRegistry) {
+    self.font_registry = font_registry;
+  }
+
+  /// Get the fonts used by text layers, e.g. to register fonts by name
+  pub fn font_registry(&self) -> &FontRegistry {
+    &self.font_registry
+  }
+
+  /// Get the glyph atlas shared by text layers that use `TextRenderMode::GlyphAtlas`
+  pub fn glyph_atlas(&self) -> &GlyphAtlas {
+    &self.glyph_atlas
+  }
 
-    play
+  /// Add the glyphs of every visible atlas text layer to the glyph atlas and
+  /// build their quads. Returns false if the atlas was cleared on the way.
+  fn update_text_meshes(
+    layer: &mut Layer,
+    context: &WgpuContext,
+    fonts: &FontRegistry,
+    atlas: &mut GlyphAtlas,
+  ) -> bool {
+    if !layer.visible {
+      return true;
+    }
+    let mut current = layer.update_text_mesh(&context.device, &context.queue, fonts, atlas);
+    for sub_layer in layer.sub_layer_list.iter_mut() {
+      current &= Self::update_text_meshes(sub_layer, context, fonts, atlas);
+    }
+    current
   }
 
   pub fn init_wgpu(&mut self) {
@@ -137,10 +679,11 @@ impl Play {
     self.wgpu_context = Some(pollster::block_on(WgpuContext::new_with_surface(
       window, width, height,
     )));
-    
+
     // Set up the render pipeline after wgpu context is created
     self.setup_render_pipeline();
     self.create_default_texture();
+    self.create_stencil_texture(width, height);
   }
 
   /// Set up the render pipeline for drawing
@@ -210,46 +753,70 @@ impl Play {
       .map(|c| c.format)
       .unwrap_or(wgpu::TextureFormat::Bgra8UnormSrgb);
 
-    // Create render pipeline
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-      label: Some("Render Pipeline"),
-      layout: Some(&render_pipeline_layout),
-      vertex: wgpu::VertexState {
-        module: &shader,
-        entry_point: "vs_main",
-        buffers: &[crate::layer::Vertex::desc()],
-        compilation_options: wgpu::PipelineCompilationOptions::default(),
-      },
-      fragment: Some(wgpu::FragmentState {
-        module: &shader,
-        entry_point: "fs_main",
-        targets: &[Some(wgpu::ColorTargetState {
-          format: surface_format,
-          blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-          write_mask: wgpu::ColorWrites::ALL,
-        })],
-        compilation_options: wgpu::PipelineCompilationOptions::default(),
-      }),
-      primitive: wgpu::PrimitiveState {
-        topology: wgpu::PrimitiveTopology::TriangleList,
-        strip_index_format: None,
-        front_face: wgpu::FrontFace::Ccw,
-        cull_mode: Some(wgpu::Face::Back),
-        polygon_mode: wgpu::PolygonMode::Fill,
-        unclipped_depth: false,
-        conservative: false,
-      },
-      depth_stencil: None,
-      multisample: wgpu::MultisampleState {
-        count: 1,
-        mask: !0,
-        alpha_to_coverage_enabled: false,
-      },
-      multiview: None,
-      cache: None,
-    });
+    // Create render pipelines. Layers are drawn where the stencil equals the current
+    // clip depth; the clip pipelines push and pop clip shapes without touching colors.
+    let render_pipeline = create_layer_pipeline(
+      device,
+      &render_pipeline_layout,
+      &shader,
+      surface_format,
+      "Render Pipeline",
+      "fs_main",
+      wgpu::StencilOperation::Keep,
+    );
+    let clip_push_pipeline = create_layer_pipeline(
+      device,
+      &render_pipeline_layout,
+      &shader,
+      surface_format,
+      "Clip Push Pipeline",
+      "fs_clip",
+      wgpu::StencilOperation::IncrementClamp,
+    );
+    let clip_pop_pipeline = create_layer_pipeline(
+      device,
+      &render_pipeline_layout,
+      &shader,
+      surface_format,
+      "Clip Pop Pipeline",
+      "fs_clip",
+      wgpu::StencilOperation::DecrementClamp,
+    );
 
     self.render_pipeline = Some(render_pipeline);
+    self.clip_push_pipeline = Some(clip_push_pipeline);
+
+    // Masked layers are drawn offscreen, then multiplied by their mask and composited
+    let composite_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some("Composite Shader"),
+      source: wgpu::ShaderSource::Wgsl(COMPOSITE_SHADER_SOURCE.into()),
+    });
+    let composite_pipeline_layout =
+      device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Composite Pipeline Layout"),
+        bind_group_layouts: &[&texture_bind_group_layout],
+        push_constant_ranges: &[],
+      });
+    let mask_pipeline = create_composite_pipeline(
+      device,
+      &composite_pipeline_layout,
+      &composite_shader,
+      surface_format,
+      "Mask Pipeline",
+      MASK_BLEND,
+    );
+    let composite_pipeline = create_composite_pipeline(
+      device,
+      &composite_pipeline_layout,
+      &composite_shader,
+      surface_format,
+      "Composite Pipeline",
+      COMPOSITE_BLEND,
+    );
+
+    self.clip_pop_pipeline = Some(clip_pop_pipeline);
+    self.mask_pipeline = Some(mask_pipeline);
+    self.composite_pipeline = Some(composite_pipeline);
     self.bind_group_layout = Some(bind_group_layout);
     self.texture_bind_group_layout = Some(texture_bind_group_layout);
 
@@ -313,41 +880,126 @@ impl Play {
     self.default_texture_view = Some(texture_view);
   }
 
+  /// Create the stencil buffer used for clipping, matching the surface size
+  fn create_stencil_texture(&mut self, width: u32, height: u32) {
+    let Some(ref context) = self.wgpu_context else {
+      return;
+    };
+
+    let texture = context.device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("Stencil Texture"),
+      size: wgpu::Extent3d {
+        width: width.max(1),
+        height: height.max(1),
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: STENCIL_FORMAT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+      view_formats: &[],
+    });
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    self.stencil_texture = Some(texture);
+    self.stencil_view = Some(view);
+  }
+
   /// Resize the rendering surface and update projection matrix
   pub fn resize(&mut self, width: u32, height: u32) {
     if let Some(ref mut context) = self.wgpu_context {
       context.resize(width, height);
     }
-    
+    if self.stencil_texture.is_some() {
+      self.create_stencil_texture(width, height);
+    }
+
     // Update projection matrix for new viewport size
-    let orth_matrix = cgmath::ortho(
-      0.0,
-      width as f32,
-      height as f32,
-      0.0,
-      1.0,
-      -1.0,
-    );
-    self.projection = orth_matrix;
+    self.projection = orthographic_projection(width as f32, height as f32);
+  }
+
+  /// Render the layers with a mask in the subtree of `layer` offscreen, nested ones
+  /// first, and multiply them by the alpha of their mask. render_layer composites them.
+  fn render_masked_layers(
+    layer: &mut Layer,
+    encoder: &mut wgpu::CommandEncoder,
+    parent_transform: Option<&Matrix4<f32>>,
+    rc: &RenderContext,
+  ) {
+    if !layer.visible {
+      return;
+    }
+
+    let mut transform = layer.model_matrix();
+    if let Some(parent) = parent_transform {
+      transform = parent * transform;
+    }
+    let sub_layer_transform = transform * layer.sublayer_matrix();
+    for sub_layer in layer.sub_layer_list.iter_mut() {
+      Self::render_masked_layers(sub_layer, encoder, Some(&sub_layer_transform), rc);
+    }
+    // The mask is positioned in the coordinate space of the layer
+    let Some(mask) = layer.mask.as_deref_mut() else {
+      return;
+    };
+    Self::render_masked_layers(mask, encoder, Some(&transform), rc);
+
+    let target = match layer.mask_target.take() {
+      Some(target) if target.size == rc.target_size => target,
+      _ => MaskTarget::new(rc),
+    };
+    {
+      let mut render_pass = begin_offscreen_pass(encoder, &target.content.view, rc);
+      Self::render_layer_content(layer, &mut render_pass, parent_transform, 0, rc);
+    }
+    if let Some(mask) = layer.mask.as_deref_mut() {
+      let mut render_pass = begin_offscreen_pass(encoder, &target.masked.view, rc);
+      Self::render_layer(mask, &mut render_pass, Some(&transform), 0, rc);
+      render_pass.set_stencil_reference(0);
+      render_pass.set_pipeline(rc.mask_pipeline);
+      render_pass.set_bind_group(0, &target.content.bind_group, &[]);
+      render_pass.draw(0..3, 0..1);
+    }
+    layer.mask_target = Some(target);
   }
 
-  /// Recursively render a layer and its sublayers
+  /// Recursively render a layer and its sublayers.
+  /// `clip_depth` is the stencil value inside the current clip region.
   fn render_layer(
     layer: &mut Layer,
     render_pass: &mut wgpu::RenderPass,
     parent_transform: Option<&Matrix4<f32>>,
-    context: &WgpuContext,
-    projection: &Matrix4<f32>,
-    render_pipeline: &wgpu::RenderPipeline,
-    bind_group_layout: &wgpu::BindGroupLayout,
-    texture_bind_group_layout: &wgpu::BindGroupLayout,
-    sampler: &wgpu::Sampler,
-    default_texture_view: &wgpu::TextureView,
+    clip_depth: u32,
+    rc: &RenderContext,
   ) {
     if !layer.visible {
       return;
     }
 
+    // A layer with a mask was rendered offscreen by render_masked_layers
+    if layer.mask.is_some() {
+      if let Some(target) = &layer.mask_target {
+        render_pass.set_stencil_reference(clip_depth);
+        render_pass.set_pipeline(rc.composite_pipeline);
+        render_pass.set_bind_group(0, &target.masked.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+      }
+      return;
+    }
+    Self::render_layer_content(layer, render_pass, parent_transform, clip_depth, rc);
+  }
+
+  /// Render a layer and its sublayers, ignoring the mask of the layer.
+  fn render_layer_content(
+    layer: &mut Layer,
+    render_pass: &mut wgpu::RenderPass,
+    parent_transform: Option<&Matrix4<f32>>,
+    clip_depth: u32,
+    rc: &RenderContext,
+  ) {
+    let context = rc.context;
+
     // Initialize buffers if needed
     if layer.vertex_buffer.is_none() || layer.index_buffer.is_none() {
       layer.init_buffers(&context.device);
@@ -357,6 +1009,7 @@ impl Play {
     if !layer.image_path.is_empty() && layer.texture.is_none() {
       layer.load_image_texture(&context.device, &context.queue);
     }
+    layer.update_text_texture(&context.device, &context.queue, rc.fonts);
 
     // Early return if buffers don't exist
     if layer.vertex_buffer.is_none() || layer.index_buffer.is_none() {
@@ -366,74 +1019,240 @@ impl Play {
     // Calculate transform
     let mut transform = layer.model_matrix();
     if let Some(parent) = parent_transform {
-      transform = transform * parent;
+      transform = parent * transform;
     }
 
-    // Create uniform buffer
-    let uniform_buffer = layer.create_uniform_buffer(&context.device, &transform, projection);
+    render_pass.set_stencil_reference(clip_depth);
 
-    // Create bind group for uniforms
-    let uniform_bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
-      label: Some("Uniform Bind Group"),
-      layout: bind_group_layout,
-      entries: &[wgpu::BindGroupEntry {
-        binding: 0,
-        resource: uniform_buffer.as_entire_binding(),
-      }],
-    });
+    if layer.double_sided || layer.is_front_facing(&(rc.projection * transform)) {
+      if layer.shape.is_some() {
+        // Shape layers draw their fill and stroke instead of the layer quad
+        Self::draw_shape(layer, render_pass, &transform, rc);
+      } else if layer.uses_glyph_atlas() {
+        // Glyph atlas text layers draw their glyph quads instead of the layer quad
+        Self::draw_text_mesh(layer, render_pass, &transform, rc);
+      } else {
+        // Draw the shadow behind the layer with the same quad
+        if layer.has_shadow() {
+          let shadow_buffer =
+            layer.create_shadow_uniform_buffer(&context.device, &transform, rc.projection);
+          Self::draw_layer_quad(layer, render_pass, &shadow_buffer, rc);
+        }
 
-    // Get or create texture bind group
-    let texture_bind_group = layer.get_or_create_bind_group(
-      &context.device,
-      texture_bind_group_layout,
-      sampler,
-      default_texture_view,
-    );
+        // Create uniform buffer
+        let uniform_buffer =
+          layer.create_uniform_buffer(&context.device, &transform, rc.projection);
+        Self::draw_layer_quad(layer, render_pass, &uniform_buffer, rc);
+      }
+    }
 
-    // Set pipeline and bindings
-    render_pass.set_pipeline(render_pipeline);
-    render_pass.set_bind_group(0, &uniform_bind_group, &[]);
-    render_pass.set_bind_group(1, texture_bind_group, &[]);
-    render_pass.set_vertex_buffer(0, layer.vertex_buffer.as_ref().unwrap().slice(..));
-    render_pass.set_index_buffer(
-      layer.index_buffer.as_ref().unwrap().slice(..),
-      wgpu::IndexFormat::Uint16,
-    );
-    render_pass.draw_indexed(0..6, 0, 0..1);
+    // Sublayers are positioned by the sublayer transform (e.g. perspective) of this layer
+    let sub_layer_transform = transform * layer.sublayer_matrix();
+
+    // Clip sublayers to the bounds of this layer
+    let mut sub_layer_depth = clip_depth;
+    if layer.masks_to_bounds && !layer.sub_layer_list.is_empty() {
+      Self::draw_clip_shape(layer, render_pass, &transform, clip_depth, ClipOp::Push, rc);
+      sub_layer_depth += 1;
+    }
 
     // Render sublayers (non-focused first, then focused)
-    for (i, sub_layer) in layer.sub_layer_list.iter_mut().enumerate() {
-      if !sub_layer.focused && i != layer.focused_sub_layer {
-        Self::render_layer(
-          sub_layer,
+    for i in layer.render_order() {
+      Self::render_layer(
+        &mut layer.sub_layer_list[i],
+        render_pass,
+        Some(&sub_layer_transform),
+        sub_layer_depth,
+        rc,
+      );
+    }
+
+    // Restore the clip region of the parent
+    if sub_layer_depth != clip_depth {
+      Self::draw_clip_shape(
+        layer,
+        render_pass,
+        &transform,
+        sub_layer_depth,
+        ClipOp::Pop,
+        rc,
+      );
+    }
+    render_pass.set_stencil_reference(clip_depth);
+  }
+
+  /// Push (increment) or pop (decrement) the rounded bounds of a layer in the stencil
+  /// buffer. Only pixels whose stencil equals `clip_depth` are affected.
+  fn draw_clip_shape(
+    layer: &mut Layer,
+    render_pass: &mut wgpu::RenderPass,
+    transform: &Matrix4<f32>,
+    clip_depth: u32,
+    op: ClipOp,
+    rc: &RenderContext,
+  ) {
+    let context = rc.context;
+
+    let pipeline = match op {
+      ClipOp::Push => rc.clip_push_pipeline,
+      ClipOp::Pop => rc.clip_pop_pipeline,
+    };
+    render_pass.set_stencil_reference(clip_depth);
+
+    let uniform_buffer =
+      layer.create_bounds_uniform_buffer(&context.device, transform, rc.projection);
+    Self::draw_layer_quad_with_pipeline(layer, render_pass, &uniform_buffer, pipeline, rc);
+  }
+
+  /// Draw the layer quad with the given uniforms
+  fn draw_layer_quad(
+    layer: &mut Layer,
+    render_pass: &mut wgpu::RenderPass,
+    uniform_buffer: &wgpu::Buffer,
+    rc: &RenderContext,
+  ) {
+    Self::draw_layer_quad_with_pipeline(layer, render_pass, uniform_buffer, rc.render_pipeline, rc);
+  }
+
+  fn draw_layer_quad_with_pipeline(
+    layer: &mut Layer,
+    render_pass: &mut wgpu::RenderPass,
+    uniform_buffer: &wgpu::Buffer,
+    pipeline: &wgpu::RenderPipeline,
+    rc: &RenderContext,
+  ) {
+    Self::prepare_bind_group(layer, rc);
+    if let (Some(vertex_buffer), Some(index_buffer), Some(bind_group)) =
+      (&layer.vertex_buffer, &layer.index_buffer, &layer.bind_group)
+    {
+      let mesh = DrawMesh {
+        vertex_buffer,
+        index_buffer,
+        index_format: wgpu::IndexFormat::Uint16,
+        index_count: 6,
+      };
+      Self::draw_mesh(bind_group, render_pass, uniform_buffer, pipeline, mesh, rc);
+    }
+  }
+
+  /// Draw the tessellated fill and stroke of a shape layer
+  fn draw_shape(
+    layer: &mut Layer,
+    render_pass: &mut wgpu::RenderPass,
+    transform: &Matrix4<f32>,
+    rc: &RenderContext,
+  ) {
+    let context = rc.context;
+    layer.update_shape_buffers(&context.device);
+    Self::prepare_bind_group(layer, rc);
+
+    let (Some(shape), Some(buffers), Some(bind_group)) =
+      (&layer.shape, &layer.shape_buffers, &layer.bind_group)
+    else {
+      return;
+    };
+    let parts = [
+      (shape.fill_paint(), &buffers.fill),
+      (shape.stroke_paint(), &buffers.stroke),
+    ];
+    for (paint, mesh_buffers) in parts {
+      if let (Some((color, gradient)), Some(mesh_buffers)) = (paint, mesh_buffers) {
+        let uniform_buffer = layer.create_shape_uniform_buffer(
+          &context.device,
+          transform,
+          rc.projection,
+          color,
+          gradient,
+        );
+        let mesh = DrawMesh {
+          vertex_buffer: &mesh_buffers.vertex_buffer,
+          index_buffer: &mesh_buffers.index_buffer,
+          index_format: wgpu::IndexFormat::Uint32,
+          index_count: mesh_buffers.index_count,
+        };
+        Self::draw_mesh(
+          bind_group,
           render_pass,
-          Some(&transform),
-          context,
-          projection,
-          render_pipeline,
-          bind_group_layout,
-          texture_bind_group_layout,
-          sampler,
-          default_texture_view,
+          &uniform_buffer,
+          rc.render_pipeline,
+          mesh,
+          rc,
         );
       }
     }
+  }
 
-    // Render focused sublayer last
-    if !layer.sub_layer_list.is_empty() && layer.focused_sub_layer < layer.sub_layer_list.len() {
-      Self::render_layer(
-        &mut layer.sub_layer_list[layer.focused_sub_layer],
-        render_pass,
-        Some(&transform),
-        context,
-        projection,
-        render_pipeline,
-        bind_group_layout,
-        texture_bind_group_layout,
-        sampler,
-        default_texture_view,
-      );
+  /// Draw the glyph quads of a text layer with the glyph atlas texture
+  fn draw_text_mesh(
+    layer: &Layer,
+    render_pass: &mut wgpu::RenderPass,
+    transform: &Matrix4<f32>,
+    rc: &RenderContext,
+  ) {
+    let (Some(mesh_buffers), Some(atlas_bind_group)) =
+      (&layer.text_mesh, rc.glyph_atlas_bind_group)
+    else {
+      return;
+    };
+    if mesh_buffers.index_count == 0 {
+      return;
     }
+    let uniform_buffer =
+      layer.create_text_uniform_buffer(&rc.context.device, transform, rc.projection);
+    let mesh = DrawMesh {
+      vertex_buffer: &mesh_buffers.vertex_buffer,
+      index_buffer: &mesh_buffers.index_buffer,
+      index_format: wgpu::IndexFormat::Uint32,
+      index_count: mesh_buffers.index_count,
+    };
+    Self::draw_mesh(
+      atlas_bind_group,
+      render_pass,
+      &uniform_buffer,
+      rc.render_pipeline,
+      mesh,
+      rc,
+    );
+  }
+
+  /// Create the texture bind group of the layer if needed
+  fn prepare_bind_group(layer: &mut Layer, rc: &RenderContext) {
+    layer.get_or_create_bind_group(
+      &rc.context.device,
+      rc.texture_bind_group_layout,
+      rc.sampler,
+      rc.default_texture_view,
+    );
+  }
+
+  fn draw_mesh(
+    texture_bind_group: &wgpu::BindGroup,
+    render_pass: &mut wgpu::RenderPass,
+    uniform_buffer: &wgpu::Buffer,
+    pipeline: &wgpu::RenderPipeline,
+    mesh: DrawMesh,
+    rc: &RenderContext,
+  ) {
+    // Create bind group for uniforms
+    let uniform_bind_group = rc
+      .context
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Uniform Bind Group"),
+        layout: rc.bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+          binding: 0,
+          resource: uniform_buffer.as_entire_binding(),
+        }],
+      });
+
+    // Set pipeline and bindings
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, &uniform_bind_group, &[]);
+    render_pass.set_bind_group(1, texture_bind_group, &[]);
+    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+    render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+    render_pass.draw_indexed(0..mesh.index_count, 0, 0..1);
   }
 
   pub fn new_layer(
@@ -474,14 +1293,525 @@ impl Play {
     stage_name
   }
 
-  pub fn handle_input(&mut self, key: Key) {
-    // println!("key: {}", key);
+  /// Get a stage by name, e.g. to update its layers between frames
+  pub fn stage_mut(&mut self, name: &str) -> Option<&mut Layer> {
+    let index = *self.stage_map.get(name)?;
+    self.stage_list.get_mut(index)
+  }
+
+  /// Send a key press to the focused layers (see `handle_key_input`)
+  pub fn handle_input(&mut self, key: Key) -> bool {
+    self.handle_key_input(KeyInput::Down { key, repeat: false })
+  }
+
+  /// Send a key down or up to the focused layer of every stage in turn, until a
+  /// layer stops its propagation (see `crate::event`). Modifier keys update
+  /// the modifiers, and arrow keys and Tab that no layer handled move the focus
+  /// (see `move_focus` and `focus_next`). Returns true if a layer handled it or
+  /// the focus moved.
+  pub fn handle_key_input(&mut self, input: KeyInput) -> bool {
+    // println!("key: {:?}", input);
+    self.record(InputEvent::Key(input));
+    self.update_focus();
+    let (kind, key, repeat) = match input {
+      KeyInput::Down { key, repeat } => (KeyEventKind::Down, key, repeat),
+      KeyInput::Up { key } => (KeyEventKind::Up, key, false),
+    };
+    let pressed = kind == KeyEventKind::Down;
+    match key {
+      Key::Shift => self.modifiers.shift = pressed,
+      Key::Control => self.modifiers.control = pressed,
+      Key::Alt => self.modifiers.alt = pressed,
+      Key::Logo => self.modifiers.logo = pressed,
+      _ => {}
+    }
+    let mut handled = false;
+    for stage in self.stage_list.iter_mut() {
+      let mut event = KeyEvent::new(key, self.modifiers, stage.focus_path());
+      event.kind = kind;
+      event.repeat = repeat;
+      stage.dispatch_key_event(&mut event);
+      handled |= event.is_handled();
+      if event.is_propagation_stopped() {
+        break;
+      }
+    }
+    if !handled && kind == KeyEventKind::Down {
+      if let Some(direction) = FocusDirection::from_key(key) {
+        handled = self.move_focus(direction);
+      } else if key == Key::Tab {
+        handled = match self.modifiers.shift {
+          true => self.focus_previous(),
+          false => self.focus_next(),
+        };
+      }
+    }
+    handled
+  }
+
+  /// Move the focus to the nearest focusable layer in the direction, across the
+  /// shown stages (see `crate::focus`). Returns true if the focus moved.
+  pub fn move_focus(&mut self, direction: FocusDirection) -> bool {
+    let current = focus::current(&self.stage_list);
+    match focus::find_next(&self.stage_list, current.as_ref(), direction) {
+      Some(next) => self.focus_layer(next),
+      None => false,
+    }
+  }
+
+  /// The focused layer: the index of its stage and its path in the stage. It is
+  /// the deepest focusable layer (see `Layer::set_focusable`) on the focus path
+  /// of the topmost shown stage that has one.
+  pub fn current_focus(&self) -> Option<(usize, Vec<usize>)> {
+    focus::current(&self.stage_list)
+  }
+
+  pub fn focused_layer(&self) -> Option<&Layer> {
+    let (stage, path) = self.current_focus()?;
+    self.stage_list[stage].sublayer_at_path(&path)
+  }
+
+  /// Focus the first shown layer called `name`. A focus group gives the layer
+  /// focused last in it. Returns false if the layer can't be focused.
+  pub fn request_focus(&mut self, name: &str) -> bool {
+    let Some(target) = focus::find_by_name(&self.stage_list, name) else {
+      return false;
+    };
+    let rect = focus::screen_bounds(&self.stage_list[target.0], &target.1);
+    let target = rect.and_then(|rect| focus::resolve(&self.stage_list, target, &rect, None));
+    match target {
+      Some(target) if focus::can_focus(&self.stage_list, &target) => self.focus_layer(target),
+      _ => false,
+    }
+  }
+
+  /// Focus the next focusable layer in tree order, after the last one going back
+  /// to the first (Tab)
+  pub fn focus_next(&mut self) -> bool {
+    self.focus_in_order(true)
+  }
+
+  /// Focus the previous focusable layer in tree order (Shift+Tab)
+  pub fn focus_previous(&mut self) -> bool {
+    self.focus_in_order(false)
+  }
+
+  fn focus_in_order(&mut self, forward: bool) -> bool {
+    let order = focus::in_order(&self.stage_list);
+    if order.is_empty() {
+      return false;
+    }
+    let current = focus::current(&self.stage_list);
+    let index = current.and_then(|current| order.iter().position(|target| *target == current));
+    let next = match (index, forward) {
+      (Some(index), true) => (index + 1) % order.len(),
+      (Some(index), false) => (index + order.len() - 1) % order.len(),
+      (None, true) => 0,
+      (None, false) => order.len() - 1,
+    };
+    self.focus_layer(order[next].clone())
+  }
+
+  /// Focus the layer that was focused last in a stage, e.g. when the stage is
+  /// shown again, or its first focusable layer. Returns true if it is focused.
+  pub fn restore_focus(&mut self, stage_name: &str) -> bool {
+    let Some(&index) = self.stage_map.get(stage_name) else {
+      return false;
+    };
+    let target = focus::restore_stage(&self.stage_list, index).or_else(|| {
+      focus::in_order(&self.stage_list)
+        .into_iter()
+        .find(|target| target.0 == index)
+    });
+    let Some(target) = target else {
+      return false;
+    };
+    if !self.focus_layer(target.clone()) {
+      self.update_focus();
+    }
+    self.current_focus() == Some(target)
+  }
+
+  /// Call `handler` with the old and new focused layer when the focus changes
+  pub fn set_focus_change_handler(&mut self, handler: Option<FocusChangeHandler>) {
+    self.focus_change_handler = handler;
+  }
+
+  // Move the focus from the focused layer to `target`
+  fn focus_layer(&mut self, target: LayerTarget) -> bool {
+    let current = focus::current(&self.stage_list);
+    if current.as_ref() == Some(&target) {
+      return false;
+    }
+    focus::set_focus_target(&mut self.stage_list, current.as_ref(), &target);
+    self.update_focus();
+    true
+  }
+
+  /// Keep the focus on a shown layer and report its changes. Without a focused
+  /// layer, e.g. at the start or after the stage of the focused layer was
+  /// hidden, the topmost shown stage with a focusable layer gets the focus
+  /// back (see `restore_focus`). Only that stage keeps a focused layer. Called
+  /// by `render` and for key input.
+  pub fn update_focus(&mut self) {
+    let current = focus::current(&self.stage_list).or_else(|| {
+      let target = focus::restore(&self.stage_list)?;
+      focus::set_focus_target(&mut self.stage_list, None, &target);
+      Some(target)
+    });
+    if let Some((focused_stage, _)) = current {
+      for (index, stage) in self.stage_list.iter_mut().enumerate() {
+        if index != focused_stage && stage.visible {
+          focus::clear(stage);
+        }
+      }
+    }
+    // The name is kept with the target, since the old target may be gone or
+    // point to another layer by now
+    let current = current.and_then(|(stage, path)| {
+      let name = self
+        .stage_list
+        .get(stage)?
+        .sublayer_at_path(&path)?
+        .name
+        .clone();
+      Some(((stage, path), name))
+    });
+    if current == self.focus {
+      return;
+    }
+    let (old, old_name) = std::mem::replace(&mut self.focus, current).unzip();
+    let (new, new_name) = self.focus.clone().unzip();
+    let change = FocusChange {
+      old,
+      new,
+      old_name,
+      new_name,
+    };
+    if let Some(handler) = self.focus_change_handler.as_mut() {
+      handler(&change);
+    }
+  }
+
+  /// Set the modifier keys held down, e.g. from winit's ModifiersChanged event.
+  /// They are sent with the following keys.
+  pub fn set_modifiers(&mut self, modifiers: Modifiers) {
+    self.record(InputEvent::Modifiers(modifiers));
+    self.modifiers = modifiers;
+  }
+
+  pub fn modifiers(&self) -> Modifiers {
+    self.modifiers
+  }
+
+  /// Send typed text to the focused layers, e.g. the text of winit's KeyEvent
+  pub fn handle_text_input(&mut self, text: &str) {
+    self.record(InputEvent::Text(text.to_string()));
+    self.update_focus();
     for stage in self.stage_list.iter_mut() {
-      stage.handle_input(key);
+      stage.handle_text_input(text);
+    }
+  }
+
+  /// Find the topmost layer at a window point. Returns the index of its stage
+  /// (stages added later are on top) and its path (see `Layer::sublayer_at_path`).
+  pub fn hit_test(&self, x: f32, y: f32) -> Option<(usize, Vec<usize>)> {
+    self
+      .stage_list
+      .iter()
+      .enumerate()
+      .rev()
+      .find_map(|(index, stage)| stage.hit_test(x, y).map(|path| (index, path)))
+  }
+
+  /// Send pointer input to the topmost layer under the pointer. A pointer that
+  /// goes down is captured by the layer under it until it goes up or is
+  /// cancelled. Enter and Leave are sent when the pointer moves between layers.
+  pub fn handle_pointer_input(&mut self, input: PointerInput) {
+    self.handle_pointer_input_at(input, self.now());
+  }
+
+  // Handle pointer input that happened at `time`, which gesture recognizers use
+  // for timeouts and velocities
+  pub(crate) fn handle_pointer_input_at(&mut self, input: PointerInput, time: Instant) {
+    self.record(InputEvent::Pointer(input));
+    match input {
+      PointerInput::Down {
+        pointer,
+        button,
+        x,
+        y,
+      } => {
+        let target = self.hover(pointer, x, y);
+        if let Some(state) = self.pointers.get_mut(&pointer) {
+          state.captured = target.is_some();
+          state.claimed = false;
+        }
+        let mut event = PointerEvent::new(PointerEventKind::Down, pointer, x, y);
+        event.button = Some(button);
+        self.dispatch_pointer_event(target.as_ref(), event, time);
+      }
+      PointerInput::Move { pointer, x, y } => {
+        let target = match self.pointers.get_mut(&pointer) {
+          Some(state) if state.captured => {
+            (state.x, state.y) = (x, y);
+            state.target.clone()
+          }
+          _ => self.hover(pointer, x, y),
+        };
+        let event = PointerEvent::new(PointerEventKind::Move, pointer, x, y);
+        self.dispatch_pointer_event(target.as_ref(), event, time);
+      }
+      PointerInput::Up {
+        pointer,
+        button,
+        x,
+        y,
+      } => {
+        let target = match self.pointers.get_mut(&pointer) {
+          Some(state) if state.captured => {
+            state.captured = false;
+            state.target.clone()
+          }
+          _ => self.hover(pointer, x, y),
+        };
+        let mut event = PointerEvent::new(PointerEventKind::Up, pointer, x, y);
+        event.button = Some(button);
+        self.dispatch_pointer_event(target.as_ref(), event, time);
+        if let Some(state) = self.pointers.get_mut(&pointer) {
+          state.claimed = false;
+        }
+        match pointer {
+          // A lifted finger leaves the layer
+          PointerId::Touch(_) => self.remove_pointer(pointer),
+          PointerId::Mouse => {
+            self.hover(pointer, x, y);
+          }
+        }
+      }
+      PointerInput::Cancel { pointer } => {
+        if let Some(state) = self.pointers.get(&pointer).filter(|state| state.captured) {
+          let event = PointerEvent::new(PointerEventKind::Cancel, pointer, state.x, state.y);
+          let target = state.target.clone();
+          self.dispatch_pointer_event(target.as_ref(), event, time);
+        }
+        self.remove_pointer(pointer);
+      }
+      PointerInput::Leave { pointer } => {
+        // A captured pointer keeps its layer until it is released
+        if !self
+          .pointers
+          .get(&pointer)
+          .is_some_and(|state| state.captured)
+        {
+          self.remove_pointer(pointer);
+        }
+      }
+      PointerInput::Scroll { delta_x, delta_y } => {
+        let Some((x, y)) = self
+          .pointers
+          .get(&PointerId::Mouse)
+          .map(|state| (state.x, state.y))
+        else {
+          return;
+        };
+        let mut event = PointerEvent::new(PointerEventKind::Scroll, PointerId::Mouse, x, y);
+        event.scroll_delta = (delta_x, delta_y);
+        let target = self.hit_test(x, y);
+        self.send_pointer_event(target.as_ref(), event);
+      }
+    }
+  }
+
+  // Move a pointer that isn't captured to the layer under it and send Leave and
+  // Enter if that layer changed. Returns the layer.
+  fn hover(&mut self, pointer: PointerId, x: f32, y: f32) -> Option<LayerTarget> {
+    let target = self.hit_test(x, y);
+    let state = self.pointers.entry(pointer).or_default();
+    (state.x, state.y) = (x, y);
+    if state.target != target {
+      let previous = std::mem::replace(&mut state.target, target.clone());
+      let leave = PointerEvent::new(PointerEventKind::Leave, pointer, x, y);
+      self.send_pointer_event(previous.as_ref(), leave);
+      let enter = PointerEvent::new(PointerEventKind::Enter, pointer, x, y);
+      self.send_pointer_event(target.as_ref(), enter);
+    }
+    target
+  }
+
+  /// Advance the gesture recognizers of every stage to the time of the clock,
+  /// for gestures that are recognized by waiting, like long presses. Called by
+  /// `render`.
+  pub fn update_gestures(&mut self) {
+    self.update_gestures_at(self.now());
+  }
+
+  pub(crate) fn update_gestures_at(&mut self, time: Instant) {
+    for index in 0..self.stage_list.len() {
+      let claimed = gesture::process(&mut self.stage_list[index], None, time);
+      self.cancel_claimed_pointers(&claimed);
+    }
+  }
+
+  // Send a pointer event of the layer at `target` to the gesture recognizers of
+  // its stage, then to the layer unless a gesture claimed the pointer
+  fn dispatch_pointer_event(
+    &mut self,
+    target: Option<&LayerTarget>,
+    mut event: PointerEvent,
+    time: Instant,
+  ) {
+    let Some((stage_index, path)) = target else {
+      return;
+    };
+    let Some(stage) = self.stage_list.get_mut(*stage_index) else {
+      return;
+    };
+    event.modifiers = self.modifiers;
+    let claimed = gesture::process(stage, Some((path, &event)), time);
+    self.cancel_claimed_pointers(&claimed);
+    if !self
+      .pointers
+      .get(&event.pointer)
+      .is_some_and(|state| state.claimed)
+    {
+      self.send_pointer_event(target, event);
+    }
+  }
+
+  // Send Cancel to the layers of pointers that a gesture recognized, once
+  fn cancel_claimed_pointers(&mut self, claimed: &[PointerId]) {
+    for pointer in claimed {
+      let Some(state) = self.pointers.get_mut(pointer) else {
+        continue;
+      };
+      if state.claimed {
+        continue;
+      }
+      state.claimed = true;
+      let event = PointerEvent::new(PointerEventKind::Cancel, *pointer, state.x, state.y);
+      let target = state.target.clone();
+      self.send_pointer_event(target.as_ref(), event);
+    }
+  }
+
+  // Forget a pointer, sending Leave to the layer it was over
+  fn remove_pointer(&mut self, pointer: PointerId) {
+    if let Some(state) = self.pointers.remove(&pointer) {
+      let event = PointerEvent::new(PointerEventKind::Leave, pointer, state.x, state.y);
+      self.send_pointer_event(state.target.as_ref(), event);
+    }
+  }
+
+  // Send an event to a layer, with the point in the coordinates of the layer
+  fn send_pointer_event(&mut self, target: Option<&LayerTarget>, mut event: PointerEvent) {
+    let Some((stage_index, path)) = target else {
+      return;
+    };
+    let Some(stage) = self.stage_list.get_mut(*stage_index) else {
+      return;
+    };
+    if let Some(((x, y), _)) =
+      path_transform(stage, path).and_then(|transform| unproject(&transform, event.x, event.y))
+    {
+      (event.local_x, event.local_y) = (x, y);
+    }
+    event.modifiers = self.modifiers;
+    if let Some(layer) = stage.sublayer_at_path_mut(path) {
+      layer.handle_pointer_event(&event);
+    }
+  }
+
+  /// Send input to the `handle_*` method of its kind
+  pub fn handle_input_event(&mut self, event: &InputEvent) {
+    match event {
+      InputEvent::Key(input) => {
+        self.handle_key_input(*input);
+      }
+      InputEvent::Text(text) => self.handle_text_input(text),
+      InputEvent::Modifiers(modifiers) => self.set_modifiers(*modifiers),
+      InputEvent::Pointer(input) => self.handle_pointer_input(*input),
     }
   }
 
+  /// Set the clock that animations, gestures and caret blinks run by. A
+  /// `Clock::manual()` only moves with `advance_clock`, for tests and replays.
+  pub fn set_clock(&mut self, clock: Clock) {
+    self.clock = clock;
+  }
+
+  pub fn clock(&self) -> Clock {
+    self.clock
+  }
+
+  /// Move a manual clock forward. The system clock can't be moved.
+  pub fn advance_clock(&mut self, duration: Duration) {
+    if let Clock::Manual { elapsed, .. } = &mut self.clock {
+      *elapsed += duration;
+    }
+  }
+
+  /// The time of the clock
+  pub fn now(&self) -> Instant {
+    self.clock.now()
+  }
+
+  /// Record the input given to this play from now on (see `crate::recording`),
+  /// replacing a recording in progress
+  pub fn start_recording(&mut self) {
+    self.recording = Some((self.now(), InputRecording::new()));
+  }
+
+  /// Stop recording and return the input recorded since `start_recording`
+  pub fn stop_recording(&mut self) -> Option<InputRecording> {
+    self.recording.take().map(|(_, recording)| recording)
+  }
+
+  pub fn is_recording(&self) -> bool {
+    self.recording.is_some()
+  }
+
+  fn record(&mut self, event: InputEvent) {
+    let now = self.now();
+    if let Some((start, recording)) = self.recording.as_mut() {
+      recording.push(now.saturating_duration_since(*start), event);
+    }
+  }
+
+  /// Send the input of a recording again on a new manual clock, each event at
+  /// its time, and render a frame every `frame_interval` in between, calling
+  /// `frame` after each. The last frame is the first one after the last event.
+  /// The clock is left manual, at the time of the last frame.
+  pub fn replay(
+    &mut self,
+    recording: &InputRecording,
+    frame_interval: Duration,
+    mut frame: impl FnMut(&mut Play),
+  ) {
+    assert!(!frame_interval.is_zero(), "frame interval must not be zero");
+    let start = Instant::now();
+    let set_time = |play: &mut Play, elapsed| play.clock = Clock::Manual { start, elapsed };
+    let mut next_frame = Duration::ZERO;
+    for input in &recording.events {
+      while next_frame < input.time {
+        set_time(self, next_frame);
+        self.render();
+        frame(self);
+        next_frame += frame_interval;
+      }
+      set_time(self, input.time);
+      self.handle_input_event(&input.event);
+    }
+    set_time(self, next_frame);
+    self.render();
+    frame(self);
+  }
+
   pub fn render(&mut self) {
+    let now = self.now();
+    self.update_gestures_at(now);
+    self.update_focus();
+
     // Update animations and layout
     for stage in self.stage_list.iter_mut() {
       if stage.needs_update {
@@ -497,7 +1827,7 @@ impl Play {
         stage.needs_update = false;
       }
 
-      stage.animate();
+      stage.animate_at(now);
       stage.render(None, &self.projection);
     }
 
@@ -522,6 +1852,54 @@ impl Play {
             label: Some("Render Encoder"),
           });
 
+        // Glyphs must be in the atlas before the render pass. Meshes built before
+        // the atlas was cleared are built again in the second pass.
+        for _ in 0..2 {
+          let mut current = true;
+          for stage in self.stage_list.iter_mut() {
+            current &=
+              Self::update_text_meshes(stage, context, &self.font_registry, &mut self.glyph_atlas);
+          }
+          if current {
+            break;
+          }
+        }
+        self.glyph_atlas.upload(&context.device, &context.queue);
+        let glyph_atlas_bind_group = self.glyph_atlas.bind_group(
+          &context.device,
+          self.texture_bind_group_layout.as_ref().unwrap(),
+          self.sampler.as_ref().unwrap(),
+        );
+
+        let (target_format, target_size) = context
+          .surface_config
+          .as_ref()
+          .map(|c| (c.format, (c.width.max(1), c.height.max(1))))
+          .unwrap_or((wgpu::TextureFormat::Bgra8UnormSrgb, (1, 1)));
+        let rc = RenderContext {
+          context,
+          projection: &self.projection,
+          render_pipeline: self.render_pipeline.as_ref().unwrap(),
+          clip_push_pipeline: self.clip_push_pipeline.as_ref().unwrap(),
+          clip_pop_pipeline: self.clip_pop_pipeline.as_ref().unwrap(),
+          mask_pipeline: self.mask_pipeline.as_ref().unwrap(),
+          composite_pipeline: self.composite_pipeline.as_ref().unwrap(),
+          bind_group_layout: self.bind_group_layout.as_ref().unwrap(),
+          texture_bind_group_layout: self.texture_bind_group_layout.as_ref().unwrap(),
+          sampler: self.sampler.as_ref().unwrap(),
+          default_texture_view: self.default_texture_view.as_ref().unwrap(),
+          fonts: &self.font_registry,
+          glyph_atlas_bind_group,
+          stencil_view: self.stencil_view.as_ref(),
+          target_format,
+          target_size,
+        };
+
+        // Masked layers are rendered offscreen before the render pass
+        for stage in self.stage_list.iter_mut() {
+          Self::render_masked_layers(stage, &mut encoder, None, &rc);
+        }
+
         {
           let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
@@ -538,25 +1916,14 @@ impl Play {
                 store: wgpu::StoreOp::Store,
               },
             })],
-            depth_stencil_attachment: None,
+            depth_stencil_attachment: rc.stencil_view.map(stencil_attachment),
             timestamp_writes: None,
             occlusion_query_set: None,
           });
-          
+
           // Render all stages
           for stage in self.stage_list.iter_mut() {
-            Self::render_layer(
-              stage,
-              &mut render_pass,
-              None,
-              context,
-              &self.projection,
-              self.render_pipeline.as_ref().unwrap(),
-              self.bind_group_layout.as_ref().unwrap(),
-              self.texture_bind_group_layout.as_ref().unwrap(),
-              self.sampler.as_ref().unwrap(),
-              self.default_texture_view.as_ref().unwrap(),
-            );
+            Self::render_layer(stage, &mut render_pass, None, 0, &rc);
           }
         }
 
@@ -566,3 +1933,62 @@ impl Play {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The result of blending src over dst with the given state, as the GPU does it
+  fn blend(state: wgpu::BlendState, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    let factor = |factor: wgpu::BlendFactor| match factor {
+      wgpu::BlendFactor::Zero => 0.0,
+      wgpu::BlendFactor::One => 1.0,
+      wgpu::BlendFactor::SrcAlpha => src[3],
+      wgpu::BlendFactor::OneMinusSrcAlpha => 1.0 - src[3],
+      wgpu::BlendFactor::DstAlpha => dst[3],
+      wgpu::BlendFactor::OneMinusDstAlpha => 1.0 - dst[3],
+      factor => unimplemented!("{:?}", factor),
+    };
+    let mut out = [0.0; 4];
+    for i in 0..4 {
+      let component = if i < 3 { state.color } else { state.alpha };
+      assert_eq!(component.operation, wgpu::BlendOperation::Add);
+      out[i] = src[i] * factor(component.src_factor) + dst[i] * factor(component.dst_factor);
+    }
+    out
+  }
+
+  // A pixel of a masked layer, drawn offscreen and composited over the background
+  fn composite_masked(content: [f32; 4], mask: [f32; 4], background: [f32; 4]) -> [f32; 4] {
+    let transparent = [0.0; 4];
+    let content = blend(LAYER_BLEND, content, transparent);
+    let mask = blend(LAYER_BLEND, mask, transparent);
+    let masked = blend(MASK_BLEND, content, mask);
+    blend(COMPOSITE_BLEND, masked, background)
+  }
+
+  fn assert_color_eq(actual: [f32; 4], expected: [f32; 4]) {
+    for (a, e) in actual.iter().zip(expected) {
+      assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+    }
+  }
+
+  #[test]
+  fn test_mask_alpha_modulates_coverage() {
+    let red = [1.0, 0.0, 0.0, 1.0];
+    let black = [0.0, 0.0, 0.0, 1.0];
+
+    // A 50% alpha mask pixel covers half of the layer
+    let half = composite_masked(red, [1.0, 1.0, 1.0, 0.5], black);
+    assert_color_eq(half, [0.5, 0.0, 0.0, 1.0]);
+
+    // The color of the mask doesn't matter, only its alpha
+    assert_color_eq(composite_masked(red, [0.0, 0.0, 0.0, 1.0], black), red);
+    assert_color_eq(composite_masked(red, [1.0, 1.0, 1.0, 0.0], black), black);
+
+    // Mask and layer alpha multiply
+    let half_red = [1.0, 0.0, 0.0, 0.5];
+    let quarter = composite_masked(half_red, [1.0, 1.0, 1.0, 0.5], black);
+    assert_color_eq(quarter, [0.25, 0.0, 0.0, 1.0]);
+  }
+}
@@ -0,0 +1,347 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Pointer (mouse, pen and touch) input.
+//!
+//! The windowing system sends `PointerInput` to `Play::handle_pointer_input` in
+//! window coordinates. Play hit tests the stages (see `Layer::hit_test`) and
+//! sends a `PointerEvent` to the event handler of the topmost layer under the
+//! pointer. A pointer that goes down on a layer is captured by it: its moves and
+//! its release go to that layer even outside of its bounds. Every touch is a
+//! separate pointer, so several layers can be touched at the same time.
+
+use cgmath::{Matrix3, Matrix4, SquareMatrix, Vector3};
+
+use crate::layer::{Layer, Modifiers};
+
+/// Identifies a pointer. There is one mouse, and every touch has its own id
+/// (e.g. winit's `Touch::id`) for as long as the finger is down.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PointerId {
+  Mouse,
+  Touch(u64),
+}
+
+/// The button of a pointer. Touches are `Primary`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PointerButton {
+  Primary,
+  Secondary,
+  Middle,
+  Other(u16),
+}
+
+/// Pointer input from the windowing system, in window coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PointerInput {
+  Down {
+    pointer: PointerId,
+    button: PointerButton,
+    x: f32,
+    y: f32,
+  },
+  Move {
+    pointer: PointerId,
+    x: f32,
+    y: f32,
+  },
+  Up {
+    pointer: PointerId,
+    button: PointerButton,
+    x: f32,
+    y: f32,
+  },
+  /// The system took the pointer over, e.g. a touch turned into a system gesture
+  Cancel {
+    pointer: PointerId,
+  },
+  /// The pointer left the window
+  Leave {
+    pointer: PointerId,
+  },
+  /// The scroll wheel or touchpad scrolled at the mouse position, in pixels
+  Scroll {
+    delta_x: f32,
+    delta_y: f32,
+  },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PointerEventKind {
+  Down,
+  Move,
+  Up,
+  /// The pointer moved onto the layer
+  Enter,
+  /// The pointer moved off the layer, was released (touches) or left the window
+  Leave,
+  Scroll,
+  /// The pointer was cancelled while it was captured by the layer
+  Cancel,
+}
+
+/// A pointer event sent to `EventHandler::pointer_event`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PointerEvent {
+  pub kind: PointerEventKind,
+  pub pointer: PointerId,
+  pub button: Option<PointerButton>, // for Down and Up
+  pub x: f32,                        // in window coordinates
+  pub y: f32,
+  pub local_x: f32, // in the coordinates of the layer, from its top left corner
+  pub local_y: f32,
+  pub scroll_delta: (f32, f32), // for Scroll
+  pub modifiers: Modifiers,
+}
+
+impl PointerEvent {
+  pub fn new(kind: PointerEventKind, pointer: PointerId, x: f32, y: f32) -> Self {
+    PointerEvent {
+      kind,
+      pointer,
+      button: None,
+      x,
+      y,
+      local_x: x,
+      local_y: y,
+      scroll_delta: (0.0, 0.0),
+      modifiers: Modifiers::default(),
+    }
+  }
+}
+
+/// Map a window point onto the z = 0 plane of a layer drawn with `transform`
+/// (the model matrices of the layer and its ancestors). Returns the point in
+/// layer coordinates and whether the layer faces the viewer there, or None if
+/// the plane is seen edge on or the point is behind the viewer.
+pub(crate) fn unproject(transform: &Matrix4<f32>, x: f32, y: f32) -> Option<((f32, f32), bool)> {
+  // The plane maps to the window with a homography: rows x, y and w of the
+  // matrix, columns x, y and translation
+  let t = transform;
+  let homography = Matrix3::new(
+    t.x.x, t.x.y, t.x.w, // column for x
+    t.y.x, t.y.y, t.y.w, // column for y
+    t.w.x, t.w.y, t.w.w, // column for the translation
+  );
+  let inverse = homography.invert()?;
+  let local = inverse * Vector3::new(x, y, 1.0);
+  if local.z.abs() < f32::EPSILON {
+    return None;
+  }
+  let point = (local.x / local.z, local.y / local.z);
+  // w of the point must be positive to be in front of the viewer
+  let w = t.x.w * point.0 + t.y.w * point.1 + t.w.w;
+  if w <= 0.0 {
+    return None;
+  }
+  Some((point, homography.determinant() > 0.0))
+}
+
+/// The transform of the layer at `path` below `root`, as used for drawing it:
+/// the model matrices of every layer on the path and the sublayer matrices of
+/// their parents.
+pub(crate) fn path_transform(root: &Layer, path: &[usize]) -> Option<Matrix4<f32>> {
+  let mut transform = root.model_matrix();
+  let mut layer = root;
+  for &index in path {
+    let sub_layer = layer.sub_layer_list.get(index)?;
+    transform = transform * layer.sublayer_matrix() * sub_layer.model_matrix();
+    layer = sub_layer;
+  }
+  Some(transform)
+}
+
+/// Returns true if a point in layer coordinates is inside the rounded bounds of
+/// a layer
+pub(crate) fn rounded_bounds_contain(layer: &Layer, (x, y): (f32, f32)) -> bool {
+  let (width, height) = (layer.width as f32, layer.height as f32);
+  if x < 0.0 || y < 0.0 || x >= width || y >= height {
+    return false;
+  }
+  let radius = layer.corner_radius.min(width / 2.0).min(height / 2.0);
+  if radius <= 0.0 {
+    return true;
+  }
+  // Distance from the center of the nearest corner circle
+  let dx = (radius - x).max(x - (width - radius)).max(0.0);
+  let dy = (radius - y).max(y - (height - radius)).max(0.0);
+  dx * dx + dy * dy <= radius * radius
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::layer::{EventHandler, Key, LayoutMode};
+  use crate::play::Play;
+  use cgmath::Deg;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  type Log = Rc<RefCell<Vec<(String, PointerEventKind, f32, f32)>>>;
+
+  // Records the pointer events of a layer with their local coordinates
+  struct Recorder(Log);
+
+  impl EventHandler for Recorder {
+    fn key_focus_in(&mut self, _layer: &mut Layer) {}
+    fn key_focus_out(&mut self, _layer: &mut Layer) {}
+    fn key_down(&mut self, _key: Key, _layer: &mut Layer) -> bool {
+      false
+    }
+    fn pointer_event(&mut self, event: &PointerEvent, layer: &mut Layer) {
+      let entry = (layer.name.clone(), event.kind, event.local_x, event.local_y);
+      self.0.borrow_mut().push(entry);
+    }
+  }
+
+  #[test]
+  fn test_unproject() {
+    let translation = Matrix4::from_translation(Vector3::new(10.0, 20.0, 0.0));
+    let ((x, y), front) = unproject(&translation, 15.0, 30.0).unwrap();
+    assert!((x - 5.0).abs() < 1e-4 && (y - 10.0).abs() < 1e-4);
+    assert!(front);
+
+    // Seen from behind
+    let flipped = Matrix4::from_angle_y(Deg(180.0));
+    let ((x, _), front) = unproject(&flipped, -5.0, 0.0).unwrap();
+    assert!((x - 5.0).abs() < 1e-4);
+    assert!(!front);
+
+    // Behind the viewer of a perspective transform
+    let mut perspective = Matrix4::identity();
+    perspective.z.w = -1.0 / 500.0;
+    let behind = perspective * Matrix4::from_angle_x(Deg(80.0));
+    assert!(unproject(&behind, 0.0, -1000.0).is_none());
+  }
+
+  #[test]
+  fn test_rounded_bounds() {
+    let mut layer = Layer::new("test".to_string(), 100, 50, None);
+    assert!(rounded_bounds_contain(&layer, (0.0, 0.0)));
+    assert!(!rounded_bounds_contain(&layer, (100.0, 10.0)));
+    layer.set_corner_radius(20.0);
+    assert!(!rounded_bounds_contain(&layer, (1.0, 1.0)));
+    assert!(rounded_bounds_contain(&layer, (20.0, 1.0)));
+    assert!(rounded_bounds_contain(&layer, (50.0, 25.0)));
+  }
+
+  #[test]
+  fn test_pointer_dispatch() {
+    let log: Log = Rc::default();
+    let recorder = || Some(Box::new(Recorder(log.clone())) as Box<dyn EventHandler>);
+    let mut play = Play::new("test".to_string(), 400, 400, LayoutMode::UserDefine);
+    let mut stage = Layer::new("stage".to_string(), 400, 400, recorder());
+    let mut button = Layer::new("button".to_string(), 100, 50, recorder());
+    button.set_position(100, 100);
+    stage.add_sublayer(button);
+    play.add_stage(stage);
+    let events = |log: &Log| {
+      let events: Vec<_> = log.borrow_mut().drain(..).collect();
+      events
+        .into_iter()
+        .map(|(name, kind, x, y)| (name, kind, x.round(), y.round()))
+        .collect::<Vec<_>>()
+    };
+    let button = |kind| ("button".to_string(), kind, 10.0, 20.0);
+    let stage = |kind, x, y| ("stage".to_string(), kind, x, y);
+
+    play.handle_pointer_input(PointerInput::Move {
+      pointer: PointerId::Mouse,
+      x: 110.0,
+      y: 120.0,
+    });
+    assert_eq!(
+      events(&log),
+      vec![
+        button(PointerEventKind::Enter),
+        button(PointerEventKind::Move)
+      ]
+    );
+
+    // The button captures the pressed mouse, even outside of its bounds
+    play.handle_pointer_input(PointerInput::Down {
+      pointer: PointerId::Mouse,
+      button: PointerButton::Primary,
+      x: 110.0,
+      y: 120.0,
+    });
+    play.handle_pointer_input(PointerInput::Move {
+      pointer: PointerId::Mouse,
+      x: 10.0,
+      y: 20.0,
+    });
+    assert_eq!(
+      events(&log),
+      vec![
+        button(PointerEventKind::Down),
+        ("button".to_string(), PointerEventKind::Move, -90.0, -80.0)
+      ]
+    );
+    play.handle_pointer_input(PointerInput::Up {
+      pointer: PointerId::Mouse,
+      button: PointerButton::Primary,
+      x: 10.0,
+      y: 20.0,
+    });
+    assert_eq!(
+      events(&log),
+      vec![
+        ("button".to_string(), PointerEventKind::Up, -90.0, -80.0),
+        ("button".to_string(), PointerEventKind::Leave, -90.0, -80.0),
+        stage(PointerEventKind::Enter, 10.0, 20.0),
+      ]
+    );
+
+    // Scrolling goes to the layer under the mouse
+    play.handle_pointer_input(PointerInput::Scroll {
+      delta_x: 0.0,
+      delta_y: 5.0,
+    });
+    assert_eq!(
+      events(&log),
+      vec![stage(PointerEventKind::Scroll, 10.0, 20.0)]
+    );
+
+    // Touches are separate pointers that leave when they are lifted
+    let touch = PointerId::Touch(1);
+    play.handle_pointer_input(PointerInput::Down {
+      pointer: touch,
+      button: PointerButton::Primary,
+      x: 110.0,
+      y: 120.0,
+    });
+    play.handle_pointer_input(PointerInput::Up {
+      pointer: touch,
+      button: PointerButton::Primary,
+      x: 110.0,
+      y: 120.0,
+    });
+    assert_eq!(
+      events(&log),
+      vec![
+        button(PointerEventKind::Enter),
+        button(PointerEventKind::Down),
+        button(PointerEventKind::Up),
+        button(PointerEventKind::Leave),
+      ]
+    );
+
+    // A cancelled touch
+    play.handle_pointer_input(PointerInput::Down {
+      pointer: touch,
+      button: PointerButton::Primary,
+      x: 110.0,
+      y: 120.0,
+    });
+    log.borrow_mut().clear();
+    play.handle_pointer_input(PointerInput::Cancel { pointer: touch });
+    assert_eq!(
+      events(&log),
+      vec![
+        button(PointerEventKind::Cancel),
+        button(PointerEventKind::Leave)
+      ]
+    );
+  }
+}
@@ -0,0 +1,498 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Input recording and deterministic replay.
+//!
+//! `Play::start_recording` records the input given to a `Play` (keys, typed
+//! text, modifiers and pointers) with the time since the recording started,
+//! until `Play::stop_recording` returns it. `InputRecording::save` writes it as
+//! text, one event per line with its time in microseconds:
+//!
+//! ```text
+//! 0 key down Shift
+//! 16000 key repeat Left
+//! 48000 key up Shift
+//! 60000 text Hello\nWorld
+//! 90000 pointer down touch:3 primary 120.5 40
+//! 90000 modifiers shift control
+//! ```
+//!
+//! `Play::replay` sends the events again on a manual `Clock`, which only moves
+//! when told to, rendering frames at a fixed interval in between. Animations,
+//! gestures and caret blinks run by that clock, so a replay gives the same
+//! frames every time, e.g. to reproduce a bug or run an end-to-end test.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::event::KeyInput;
+use crate::layer::{Key, Modifiers};
+use crate::pointer::{PointerButton, PointerId, PointerInput};
+
+/// The time source of a `Play`, see `Play::set_clock`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Clock {
+  System,
+  /// Stands at `start + elapsed` until `Play::advance_clock`
+  Manual {
+    start: Instant,
+    elapsed: Duration,
+  },
+}
+
+impl Clock {
+  /// A manual clock starting now
+  pub fn manual() -> Self {
+    Clock::Manual {
+      start: Instant::now(),
+      elapsed: Duration::ZERO,
+    }
+  }
+
+  pub fn now(&self) -> Instant {
+    match self {
+      Clock::System => Instant::now(),
+      Clock::Manual { start, elapsed } => *start + *elapsed,
+    }
+  }
+}
+
+/// Input given to a `Play`
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+  Key(KeyInput),
+  Text(String),
+  Modifiers(Modifiers),
+  Pointer(PointerInput),
+}
+
+/// An input event and its time since the recording started
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedInput {
+  pub time: Duration,
+  pub event: InputEvent,
+}
+
+/// Input events in the order they were given
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputRecording {
+  pub events: Vec<RecordedInput>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RecordingError {
+  Io(std::io::ErrorKind),
+  // The line number, starting at 1, of a line that isn't an event
+  InvalidLine(usize),
+}
+
+impl fmt::Display for RecordingError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RecordingError::Io(kind) => write!(f, "can't access the recording: {}", kind),
+      RecordingError::InvalidLine(line) => write!(f, "invalid input event at line {}", line),
+    }
+  }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl InputRecording {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn push(&mut self, time: Duration, event: InputEvent) {
+    self.events.push(RecordedInput { time, event });
+  }
+
+  /// The time of the last event
+  pub fn duration(&self) -> Duration {
+    self
+      .events
+      .last()
+      .map_or(Duration::ZERO, |input| input.time)
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+    std::fs::write(path, self.to_string()).map_err(|err| RecordingError::Io(err.kind()))
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+    let text = std::fs::read_to_string(path).map_err(|err| RecordingError::Io(err.kind()))?;
+    text.parse()
+  }
+}
+
+impl fmt::Display for InputRecording {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for input in &self.events {
+      write!(f, "{} ", input.time.as_micros())?;
+      match &input.event {
+        InputEvent::Key(KeyInput::Down { key, repeat }) => {
+          let kind = if *repeat { "repeat" } else { "down" };
+          write!(f, "key {} {}", kind, key.name())?;
+        }
+        InputEvent::Key(KeyInput::Up { key }) => write!(f, "key up {}", key.name())?,
+        InputEvent::Text(text) => write!(f, "text {}", escape(text))?,
+        InputEvent::Modifiers(modifiers) => {
+          write!(f, "modifiers")?;
+          for (name, held) in modifier_names(*modifiers) {
+            if held {
+              write!(f, " {}", name)?;
+            }
+          }
+        }
+        InputEvent::Pointer(pointer) => write_pointer(f, pointer)?,
+      }
+      writeln!(f)?;
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for InputRecording {
+  type Err = RecordingError;
+
+  /// Parse the format written by `Display`. Empty lines and lines starting
+  /// with `#` are skipped.
+  fn from_str(text: &str) -> Result<Self, Self::Err> {
+    let mut recording = InputRecording::new();
+    for (index, line) in text.lines().enumerate() {
+      let line = line.trim_end_matches('\r');
+      if line.trim().is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let input = parse_line(line).ok_or(RecordingError::InvalidLine(index + 1))?;
+      recording.events.push(input);
+    }
+    Ok(recording)
+  }
+}
+
+fn modifier_names(modifiers: Modifiers) -> [(&'static str, bool); 4] {
+  [
+    ("shift", modifiers.shift),
+    ("control", modifiers.control),
+    ("alt", modifiers.alt),
+    ("logo", modifiers.logo),
+  ]
+}
+
+fn write_pointer(f: &mut fmt::Formatter<'_>, input: &PointerInput) -> fmt::Result {
+  let id = |pointer: &PointerId| match pointer {
+    PointerId::Mouse => "mouse".to_string(),
+    PointerId::Touch(id) => format!("touch:{}", id),
+  };
+  let button = |button: &PointerButton| match button {
+    PointerButton::Primary => "primary".to_string(),
+    PointerButton::Secondary => "secondary".to_string(),
+    PointerButton::Middle => "middle".to_string(),
+    PointerButton::Other(number) => format!("other:{}", number),
+  };
+  match input {
+    PointerInput::Down {
+      pointer,
+      button: pressed,
+      x,
+      y,
+    } => write!(
+      f,
+      "pointer down {} {} {} {}",
+      id(pointer),
+      button(pressed),
+      x,
+      y
+    ),
+    PointerInput::Move { pointer, x, y } => write!(f, "pointer move {} {} {}", id(pointer), x, y),
+    PointerInput::Up {
+      pointer,
+      button: released,
+      x,
+      y,
+    } => write!(
+      f,
+      "pointer up {} {} {} {}",
+      id(pointer),
+      button(released),
+      x,
+      y
+    ),
+    PointerInput::Cancel { pointer } => write!(f, "pointer cancel {}", id(pointer)),
+    PointerInput::Leave { pointer } => write!(f, "pointer leave {}", id(pointer)),
+    PointerInput::Scroll { delta_x, delta_y } => {
+      write!(f, "pointer scroll {} {}", delta_x, delta_y)
+    }
+  }
+}
+
+fn parse_line(line: &str) -> Option<RecordedInput> {
+  let (time, rest) = line.split_once(' ')?;
+  let time = Duration::from_micros(time.parse().ok()?);
+  let (kind, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+  let mut words = rest.split_whitespace();
+  let event = match kind {
+    // The text may have spaces
+    "text" => InputEvent::Text(unescape(rest)?),
+    "key" => {
+      let kind = words.next()?;
+      let key = Key::from_name(words.next()?)?;
+      InputEvent::Key(match kind {
+        "down" => KeyInput::Down { key, repeat: false },
+        "repeat" => KeyInput::Down { key, repeat: true },
+        "up" => KeyInput::Up { key },
+        _ => return None,
+      })
+    }
+    "modifiers" => {
+      let mut modifiers = Modifiers::default();
+      for name in words.by_ref() {
+        match name {
+          "shift" => modifiers.shift = true,
+          "control" => modifiers.control = true,
+          "alt" => modifiers.alt = true,
+          "logo" => modifiers.logo = true,
+          _ => return None,
+        }
+      }
+      InputEvent::Modifiers(modifiers)
+    }
+    "pointer" => InputEvent::Pointer(parse_pointer(&mut words)?),
+    _ => return None,
+  };
+  if kind != "text" && words.next().is_some() {
+    return None;
+  }
+  Some(RecordedInput { time, event })
+}
+
+fn parse_pointer<'a>(words: &mut impl Iterator<Item = &'a str>) -> Option<PointerInput> {
+  let kind = words.next()?;
+  if kind == "scroll" {
+    return Some(PointerInput::Scroll {
+      delta_x: words.next()?.parse().ok()?,
+      delta_y: words.next()?.parse().ok()?,
+    });
+  }
+  let pointer = match words.next()? {
+    "mouse" => PointerId::Mouse,
+    id => PointerId::Touch(id.strip_prefix("touch:")?.parse().ok()?),
+  };
+  let mut button = || {
+    Some(match words.next()? {
+      "primary" => PointerButton::Primary,
+      "secondary" => PointerButton::Secondary,
+      "middle" => PointerButton::Middle,
+      other => PointerButton::Other(other.strip_prefix("other:")?.parse().ok()?),
+    })
+  };
+  Some(match kind {
+    "down" => PointerInput::Down {
+      pointer,
+      button: button()?,
+      x: words.next()?.parse().ok()?,
+      y: words.next()?.parse().ok()?,
+    },
+    "up" => PointerInput::Up {
+      pointer,
+      button: button()?,
+      x: words.next()?.parse().ok()?,
+      y: words.next()?.parse().ok()?,
+    },
+    "move" => PointerInput::Move {
+      pointer,
+      x: words.next()?.parse().ok()?,
+      y: words.next()?.parse().ok()?,
+    },
+    "cancel" => PointerInput::Cancel { pointer },
+    "leave" => PointerInput::Leave { pointer },
+    _ => return None,
+  })
+}
+
+// Keep typed text on one line
+fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      '\r' => escaped.push_str("\\r"),
+      '\t' => escaped.push_str("\\t"),
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+fn unescape(text: &str) -> Option<String> {
+  let mut unescaped = String::with_capacity(text.len());
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      unescaped.push(c);
+      continue;
+    }
+    unescaped.push(match chars.next()? {
+      '\\' => '\\',
+      'n' => '\n',
+      'r' => '\r',
+      't' => '\t',
+      _ => return None,
+    });
+  }
+  Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::animation::{Animation, EasingFunction};
+  use crate::layer::{Layer, LayoutMode};
+  use crate::play::Play;
+  use crate::text_field::TextField;
+
+  #[test]
+  fn test_recording_format() {
+    let mut recording = InputRecording::new();
+    let events = [
+      InputEvent::Key(KeyInput::Down {
+        key: Key::PageUp,
+        repeat: false,
+      }),
+      InputEvent::Key(KeyInput::Down {
+        key: Key::Left,
+        repeat: true,
+      }),
+      InputEvent::Key(KeyInput::Up { key: Key::Digit7 }),
+      InputEvent::Text(" a\\b\nc ".to_string()),
+      InputEvent::Modifiers(Modifiers {
+        shift: true,
+        logo: true,
+        ..Modifiers::default()
+      }),
+      InputEvent::Modifiers(Modifiers::default()),
+      InputEvent::Pointer(PointerInput::Down {
+        pointer: PointerId::Touch(3),
+        button: PointerButton::Other(8),
+        x: 120.5,
+        y: -0.1,
+      }),
+      InputEvent::Pointer(PointerInput::Move {
+        pointer: PointerId::Mouse,
+        x: 1e-7,
+        y: 3.0,
+      }),
+      InputEvent::Pointer(PointerInput::Cancel {
+        pointer: PointerId::Touch(3),
+      }),
+      InputEvent::Pointer(PointerInput::Scroll {
+        delta_x: 0.0,
+        delta_y: -40.0,
+      }),
+    ];
+    for (index, event) in events.into_iter().enumerate() {
+      recording.push(Duration::from_micros(index as u64 * 16_667), event);
+    }
+    let text = recording.to_string();
+    assert!(text.starts_with("0 key down PageUp\n16667 key repeat Left\n"));
+    assert!(text.contains("text  a\\\\b\\nc \n"));
+    assert!(text.contains("modifiers shift logo\n"));
+    assert_eq!(text.parse::<InputRecording>(), Ok(recording));
+
+    let text = "# comment\n\n0 key down Left\n10 key down Lft\n";
+    assert_eq!(
+      text.parse::<InputRecording>(),
+      Err(RecordingError::InvalidLine(4))
+    );
+    assert!("0 pointer leave mouse 3".parse::<InputRecording>().is_err());
+  }
+
+  // A text field and a box that moves right when it is clicked
+  fn play() -> Play {
+    let mut play = Play::new("test".to_string(), 400, 400, LayoutMode::UserDefine);
+    let mut stage = Layer::new("stage".to_string(), 400, 400, None);
+    let mut field = Layer::new("field".to_string(), 200, 30, None);
+    field.set_text_field(Some(TextField::new()));
+    field.set_focusable(true);
+    let mut button = Layer::new("box".to_string(), 50, 50, None);
+    button.set_position(0, 100);
+    button.on_pointer_up(|_, layer| {
+      let mut animation = Animation::new();
+      animation.apply_translation_x(0, 100, 1.0, EasingFunction::Linear);
+      layer.set_animation(Some(animation));
+    });
+    stage.add_sublayer(field);
+    stage.add_sublayer(button);
+    play.add_stage(stage);
+    play
+  }
+
+  fn replay(recording: &InputRecording) -> (Vec<(i32, bool)>, String) {
+    let mut play = play();
+    let mut frames = Vec::new();
+    play.replay(recording, Duration::from_millis(100), |play| {
+      let stage = play.stage_mut("stage").unwrap();
+      let field = stage.sublayer_at_path(&[0]).unwrap().text_field().unwrap();
+      let caret = field.caret_visible(true);
+      frames.push((stage.sublayer_at_path(&[1]).unwrap().x, caret));
+    });
+    // The clock stays where the replay left it
+    play.advance_clock(Duration::from_millis(250));
+    play.render();
+    let stage = play.stage_mut("stage").unwrap();
+    frames.push((stage.sublayer_at_path(&[1]).unwrap().x, false));
+    let field = stage.sublayer_at_path(&[0]).unwrap().text_field().unwrap();
+    (frames, field.value().to_string())
+  }
+
+  #[test]
+  fn test_record_and_replay() {
+    let mut play = play();
+    play.set_clock(Clock::manual());
+    play.start_recording();
+    play.handle_text_input("hello");
+    play.advance_clock(Duration::from_millis(120));
+    play.handle_key_input(KeyInput::Down {
+      key: Key::Backspace,
+      repeat: false,
+    });
+    play.advance_clock(Duration::from_millis(80));
+    let (pointer, button) = (PointerId::Mouse, PointerButton::Primary);
+    for input in [
+      PointerInput::Down {
+        pointer,
+        button,
+        x: 10.0,
+        y: 110.0,
+      },
+      PointerInput::Up {
+        pointer,
+        button,
+        x: 10.0,
+        y: 110.0,
+      },
+    ] {
+      play.handle_pointer_input(input);
+      play.advance_clock(Duration::from_millis(50));
+    }
+    let recording = play.stop_recording().unwrap();
+    assert!(play.stop_recording().is_none());
+    assert_eq!(recording.events.len(), 4);
+    assert_eq!(recording.duration(), Duration::from_millis(250));
+
+    // Frames at 0, 100, 200 and 300 ms. The box starts moving in the frame
+    // after the click and the caret blinks by the clock.
+    let recording: InputRecording = recording.to_string().parse().unwrap();
+    let (frames, value) = replay(&recording);
+    assert_eq!(value, "hell");
+    assert_eq!(
+      frames,
+      vec![(0, true), (0, true), (0, true), (0, true), (25, false)]
+    );
+    assert_eq!(replay(&recording), (frames, value));
+  }
+}
@@ -0,0 +1,1094 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::f32::consts::PI;
+
+use crate::gradient::Gradient;
+use crate::layer::Vertex;
+
+/// Maximum distance between a curve and its flattened polyline, in layer units
+const FLATTEN_TOLERANCE: f32 = 0.25;
+const EPSILON: f32 = 1e-4;
+
+type Point = (f32, f32);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PathElement {
+  MoveTo(Point),
+  LineTo(Point),
+  QuadCurveTo(Point, Point),
+  CurveTo(Point, Point, Point),
+  Arc {
+    center: Point,
+    radius: f32,
+    start_angle: f32,
+    end_angle: f32,
+    clockwise: bool,
+  },
+  Close,
+}
+
+/// Vector path in the coordinate space of the layer (CoreGraphics-style CGPath).
+///
+/// Angles are in radians and measured from the positive X axis. Since Y points
+/// down, a clockwise arc goes from `start_angle` towards larger angles.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Path {
+  pub elements: Vec<PathElement>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FillRule {
+  NonZero,
+  EvenOdd,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LineCap {
+  Butt,
+  Round,
+  Square,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LineJoin {
+  Miter,
+  Round,
+  Bevel,
+}
+
+/// Vector shape content of a layer (CoreAnimation-style CAShapeLayer).
+///
+/// The path is tessellated into triangles on the CPU whenever the shape changes.
+/// Overlapping parts of a stroke (joins, self-intersections) are blended twice,
+/// so translucent strokes can look darker where they overlap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shape {
+  pub path: Path,
+  pub fill_color: Option<[f32; 4]>,
+  pub fill_rule: FillRule,
+  pub stroke_color: Option<[f32; 4]>,
+  pub line_width: f32,
+  pub line_cap: LineCap,
+  pub line_join: LineJoin,
+  pub miter_limit: f32,
+  // Alternating lengths of dashes and gaps. A solid line if empty.
+  pub line_dash_pattern: Vec<f32>,
+  pub line_dash_phase: f32,
+  // Portion of the path to stroke, as fractions of its length
+  pub stroke_start: f32,
+  pub stroke_end: f32,
+  // Gradients painted instead of the fill and stroke colors
+  pub fill_gradient: Option<Gradient>,
+  pub stroke_gradient: Option<Gradient>,
+}
+
+/// Triangles produced by the tessellator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+  pub vertices: Vec<[f32; 2]>,
+  pub indices: Vec<u32>,
+}
+
+/// GPU buffers of a tessellated mesh
+pub(crate) struct MeshBuffers {
+  pub(crate) vertex_buffer: wgpu::Buffer,
+  pub(crate) index_buffer: wgpu::Buffer,
+  pub(crate) index_count: u32,
+}
+
+/// Tessellated fill and stroke of a shape, kept until the shape changes
+pub(crate) struct ShapeBuffers {
+  pub(crate) shape: Shape,
+  pub(crate) fill: Option<MeshBuffers>,
+  pub(crate) stroke: Option<MeshBuffers>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Polyline {
+  points: Vec<Point>,
+  closed: bool,
+}
+
+impl Path {
+  pub fn new() -> Self {
+    Path {
+      elements: Vec::new(),
+    }
+  }
+
+  pub fn rect(x: f32, y: f32, width: f32, height: f32) -> Self {
+    Path::new()
+      .move_to(x, y)
+      .line_to(x + width, y)
+      .line_to(x + width, y + height)
+      .line_to(x, y + height)
+      .close()
+  }
+
+  pub fn circle(center_x: f32, center_y: f32, radius: f32) -> Self {
+    Path::new()
+      .arc(center_x, center_y, radius, 0.0, 2.0 * PI, true)
+      .close()
+  }
+
+  pub fn move_to(mut self, x: f32, y: f32) -> Self {
+    self.elements.push(PathElement::MoveTo((x, y)));
+    self
+  }
+
+  pub fn line_to(mut self, x: f32, y: f32) -> Self {
+    self.elements.push(PathElement::LineTo((x, y)));
+    self
+  }
+
+  pub fn quad_to(mut self, cx: f32, cy: f32, x: f32, y: f32) -> Self {
+    self
+      .elements
+      .push(PathElement::QuadCurveTo((cx, cy), (x, y)));
+    self
+  }
+
+  pub fn cubic_to(mut self, c1x: f32, c1y: f32, c2x: f32, c2y: f32, x: f32, y: f32) -> Self {
+    self
+      .elements
+      .push(PathElement::CurveTo((c1x, c1y), (c2x, c2y), (x, y)));
+    self
+  }
+
+  /// Add a circular arc. A line is added from the current point to the start of the arc.
+  pub fn arc(
+    mut self,
+    center_x: f32,
+    center_y: f32,
+    radius: f32,
+    start_angle: f32,
+    end_angle: f32,
+    clockwise: bool,
+  ) -> Self {
+    self.elements.push(PathElement::Arc {
+      center: (center_x, center_y),
+      radius,
+      start_angle,
+      end_angle,
+      clockwise,
+    });
+    self
+  }
+
+  pub fn close(mut self) -> Self {
+    self.elements.push(PathElement::Close);
+    self
+  }
+
+  /// Convert the path into polylines, one per subpath
+  fn flatten(&self) -> Vec<Polyline> {
+    let mut polylines = Vec::new();
+    let mut current: Vec<Point> = Vec::new();
+    let mut cursor = (0.0, 0.0);
+    let mut subpath_start = (0.0, 0.0);
+
+    for element in &self.elements {
+      match *element {
+        PathElement::MoveTo(p) => {
+          finish_subpath(&mut polylines, &mut current, false);
+          current.push(p);
+          subpath_start = p;
+          cursor = p;
+        }
+        PathElement::LineTo(p) => {
+          begin_subpath(&mut current, &mut subpath_start, cursor);
+          push_point(&mut current, p);
+          cursor = p;
+        }
+        PathElement::QuadCurveTo(c, p) => {
+          begin_subpath(&mut current, &mut subpath_start, cursor);
+          let dd = length(sub(add(cursor, p), scale(c, 2.0)));
+          let segments = curve_segments(0.25 * dd);
+          for i in 1..=segments {
+            let t = i as f32 / segments as f32;
+            let mt = 1.0 - t;
+            let point = add(
+              add(scale(cursor, mt * mt), scale(c, 2.0 * mt * t)),
+              scale(p, t * t),
+            );
+            push_point(&mut current, point);
+          }
+          cursor = p;
+        }
+        PathElement::CurveTo(c1, c2, p) => {
+          begin_subpath(&mut current, &mut subpath_start, cursor);
+          let dd = length(sub(add(cursor, c2), scale(c1, 2.0)))
+            .max(length(sub(add(c1, p), scale(c2, 2.0))));
+          let segments = curve_segments(0.75 * dd);
+          for i in 1..=segments {
+            let t = i as f32 / segments as f32;
+            let mt = 1.0 - t;
+            let point = add(
+              add(scale(cursor, mt * mt * mt), scale(c1, 3.0 * mt * mt * t)),
+              add(scale(c2, 3.0 * mt * t * t), scale(p, t * t * t)),
+            );
+            push_point(&mut current, point);
+          }
+          cursor = p;
+        }
+        PathElement::Arc {
+          center,
+          radius,
+          start_angle,
+          end_angle,
+          clockwise,
+        } => {
+          let start = arc_point(center, radius, start_angle);
+          if current.is_empty() {
+            subpath_start = start;
+          }
+          push_point(&mut current, start);
+
+          let sweep = arc_sweep(start_angle, end_angle, clockwise);
+          let segments = arc_segments(radius, sweep);
+          for i in 1..=segments {
+            let angle = start_angle + sweep * i as f32 / segments as f32;
+            push_point(&mut current, arc_point(center, radius, angle));
+          }
+          cursor = arc_point(center, radius, start_angle + sweep);
+        }
+        PathElement::Close => {
+          finish_subpath(&mut polylines, &mut current, true);
+          cursor = subpath_start;
+        }
+      }
+    }
+    finish_subpath(&mut polylines, &mut current, false);
+
+    polylines
+  }
+}
+
+impl Shape {
+  /// Shape filled with black and without stroke, as in CAShapeLayer
+  pub fn new(path: Path) -> Self {
+    Shape {
+      path,
+      fill_color: Some([0.0, 0.0, 0.0, 1.0]),
+      fill_rule: FillRule::NonZero,
+      stroke_color: None,
+      line_width: 1.0,
+      line_cap: LineCap::Butt,
+      line_join: LineJoin::Miter,
+      miter_limit: 10.0,
+      line_dash_pattern: Vec::new(),
+      line_dash_phase: 0.0,
+      stroke_start: 0.0,
+      stroke_end: 1.0,
+      fill_gradient: None,
+      stroke_gradient: None,
+    }
+  }
+
+  pub fn with_fill_color(mut self, fill_color: Option<[f32; 4]>) -> Self {
+    self.fill_color = fill_color;
+    self
+  }
+
+  pub fn with_fill_gradient(mut self, fill_gradient: Option<Gradient>) -> Self {
+    self.fill_gradient = fill_gradient;
+    self
+  }
+
+  /// Color and gradient the fill is painted with, or None if it is not painted
+  pub(crate) fn fill_paint(&self) -> Option<([f32; 4], Option<&Gradient>)> {
+    paint(self.fill_color, self.fill_gradient.as_ref())
+  }
+
+  /// Color and gradient the stroke is painted with, or None if it is not painted
+  pub(crate) fn stroke_paint(&self) -> Option<([f32; 4], Option<&Gradient>)> {
+    paint(self.stroke_color, self.stroke_gradient.as_ref())
+  }
+
+  pub fn with_fill_rule(mut self, fill_rule: FillRule) -> Self {
+    self.fill_rule = fill_rule;
+    self
+  }
+
+  pub fn with_stroke(mut self, stroke_color: [f32; 4], line_width: f32) -> Self {
+    self.stroke_color = Some(stroke_color);
+    self.line_width = line_width;
+    self
+  }
+
+  pub fn with_line_cap(mut self, line_cap: LineCap) -> Self {
+    self.line_cap = line_cap;
+    self
+  }
+
+  pub fn with_line_join(mut self, line_join: LineJoin) -> Self {
+    self.line_join = line_join;
+    self
+  }
+
+  pub fn with_line_dash(mut self, pattern: Vec<f32>, phase: f32) -> Self {
+    self.line_dash_pattern = pattern;
+    self.line_dash_phase = phase;
+    self
+  }
+
+  /// Triangulate the interior of the path according to the fill rule.
+  /// Open subpaths are closed implicitly.
+  pub fn tessellate_fill(&self) -> Mesh {
+    let mut mesh = Mesh::default();
+
+    // Non-horizontal edges, oriented top to bottom
+    let mut edges: Vec<Edge> = Vec::new();
+    let mut ys = Vec::new();
+    for polyline in self.path.flatten() {
+      let points = &polyline.points;
+      for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        ys.push(a.1);
+        if (a.1 - b.1).abs() < EPSILON {
+          continue;
+        }
+        if a.1 < b.1 {
+          edges.push((a, b, 1));
+        } else {
+          edges.push((b, a, -1));
+        }
+      }
+    }
+
+    edges.sort_by(|a, b| a.0 .1.total_cmp(&b.0 .1));
+    ys.sort_by(|a, b| a.total_cmp(b));
+    ys.dedup_by(|a, b| (*a - *b).abs() < EPSILON);
+
+    // Sweep down the bands between vertices, keeping the edges that span the band
+    let mut active: Vec<Edge> = Vec::new();
+    let mut next_edge = 0;
+    let mut slab_ys = Vec::new();
+    for band in ys.windows(2) {
+      let (top, bottom) = (band[0], band[1]);
+      let middle = (top + bottom) * 0.5;
+      active.retain(|(_, b, _)| b.1 >= middle);
+      while next_edge < edges.len() && edges[next_edge].0 .1 <= middle {
+        if edges[next_edge].1 .1 >= middle {
+          active.push(edges[next_edge]);
+        }
+        next_edge += 1;
+      }
+
+      // Split the band into slabs in which no edges cross. Edges that cross in the
+      // band are in a different order at its bottom than at its top.
+      slab_ys.clear();
+      slab_ys.extend([top, bottom]);
+      active.sort_by(|a, b| {
+        let top_order = x_at(a.0, a.1, top).total_cmp(&x_at(b.0, b.1, top));
+        top_order.then(x_at(a.0, a.1, bottom).total_cmp(&x_at(b.0, b.1, bottom)))
+      });
+      for i in 1..active.len() {
+        let mut j = i;
+        while j > 0
+          && x_at(active[j - 1].0, active[j - 1].1, bottom) > x_at(active[j].0, active[j].1, bottom)
+        {
+          let ((a0, a1, _), (b0, b1, _)) = (active[j - 1], active[j]);
+          if let Some(y) = intersection_y(a0, a1, b0, b1) {
+            slab_ys.push(y);
+          }
+          active.swap(j - 1, j);
+          j -= 1;
+        }
+      }
+      slab_ys.sort_by(|a, b| a.total_cmp(b));
+      slab_ys.dedup_by(|a, b| (*a - *b).abs() < EPSILON);
+
+      for slab in slab_ys.windows(2) {
+        self.fill_slab(&mut mesh, &active, slab[0], slab[1]);
+      }
+    }
+
+    mesh
+  }
+
+  // Fill the parts of a slab inside the shape, given the edges that span it
+  fn fill_slab(&self, mesh: &mut Mesh, edges: &[Edge], top: f32, bottom: f32) {
+    let middle = (top + bottom) * 0.5;
+    let mut crossings: Vec<(f32, f32, f32, i32)> = edges
+      .iter()
+      .map(|(a, b, winding)| {
+        (
+          x_at(*a, *b, middle),
+          x_at(*a, *b, top),
+          x_at(*a, *b, bottom),
+          *winding,
+        )
+      })
+      .collect();
+    crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut winding = 0;
+    for pair in crossings.windows(2) {
+      winding += pair[0].3;
+      let inside = match self.fill_rule {
+        FillRule::NonZero => winding != 0,
+        FillRule::EvenOdd => winding % 2 != 0,
+      };
+      if inside {
+        mesh.push_quad(
+          (pair[0].1, top),
+          (pair[1].1, top),
+          (pair[1].2, bottom),
+          (pair[0].2, bottom),
+        );
+      }
+    }
+  }
+
+  /// Triangulate the outline of the path, honoring the dash pattern and
+  /// `stroke_start`/`stroke_end`
+  pub fn tessellate_stroke(&self) -> Mesh {
+    let mut mesh = Mesh::default();
+    let half_width = self.line_width * 0.5;
+    if half_width <= 0.0 {
+      return mesh;
+    }
+
+    let polylines = self.path.flatten();
+    let total_length = polylines.iter().map(|p| p.length()).sum();
+    let intervals = self.visible_intervals(total_length);
+
+    let mut offset = 0.0;
+    for polyline in &polylines {
+      let polyline_length = polyline.length();
+      for &(start, end) in &intervals {
+        let start = start.max(offset) - offset;
+        let end = end.min(offset + polyline_length) - offset;
+        if start > end {
+          continue;
+        }
+
+        if end - start < EPSILON {
+          let (point, direction) = polyline.point_at(start);
+          self.stroke_dot(&mut mesh, point, direction, half_width);
+        } else if polyline.closed && start < EPSILON && end > polyline_length - EPSILON {
+          self.stroke_polyline(&mut mesh, polyline.points.clone(), true, half_width);
+        } else {
+          let points = polyline.extract(start, end);
+          self.stroke_polyline(&mut mesh, points, false, half_width);
+        }
+      }
+      offset += polyline_length;
+    }
+
+    mesh
+  }
+
+  /// Ranges along the path (in path length units) that are stroked
+  fn visible_intervals(&self, total_length: f32) -> Vec<(f32, f32)> {
+    let start = self.stroke_start.clamp(0.0, 1.0) * total_length;
+    let end = self.stroke_end.clamp(0.0, 1.0) * total_length;
+    if end <= start || total_length <= 0.0 {
+      return Vec::new();
+    }
+
+    let mut pattern = self.line_dash_pattern.clone();
+    let period: f32 = pattern.iter().sum();
+    if pattern.is_empty() || period <= 0.0 || pattern.iter().any(|v| *v < 0.0) {
+      return vec![(start, end)];
+    }
+    // An odd pattern alternates between dashes and gaps on each repetition
+    if pattern.len() % 2 == 1 {
+      pattern.extend(pattern.clone());
+    }
+    let period = period * (pattern.len() / self.line_dash_pattern.len()) as f32;
+
+    let mut intervals = Vec::new();
+    let mut position = -self.line_dash_phase.rem_euclid(period);
+    let mut i = 0;
+    while position <= end {
+      let dash_start = position.max(start);
+      let dash_end = (position + pattern[i]).min(end);
+      if dash_start <= dash_end {
+        intervals.push((dash_start, dash_end));
+      }
+      position += pattern[i] + pattern[i + 1];
+      i = (i + 2) % pattern.len();
+    }
+    intervals
+  }
+
+  fn stroke_polyline(
+    &self,
+    mesh: &mut Mesh,
+    mut points: Vec<Point>,
+    closed: bool,
+    half_width: f32,
+  ) {
+    if points.len() < 2 {
+      return;
+    }
+    let count = points.len();
+
+    if !closed && self.line_cap == LineCap::Square {
+      let first = normalize(sub(points[1], points[0]));
+      let last = normalize(sub(points[count - 1], points[count - 2]));
+      points[0] = sub(points[0], scale(first, half_width));
+      points[count - 1] = add(points[count - 1], scale(last, half_width));
+    }
+
+    let segment_count = if closed { count } else { count - 1 };
+    let directions: Vec<Point> = (0..segment_count)
+      .map(|i| normalize(sub(points[(i + 1) % count], points[i])))
+      .collect();
+
+    for (i, direction) in directions.iter().enumerate() {
+      let a = points[i];
+      let b = points[(i + 1) % count];
+      let offset = scale(normal(*direction), half_width);
+      mesh.push_quad(
+        add(a, offset),
+        add(b, offset),
+        sub(b, offset),
+        sub(a, offset),
+      );
+    }
+
+    // Joins between consecutive segments
+    let joins = if closed { 0..count } else { 1..count - 1 };
+    for i in joins {
+      let incoming = directions[(i + segment_count - 1) % segment_count];
+      let outgoing = directions[i % segment_count];
+      self.stroke_join(mesh, points[i], incoming, outgoing, half_width);
+    }
+
+    if !closed && self.line_cap == LineCap::Round {
+      let first = directions[0];
+      let last = directions[segment_count - 1];
+      let start_normal = normal(first);
+      let end_normal = scale(normal(last), -1.0);
+      mesh.push_fan(
+        points[0],
+        half_width,
+        start_normal.1.atan2(start_normal.0),
+        PI,
+      );
+      mesh.push_fan(
+        points[count - 1],
+        half_width,
+        end_normal.1.atan2(end_normal.0),
+        PI,
+      );
+    }
+  }
+
+  fn stroke_join(
+    &self,
+    mesh: &mut Mesh,
+    point: Point,
+    incoming: Point,
+    outgoing: Point,
+    half_width: f32,
+  ) {
+    let turn = cross(incoming, outgoing);
+    if turn.abs() < EPSILON && dot(incoming, outgoing) > 0.0 {
+      return;
+    }
+
+    // The join fills the gap on the outer side of the turn
+    let side = if turn > 0.0 { -1.0 } else { 1.0 };
+    let outer_in = scale(normal(incoming), side);
+    let outer_out = scale(normal(outgoing), side);
+    let corner_in = add(point, scale(outer_in, half_width));
+    let corner_out = add(point, scale(outer_out, half_width));
+
+    match self.line_join {
+      LineJoin::Round => {
+        let start_angle = outer_in.1.atan2(outer_in.0);
+        let mut sweep = outer_out.1.atan2(outer_out.0) - start_angle;
+        if sweep > PI {
+          sweep -= 2.0 * PI;
+        } else if sweep < -PI {
+          sweep += 2.0 * PI;
+        }
+        mesh.push_fan(point, half_width, start_angle, sweep);
+      }
+      LineJoin::Miter => {
+        let bisector = add(outer_in, outer_out);
+        let cos_half = dot(normalize(bisector), outer_in);
+        if length(bisector) > EPSILON && 1.0 / cos_half <= self.miter_limit {
+          let tip = add(point, scale(normalize(bisector), half_width / cos_half));
+          mesh.push_quad(point, corner_in, tip, corner_out);
+        } else {
+          mesh.push_triangle(point, corner_in, corner_out);
+        }
+      }
+      LineJoin::Bevel => mesh.push_triangle(point, corner_in, corner_out),
+    }
+  }
+
+  /// A zero length dash, which is only visible with round or square caps
+  fn stroke_dot(&self, mesh: &mut Mesh, point: Point, direction: Point, half_width: f32) {
+    match self.line_cap {
+      LineCap::Butt => {}
+      LineCap::Round => mesh.push_fan(point, half_width, 0.0, 2.0 * PI),
+      LineCap::Square => {
+        let along = scale(direction, half_width);
+        let across = scale(normal(direction), half_width);
+        mesh.push_quad(
+          add(sub(point, along), across),
+          add(add(point, along), across),
+          sub(add(point, along), across),
+          sub(sub(point, along), across),
+        );
+      }
+    }
+  }
+}
+
+impl Mesh {
+  pub fn is_empty(&self) -> bool {
+    self.indices.is_empty()
+  }
+
+  /// Vertices for the layer pipeline. Texture coordinates map the layer bounds to 0..1.
+  pub(crate) fn to_vertices(&self, width: f32, height: f32) -> Vec<Vertex> {
+    self
+      .vertices
+      .iter()
+      .map(|v| Vertex {
+        position: [v[0], v[1], 0.0],
+        tex_coords: [v[0] / width.max(1.0), v[1] / height.max(1.0)],
+      })
+      .collect()
+  }
+
+  fn push_vertex(&mut self, point: Point) -> u32 {
+    self.vertices.push([point.0, point.1]);
+    (self.vertices.len() - 1) as u32
+  }
+
+  fn push_triangle(&mut self, a: Point, b: Point, c: Point) {
+    let a = self.push_vertex(a);
+    let b = self.push_vertex(b);
+    let c = self.push_vertex(c);
+    self.indices.extend_from_slice(&[a, b, c]);
+  }
+
+  /// Quad with corners in order around its edge
+  fn push_quad(&mut self, a: Point, b: Point, c: Point, d: Point) {
+    let a = self.push_vertex(a);
+    let b = self.push_vertex(b);
+    let c = self.push_vertex(c);
+    let d = self.push_vertex(d);
+    self.indices.extend_from_slice(&[a, b, c, a, c, d]);
+  }
+
+  /// Circular sector around center
+  fn push_fan(&mut self, center: Point, radius: f32, start_angle: f32, sweep: f32) {
+    let segments = arc_segments(radius, sweep);
+    let center_index = self.push_vertex(center);
+    let mut previous = self.push_vertex(arc_point(center, radius, start_angle));
+    for i in 1..=segments {
+      let angle = start_angle + sweep * i as f32 / segments as f32;
+      let next = self.push_vertex(arc_point(center, radius, angle));
+      self
+        .indices
+        .extend_from_slice(&[center_index, previous, next]);
+      previous = next;
+    }
+  }
+}
+
+impl MeshBuffers {
+  fn new(device: &wgpu::Device, mesh: &Mesh, width: f32, height: f32) -> Option<Self> {
+    if mesh.is_empty() {
+      return None;
+    }
+
+    use wgpu::util::DeviceExt;
+    let vertices = mesh.to_vertices(width, height);
+    Some(MeshBuffers {
+      vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Shape Vertex Buffer"),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+      }),
+      index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Shape Index Buffer"),
+        contents: bytemuck::cast_slice(&mesh.indices),
+        usage: wgpu::BufferUsages::INDEX,
+      }),
+      index_count: mesh.indices.len() as u32,
+    })
+  }
+}
+
+impl ShapeBuffers {
+  pub(crate) fn new(device: &wgpu::Device, shape: &Shape, width: f32, height: f32) -> Self {
+    let fill = match shape.fill_paint() {
+      Some(_) => MeshBuffers::new(device, &shape.tessellate_fill(), width, height),
+      None => None,
+    };
+    let stroke = match shape.stroke_paint() {
+      Some(_) => MeshBuffers::new(device, &shape.tessellate_stroke(), width, height),
+      None => None,
+    };
+
+    ShapeBuffers {
+      shape: shape.clone(),
+      fill,
+      stroke,
+    }
+  }
+}
+
+impl Polyline {
+  fn segment_count(&self) -> usize {
+    if self.closed {
+      self.points.len()
+    } else {
+      self.points.len() - 1
+    }
+  }
+
+  fn segment(&self, i: usize) -> (Point, Point) {
+    (self.points[i], self.points[(i + 1) % self.points.len()])
+  }
+
+  fn length(&self) -> f32 {
+    (0..self.segment_count())
+      .map(|i| {
+        let (a, b) = self.segment(i);
+        length(sub(b, a))
+      })
+      .sum()
+  }
+
+  /// Point and direction at the given distance along the polyline
+  fn point_at(&self, distance: f32) -> (Point, Point) {
+    let mut travelled = 0.0;
+    let mut last = (self.points[0], (1.0, 0.0));
+    for i in 0..self.segment_count() {
+      let (a, b) = self.segment(i);
+      let segment_length = length(sub(b, a));
+      let direction = normalize(sub(b, a));
+      if travelled + segment_length >= distance {
+        let t = (distance - travelled).max(0.0);
+        return (add(a, scale(direction, t)), direction);
+      }
+      travelled += segment_length;
+      last = (b, direction);
+    }
+    last
+  }
+
+  /// Open polyline covering the given distances along this polyline
+  fn extract(&self, start: f32, end: f32) -> Vec<Point> {
+    let mut points = vec![self.point_at(start).0];
+    let mut travelled = 0.0;
+    for i in 0..self.segment_count() {
+      let (_, b) = self.segment(i);
+      travelled += length(sub(b, self.segment(i).0));
+      if travelled > start && travelled < end {
+        push_point(&mut points, b);
+      }
+    }
+    push_point(&mut points, self.point_at(end).0);
+    points
+  }
+}
+
+fn paint(
+  color: Option<[f32; 4]>,
+  gradient: Option<&Gradient>,
+) -> Option<([f32; 4], Option<&Gradient>)> {
+  match (color, gradient) {
+    (color, Some(gradient)) => Some((color.unwrap_or([1.0; 4]), Some(gradient))),
+    (Some(color), None) => Some((color, None)),
+    (None, None) => None,
+  }
+}
+
+fn begin_subpath(current: &mut Vec<Point>, subpath_start: &mut Point, cursor: Point) {
+  if current.is_empty() {
+    current.push(cursor);
+    *subpath_start = cursor;
+  }
+}
+
+fn finish_subpath(polylines: &mut Vec<Polyline>, current: &mut Vec<Point>, closed: bool) {
+  let mut points = std::mem::take(current);
+  if closed && points.len() > 2 && length(sub(points[points.len() - 1], points[0])) < EPSILON {
+    points.pop();
+  }
+  if points.len() > 1 {
+    polylines.push(Polyline { points, closed });
+  }
+}
+
+fn push_point(points: &mut Vec<Point>, point: Point) {
+  match points.last() {
+    Some(last) if length(sub(point, *last)) < EPSILON => {}
+    _ => points.push(point),
+  }
+}
+
+/// Number of line segments for a curve, from Wang's formula
+fn curve_segments(max_second_difference: f32) -> usize {
+  ((max_second_difference / FLATTEN_TOLERANCE).sqrt().ceil() as usize).clamp(1, 256)
+}
+
+fn arc_segments(radius: f32, sweep: f32) -> usize {
+  let step = if radius > FLATTEN_TOLERANCE {
+    2.0 * (1.0 - FLATTEN_TOLERANCE / radius).acos()
+  } else {
+    PI / 2.0
+  };
+  ((sweep.abs() / step).ceil() as usize).clamp(1, 256)
+}
+
+/// Signed sweep angle of an arc, at most one full turn
+fn arc_sweep(start_angle: f32, end_angle: f32, clockwise: bool) -> f32 {
+  let sweep = end_angle - start_angle;
+  if clockwise {
+    if sweep < 0.0 {
+      sweep.rem_euclid(2.0 * PI)
+    } else {
+      sweep.min(2.0 * PI)
+    }
+  } else if sweep > 0.0 {
+    -(-sweep).rem_euclid(2.0 * PI)
+  } else {
+    sweep.max(-2.0 * PI)
+  }
+}
+
+fn arc_point(center: Point, radius: f32, angle: f32) -> Point {
+  (
+    center.0 + radius * angle.cos(),
+    center.1 + radius * angle.sin(),
+  )
+}
+
+// A path edge from top to bottom, with +1 for downward and -1 for upward winding
+type Edge = (Point, Point, i32);
+
+/// Y coordinate where two segments cross, if they do
+fn intersection_y(a0: Point, a1: Point, b0: Point, b1: Point) -> Option<f32> {
+  let da = sub(a1, a0);
+  let db = sub(b1, b0);
+  let denominator = cross(da, db);
+  if denominator.abs() < EPSILON {
+    return None;
+  }
+  let t = cross(sub(b0, a0), db) / denominator;
+  let u = cross(sub(b0, a0), da) / denominator;
+  if t > 0.0 && t < 1.0 && u > 0.0 && u < 1.0 {
+    Some(a0.1 + t * da.1)
+  } else {
+    None
+  }
+}
+
+fn x_at(a: Point, b: Point, y: f32) -> f32 {
+  let t = ((y - a.1) / (b.1 - a.1)).clamp(0.0, 1.0);
+  a.0 + (b.0 - a.0) * t
+}
+
+fn add(a: Point, b: Point) -> Point {
+  (a.0 + b.0, a.1 + b.1)
+}
+
+fn sub(a: Point, b: Point) -> Point {
+  (a.0 - b.0, a.1 - b.1)
+}
+
+fn scale(a: Point, s: f32) -> Point {
+  (a.0 * s, a.1 * s)
+}
+
+fn dot(a: Point, b: Point) -> f32 {
+  a.0 * b.0 + a.1 * b.1
+}
+
+fn cross(a: Point, b: Point) -> f32 {
+  a.0 * b.1 - a.1 * b.0
+}
+
+fn length(a: Point) -> f32 {
+  dot(a, a).sqrt()
+}
+
+fn normalize(a: Point) -> Point {
+  let l = length(a);
+  if l < EPSILON {
+    (1.0, 0.0)
+  } else {
+    scale(a, 1.0 / l)
+  }
+}
+
+/// The direction rotated by 90 degrees
+fn normal(direction: Point) -> Point {
+  (-direction.1, direction.0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn area(mesh: &Mesh) -> f32 {
+    mesh
+      .indices
+      .chunks(3)
+      .map(|t| {
+        let a = mesh.vertices[t[0] as usize];
+        let b = mesh.vertices[t[1] as usize];
+        let c = mesh.vertices[t[2] as usize];
+        (cross((b[0] - a[0], b[1] - a[1]), (c[0] - a[0], c[1] - a[1])) * 0.5).abs()
+      })
+      .sum()
+  }
+
+  fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+    assert!(
+      (actual - expected).abs() <= tolerance,
+      "expected {} but got {}",
+      expected,
+      actual
+    );
+  }
+
+  #[test]
+  fn test_flatten_path() {
+    let polylines = Path::rect(0.0, 0.0, 10.0, 20.0).flatten();
+    assert_eq!(polylines.len(), 1);
+    assert!(polylines[0].closed);
+    assert_eq!(polylines[0].points.len(), 4);
+    assert_close(polylines[0].length(), 60.0, 0.001);
+
+    let curve = Path::new()
+      .move_to(0.0, 0.0)
+      .cubic_to(0.0, 50.0, 100.0, 50.0, 100.0, 0.0)
+      .flatten();
+    assert!(curve[0].points.len() > 8);
+    assert_eq!(*curve[0].points.last().unwrap(), (100.0, 0.0));
+
+    let circle = Path::circle(50.0, 50.0, 50.0).flatten();
+    assert_close(circle[0].length(), 2.0 * PI * 50.0, 1.0);
+  }
+
+  #[test]
+  fn test_arc_sweep() {
+    assert_close(arc_sweep(0.0, PI / 2.0, true), PI / 2.0, 0.0001);
+    assert_close(arc_sweep(0.0, PI / 2.0, false), -1.5 * PI, 0.0001);
+    assert_close(arc_sweep(0.0, -PI / 2.0, true), 1.5 * PI, 0.0001);
+    assert_close(arc_sweep(0.0, 4.0 * PI, true), 2.0 * PI, 0.0001);
+  }
+
+  #[test]
+  fn test_fill_rules() {
+    let mut path = Path::rect(0.0, 0.0, 100.0, 100.0);
+    path
+      .elements
+      .extend(Path::rect(25.0, 25.0, 50.0, 50.0).elements);
+
+    let shape = Shape::new(path);
+    assert_close(area(&shape.tessellate_fill()), 10000.0, 0.1);
+
+    let shape = shape.with_fill_rule(FillRule::EvenOdd);
+    assert_close(area(&shape.tessellate_fill()), 7500.0, 0.1);
+  }
+
+  #[test]
+  fn test_fill_self_intersecting_path() {
+    // Two triangles meeting at (50, 50)
+    let path = Path::new()
+      .move_to(0.0, 0.0)
+      .line_to(100.0, 100.0)
+      .line_to(100.0, 0.0)
+      .line_to(0.0, 100.0)
+      .close();
+    let mesh = Shape::new(path).tessellate_fill();
+    assert_close(area(&mesh), 5000.0, 0.1);
+  }
+
+  #[test]
+  fn test_fill_star_and_circle() {
+    // A pentagram, whose edges cross each other twice
+    let (r, inner_r) = (100.0, 100.0 * (0.4 * PI).cos() / (0.2 * PI).cos());
+    let mut path = Path::new();
+    for i in 0..5 {
+      let angle = i as f32 * 4.0 * PI / 5.0;
+      let point = (150.0 + r * angle.sin(), 150.0 - r * angle.cos());
+      path = if i == 0 {
+        path.move_to(point.0, point.1)
+      } else {
+        path.line_to(point.0, point.1)
+      };
+    }
+    let shape = Shape::new(path.close());
+    let star = 5.0 * r * inner_r * (0.2 * PI).sin();
+    let pentagon = 2.5 * inner_r * inner_r * (0.4 * PI).sin();
+    assert_close(area(&shape.tessellate_fill()), star, 0.5);
+    let shape = shape.with_fill_rule(FillRule::EvenOdd);
+    assert_close(area(&shape.tessellate_fill()), star - pentagon, 0.5);
+
+    // The flattened circle, with many edges
+    let circle = Shape::new(Path::circle(50.0, 50.0, 50.0));
+    let points = &circle.path.flatten()[0].points;
+    let polygon: f32 = (0..points.len())
+      .map(|i| cross(points[i], points[(i + 1) % points.len()]) * 0.5)
+      .sum();
+    assert!(points.len() > 16);
+    assert_close(area(&circle.tessellate_fill()), polygon.abs(), 0.5);
+  }
+
+  #[test]
+  fn test_stroke_caps() {
+    let path = Path::new().move_to(0.0, 0.0).line_to(100.0, 0.0);
+    let shape = Shape::new(path).with_stroke([1.0; 4], 10.0);
+    assert_close(area(&shape.tessellate_stroke()), 1000.0, 0.1);
+
+    let square = shape.clone().with_line_cap(LineCap::Square);
+    assert_close(area(&square.tessellate_stroke()), 1100.0, 0.1);
+
+    let round = shape.with_line_cap(LineCap::Round);
+    // The caps are flattened, so they are slightly smaller than a circle
+    assert_close(area(&round.tessellate_stroke()), 1000.0 + PI * 25.0, 6.0);
+  }
+
+  #[test]
+  fn test_stroke_joins() {
+    // Segments overlap by 5x5 at each corner, and joins fill the outer 5x5 gap
+    let shape = Shape::new(Path::rect(0.0, 0.0, 100.0, 100.0)).with_stroke([1.0; 4], 10.0);
+    let segments = 4.0 * 100.0 * 10.0;
+    assert_close(area(&shape.tessellate_stroke()), segments + 4.0 * 25.0, 0.1);
+
+    let bevel = shape.clone().with_line_join(LineJoin::Bevel);
+    assert_close(area(&bevel.tessellate_stroke()), segments + 4.0 * 12.5, 0.1);
+
+    let round = shape.with_line_join(LineJoin::Round);
+    assert_close(area(&round.tessellate_stroke()), segments + PI * 25.0, 6.0);
+  }
+
+  #[test]
+  fn test_stroke_start_end_and_dashes() {
+    let path = Path::new().move_to(0.0, 0.0).line_to(100.0, 0.0);
+    let mut shape = Shape::new(path).with_stroke([1.0; 4], 2.0);
+    shape.stroke_end = 0.25;
+    assert_close(area(&shape.tessellate_stroke()), 50.0, 0.01);
+
+    shape.stroke_start = 0.25;
+    assert!(shape.tessellate_stroke().is_empty());
+
+    let dashed = Shape::new(Path::new().move_to(0.0, 0.0).line_to(100.0, 0.0))
+      .with_stroke([1.0; 4], 2.0)
+      .with_line_dash(vec![10.0, 10.0], 5.0);
+    assert_eq!(
+      dashed.visible_intervals(100.0)[..2],
+      [(0.0, 5.0), (15.0, 25.0)]
+    );
+    assert_close(area(&dashed.tessellate_stroke()), 100.0, 0.01);
+  }
+}
@@ -0,0 +1,270 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Converts a line of text into positioned glyphs.
+//!
+//! With the `shaping` feature, lines are reordered with the Unicode
+//! bidirectional algorithm and every run is shaped with rustybuzz, so
+//! ligatures, marks and complex scripts (Arabic, Hebrew, Devanagari, Thai)
+//! are laid out correctly. Without it, each character maps to one glyph and
+//! only kerning is applied.
+//!
+//! Each character is drawn with the first font of the fallback list that has it
+//! (see `font::fallback_font_index`), and every run of characters in the same
+//! font is shaped with that font.
+//!
+//! Parsing a font for shaping takes time, so code that shapes many lines with
+//! the same fonts parses them once with `shaping_faces` and shapes with
+//! `shape_line_with_faces`.
+
+use ab_glyph::{Font, GlyphId, PxScale, ScaleFont};
+
+use crate::font::fallback_font_index;
+
+/// A glyph of a shaped line, in visual order from left to right
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShapedGlyph {
+  pub font: usize, // index in the fonts the line was shaped with
+  pub id: GlyphId,
+  pub x: f32,         // from the start of the line
+  pub y_offset: f32,  // from the baseline, positive down
+  pub cluster: usize, // byte index of the first character of the glyph in the line
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapedLine {
+  pub glyphs: Vec<ShapedGlyph>,
+  pub width: f32,
+}
+
+/// A font parsed for shaping. Empty without the `shaping` feature.
+pub struct ShapingFace<'a> {
+  #[cfg(feature = "shaping")]
+  face: Option<rustybuzz::Face<'a>>, // None if rustybuzz can't parse the font
+  #[cfg(not(feature = "shaping"))]
+  _font: std::marker::PhantomData<&'a [u8]>,
+}
+
+/// Parse fonts for `shape_line_with_faces`
+pub fn shaping_faces<F: Font>(fonts: &[F]) -> Vec<ShapingFace<'_>> {
+  fonts
+    .iter()
+    .map(|_font| ShapingFace {
+      #[cfg(feature = "shaping")]
+      face: rustybuzz::Face::from_slice(_font.font_data(), 0),
+      #[cfg(not(feature = "shaping"))]
+      _font: std::marker::PhantomData,
+    })
+    .collect()
+}
+
+/// Shape a single line (without line breaks) with a font and its fallback
+/// fonts. `letter_spacing` is added between clusters, so ligatures and
+/// combining marks stay together.
+pub fn shape_line<F: Font>(
+  fonts: &[F],
+  scale: PxScale,
+  line: &str,
+  letter_spacing: f32,
+) -> ShapedLine {
+  shape_line_with_faces(fonts, &shaping_faces(fonts), scale, line, letter_spacing)
+}
+
+/// Like `shape_line`, with the fonts already parsed by `shaping_faces`
+pub fn shape_line_with_faces<F: Font>(
+  fonts: &[F],
+  _faces: &[ShapingFace],
+  scale: PxScale,
+  line: &str,
+  letter_spacing: f32,
+) -> ShapedLine {
+  if fonts.is_empty() {
+    return ShapedLine::default();
+  }
+
+  #[cfg(feature = "shaping")]
+  if let Some(shaped) = shape_bidi(fonts, _faces, scale, line, letter_spacing) {
+    return shaped;
+  }
+
+  shape_simple(fonts, scale, line, letter_spacing)
+}
+
+// One glyph per character, with kerning between glyphs of the same font
+fn shape_simple<F: Font>(
+  fonts: &[F],
+  scale: PxScale,
+  line: &str,
+  letter_spacing: f32,
+) -> ShapedLine {
+  let mut shaped = ShapedLine::default();
+  let mut last: Option<(usize, GlyphId)> = None;
+  let mut font = 0;
+  for (cluster, c) in line.char_indices() {
+    font = fallback_font_index(fonts, font, c);
+    let scaled = fonts[font].as_scaled(scale);
+    let id = scaled.glyph_id(c);
+    if let Some((last_font, previous)) = last {
+      if last_font == font {
+        shaped.width += scaled.kern(previous, id);
+      }
+      shaped.width += letter_spacing;
+    }
+    shaped.glyphs.push(ShapedGlyph {
+      font,
+      id,
+      x: shaped.width,
+      y_offset: 0.0,
+      cluster,
+    });
+    shaped.width += scaled.h_advance(id);
+    last = Some((font, id));
+  }
+  shaped
+}
+
+// Split text into runs of characters drawn with the same font, in logical order
+#[cfg(feature = "shaping")]
+fn font_runs<F: Font>(fonts: &[F], text: &str) -> Vec<(std::ops::Range<usize>, usize)> {
+  let mut runs: Vec<(std::ops::Range<usize>, usize)> = Vec::new();
+  let mut font = 0;
+  for (i, c) in text.char_indices() {
+    font = fallback_font_index(fonts, font, c);
+    let end = i + c.len_utf8();
+    match runs.last_mut() {
+      Some((range, run_font)) if *run_font == font => range.end = end,
+      _ => runs.push((i..end, font)),
+    }
+  }
+  runs
+}
+
+// Reorder the line into visual runs and shape the font runs of each of them.
+// Returns None if the data of a font can't be parsed by rustybuzz.
+#[cfg(feature = "shaping")]
+fn shape_bidi<F: Font>(
+  fonts: &[F],
+  faces: &[ShapingFace],
+  scale: PxScale,
+  line: &str,
+  letter_spacing: f32,
+) -> Option<ShapedLine> {
+  use unicode_bidi::BidiInfo;
+
+  let faces = faces
+    .iter()
+    .map(|face| face.face.as_ref())
+    .collect::<Option<Vec<_>>>()?;
+
+  let mut shaped = ShapedLine::default();
+  if line.is_empty() {
+    return Some(shaped);
+  }
+  let bidi = BidiInfo::new(line, None);
+  let mut first_cluster = true;
+  for paragraph in &bidi.paragraphs {
+    let (levels, runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+    for run in runs {
+      let rtl = levels[run.start].is_rtl();
+      let mut segments = font_runs(fonts, &line[run.clone()]);
+      if rtl {
+        segments.reverse();
+      }
+      for (segment, font) in segments {
+        let start = run.start + segment.start;
+        let scaled = fonts[font].as_scaled(scale);
+        // Font units to pixels, matching the metrics of ab_glyph
+        let (h_factor, v_factor) = (scaled.h_scale_factor(), scaled.v_scale_factor());
+
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(&line[start..run.start + segment.end]);
+        buffer.set_direction(if rtl {
+          rustybuzz::Direction::RightToLeft
+        } else {
+          rustybuzz::Direction::LeftToRight
+        });
+        buffer.guess_segment_properties();
+        let output = rustybuzz::shape(faces[font], &[], buffer);
+
+        let mut last_cluster = None;
+        for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+          if last_cluster != Some(info.cluster) {
+            if !first_cluster {
+              shaped.width += letter_spacing;
+            }
+            first_cluster = false;
+            last_cluster = Some(info.cluster);
+          }
+          shaped.glyphs.push(ShapedGlyph {
+            font,
+            id: GlyphId(info.glyph_id as u16),
+            x: shaped.width + position.x_offset as f32 * h_factor,
+            y_offset: -position.y_offset as f32 * v_factor,
+            cluster: start + info.cluster as usize,
+          });
+          shaped.width += position.x_advance as f32 * h_factor;
+        }
+      }
+    }
+  }
+  Some(shaped)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::font::FontRegistry;
+
+  #[test]
+  fn test_shape_latin() {
+    let font = FontRegistry::new().font("DejaVu Sans").unwrap();
+    let scale = PxScale::from(20.0);
+    let line = shape_line(&[font.as_ref()], scale, "AVA", 0.0);
+    assert_eq!(line.glyphs.len(), 3);
+    assert_eq!(line.glyphs[0].x, 0.0);
+    assert!(line.glyphs[1].x < line.glyphs[2].x);
+    assert_eq!(
+      line.glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>(),
+      vec![0, 1, 2]
+    );
+
+    // Letter spacing between clusters
+    let spaced = shape_line(&[font.as_ref()], scale, "AVA", 3.0);
+    assert!((spaced.width - line.width - 6.0).abs() < 0.01);
+
+    // Fonts parsed once shape the same
+    let fonts = [font.as_ref()];
+    let faces = shaping_faces(&fonts);
+    assert_eq!(
+      shape_line_with_faces(&fonts, &faces, scale, "AVA", 0.0),
+      line
+    );
+  }
+
+  #[cfg(feature = "shaping")]
+  #[test]
+  fn test_shape_rtl_and_joining() {
+    let font = FontRegistry::new().font("DejaVu Sans").unwrap();
+    let scale = PxScale::from(20.0);
+
+    // Hebrew is laid out right to left: the first character is drawn last
+    let hebrew = "\u{05e9}\u{05dc}\u{05d5}\u{05dd}";
+    let line = shape_line(&[font.as_ref()], scale, hebrew, 0.0);
+    assert_eq!(line.glyphs.len(), 4);
+    assert_eq!(line.glyphs.last().unwrap().cluster, 0);
+    assert_eq!(line.glyphs[0].cluster, hebrew.len() - 2);
+
+    // Mixed text keeps the Latin run left to right before the Hebrew run
+    let mixed = format!("abc {}", hebrew);
+    let line = shape_line(&[font.as_ref()], scale, &mixed, 0.0);
+    assert_eq!(line.glyphs[0].cluster, 0);
+    assert_eq!(line.glyphs.last().unwrap().cluster, 4);
+
+    // Arabic letters take their joining forms, which differ from the isolated ones
+    let isolated = shape_line(&[font.as_ref()], scale, "\u{0644}", 0.0);
+    let joined = shape_line(&[font.as_ref()], scale, "\u{0644}\u{0645}", 0.0);
+    let lam = joined.glyphs.iter().find(|g| g.cluster == 0).unwrap();
+    assert_ne!(lam.id, isolated.glyphs[0].id);
+  }
+}
@@ -0,0 +1,355 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use cgmath::Matrix4;
+use image::RgbaImage;
+use resvg::{tiny_skia, usvg};
+
+use crate::gradient::Gradient;
+use crate::layer::Layer;
+use crate::shape::{FillRule, LineCap, LineJoin, Path, Shape};
+
+#[derive(Debug)]
+pub enum SvgError {
+  Io(std::io::Error),
+  Parse(usvg::Error),
+  // The document is too large (or empty) to be rasterized at the requested scale
+  InvalidSize,
+}
+
+impl std::fmt::Display for SvgError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SvgError::Io(err) => write!(f, "can't read the SVG file: {}", err),
+      SvgError::Parse(err) => write!(f, "can't parse the SVG document: {}", err),
+      SvgError::InvalidSize => write!(f, "invalid SVG image size"),
+    }
+  }
+}
+
+impl std::error::Error for SvgError {}
+
+impl From<std::io::Error> for SvgError {
+  fn from(err: std::io::Error) -> Self {
+    SvgError::Io(err)
+  }
+}
+
+impl From<usvg::Error> for SvgError {
+  fn from(err: usvg::Error) -> Self {
+    SvgError::Parse(err)
+  }
+}
+
+/// Returns true if the path has an .svg extension
+pub fn is_svg_path(path: &str) -> bool {
+  std::path::Path::new(path)
+    .extension()
+    .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"))
+}
+
+/// Load an SVG file into a subtree of shape layers.
+///
+/// The returned layer has the size of the document. Every group becomes a layer whose
+/// `transform` is the group transform, and every path becomes a shape layer with the
+/// fill, stroke and gradients of the path. Group opacity is applied to each path, so
+/// overlapping paths inside a translucent group blend with each other.
+/// Images, text, clip paths, masks, patterns and filters are not imported; use
+/// `Layer::set_image` to rasterize documents that rely on them.
+pub fn load_layer(name: String, path: &str) -> Result<Layer, SvgError> {
+  let data = std::fs::read(path)?;
+  let tree = parse(&data, Some(path))?;
+  Ok(layer_from_tree(name, &tree))
+}
+
+/// Load SVG data into a subtree of shape layers. See `load_layer`.
+pub fn layer_from_data(name: String, data: &[u8]) -> Result<Layer, SvgError> {
+  let tree = parse(data, None)?;
+  Ok(layer_from_tree(name, &tree))
+}
+
+/// Rasterize an SVG file into an image, `scale` times the size of the document
+pub fn rasterize(path: &str, scale: f32) -> Result<RgbaImage, SvgError> {
+  let data = std::fs::read(path)?;
+  let tree = parse(&data, Some(path))?;
+  rasterize_tree(&tree, scale)
+}
+
+/// Rasterize SVG data into an image, `scale` times the size of the document
+pub fn rasterize_data(data: &[u8], scale: f32) -> Result<RgbaImage, SvgError> {
+  let tree = parse(data, None)?;
+  rasterize_tree(&tree, scale)
+}
+
+fn parse(data: &[u8], path: Option<&str>) -> Result<usvg::Tree, SvgError> {
+  // Relative references (e.g. images) are resolved next to the SVG file
+  let options = usvg::Options {
+    resources_dir: path
+      .and_then(|path| std::path::Path::new(path).parent())
+      .map(|dir| dir.to_path_buf()),
+    ..Default::default()
+  };
+  Ok(usvg::Tree::from_data(data, &options)?)
+}
+
+fn rasterize_tree(tree: &usvg::Tree, scale: f32) -> Result<RgbaImage, SvgError> {
+  let size = tree
+    .size()
+    .to_int_size()
+    .scale_by(scale)
+    .ok_or(SvgError::InvalidSize)?;
+  let mut pixmap =
+    tiny_skia::Pixmap::new(size.width(), size.height()).ok_or(SvgError::InvalidSize)?;
+  resvg::render(
+    tree,
+    tiny_skia::Transform::from_scale(scale, scale),
+    &mut pixmap.as_mut(),
+  );
+
+  RgbaImage::from_raw(size.width(), size.height(), pixmap.take_demultiplied())
+    .ok_or(SvgError::InvalidSize)
+}
+
+fn layer_from_tree(name: String, tree: &usvg::Tree) -> Layer {
+  let size = tree.size();
+  let mut layer = group_layer(
+    name,
+    size.width().ceil() as u32,
+    size.height().ceil() as u32,
+    tree.root().transform(),
+  );
+
+  // Sublayers are square so that gradients keep their proportions in the
+  // unit coordinate space of the layer
+  let side = size.width().max(size.height());
+  add_children(&mut layer, tree.root(), 1.0, side);
+  layer
+}
+
+/// A shape layer without content, transformed around its top left corner
+fn group_layer(name: String, width: u32, height: u32, transform: usvg::Transform) -> Layer {
+  let mut layer = Layer::new(name, width, height, None);
+  layer.anchor_x = 0.0;
+  layer.anchor_y = 0.0;
+  layer.set_transform(to_matrix(transform));
+  layer.set_shape(Some(Shape::new(Path::new()).with_fill_color(None)));
+  layer
+}
+
+fn add_children(parent: &mut Layer, group: &usvg::Group, opacity: f32, side: f32) {
+  for (i, node) in group.children().iter().enumerate() {
+    let name = if node.id().is_empty() {
+      format!("{}_{}", parent.name, i)
+    } else {
+      node.id().to_string()
+    };
+
+    match node {
+      usvg::Node::Group(group) => {
+        let size = side.ceil() as u32;
+        let mut layer = group_layer(name, size, size, group.transform());
+        add_children(&mut layer, group, opacity * group.opacity().get(), side);
+        parent.add_sub_layer(layer);
+      }
+      usvg::Node::Path(path) if path.is_visible() => {
+        let size = side.ceil() as u32;
+        let mut layer = Layer::new(name, size, size, None);
+        layer.anchor_x = 0.0;
+        layer.anchor_y = 0.0;
+        layer.opacity = opacity;
+        layer.set_shape(Some(to_shape(path, side)));
+        parent.add_sub_layer(layer);
+      }
+      _ => {}
+    }
+  }
+}
+
+fn to_shape(path: &usvg::Path, side: f32) -> Shape {
+  let mut shape_path = Path::new();
+  for segment in path.data().segments() {
+    shape_path = match segment {
+      tiny_skia::PathSegment::MoveTo(p) => shape_path.move_to(p.x, p.y),
+      tiny_skia::PathSegment::LineTo(p) => shape_path.line_to(p.x, p.y),
+      tiny_skia::PathSegment::QuadTo(c, p) => shape_path.quad_to(c.x, c.y, p.x, p.y),
+      tiny_skia::PathSegment::CubicTo(c1, c2, p) => {
+        shape_path.cubic_to(c1.x, c1.y, c2.x, c2.y, p.x, p.y)
+      }
+      tiny_skia::PathSegment::Close => shape_path.close(),
+    };
+  }
+
+  let mut shape = Shape::new(shape_path).with_fill_color(None);
+  if let Some(fill) = path.fill() {
+    (shape.fill_color, shape.fill_gradient) = to_paint(fill.paint(), fill.opacity().get(), side);
+    shape.fill_rule = match fill.rule() {
+      usvg::FillRule::NonZero => FillRule::NonZero,
+      usvg::FillRule::EvenOdd => FillRule::EvenOdd,
+    };
+  }
+  if let Some(stroke) = path.stroke() {
+    (shape.stroke_color, shape.stroke_gradient) =
+      to_paint(stroke.paint(), stroke.opacity().get(), side);
+    shape.line_width = stroke.width().get();
+    shape.line_cap = match stroke.linecap() {
+      usvg::LineCap::Butt => LineCap::Butt,
+      usvg::LineCap::Round => LineCap::Round,
+      usvg::LineCap::Square => LineCap::Square,
+    };
+    shape.line_join = match stroke.linejoin() {
+      usvg::LineJoin::Miter | usvg::LineJoin::MiterClip => LineJoin::Miter,
+      usvg::LineJoin::Round => LineJoin::Round,
+      usvg::LineJoin::Bevel => LineJoin::Bevel,
+    };
+    shape.miter_limit = stroke.miterlimit().get();
+    shape.line_dash_pattern = stroke.dasharray().map(|d| d.to_vec()).unwrap_or_default();
+    shape.line_dash_phase = stroke.dashoffset();
+  }
+  shape
+}
+
+/// Color or gradient of an SVG paint. Patterns are not supported and not painted.
+fn to_paint(paint: &usvg::Paint, opacity: f32, side: f32) -> (Option<[f32; 4]>, Option<Gradient>) {
+  match paint {
+    usvg::Paint::Color(color) => (Some(to_color(*color, opacity)), None),
+    usvg::Paint::LinearGradient(gradient) => {
+      let transform = gradient.transform();
+      let start = map_point(transform, gradient.x1(), gradient.y1(), side);
+      let end = map_point(transform, gradient.x2(), gradient.y2(), side);
+      let (colors, locations) = to_stops(gradient.stops(), opacity);
+      let gradient = Gradient::linear(colors)
+        .with_locations(locations)
+        .with_points(start, end);
+      (None, Some(gradient))
+    }
+    usvg::Paint::RadialGradient(gradient) => {
+      // The focal point is not supported, the gradient is centered on (cx, cy)
+      let transform = gradient.transform();
+      let (cx, cy, r) = (gradient.cx(), gradient.cy(), gradient.r().get());
+      let center = map_point(transform, cx, cy, side);
+      let edge = map_point(transform, cx + r, cy, side);
+      let (colors, locations) = to_stops(gradient.stops(), opacity);
+      let gradient = Gradient::radial(colors)
+        .with_locations(locations)
+        .with_points(center, edge);
+      (None, Some(gradient))
+    }
+    usvg::Paint::Pattern(_) => (None, None),
+  }
+}
+
+fn to_stops(stops: &[usvg::Stop], opacity: f32) -> (Vec<[f32; 4]>, Vec<f32>) {
+  stops
+    .iter()
+    .map(|stop| {
+      (
+        to_color(stop.color(), stop.opacity().get() * opacity),
+        stop.offset().get(),
+      )
+    })
+    .unzip()
+}
+
+fn to_color(color: usvg::Color, opacity: f32) -> [f32; 4] {
+  [
+    color.red as f32 / 255.0,
+    color.green as f32 / 255.0,
+    color.blue as f32 / 255.0,
+    opacity,
+  ]
+}
+
+/// Transform a point in user space into the unit coordinate space of a layer
+fn map_point(transform: usvg::Transform, x: f32, y: f32, side: f32) -> (f32, f32) {
+  let mapped_x = transform.sx * x + transform.kx * y + transform.tx;
+  let mapped_y = transform.ky * x + transform.sy * y + transform.ty;
+  (mapped_x / side, mapped_y / side)
+}
+
+fn to_matrix(transform: usvg::Transform) -> Matrix4<f32> {
+  #[rustfmt::skip]
+  let matrix = Matrix4::new(
+    transform.sx, transform.ky, 0.0, 0.0,
+    transform.kx, transform.sy, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    transform.tx, transform.ty, 0.0, 1.0,
+  );
+  matrix
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cgmath::Vector4;
+
+  const ICON: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">
+    <defs>
+      <linearGradient id="fade" x1="0" y1="0" x2="100" y2="0" gradientUnits="userSpaceOnUse">
+        <stop offset="0" stop-color="#ff0000"/>
+        <stop offset="1" stop-color="#0000ff" stop-opacity="0.5"/>
+      </linearGradient>
+    </defs>
+    <rect id="background" width="100" height="50" fill="url(#fade)"/>
+    <g id="badge" transform="translate(10 20)" opacity="0.5">
+      <circle id="dot" cx="5" cy="5" r="5" fill="#00ff00" stroke="#000000" stroke-width="2"/>
+    </g>
+  </svg>"##;
+
+  #[test]
+  fn test_is_svg_path() {
+    assert!(is_svg_path("icons/play.svg"));
+    assert!(is_svg_path("icons/PLAY.SVG"));
+    assert!(!is_svg_path("icons/play.png"));
+  }
+
+  #[test]
+  fn test_layer_from_data() {
+    let layer = layer_from_data("icon".to_string(), ICON.as_bytes()).unwrap();
+    assert_eq!((layer.width, layer.height), (100, 50));
+    assert_eq!(layer.sublayers().len(), 2);
+
+    let background = &layer.sublayers()[0];
+    assert_eq!(background.name, "background");
+    let shape = background.shape().unwrap();
+    assert!(shape.fill_color.is_none());
+    let gradient = shape.fill_gradient.as_ref().unwrap();
+    assert_eq!(gradient.colors[1], [0.0, 0.0, 1.0, 0.5]);
+    assert_eq!(gradient.start_point, (0.0, 0.0));
+    assert_eq!(gradient.end_point, (1.0, 0.0));
+    assert!(!shape.tessellate_fill().is_empty());
+
+    let badge = &layer.sublayers()[1];
+    assert_eq!(badge.name, "badge");
+    let origin = badge.transform * Vector4::new(0.0, 0.0, 0.0, 1.0);
+    assert_eq!((origin.x, origin.y), (10.0, 20.0));
+
+    let dot = &badge.sublayers()[0];
+    assert_eq!(dot.opacity, 0.5);
+    let shape = dot.shape().unwrap();
+    assert_eq!(shape.fill_color, Some([0.0, 1.0, 0.0, 1.0]));
+    assert_eq!(shape.stroke_color, Some([0.0, 0.0, 0.0, 1.0]));
+    assert_eq!(shape.line_width, 2.0);
+  }
+
+  #[test]
+  fn test_rasterize_data() {
+    let image = rasterize_data(ICON.as_bytes(), 2.0).unwrap();
+    assert_eq!(image.dimensions(), (200, 100));
+    // Left edge of the gradient background
+    let [r, g, b, a] = image.get_pixel(0, 50).0;
+    assert!(r > 250 && g < 5 && b < 5 && a == 255);
+  }
+
+  #[test]
+  fn test_invalid_svg() {
+    assert!(matches!(
+      layer_from_data("broken".to_string(), b"<svg"),
+      Err(SvgError::Parse(_))
+    ));
+    assert!(matches!(
+      load_layer("missing".to_string(), "missing.svg"),
+      Err(SvgError::Io(_))
+    ));
+  }
+}
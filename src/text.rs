@@ -0,0 +1,1352 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use ab_glyph::{point, Font, FontVec, Glyph, GlyphId, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+use std::ops::Range;
+use stretch::geometry::Size;
+use stretch::node::MeasureFunc;
+use stretch::number::Number;
+
+use crate::font::{FontCollection, FontRegistry};
+use crate::shaping::{shape_line_with_faces, shaping_faces, ShapedLine, ShapingFace};
+
+/// The default font family, found in the fonts directory of the project
+pub const DEFAULT_FONT: &str = "DejaVu Sans";
+
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Horizontal alignment of the lines in a text layer (CATextLayer alignmentMode)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextAlignment {
+  Left,
+  Center,
+  Right,
+}
+
+/// Vertical alignment of the text block in a text layer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VerticalAlignment {
+  Top,
+  Center,
+  Bottom,
+}
+
+/// How a text layer is drawn
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextRenderMode {
+  // The whole text is rendered into a texture of the layer size
+  Bitmap,
+  // Glyphs are drawn as quads from the glyph atlas shared by all layers, which
+  // is cheaper for text that changes often
+  GlyphAtlas,
+  // Glyphs are drawn from signed distance fields in the glyph atlas, which stay
+  // crisp when the layer is scaled or rotated and support outline and glow
+  Sdf,
+}
+
+/// Position of a line of laid out text
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LineMetrics {
+  pub text: String, // the line after wrapping and truncation
+  pub x: f32,       // left edge, after alignment
+  pub y: f32,       // top of the line
+  pub baseline: f32,
+  pub width: f32,
+  pub height: f32, // ascent to descent of the font
+}
+
+/// Position of a glyph of laid out text, on the baseline
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GlyphPosition {
+  pub font: usize, // index in the fonts the text was laid out with
+  pub id: GlyphId,
+  pub size: f32, // font size
+  pub line: usize,
+  pub cluster: usize, // byte index in the text of the line
+  pub index: usize,   // byte index in `Text::string`
+  pub x: f32,
+  pub y: f32,
+}
+
+/// Size and layout of a text, see `Text::measure`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextMetrics {
+  pub width: f32, // of the widest line
+  pub height: f32,
+  pub lines: Vec<LineMetrics>,
+  pub glyphs: Vec<GlyphPosition>, // including spaces
+}
+
+impl TextMetrics {
+  pub fn line_count(&self) -> usize {
+    self.lines.len()
+  }
+
+  fn is_space(&self, glyph: &GlyphPosition) -> bool {
+    self.lines[glyph.line].text[glyph.cluster..]
+      .chars()
+      .next()
+      .is_some_and(char::is_whitespace)
+  }
+}
+
+/// The text drawn by a layer and its style.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Text {
+  pub string: String,
+  pub font: String, // font family name or path to a font file
+  pub font_size: f32,
+  pub color: [f32; 4],
+  pub line_height: f32,    // multiplier of the font line height
+  pub letter_spacing: f32, // extra pixels between characters
+  pub alignment: TextAlignment,
+  pub vertical_alignment: VerticalAlignment,
+  pub max_width: Option<f32>, // wraps at the layer width if None
+  pub max_lines: usize,       // 0 means unlimited
+  pub render_mode: TextRenderMode,
+  // Outline and glow around the glyphs, drawn in the Sdf render mode only
+  pub outline_color: [f32; 4],
+  pub outline_width: f32,
+  pub glow_color: [f32; 4],
+  pub glow_radius: f32,
+  // Styles of ranges of the string, for rich text
+  pub runs: Vec<TextRun>,
+}
+
+impl Text {
+  pub fn new(string: &str) -> Self {
+    Self {
+      string: string.to_string(),
+      font: DEFAULT_FONT.to_string(),
+      font_size: 17.0,
+      color: [0.0, 0.0, 0.0, 1.0],
+      line_height: 1.0,
+      letter_spacing: 0.0,
+      alignment: TextAlignment::Left,
+      vertical_alignment: VerticalAlignment::Top,
+      max_width: None,
+      max_lines: 0,
+      render_mode: TextRenderMode::Bitmap,
+      outline_color: [0.0, 0.0, 0.0, 1.0],
+      outline_width: 0.0,
+      glow_color: [1.0, 1.0, 1.0, 1.0],
+      glow_radius: 0.0,
+      runs: Vec::new(),
+    }
+  }
+
+  /// Rich text from markup, e.g. "Press <b>Start</b>". See `markup::parse`.
+  pub fn from_markup(markup: &str) -> Self {
+    let (string, runs) = crate::markup::parse(markup);
+    Self {
+      runs,
+      ..Self::new(&string)
+    }
+  }
+
+  pub fn with_font(mut self, font: &str) -> Self {
+    self.font = font.to_string();
+    self
+  }
+
+  pub fn with_font_size(mut self, font_size: f32) -> Self {
+    self.font_size = font_size;
+    self
+  }
+
+  pub fn with_color(mut self, r: f32, g: f32, b: f32, a: f32) -> Self {
+    self.color = [r, g, b, a];
+    self
+  }
+
+  pub fn with_line_height(mut self, line_height: f32) -> Self {
+    self.line_height = line_height;
+    self
+  }
+
+  pub fn with_letter_spacing(mut self, letter_spacing: f32) -> Self {
+    self.letter_spacing = letter_spacing;
+    self
+  }
+
+  pub fn with_alignment(mut self, alignment: TextAlignment) -> Self {
+    self.alignment = alignment;
+    self
+  }
+
+  pub fn with_vertical_alignment(mut self, vertical_alignment: VerticalAlignment) -> Self {
+    self.vertical_alignment = vertical_alignment;
+    self
+  }
+
+  pub fn with_max_width(mut self, max_width: f32) -> Self {
+    self.max_width = Some(max_width);
+    self
+  }
+
+  pub fn with_max_lines(mut self, max_lines: usize) -> Self {
+    self.max_lines = max_lines;
+    self
+  }
+
+  pub fn with_render_mode(mut self, render_mode: TextRenderMode) -> Self {
+    self.render_mode = render_mode;
+    self
+  }
+
+  /// Outline the glyphs with `width` pixels of `color` (Sdf render mode)
+  pub fn with_outline(mut self, color: [f32; 4], width: f32) -> Self {
+    self.outline_color = color;
+    self.outline_width = width;
+    self
+  }
+
+  /// Add a glow of `color` fading out over `radius` pixels (Sdf render mode)
+  pub fn with_glow(mut self, color: [f32; 4], radius: f32) -> Self {
+    self.glow_color = color;
+    self.glow_radius = radius;
+    self
+  }
+
+  /// Apply `style` to a byte range of the string
+  pub fn with_run(mut self, range: Range<usize>, style: TextStyle) -> Self {
+    self.runs.push(TextRun { range, style });
+    self
+  }
+
+  /// Break the string into lines that fit in `width` and truncate it to
+  /// `max_lines` with an ellipsis.
+  /// `fonts` is the font of the text followed by its fallback fonts.
+  pub fn layout_lines<F: Font>(&self, fonts: &[F], width: f32) -> Vec<String> {
+    let fonts = StyleFonts::uniform(self, fonts);
+    let layout = RichLayout::new(self, &fonts);
+    layout
+      .lines(width)
+      .into_iter()
+      .map(|line| line.text)
+      .collect()
+  }
+
+  /// Measure the text without rendering it. Lines wrap at `max_width` (and at
+  /// the `max_width` of the text), or only at newlines if both are None; lines
+  /// are aligned within the measured width.
+  pub fn measure<F: Font>(&self, fonts: &[F], max_width: Option<f32>) -> TextMetrics {
+    let fonts = StyleFonts::uniform(self, fonts);
+    RichLayout::new(self, &fonts).metrics(max_width.unwrap_or(f32::INFINITY), None)
+  }
+
+  /// Measure the text with its fonts from a registry. Returns None if no font
+  /// of the text can be loaded.
+  pub fn measure_with(&self, fonts: &FontRegistry, max_width: Option<f32>) -> Option<TextMetrics> {
+    let fonts = self.fonts(fonts)?;
+    let fonts = fonts.style_fonts();
+    Some(RichLayout::new(self, &fonts).metrics(max_width.unwrap_or(f32::INFINITY), None))
+  }
+
+  /// A stretch measure function that sizes a leaf node to the text, wrapping
+  /// at the width available to the node
+  pub fn measure_func(&self, fonts: &FontRegistry) -> MeasureFunc {
+    let text = self.clone();
+    let fonts = fonts.clone();
+    Box::new(move |constraint: Size<Number>| {
+      let max_width = match constraint.width {
+        Number::Defined(width) => Some(width),
+        Number::Undefined => None,
+      };
+      let metrics = text.measure_with(&fonts, max_width).unwrap_or_default();
+      Ok(Size {
+        width: metrics.width.ceil(),
+        height: metrics.height.ceil(),
+      })
+    })
+  }
+
+  /// Load the fonts of the text and of its runs from a registry. Bold and italic
+  /// runs use the "Bold", "Italic" or "Oblique" face of their font family if the
+  /// registry can find one. Returns None if no font of the text can be loaded.
+  pub fn fonts(&self, registry: &FontRegistry) -> Option<TextFonts> {
+    let mut fonts = TextFonts {
+      collections: Vec::new(),
+      styles: Vec::new(),
+    };
+    for key in self.font_styles() {
+      let face = match (key.bold, key.italic) {
+        (false, false) => None,
+        (true, false) => Some(&["Bold"][..]),
+        (false, true) => Some(&["Italic", "Oblique"][..]),
+        (true, true) => Some(&["Bold Italic", "Bold Oblique"][..]),
+      };
+      let family = key.font.split(',').next().unwrap_or_default().trim();
+      let face = face.and_then(|suffixes| {
+        suffixes
+          .iter()
+          .map(|suffix| format!("{} {}", family, suffix))
+          .find(|name| registry.load(name).is_ok())
+      });
+      let names = match &face {
+        Some(face) => format!("{}, {}", face, key.font),
+        None => key.font.clone(),
+      };
+      let synthetic = face.is_none();
+      match registry.collection(&names) {
+        Some(collection) => {
+          fonts.collections.push(collection);
+          fonts.styles.push((
+            fonts.collections.len() - 1,
+            key.bold && synthetic,
+            key.italic && synthetic,
+          ));
+        }
+        // The font of the text is required; runs fall back to it
+        None if fonts.collections.is_empty() => return None,
+        None => fonts.styles.push((0, key.bold, key.italic)),
+      }
+    }
+    Some(fonts)
+  }
+
+  /// Layout the glyphs inside a `width` x `height` box. Each glyph comes with
+  /// the index of its font in `fonts`; line metrics are those of the first font.
+  pub fn layout<F: Font>(&self, fonts: &[F], width: f32, height: f32) -> Vec<(usize, Glyph)> {
+    self.layout_styled(&StyleFonts::uniform(self, fonts), width, height)
+  }
+
+  /// Layout the glyphs with the fonts of the text and its runs (see `Text::fonts`).
+  /// Each glyph comes with the index of its font in `fonts`.
+  pub fn layout_with_fonts(
+    &self,
+    fonts: &TextFonts,
+    width: f32,
+    height: f32,
+  ) -> Vec<(usize, Glyph)> {
+    self.layout_styled(&fonts.style_fonts(), width, height)
+  }
+
+  fn layout_styled<F: Font>(
+    &self,
+    fonts: &StyleFonts<F>,
+    width: f32,
+    height: f32,
+  ) -> Vec<(usize, Glyph)> {
+    let metrics = RichLayout::new(self, fonts).metrics(width, Some((width, height)));
+    let mut glyphs = Vec::new();
+    for glyph in &metrics.glyphs {
+      if !metrics.is_space(glyph) {
+        let position = point(glyph.x, glyph.y);
+        glyphs.push((
+          glyph.font,
+          glyph
+            .id
+            .with_scale_and_position(PxScale::from(glyph.size), position),
+        ));
+      }
+    }
+    glyphs
+  }
+
+  /// Render the text into a `width` x `height` image.
+  pub fn rasterize<F: Font>(&self, fonts: &[F], width: u32, height: u32) -> RgbaImage {
+    self.rasterize_styled(&StyleFonts::uniform(self, fonts), width, height)
+  }
+
+  /// Render the text with the fonts of the text and its runs (see `Text::fonts`)
+  pub fn rasterize_with_fonts(&self, fonts: &TextFonts, width: u32, height: u32) -> RgbaImage {
+    self.rasterize_styled(&fonts.style_fonts(), width, height)
+  }
+
+  fn rasterize_styled<F: Font>(&self, fonts: &StyleFonts<F>, width: u32, height: u32) -> RgbaImage {
+    let mut image = RgbaImage::new(width, height);
+    let layout = RichLayout::new(self, fonts);
+    let metrics = layout.metrics(width as f32, Some((width as f32, height as f32)));
+    for glyph in &metrics.glyphs {
+      if metrics.is_space(glyph) {
+        continue;
+      }
+      let span = layout.span_at(glyph.index);
+      let (_, synthetic_bold, synthetic_italic) = fonts.styles[span.font_style];
+      let color = color_to_rgba8(span.color);
+      let positioned = glyph
+        .id
+        .with_scale_and_position(PxScale::from(glyph.size), point(glyph.x, glyph.y));
+      let Some(outlined) = fonts.fonts[glyph.font].outline_glyph(positioned) else {
+        continue;
+      };
+      let bounds = outlined.px_bounds();
+      // Faces the font family doesn't have are synthesized: bold draws the glyph
+      // again shifted to the right, italic slants it around the baseline
+      let bold_offset = if synthetic_bold {
+        (glyph.size / 24.0).round().max(1.0) as i32
+      } else {
+        0
+      };
+      let slant = if synthetic_italic { 0.2 } else { 0.0 };
+      outlined.draw(|x, y, coverage| {
+        let py = y as i32 + bounds.min.y as i32;
+        let px = x as i32 + bounds.min.x as i32 + ((glyph.y - py as f32) * slant).round() as i32;
+        for dx in 0..=bold_offset {
+          blend_pixel(&mut image, px + dx, py, color, coverage);
+        }
+      });
+    }
+
+    for (x0, x1, y, thickness, color) in layout.decorations(&metrics) {
+      for py in y.round() as i32..(y + thickness).round() as i32 {
+        for px in x0.round() as i32..x1.round() as i32 {
+          blend_pixel(&mut image, px, py, color, 1.0);
+        }
+      }
+    }
+    image
+  }
+
+  // The font of the text followed by the distinct fonts of its runs
+  fn font_styles(&self) -> Vec<FontStyleKey> {
+    let mut keys = vec![FontStyleKey {
+      font: self.font.clone(),
+      bold: false,
+      italic: false,
+    }];
+    for (_, style) in self.styles() {
+      let key = FontStyleKey {
+        font: style.font.unwrap_or_else(|| self.font.clone()),
+        bold: style.bold,
+        italic: style.italic,
+      };
+      if !keys.contains(&key) {
+        keys.push(key);
+      }
+    }
+    keys
+  }
+
+  // The merged style of the runs from every run boundary on
+  fn styles(&self) -> Vec<(usize, TextStyle)> {
+    let len = self.string.len();
+    let mut boundaries = vec![0];
+    for run in &self.runs {
+      boundaries.push(run.range.start.min(len));
+      boundaries.push(run.range.end.min(len));
+    }
+    boundaries.sort_unstable();
+    boundaries.dedup();
+    boundaries.retain(|&boundary| boundary == 0 || boundary < len);
+    boundaries
+      .into_iter()
+      .map(|start| {
+        let mut style = TextStyle::default();
+        for run in &self.runs {
+          if run.range.contains(&start) {
+            style.merge(&run.style);
+          }
+        }
+        (start, style)
+      })
+      .collect()
+  }
+}
+
+/// Style of a range of a rich text. Attributes that are not set come from the
+/// `Text`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextStyle {
+  pub font: Option<String>,
+  pub font_size: Option<f32>,
+  pub color: Option<[f32; 4]>,
+  pub bold: bool,
+  pub italic: bool,
+  pub underline: bool,
+  pub strikethrough: bool,
+}
+
+impl TextStyle {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_font(mut self, font: &str) -> Self {
+    self.font = Some(font.to_string());
+    self
+  }
+
+  pub fn with_font_size(mut self, font_size: f32) -> Self {
+    self.font_size = Some(font_size);
+    self
+  }
+
+  pub fn with_color(mut self, r: f32, g: f32, b: f32, a: f32) -> Self {
+    self.color = Some([r, g, b, a]);
+    self
+  }
+
+  pub fn with_bold(mut self, bold: bool) -> Self {
+    self.bold = bold;
+    self
+  }
+
+  pub fn with_italic(mut self, italic: bool) -> Self {
+    self.italic = italic;
+    self
+  }
+
+  pub fn with_underline(mut self, underline: bool) -> Self {
+    self.underline = underline;
+    self
+  }
+
+  pub fn with_strikethrough(mut self, strikethrough: bool) -> Self {
+    self.strikethrough = strikethrough;
+    self
+  }
+
+  // Apply the attributes set in `style` on top of this style
+  fn merge(&mut self, style: &TextStyle) {
+    if style.font.is_some() {
+      self.font = style.font.clone();
+    }
+    self.font_size = style.font_size.or(self.font_size);
+    self.color = style.color.or(self.color);
+    self.bold |= style.bold;
+    self.italic |= style.italic;
+    self.underline |= style.underline;
+    self.strikethrough |= style.strikethrough;
+  }
+}
+
+/// A style applied to a byte range of `Text::string`. Where runs overlap, the
+/// attributes of later runs win.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextRun {
+  pub range: Range<usize>,
+  pub style: TextStyle,
+}
+
+/// The fonts of a text and of the runs of a rich text, see `Text::fonts`
+pub struct TextFonts {
+  collections: Vec<FontCollection>,
+  // Per font style of the text: its collection, synthetic bold and italic
+  styles: Vec<(usize, bool, bool)>,
+}
+
+impl TextFonts {
+  /// The number of fonts of all collections
+  pub fn len(&self) -> usize {
+    self.collections.iter().map(FontCollection::len).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// The name of a font by the index of a glyph from `Text::layout_with_fonts`
+  pub fn name(&self, index: usize) -> &str {
+    let (collection, index) = self.locate(index);
+    collection.name(index)
+  }
+
+  pub fn font(&self, index: usize) -> &FontVec {
+    let (collection, index) = self.locate(index);
+    collection.font(index)
+  }
+
+  fn locate(&self, mut index: usize) -> (&FontCollection, usize) {
+    for collection in &self.collections {
+      if index < collection.len() {
+        return (collection, index);
+      }
+      index -= collection.len();
+    }
+    panic!("font index out of range");
+  }
+
+  fn style_fonts(&self) -> StyleFonts<'_, FontVec> {
+    let mut fonts = Vec::new();
+    let mut ranges = Vec::new();
+    for collection in &self.collections {
+      let start = fonts.len();
+      fonts.extend((0..collection.len()).map(|i| collection.font(i)));
+      ranges.push(start..fonts.len());
+    }
+    StyleFonts {
+      fonts,
+      styles: self
+        .styles
+        .iter()
+        .map(|&(collection, bold, italic)| (ranges[collection].clone(), bold, italic))
+        .collect(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct FontStyleKey {
+  font: String,
+  bold: bool,
+  italic: bool,
+}
+
+// The fonts of every font style of a text (see Text::font_styles), flattened
+// so that a glyph refers to its font with one index
+struct StyleFonts<'a, F> {
+  fonts: Vec<&'a F>,
+  // Per font style: its fonts in `fonts`, synthetic bold and italic
+  styles: Vec<(Range<usize>, bool, bool)>,
+}
+
+impl<'a, F: Font> StyleFonts<'a, F> {
+  // The same fonts for every style; bold and italic are synthesized
+  fn uniform(text: &Text, fonts: &'a [F]) -> Self {
+    StyleFonts {
+      fonts: fonts.iter().collect(),
+      styles: text
+        .font_styles()
+        .into_iter()
+        .map(|key| (0..fonts.len(), key.bold, key.italic))
+        .collect(),
+    }
+  }
+}
+
+// The resolved style of the characters from a byte offset on
+#[derive(Clone, Debug, PartialEq)]
+struct Span {
+  start: usize,
+  font_style: usize,
+  font_size: f32,
+  color: [f32; 4],
+  underline: bool,
+  strikethrough: bool,
+}
+
+// A line of text with the byte offset in `Text::string` of each of its bytes,
+// so that its characters keep the style of their run
+#[derive(Clone, Debug, Default)]
+struct SourceLine {
+  text: String,
+  sources: Vec<usize>,
+}
+
+impl SourceLine {
+  fn push(&mut self, c: char, source: usize) {
+    self.text.push(c);
+    self.sources.resize(self.text.len(), source);
+  }
+
+  fn append(&mut self, other: &SourceLine) {
+    self.text.push_str(&other.text);
+    self.sources.extend_from_slice(&other.sources);
+  }
+
+  fn pop(&mut self) -> Option<char> {
+    let c = self.text.pop()?;
+    self.sources.truncate(self.text.len());
+    Some(c)
+  }
+
+  fn trim_end(&mut self) {
+    while self.text.ends_with(char::is_whitespace) {
+      self.pop();
+    }
+  }
+}
+
+// Lays out a text whose runs may change font, size and style
+struct RichLayout<'a, F> {
+  text: &'a Text,
+  fonts: &'a StyleFonts<'a, F>,
+  faces: Vec<ShapingFace<'a>>, // parsed once for every line
+  spans: Vec<Span>,
+}
+
+impl<'a, F: Font> RichLayout<'a, F> {
+  fn new(text: &'a Text, fonts: &'a StyleFonts<'a, F>) -> Self {
+    let font_styles = text.font_styles();
+    let spans = text
+      .styles()
+      .into_iter()
+      .map(|(start, style)| {
+        let key = FontStyleKey {
+          font: style.font.unwrap_or_else(|| text.font.clone()),
+          bold: style.bold,
+          italic: style.italic,
+        };
+        Span {
+          start,
+          font_style: font_styles.iter().position(|k| *k == key).unwrap_or(0),
+          font_size: style.font_size.unwrap_or(text.font_size),
+          color: style.color.unwrap_or(text.color),
+          underline: style.underline,
+          strikethrough: style.strikethrough,
+        }
+      })
+      .collect();
+    RichLayout {
+      text,
+      fonts,
+      faces: shaping_faces(&fonts.fonts),
+      spans,
+    }
+  }
+
+  fn span_at(&self, index: usize) -> &Span {
+    let i = self.spans.partition_point(|span| span.start <= index);
+    &self.spans[i.saturating_sub(1)]
+  }
+
+  fn style_fonts(&self, font_style: usize) -> &[&'a F] {
+    &self.fonts.fonts[self.fonts.styles[font_style].0.clone()]
+  }
+
+  // Shape a line in segments of the same font and size. The glyphs are on
+  // line 0, with `y` relative to the baseline.
+  fn shape(&self, line: &SourceLine) -> (Vec<GlyphPosition>, f32) {
+    let mut glyphs = Vec::new();
+    let mut width = 0.0;
+    let mut start = 0;
+    while start < line.text.len() {
+      let span = self.span_at(line.sources[start]);
+      let mut end = start;
+      for (i, c) in line.text[start..].char_indices() {
+        let other = self.span_at(line.sources[start + i]);
+        if other.font_style != span.font_style || other.font_size != span.font_size {
+          break;
+        }
+        end = start + i + c.len_utf8();
+      }
+      if start > 0 {
+        width += self.text.letter_spacing;
+      }
+      let fonts = self.fonts.styles[span.font_style].0.clone();
+      let first_font = fonts.start;
+      let shaped: ShapedLine = shape_line_with_faces(
+        &self.fonts.fonts[fonts.clone()],
+        &self.faces[fonts],
+        PxScale::from(span.font_size),
+        &line.text[start..end],
+        self.text.letter_spacing,
+      );
+      glyphs.extend(shaped.glyphs.iter().map(|glyph| GlyphPosition {
+        font: first_font + glyph.font,
+        id: glyph.id,
+        size: span.font_size,
+        line: 0,
+        cluster: start + glyph.cluster,
+        index: line.sources[start + glyph.cluster],
+        x: width + glyph.x,
+        y: glyph.y_offset,
+      }));
+      width += shaped.width;
+      start = end;
+    }
+    (glyphs, width)
+  }
+
+  fn width(&self, line: &SourceLine) -> f32 {
+    self.shape(line).1
+  }
+
+  // Ascent, descent and line gap of the largest font of a line
+  fn extent(&self, line: &SourceLine) -> (f32, f32, f32) {
+    let mut styles: Vec<(usize, f32)> = line
+      .sources
+      .iter()
+      .map(|&source| {
+        let span = self.span_at(source);
+        (span.font_style, span.font_size)
+      })
+      .collect();
+    if styles.is_empty() {
+      styles.push((0, self.text.font_size));
+    }
+    styles.dedup();
+    styles
+      .into_iter()
+      .fold((0.0f32, 0.0f32, 0.0f32), |extent, (font_style, size)| {
+        let font = self.style_fonts(font_style)[0].as_scaled(PxScale::from(size));
+        (
+          extent.0.max(font.ascent()),
+          extent.1.max(-font.descent()),
+          extent.2.max(font.line_gap()),
+        )
+      })
+  }
+
+  // Break the string into lines that fit in `width` and truncate it to
+  // `max_lines` with an ellipsis
+  fn lines(&self, width: f32) -> Vec<SourceLine> {
+    let width = self.text.max_width.map_or(width, |max| max.min(width));
+    let mut lines = Vec::new();
+    let mut paragraph = Vec::new();
+    for (i, c) in self.text.string.char_indices() {
+      if c == '\n' {
+        self.wrap_paragraph(&paragraph, width, &mut lines);
+        paragraph.clear();
+      } else {
+        paragraph.push((i, c));
+      }
+    }
+    self.wrap_paragraph(&paragraph, width, &mut lines);
+
+    let max_lines = self.text.max_lines;
+    if max_lines > 0 && lines.len() > max_lines {
+      lines.truncate(max_lines);
+      let mut last = lines.pop().unwrap_or_default();
+      let first_source = last.sources.first().copied().unwrap_or_default();
+      let ellipsis = if self
+        .style_fonts(0)
+        .iter()
+        .all(|font| font.glyph_id('\u{2026}') == GlyphId(0))
+      {
+        "..."
+      } else {
+        ELLIPSIS
+      };
+      last.trim_end();
+      loop {
+        // The ellipsis takes the style of the character before it
+        let mut line = last.clone();
+        let source = last.sources.last().copied().unwrap_or(first_source);
+        for c in ellipsis.chars() {
+          line.push(c, source);
+        }
+        if last.text.is_empty() || self.width(&line) <= width {
+          lines.push(line);
+          break;
+        }
+        last.pop();
+        last.trim_end();
+      }
+    }
+    lines
+  }
+
+  // Greedy word wrap: break at whitespace, and between characters for words
+  // wider than the line.
+  // Words are shaped once and their widths added up, with the width of the space
+  // and the letter spacing between them, so wrapping stays linear in the length
+  // of the paragraph
+  fn wrap_paragraph(&self, paragraph: &[(usize, char)], width: f32, lines: &mut Vec<SourceLine>) {
+    let letter_spacing = self.text.letter_spacing;
+    // Width of a line with a piece of the given width appended
+    let joined_width = |line: &SourceLine, line_width: f32, piece_width: f32| {
+      if line.text.is_empty() {
+        piece_width
+      } else {
+        line_width + letter_spacing + piece_width
+      }
+    };
+    let mut line = SourceLine::default();
+    let mut line_width = 0.0;
+    let mut i = 0;
+    while i < paragraph.len() {
+      // Words are joined by a single space, in the style of the first
+      // whitespace character between them
+      let mut space = None;
+      while i < paragraph.len() && paragraph[i].1.is_whitespace() {
+        space.get_or_insert(paragraph[i].0);
+        i += 1;
+      }
+      let start = i;
+      while i < paragraph.len() && !paragraph[i].1.is_whitespace() {
+        i += 1;
+      }
+      let word = &paragraph[start..i];
+      if word.is_empty() {
+        break;
+      }
+
+      let mut word_line = SourceLine::default();
+      for &(source, c) in word {
+        word_line.push(c, source);
+      }
+      let word_width = self.width(&word_line);
+
+      let mut candidate_width = line_width;
+      let space = space.filter(|_| !line.text.is_empty());
+      if let Some(space) = space {
+        let mut space_line = SourceLine::default();
+        space_line.push(' ', space);
+        candidate_width = joined_width(&line, candidate_width, self.width(&space_line));
+      }
+      let candidate_width = joined_width(&line, candidate_width, word_width);
+      if candidate_width <= width {
+        if let Some(space) = space {
+          line.push(' ', space);
+        }
+        line.append(&word_line);
+        line_width = candidate_width;
+        continue;
+      }
+      if !line.text.is_empty() {
+        lines.push(std::mem::take(&mut line));
+      }
+      if word_width <= width {
+        (line, line_width) = (word_line, word_width);
+        continue;
+      }
+
+      // Break a word wider than the line between characters
+      for &(source, c) in word {
+        let mut char_line = SourceLine::default();
+        char_line.push(c, source);
+        let char_width = self.width(&char_line);
+        let next_width = joined_width(&line, line_width, char_width);
+        if !line.text.is_empty() && next_width > width {
+          lines.push(std::mem::take(&mut line));
+          line_width = char_width;
+        } else {
+          line_width = next_width;
+        }
+        line.append(&char_line);
+      }
+    }
+    lines.push(line);
+  }
+
+  // Lines wrap at `width`, and are aligned in the box if there is one or
+  // within the width of the widest line otherwise
+  fn metrics(&self, width: f32, bounds: Option<(f32, f32)>) -> TextMetrics {
+    if self.fonts.fonts.is_empty() {
+      return TextMetrics::default();
+    }
+    let lines = self.lines(width);
+    let shaped: Vec<(Vec<GlyphPosition>, f32)> =
+      lines.iter().map(|line| self.shape(line)).collect();
+    let extents: Vec<(f32, f32, f32)> = lines.iter().map(|line| self.extent(line)).collect();
+    let mut tops = Vec::new();
+    let mut y = 0.0;
+    for (ascent, descent, line_gap) in &extents {
+      tops.push(y);
+      y += (ascent + descent + line_gap) * self.text.line_height;
+    }
+    let mut metrics = TextMetrics {
+      width: shaped.iter().map(|(_, width)| *width).fold(0.0, f32::max),
+      height: match (tops.last(), extents.last()) {
+        (Some(top), Some((ascent, descent, _))) => top + ascent + descent,
+        _ => 0.0,
+      },
+      ..Default::default()
+    };
+    let (box_width, box_height) = bounds.unwrap_or((metrics.width, metrics.height));
+    let top = match self.text.vertical_alignment {
+      VerticalAlignment::Top => 0.0,
+      VerticalAlignment::Center => (box_height - metrics.height) / 2.0,
+      VerticalAlignment::Bottom => box_height - metrics.height,
+    };
+
+    for (i, (line, (glyphs, line_width))) in lines.into_iter().zip(shaped).enumerate() {
+      let x = match self.text.alignment {
+        TextAlignment::Left => 0.0,
+        TextAlignment::Center => (box_width - line_width) / 2.0,
+        TextAlignment::Right => box_width - line_width,
+      };
+      let (ascent, descent, _) = extents[i];
+      let y = top + tops[i];
+      let baseline = y + ascent;
+      metrics
+        .glyphs
+        .extend(glyphs.into_iter().map(|glyph| GlyphPosition {
+          line: i,
+          x: x + glyph.x,
+          y: baseline + glyph.y,
+          ..glyph
+        }));
+      metrics.lines.push(LineMetrics {
+        text: line.text,
+        x,
+        y,
+        baseline,
+        width: line_width,
+        height: ascent + descent,
+      });
+    }
+    metrics
+  }
+
+  // Underline and strikethrough rectangles: x0, x1, y, thickness and color
+  fn decorations(&self, metrics: &TextMetrics) -> Vec<(f32, f32, f32, f32, [u8; 4])> {
+    let mut rects = Vec::new();
+    for strikethrough in [false, true] {
+      // Consecutive decorated glyphs of a line with the same size and color:
+      // x0, x1, line, size and color
+      let mut runs: Vec<(f32, f32, usize, f32, [u8; 4])> = Vec::new();
+      let mut open = false;
+      for glyph in &metrics.glyphs {
+        let span = self.span_at(glyph.index);
+        let decorated = if strikethrough {
+          span.strikethrough
+        } else {
+          span.underline
+        };
+        let color = color_to_rgba8(span.color);
+        let x1 = glyph.x
+          + self.fonts.fonts[glyph.font]
+            .as_scaled(PxScale::from(glyph.size))
+            .h_advance(glyph.id);
+        match runs.last_mut() {
+          Some(run)
+            if open
+              && decorated
+              && run.2 == glyph.line
+              && run.3 == glyph.size
+              && run.4 == color =>
+          {
+            run.0 = run.0.min(glyph.x);
+            run.1 = run.1.max(x1);
+          }
+          _ => {
+            open = decorated;
+            if decorated {
+              runs.push((glyph.x, x1, glyph.line, glyph.size, color));
+            }
+          }
+        }
+      }
+      // Offsets from the baseline relative to the font size
+      let offset = if strikethrough { -0.3 } else { 0.1 };
+      for (x0, x1, line, size, color) in runs {
+        let thickness = (size / 16.0).max(1.0);
+        let y = metrics.lines[line].baseline + size * offset - thickness / 2.0;
+        rects.push((x0, x1, y, thickness, color));
+      }
+    }
+    rects
+  }
+}
+
+fn color_to_rgba8(color: [f32; 4]) -> [u8; 4] {
+  color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn blend_pixel(image: &mut RgbaImage, x: i32, y: i32, [r, g, b, a]: [u8; 4], coverage: f32) {
+  if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+    return;
+  }
+  let pixel = image.get_pixel_mut(x as u32, y as u32);
+  let alpha = (coverage.min(1.0) * a as f32) as u8;
+  *pixel = Rgba([r, g, b, pixel[3].max(alpha)]);
+}
+
+// Keeps the last rendered text of a layer so that the texture is only
+// rendered again when the text changes.
+#[derive(Default)]
+pub(crate) struct TextCache {
+  rendered: Option<(Text, u32, u32)>,
+}
+
+impl TextCache {
+  /// Render the text if it or the layer size changed since the last call.
+  pub(crate) fn render(
+    &mut self,
+    text: &Text,
+    width: u32,
+    height: u32,
+    fonts: &FontRegistry,
+  ) -> Option<RgbaImage> {
+    if self
+      .rendered
+      .as_ref()
+      .is_some_and(|(t, w, h)| t == text && *w == width && *h == height)
+    {
+      return None;
+    }
+    if width == 0 || height == 0 {
+      self.rendered = Some((text.clone(), width, height));
+      return None;
+    }
+    // Without its fonts the text is tried again, since they may be registered later
+    let fonts = text.fonts(fonts)?;
+    self.rendered = Some((text.clone(), width, height));
+    Some(text.rasterize_with_fonts(&fonts, width, height))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::font;
+  use crate::shaping::shape_line;
+
+  fn test_font() -> FontVec {
+    font::load_font_by_name(DEFAULT_FONT).expect("fonts/DejaVuSans.ttf")
+  }
+
+  // Width of a single shaped line including letter spacing
+  fn line_width<F: Font>(fonts: &[F], scale: PxScale, line: &str, letter_spacing: f32) -> f32 {
+    shape_line(fonts, scale, line, letter_spacing).width
+  }
+
+  #[test]
+  fn test_word_wrap() {
+    let font = test_font();
+    let text = Text::new("the quick brown fox jumps over the lazy dog").with_font_size(20.0);
+    let lines = text.layout_lines(&[&font], 120.0);
+    assert!(lines.len() > 1);
+    assert_eq!(lines.join(" "), text.string);
+    let scale = PxScale::from(20.0);
+    for line in &lines {
+      assert!(line_width(&[&font], scale, line, 0.0) <= 120.0, "{}", line);
+    }
+    // The widths of the words add up to the width of the line they fill
+    let long = "the quick brown fox jumps over the lazy dog ".repeat(50);
+    let text = Text::new(long.trim_end())
+      .with_font_size(20.0)
+      .with_letter_spacing(2.0);
+    let lines = text.layout_lines(&[&font], 120.0);
+    assert_eq!(lines.join(" "), text.string);
+    for pair in lines.windows(2) {
+      assert!(
+        line_width(&[&font], scale, &pair[0], 2.0) <= 120.0,
+        "{}",
+        pair[0]
+      );
+      // and the next word didn't fit
+      let next_word = pair[1].split(' ').next().unwrap();
+      let joined = format!("{} {}", pair[0], next_word);
+      assert!(
+        line_width(&[&font], scale, &joined, 2.0) > 120.0,
+        "{}",
+        joined
+      );
+    }
+
+    // A long word is broken between characters
+    let lines = Text::new("abcdefghijklmnopqrstuvwxyz").layout_lines(&[&font], 50.0);
+    assert!(lines.len() > 1);
+    assert_eq!(lines.concat(), "abcdefghijklmnopqrstuvwxyz");
+
+    // Newlines start a new line
+    assert_eq!(
+      Text::new("a\nb").layout_lines(&[&font], 500.0),
+      vec!["a", "b"]
+    );
+  }
+
+  #[test]
+  fn test_max_lines_ellipsis() {
+    let font = test_font();
+    let text = Text::new("one two three four five six seven eight nine ten")
+      .with_font_size(20.0)
+      .with_max_lines(2);
+    let lines = text.layout_lines(&[&font], 100.0);
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].ends_with(ELLIPSIS));
+    let scale = PxScale::from(20.0);
+    assert!(line_width(&[&font], scale, &lines[1], 0.0) <= 100.0);
+
+    // max_width narrows the layer width
+    let lines = text
+      .clone()
+      .with_max_width(60.0)
+      .layout_lines(&[&font], 500.0);
+    assert!(line_width(&[&font], scale, &lines[0], 0.0) <= 60.0);
+  }
+
+  #[test]
+  fn test_alignment() {
+    let font = test_font();
+    let text = Text::new("Hi").with_font_size(20.0);
+    let left = text.layout(&[&font], 200.0, 100.0);
+    let center = text
+      .clone()
+      .with_alignment(TextAlignment::Center)
+      .with_vertical_alignment(VerticalAlignment::Center)
+      .layout(&[&font], 200.0, 100.0);
+    let right = text
+      .clone()
+      .with_alignment(TextAlignment::Right)
+      .with_vertical_alignment(VerticalAlignment::Bottom)
+      .layout(&[&font], 200.0, 100.0);
+    assert_eq!(left[0].1.position.x, 0.0);
+    assert!(center[0].1.position.x > 0.0 && center[0].1.position.x < right[0].1.position.x);
+    assert!(left[0].1.position.y < center[0].1.position.y);
+    assert!(center[0].1.position.y < right[0].1.position.y);
+
+    // Letter spacing moves the second glyph
+    let spaced = text
+      .clone()
+      .with_letter_spacing(5.0)
+      .layout(&[&font], 200.0, 100.0);
+    assert!((spaced[1].1.position.x - left[1].1.position.x - 5.0).abs() < 0.001);
+  }
+
+  #[test]
+  fn test_measure() {
+    let font = test_font();
+    let scaled = font.as_scaled(PxScale::from(20.0));
+    let line_height = scaled.ascent() - scaled.descent();
+    let text = Text::new("the quick brown fox").with_font_size(20.0);
+    let metrics = text.measure(&[&font], None);
+    assert_eq!(metrics.line_count(), 1);
+    assert!((metrics.width - line_width(&[&font], scaled.scale(), &text.string, 0.0)).abs() < 0.01);
+    assert!((metrics.height - line_height).abs() < 0.01);
+    assert_eq!(metrics.glyphs.len(), text.string.len());
+    assert_eq!(metrics.glyphs[4].cluster, 4);
+    assert_eq!(metrics.glyphs[0].y, metrics.lines[0].baseline);
+
+    // Wrapped lines, aligned within the widest one
+    let metrics = text
+      .clone()
+      .with_alignment(TextAlignment::Right)
+      .measure(&[&font], Some(100.0));
+    assert!(metrics.line_count() > 1);
+    assert!(metrics.width <= 100.0);
+    assert!(metrics.height > line_height * 1.5);
+    for line in &metrics.lines {
+      assert!((line.x + line.width - metrics.width).abs() < 0.01);
+    }
+    let last = metrics.glyphs.last().unwrap();
+    assert_eq!(last.line, metrics.line_count() - 1);
+    assert_eq!(last.y, metrics.lines[last.line].baseline);
+
+    assert_eq!(Text::new("").measure(&[&font], None).width, 0.0);
+  }
+
+  #[test]
+  fn test_measure_func() {
+    use stretch::node::Stretch;
+    use stretch::style::{Dimension, FlexDirection, Style};
+
+    let fonts = FontRegistry::new();
+    let text = Text::new("the quick brown fox").with_font_size(20.0);
+    let metrics = text.measure_with(&fonts, None).unwrap();
+    let mut stretch = Stretch::new();
+    let node = stretch
+      .new_leaf(Style::default(), text.measure_func(&fonts))
+      .unwrap();
+    stretch.compute_layout(node, Size::undefined()).unwrap();
+    let size = stretch.layout(node).unwrap().size;
+    assert_eq!(size.width, metrics.width.ceil());
+    assert_eq!(size.height, metrics.height.ceil());
+
+    // The text wraps at the width of a column
+    let column = stretch
+      .new_node(
+        Style {
+          flex_direction: FlexDirection::Column,
+          size: Size {
+            width: Dimension::Points(100.0),
+            height: Dimension::Auto,
+          },
+          ..Default::default()
+        },
+        vec![node],
+      )
+      .unwrap();
+    stretch.compute_layout(column, Size::undefined()).unwrap();
+    let size = stretch.layout(node).unwrap().size;
+    assert!(size.width <= 100.0);
+    assert!(size.height > metrics.height.ceil());
+  }
+
+  #[test]
+  fn test_rich_text_layout() {
+    let font = test_font();
+    let plain = Text::new("Big small").with_font_size(20.0);
+    let rich = plain
+      .clone()
+      .with_run(0..3, TextStyle::new().with_font_size(40.0));
+    let metrics = rich.measure(&[&font], None);
+    assert_eq!(metrics.line_count(), 1);
+    assert_eq!(metrics.glyphs[0].size, 40.0);
+    assert_eq!(metrics.glyphs[4].size, 20.0);
+    assert_eq!(metrics.glyphs[4].index, 4);
+    let plain_metrics = plain.measure(&[&font], None);
+    assert!(metrics.width > plain_metrics.width);
+    assert!(metrics.height > plain_metrics.height * 1.5);
+    // Glyphs of both sizes share the baseline
+    assert_eq!(metrics.glyphs[0].y, metrics.glyphs[4].y);
+
+    // Characters keep their style when lines wrap
+    let text = Text::from_markup("one <b>two</b> three").with_font_size(20.0);
+    assert_eq!(text.layout_lines(&[&font], 1000.0), vec!["one two three"]);
+    let metrics = text.measure(&[&font], Some(60.0));
+    assert!(metrics.line_count() > 1);
+    let two: Vec<usize> = metrics
+      .glyphs
+      .iter()
+      .filter(|glyph| glyph.line == 1)
+      .map(|glyph| glyph.index)
+      .collect();
+    assert_eq!(two, vec![4, 5, 6]);
+  }
+
+  #[test]
+  fn test_rich_text_rasterize() {
+    let font = test_font();
+    let text = Text::from_markup("<color=#ff0000>mmm</color><color=#0000ff>mmm</color>")
+      .with_font_size(20.0);
+    let image = text.rasterize(&[&font], 120, 30);
+    let (left, right): (Vec<_>, Vec<_>) = image
+      .enumerate_pixels()
+      .filter(|(_, _, p)| p[3] > 128)
+      .partition(|(x, _, _)| *x < 25);
+    assert!(!left.is_empty() && left.iter().all(|(_, _, p)| p[0] == 255 && p[2] == 0));
+    assert!(right.iter().any(|(_, _, p)| p[2] == 255 && p[0] == 0));
+
+    // Underline, strikethrough and synthetic bold add coverage
+    let coverage = |markup: &str| -> u32 {
+      Text::from_markup(markup)
+        .with_font_size(20.0)
+        .rasterize(&[&font], 120, 30)
+        .pixels()
+        .map(|p| p[3] as u32)
+        .sum()
+    };
+    let regular = coverage("mmm");
+    assert!(coverage("<u>mmm</u>") > regular);
+    assert!(coverage("<s>mmm</s>") > regular);
+    assert!(coverage("<b>mmm</b>") > regular);
+    // Synthetic italic slants the glyphs without changing their coverage much
+    let italic = coverage("<i>mmm</i>");
+    assert!(italic.abs_diff(regular) < regular / 10);
+  }
+
+  #[test]
+  fn test_rich_text_fonts() {
+    let fonts = FontRegistry::new();
+    fonts.register("Brand", test_font());
+    fonts.register(
+      "Brand Bold",
+      font::load_font_by_name("DejaVu Sans Mono").unwrap(),
+    );
+    let text =
+      Text::from_markup("a<b>b</b><i>c</i><font=DejaVu Sans Mono>d</font>").with_font("Brand");
+    let text_fonts = text.fonts(&fonts).unwrap();
+    let glyphs = text.layout_with_fonts(&text_fonts, 200.0, 50.0);
+    let names: Vec<&str> = glyphs
+      .iter()
+      .map(|(font, _)| text_fonts.name(*font))
+      .collect();
+    // The bold face is used when there is one; italic is synthesized
+    assert_eq!(
+      names,
+      vec!["Brand", "Brand Bold", "Brand", "DejaVu Sans Mono"]
+    );
+    assert!(TextCache::default()
+      .render(&text, 200, 50, &fonts)
+      .is_some());
+  }
+
+  #[test]
+  fn test_text_cache() {
+    let fonts = FontRegistry::new();
+    let mut cache = TextCache::default();
+    let text = Text::new("Hello").with_color(1.0, 0.0, 0.0, 1.0);
+    let image = cache.render(&text, 100, 40, &fonts).unwrap();
+    assert!(image.pixels().any(|p| p[3] > 0 && p[0] == 255));
+    assert!(fonts.contains(DEFAULT_FONT));
+    // Nothing to do when nothing changed
+    assert!(cache.render(&text, 100, 40, &fonts).is_none());
+    assert!(cache
+      .render(&text.clone().with_font_size(30.0), 100, 40, &fonts)
+      .is_some());
+
+    // A font registered after the first try is used
+    let text = text.with_font("Late");
+    assert!(cache.render(&text, 100, 40, &fonts).is_none());
+    fonts.register("Late", test_font());
+    assert!(cache.render(&text, 100, 40, &fonts).is_some());
+  }
+}
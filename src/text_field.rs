@@ -0,0 +1,619 @@
+// Copyright (c) 2021 Joone Hur <joone@chromium.org> All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Single line editable text, drawn by a layer (see `Layer::set_text_field`).
+//!
+//! The field keeps its value, caret and selection. Typed characters arrive with
+//! `Play::handle_text_input` and editing keys with `Play::handle_input` while the
+//! layer is focused. Like text layers, the field is drawn with a transparent
+//! background, so it is usually placed on a panel layer.
+
+use ab_glyph::{point, Font, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+use std::ops::Range;
+use std::time::Instant;
+
+use crate::font::FontRegistry;
+use crate::layer::{Key, Modifiers};
+use crate::shaping::{shape_line, ShapedLine};
+use crate::text::Text;
+
+// The caret is shown and hidden for this long
+const BLINK_INTERVAL: f32 = 0.5;
+
+pub struct TextField {
+  pub style: Text, // font, size, color and letter spacing of the value
+  pub placeholder: String,
+  pub placeholder_color: [f32; 4],
+  pub selection_color: [f32; 4],
+  pub caret_color: [f32; 4],
+  pub caret_width: f32,
+  value: String,
+  caret: usize,          // byte index in the value
+  anchor: Option<usize>, // the other end of the selection
+  scroll_x: f32,         // the value is scrolled to keep the caret visible
+  blink_start: Instant,
+  time: Option<Instant>, // of the last frame, see `set_time`
+  rendered: Option<RenderState>,
+}
+
+// Everything the rendered image depends on
+#[derive(Clone, PartialEq)]
+struct RenderState {
+  style: Text,
+  placeholder: String,
+  colors: [[f32; 4]; 3],
+  caret_width: f32,
+  value: String,
+  caret: usize,
+  anchor: Option<usize>,
+  caret_visible: bool,
+  width: u32,
+  height: u32,
+}
+
+impl Default for TextField {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TextField {
+  pub fn new() -> Self {
+    TextField {
+      style: Text::new(""),
+      placeholder: String::new(),
+      placeholder_color: [0.5, 0.5, 0.5, 1.0],
+      selection_color: [0.2, 0.45, 0.9, 0.4],
+      caret_color: [0.0, 0.0, 0.0, 1.0],
+      caret_width: 2.0,
+      value: String::new(),
+      caret: 0,
+      anchor: None,
+      scroll_x: 0.0,
+      blink_start: Instant::now(),
+      time: None,
+      rendered: None,
+    }
+  }
+
+  /// Set the font, size and color of the value. The string of `style` is ignored.
+  pub fn with_style(mut self, style: Text) -> Self {
+    self.style = style;
+    self
+  }
+
+  /// Text shown in `placeholder_color` while the value is empty
+  pub fn with_placeholder(mut self, placeholder: &str) -> Self {
+    self.placeholder = placeholder.to_string();
+    self
+  }
+
+  pub fn with_value(mut self, value: &str) -> Self {
+    self.set_value(value);
+    self
+  }
+
+  pub fn value(&self) -> &str {
+    &self.value
+  }
+
+  /// Replace the value and move the caret to its end
+  pub fn set_value(&mut self, value: &str) {
+    self.value = value.chars().filter(|c| !c.is_control()).collect();
+    self.caret = self.value.len();
+    self.anchor = None;
+    self.restart_blink();
+  }
+
+  /// The caret position as a byte index in the value
+  pub fn caret(&self) -> usize {
+    self.caret
+  }
+
+  /// The selected byte range of the value, if any
+  pub fn selection(&self) -> Option<Range<usize>> {
+    let anchor = self.anchor?;
+    (anchor != self.caret).then(|| anchor.min(self.caret)..anchor.max(self.caret))
+  }
+
+  /// Select a byte range; the caret moves to its end. The range is clamped to
+  /// the value and to character boundaries.
+  pub fn set_selection(&mut self, range: Range<usize>) {
+    self.anchor = Some(self.floor_boundary(range.start));
+    self.caret = self.floor_boundary(range.end);
+    self.restart_blink();
+  }
+
+  pub fn select_all(&mut self) {
+    self.set_selection(0..self.value.len());
+  }
+
+  /// The selected text
+  pub fn selected_text(&self) -> &str {
+    self.selection().map_or("", |range| &self.value[range])
+  }
+
+  /// Insert typed text at the caret, replacing the selection. Control
+  /// characters (including newlines) are ignored.
+  pub fn insert(&mut self, text: &str) {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    if text.is_empty() {
+      return;
+    }
+    self.delete_selection();
+    self.value.insert_str(self.caret, &text);
+    self.caret += text.len();
+    self.restart_blink();
+  }
+
+  /// Delete the selection or the character before the caret (Backspace)
+  pub fn delete_backward(&mut self) {
+    if !self.delete_selection() && self.caret > 0 {
+      let start = self.prev_boundary(self.caret);
+      self.value.replace_range(start..self.caret, "");
+      self.caret = start;
+    }
+    self.restart_blink();
+  }
+
+  /// Delete the selection or the character after the caret (Delete)
+  pub fn delete_forward(&mut self) {
+    if !self.delete_selection() && self.caret < self.value.len() {
+      let end = self.next_boundary(self.caret);
+      self.value.replace_range(self.caret..end, "");
+    }
+    self.restart_blink();
+  }
+
+  /// Move the caret one character left. With `extend` the selection is extended,
+  /// otherwise a selection collapses to its start.
+  pub fn move_left(&mut self, extend: bool) {
+    let target = match (self.selection(), extend) {
+      (Some(range), false) => range.start,
+      _ => self.prev_boundary(self.caret),
+    };
+    self.move_to(target, extend);
+  }
+
+  /// Move the caret one character right. See `move_left`.
+  pub fn move_right(&mut self, extend: bool) {
+    let target = match (self.selection(), extend) {
+      (Some(range), false) => range.end,
+      _ => self.next_boundary(self.caret),
+    };
+    self.move_to(target, extend);
+  }
+
+  /// Move the caret to the start of the word before it
+  pub fn move_word_left(&mut self, extend: bool) {
+    let mut index = self.caret;
+    let chars: Vec<(usize, char)> = self.value[..index].char_indices().collect();
+    let mut chars = chars.iter().rev().peekable();
+    while chars.next_if(|(_, c)| !is_word_char(*c)).is_some() {}
+    while let Some((i, _)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+      index = *i;
+    }
+    if index == self.caret {
+      index = 0;
+    }
+    self.move_to(index, extend);
+  }
+
+  /// Move the caret to the end of the word after it
+  pub fn move_word_right(&mut self, extend: bool) {
+    let mut chars = self.value[self.caret..].char_indices().peekable();
+    while chars.next_if(|(_, c)| !is_word_char(*c)).is_some() {}
+    while chars.next_if(|(_, c)| is_word_char(*c)).is_some() {}
+    let index = chars
+      .peek()
+      .map_or(self.value.len(), |(i, _)| self.caret + i);
+    self.move_to(index, extend);
+  }
+
+  pub fn move_to_start(&mut self, extend: bool) {
+    self.move_to(0, extend);
+  }
+
+  pub fn move_to_end(&mut self, extend: bool) {
+    self.move_to(self.value.len(), extend);
+  }
+
+  /// Handle an editing key. Shift extends the selection, and Control or Alt
+  /// moves by word. Returns true if the key was used. Up and Down, and Left and
+  /// Right when the caret is already at that end, are not used, so that they can
+  /// move the focus out of the field.
+  pub fn key_down(&mut self, key: Key, modifiers: Modifiers) -> bool {
+    let extend = modifiers.shift;
+    let by_word = modifiers.control || modifiers.alt;
+    let at_end = match key {
+      Key::Left => self.caret == 0,
+      Key::Right => self.caret == self.value.len(),
+      _ => false,
+    };
+    if matches!(key, Key::Up | Key::Down) || (at_end && !extend && self.selection().is_none()) {
+      return false;
+    }
+    match key {
+      Key::Left if by_word => self.move_word_left(extend),
+      Key::Left => self.move_left(extend),
+      Key::Right if by_word => self.move_word_right(extend),
+      Key::Right => self.move_right(extend),
+      Key::Home => self.move_to_start(extend),
+      Key::End => self.move_to_end(extend),
+      Key::Backspace if by_word && self.selection().is_none() => {
+        self.move_word_left(true);
+        self.delete_backward();
+      }
+      Key::Backspace => self.delete_backward(),
+      Key::Delete if by_word && self.selection().is_none() => {
+        self.move_word_right(true);
+        self.delete_forward();
+      }
+      Key::Delete => self.delete_forward(),
+      _ => return false,
+    }
+    true
+  }
+
+  /// Returns true if the caret of a focused field is in the visible half of its
+  /// blink cycle. The caret stays visible right after it moves.
+  pub fn caret_visible(&self, focused: bool) -> bool {
+    let elapsed = self.now().saturating_duration_since(self.blink_start);
+    let phase = (elapsed.as_secs_f32() / BLINK_INTERVAL) as u32;
+    focused && phase.is_multiple_of(2)
+  }
+
+  /// Render the field into a `width` x `height` image if anything changed since
+  /// the last call, including the blink state of the caret.
+  pub(crate) fn render(
+    &mut self,
+    width: u32,
+    height: u32,
+    focused: bool,
+    fonts: &FontRegistry,
+  ) -> Option<RgbaImage> {
+    let state = RenderState {
+      style: self.style.clone(),
+      placeholder: self.placeholder.clone(),
+      colors: [
+        self.placeholder_color,
+        self.selection_color,
+        self.caret_color,
+      ],
+      caret_width: self.caret_width,
+      value: self.value.clone(),
+      caret: self.caret,
+      anchor: self.anchor,
+      caret_visible: self.caret_visible(focused),
+      width,
+      height,
+    };
+    if self.rendered.as_ref() == Some(&state) {
+      return None;
+    }
+    if width == 0 || height == 0 {
+      self.rendered = Some(state);
+      return None;
+    }
+    // Without its font the field is tried again, since it may be registered later
+    let collection = fonts.collection(&self.style.font)?;
+    let caret_visible = state.caret_visible;
+    self.rendered = Some(state);
+    Some(self.rasterize(&collection.fonts(), width, height, caret_visible))
+  }
+
+  /// Draw the selection, the value (or the placeholder) and the caret into a
+  /// `width` x `height` image. The line is centered vertically.
+  pub fn rasterize<F: Font>(
+    &mut self,
+    fonts: &[F],
+    width: u32,
+    height: u32,
+    caret_visible: bool,
+  ) -> RgbaImage {
+    let mut image = RgbaImage::new(width, height);
+    let Some(font) = fonts.first() else {
+      return image;
+    };
+    let scale = PxScale::from(self.style.font_size);
+    let scaled = font.as_scaled(scale);
+    let line_height = scaled.ascent() - scaled.descent();
+    let top = (height as f32 - line_height) / 2.0;
+    let baseline = top + scaled.ascent();
+
+    let (string, color) = if self.value.is_empty() {
+      (self.placeholder.as_str(), self.placeholder_color)
+    } else {
+      (self.value.as_str(), self.style.color)
+    };
+    let shaped = shape_line(fonts, scale, string, self.style.letter_spacing);
+
+    // Scroll the value so that the caret stays inside the field
+    let caret_x = if self.value.is_empty() {
+      0.0
+    } else {
+      caret_position(&shaped, self.caret, self.value.len())
+    };
+    let visible_width = width as f32 - self.caret_width;
+    if shaped.width <= visible_width {
+      self.scroll_x = 0.0;
+    } else if caret_x - self.scroll_x > visible_width {
+      self.scroll_x = caret_x - visible_width;
+    } else if caret_x < self.scroll_x {
+      self.scroll_x = caret_x;
+    }
+
+    if let Some(range) = self.selection() {
+      let x0 = caret_position(&shaped, range.start, self.value.len()) - self.scroll_x;
+      let x1 = caret_position(&shaped, range.end, self.value.len()) - self.scroll_x;
+      fill_rect(
+        &mut image,
+        x0,
+        top,
+        x1 - x0,
+        line_height,
+        self.selection_color,
+      );
+    }
+
+    for glyph in &shaped.glyphs {
+      let positioned = glyph.id.with_scale_and_position(
+        scale,
+        point(glyph.x - self.scroll_x, baseline + glyph.y_offset),
+      );
+      if let Some(outlined) = fonts[glyph.font].outline_glyph(positioned) {
+        let bounds = outlined.px_bounds();
+        outlined.draw(|x, y, coverage| {
+          let px = x as i32 + bounds.min.x as i32;
+          let py = y as i32 + bounds.min.y as i32;
+          blend(&mut image, px, py, color, coverage);
+        });
+      }
+    }
+
+    if caret_visible {
+      fill_rect(
+        &mut image,
+        caret_x - self.scroll_x,
+        top,
+        self.caret_width,
+        line_height,
+        self.caret_color,
+      );
+    }
+    image
+  }
+
+  // Move the caret, extending the selection or clearing it
+  fn move_to(&mut self, index: usize, extend: bool) {
+    if extend {
+      self.anchor.get_or_insert(self.caret);
+    } else {
+      self.anchor = None;
+    }
+    self.caret = index;
+    self.restart_blink();
+  }
+
+  // Returns true if there was a selection to delete
+  fn delete_selection(&mut self) -> bool {
+    let Some(range) = self.selection() else {
+      self.anchor = None;
+      return false;
+    };
+    self.value.replace_range(range.clone(), "");
+    self.caret = range.start;
+    self.anchor = None;
+    true
+  }
+
+  fn prev_boundary(&self, index: usize) -> usize {
+    self.value[..index]
+      .char_indices()
+      .next_back()
+      .map_or(0, |(i, _)| i)
+  }
+
+  fn next_boundary(&self, index: usize) -> usize {
+    self.value[index..]
+      .chars()
+      .next()
+      .map_or(index, |c| index + c.len_utf8())
+  }
+
+  fn floor_boundary(&self, index: usize) -> usize {
+    let mut index = index.min(self.value.len());
+    while !self.value.is_char_boundary(index) {
+      index -= 1;
+    }
+    index
+  }
+
+  // Set the time of the frame, which the caret blinks by instead of the system
+  // clock
+  pub(crate) fn set_time(&mut self, time: Instant) {
+    self.time = Some(time);
+  }
+
+  fn now(&self) -> Instant {
+    self.time.unwrap_or_else(Instant::now)
+  }
+
+  fn restart_blink(&mut self) {
+    self.blink_start = self.now();
+  }
+}
+
+fn is_word_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+// X of the caret before the character at byte `index` of a shaped line.
+// Characters inside a ligature share the position of the ligature.
+fn caret_position(shaped: &ShapedLine, index: usize, len: usize) -> f32 {
+  if index >= len {
+    return shaped.width;
+  }
+  shaped
+    .glyphs
+    .iter()
+    .filter(|glyph| glyph.cluster <= index)
+    .max_by_key(|glyph| glyph.cluster)
+    .map_or(0.0, |glyph| glyph.x)
+}
+
+fn fill_rect(image: &mut RgbaImage, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+  for py in y.round() as i32..(y + height).round() as i32 {
+    for px in x.round() as i32..(x + width).round() as i32 {
+      blend(image, px, py, color, 1.0);
+    }
+  }
+}
+
+// Draw `color` over the pixel with `coverage`
+fn blend(image: &mut RgbaImage, x: i32, y: i32, color: [f32; 4], coverage: f32) {
+  if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+    return;
+  }
+  let pixel = image.get_pixel_mut(x as u32, y as u32);
+  let alpha = color[3] * coverage.clamp(0.0, 1.0);
+  let dst_alpha = pixel[3] as f32 / 255.0;
+  let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+  if out_alpha <= 0.0 {
+    return;
+  }
+  let mut out = [0u8; 4];
+  for i in 0..3 {
+    let dst = pixel[i] as f32 / 255.0;
+    let value = (color[i] * alpha + dst * dst_alpha * (1.0 - alpha)) / out_alpha;
+    out[i] = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+  }
+  out[3] = (out_alpha * 255.0).round() as u8;
+  *pixel = Rgba(out);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_editing() {
+    let mut field = TextField::new();
+    field.insert("hello");
+    field.insert(" wörld\n");
+    assert_eq!(field.value(), "hello wörld");
+    assert_eq!(field.caret(), field.value().len());
+
+    field.move_left(false);
+    field.delete_backward();
+    assert_eq!(field.value(), "hello wörd");
+    field.move_left(false);
+    field.move_left(false);
+    field.delete_backward();
+    assert_eq!(field.value(), "hello örd");
+    field.delete_forward();
+    assert_eq!(field.value(), "hello rd");
+    assert_eq!(field.caret(), 6);
+
+    // Selection is replaced by typed text
+    field.move_to_start(false);
+    field.move_right(true);
+    field.move_right(true);
+    assert_eq!(field.selected_text(), "he");
+    field.insert("J");
+    assert_eq!(field.value(), "Jllo rd");
+    assert_eq!(field.selection(), None);
+
+    field.select_all();
+    field.delete_backward();
+    assert_eq!(field.value(), "");
+    field.delete_backward();
+    assert_eq!(field.caret(), 0);
+  }
+
+  #[test]
+  fn test_word_movement() {
+    let mut field = TextField::new().with_value("one two_2, three");
+    let shift_ctrl = Modifiers {
+      shift: true,
+      control: true,
+      ..Default::default()
+    };
+    let ctrl = Modifiers {
+      control: true,
+      ..Default::default()
+    };
+    assert!(field.key_down(Key::Left, ctrl));
+    assert_eq!(field.caret(), 11);
+    field.key_down(Key::Left, shift_ctrl);
+    assert_eq!(field.selected_text(), "two_2, ");
+    // Left without Shift collapses the selection to its start
+    field.key_down(Key::Left, Modifiers::default());
+    assert_eq!(field.caret(), 4);
+    field.key_down(Key::Right, ctrl);
+    assert_eq!(field.caret(), 9);
+    field.key_down(Key::Home, Modifiers::default());
+    field.key_down(Key::Right, ctrl);
+    assert_eq!(field.caret(), 3);
+
+    // Delete a word
+    field.key_down(Key::End, Modifiers::default());
+    field.key_down(Key::Backspace, ctrl);
+    assert_eq!(field.value(), "one two_2, ");
+    assert!(!field.key_down(Key::Escape, Modifiers::default()));
+
+    // Keys that can't move the caret are left to focus navigation
+    assert!(!field.key_down(Key::Up, Modifiers::default()));
+    assert!(!field.key_down(Key::Right, Modifiers::default()));
+    assert!(field.key_down(Key::Left, Modifiers::default()));
+    field.key_down(Key::Home, Modifiers::default());
+    assert!(!field.key_down(Key::Left, ctrl));
+    assert!(field.key_down(Key::End, shift_ctrl));
+    assert!(field.key_down(Key::Right, Modifiers::default()));
+  }
+
+  #[test]
+  fn test_render() {
+    let fonts = FontRegistry::new();
+    let mut field = TextField::new()
+      .with_placeholder("Search")
+      .with_style(Text::new("").with_font_size(20.0));
+    let count =
+      |image: &RgbaImage, f: &dyn Fn(&Rgba<u8>) -> bool| image.pixels().filter(|p| f(p)).count();
+
+    // The placeholder is drawn in gray while the field is empty
+    let image = field.render(200, 40, false, &fonts).unwrap();
+    assert!(count(&image, &|p| p[3] > 200 && p[0] == 128) > 0);
+    // Nothing changed
+    assert!(field.render(200, 40, false, &fonts).is_none());
+
+    // The caret is drawn in a focused field
+    field.set_value("abc");
+    let image = field.render(200, 40, true, &fonts).unwrap();
+    let font = fonts.font("DejaVu Sans").unwrap();
+    let caret_x = shape_line(&[font.as_ref()], PxScale::from(20.0), "abc", 0.0).width;
+    assert_eq!(image.get_pixel(caret_x.round() as u32, 20)[3], 255);
+
+    // The selection is highlighted behind the text
+    field.select_all();
+    let image = field.render(200, 40, true, &fonts).unwrap();
+    assert!(count(&image, &|p| p[2] > p[0] && p[3] > 0) > 100);
+
+    // A long value scrolls to keep the caret inside the field
+    field.set_value(&"w".repeat(40));
+    field.render(200, 40, true, &fonts).unwrap();
+    assert!(field.scroll_x > 0.0);
+    field.move_to_start(false);
+    field.render(200, 40, true, &fonts).unwrap();
+    assert_eq!(field.scroll_x, 0.0);
+
+    // A field is rendered once its font is registered
+    field.style = Text::new("").with_font("Late");
+    assert!(field.render(200, 40, true, &fonts).is_none());
+    let font = crate::font::load_font_by_name("DejaVu Sans").unwrap();
+    fonts.register("Late", font);
+    assert!(field.render(200, 40, true, &fonts).is_some());
+  }
+}